@@ -0,0 +1,11 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use rust_raw_http_server::http::{self, ParseLimits};
+
+// Whatever the bytes, parsing must return a request or a ParseError, never panic
+fuzz_target!(|data: &[u8]| {
+    let _ = http::parse_request(data, ParseLimits::default());
+    let _ = http::find_head_end(data);
+    let _ = http::find_simple_request_end(data);
+});
@@ -0,0 +1,124 @@
+use std::net::IpAddr;
+use std::time::{Duration, SystemTime};
+
+use crate::http::StatusCode;
+use crate::http_date::{format_clf_date, format_rfc3339};
+
+/// How access log lines are written
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LogFormat {
+    /// Common Log Format, one human-readable line per request
+    Common,
+    /// One JSON object per line, for log shippers
+    Json,
+}
+
+impl LogFormat {
+    pub fn parse(value: &str) -> Option<LogFormat> {
+        match value {
+            "clf" | "common" => Some(LogFormat::Common),
+            "json" => Some(LogFormat::Json),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            LogFormat::Common => "clf",
+            LogFormat::Json => "json",
+        }
+    }
+}
+
+/// Everything we record about one request/response exchange
+pub struct AccessLogEntry<'a> {
+    pub timestamp: SystemTime,
+    pub client_ip: Option<IpAddr>,
+    /// Method, target and version; `None` if the request line couldn't be parsed
+    pub method: Option<&'a str>,
+    pub path: Option<&'a str>,
+    pub version: Option<&'a str>,
+    pub user_agent: Option<&'a str>,
+    pub status: StatusCode,
+    pub bytes: usize,
+    pub duration: Duration,
+    pub request_id: Option<&'a str>,
+}
+
+impl AccessLogEntry<'_> {
+    /// Formats the entry as a single line in the requested format
+    pub fn format(&self, format: LogFormat) -> String {
+        match format {
+            LogFormat::Common => self.format_common(),
+            LogFormat::Json => self.format_json(),
+        }
+    }
+
+    /// `127.0.0.1 - - [10/Oct/2000:13:55:36 +0000] "GET / HTTP/1.1" 200 2326`
+    fn format_common(&self) -> String {
+        let request_line = match (self.method, self.path) {
+            (Some(method), Some(path)) => {
+                // Escape quotes and control characters so a crafted path can't forge log lines
+                let line = format!("{} {} {}", method, path, self.version.unwrap_or("-"));
+                line.escape_debug().to_string()
+            }
+            _ => "-".to_string(),
+        };
+        let client = self.client_ip.map(|ip| ip.to_string()).unwrap_or_else(|| "-".to_string());
+        let bytes = if self.bytes == 0 { "-".to_string() } else { self.bytes.to_string() };
+
+        let mut line = format!(
+            "{} - - [{}] \"{}\" {} {}",
+            client,
+            format_clf_date(self.timestamp),
+            request_line,
+            self.status,
+            bytes
+        );
+        // The request ID goes in an extra trailing field, like combined-format extensions
+        if let Some(id) = self.request_id {
+            line.push_str(&format!(" \"{}\"", id));
+        }
+        line
+    }
+
+    fn format_json(&self) -> String {
+        format!(
+            "{{\"timestamp\":{},\"method\":{},\"path\":{},\"status\":{},\"bytes\":{},\
+             \"duration_ms\":{:.3},\"client_ip\":{},\"user_agent\":{},\"request_id\":{}}}",
+            json_string(Some(&format_rfc3339(self.timestamp))),
+            json_string(self.method),
+            json_string(self.path),
+            self.status,
+            self.bytes,
+            self.duration.as_secs_f64() * 1000.0,
+            json_string(self.client_ip.map(|ip| ip.to_string()).as_deref()),
+            json_string(self.user_agent),
+            json_string(self.request_id)
+        )
+    }
+}
+
+/// Serializes an optional string as a JSON string literal or `null`
+pub fn json_string(value: Option<&str>) -> String {
+    let value = match value {
+        Some(value) => value,
+        None => return "null".to_string(),
+    };
+
+    let mut out = String::with_capacity(value.len() + 2);
+    out.push('"');
+    for c in value.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
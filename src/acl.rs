@@ -0,0 +1,87 @@
+use crate::http::Method;
+
+/// Methods allowed for paths under a prefix, from `--allow-methods`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MethodRule {
+    prefix: String,
+    methods: Vec<Method>,
+}
+
+impl MethodRule {
+    /// Parses a `--allow-methods` value: `<prefix>=<METHOD>,<METHOD>...`,
+    /// e.g. `/upload/=PUT,DELETE`. Allowing GET allows HEAD as well.
+    pub fn parse(value: &str) -> Option<MethodRule> {
+        let (prefix, list) = value.split_once('=')?;
+        if !prefix.starts_with('/') {
+            return None;
+        }
+        let mut methods = Vec::new();
+        for token in list.split(',').map(str::trim) {
+            if token.is_empty() || !token.bytes().all(|byte| byte.is_ascii_alphabetic()) {
+                return None;
+            }
+            let method = Method::parse(&token.to_ascii_uppercase());
+            if !methods.contains(&method) {
+                methods.push(method);
+            }
+        }
+        if methods.contains(&Method::Get) && !methods.contains(&Method::Head) {
+            methods.push(Method::Head);
+        }
+        Some(MethodRule { prefix: prefix.to_string(), methods })
+    }
+}
+
+/// Methods allowed for paths no rule covers, once any rule is configured
+const DEFAULT_METHODS: &[Method] = &[Method::Get, Method::Head];
+
+/// The methods allowed on `path`: those of the longest matching prefix, or
+/// GET and HEAD when none matches. `None` when there are no rules at all, in
+/// which case every method goes through to routing as before.
+pub fn allowed_methods<'a>(rules: &'a [MethodRule], path: &str) -> Option<&'a [Method]> {
+    if rules.is_empty() {
+        return None;
+    }
+    let rule = rules.iter().filter(|rule| path.starts_with(rule.prefix.as_str())).max_by_key(|rule| rule.prefix.len());
+    Some(rule.map_or(DEFAULT_METHODS, |rule| rule.methods.as_slice()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{TempDir, TestServer};
+
+    #[test]
+    fn longest_prefix_decides_and_unlisted_paths_get_get_and_head() {
+        let rules = [
+            MethodRule::parse("/api/=get,post").unwrap(),
+            MethodRule::parse("/api/upload/=PUT").unwrap(),
+        ];
+        assert_eq!(allowed_methods(&rules, "/api/items"), Some(&[Method::Get, Method::Post, Method::Head][..]));
+        assert_eq!(allowed_methods(&rules, "/api/upload/a.bin"), Some(&[Method::Put][..]));
+        assert_eq!(allowed_methods(&rules, "/index.html"), Some(DEFAULT_METHODS));
+        assert_eq!(allowed_methods(&[], "/index.html"), None);
+        for invalid in ["api=GET", "/api/=", "/api/=GET,", "/api/=G-T"] {
+            assert_eq!(MethodRule::parse(invalid), None, "{:?}", invalid);
+        }
+    }
+
+    #[test]
+    fn post_to_a_get_only_prefix_is_405_with_its_allow() {
+        let root = TempDir::new();
+        root.write("static/app.js", "app");
+        let server = TestServer::with_args(&[
+            "--root", root.path().to_str().unwrap(),
+            "--allow-methods", "/static/=GET",
+            "--allow-methods", "/upload/=PUT,POST",
+        ]);
+
+        let refused = server.request("POST", "/static/app.js", &[]);
+        assert_eq!(refused.status, 405);
+        assert_eq!(refused.header("Allow"), Some("GET, HEAD"));
+        assert_eq!(server.get("/static/app.js").status, 200);
+        assert_eq!(server.get("/upload/file").header("Allow"), Some("PUT, POST"));
+        // Unlisted paths are GET and HEAD only
+        assert_eq!(server.request("DELETE", "/other", &[]).header("Allow"), Some("GET, HEAD"));
+    }
+}
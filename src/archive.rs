@@ -0,0 +1,599 @@
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{self, ErrorKind, Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::compress;
+use crate::http_date;
+use crate::listing;
+use crate::paths;
+
+const TAR_BLOCK: u64 = 512;
+const ZIP_LOCAL_HEADER: u32 = 0x0403_4b50;
+const ZIP_CENTRAL_HEADER: u32 = 0x0201_4b50;
+const ZIP_END_OF_DIRECTORY: u32 = 0x0605_4b50;
+/// Fixed part of the end-of-central-directory record, which a comment of
+/// up to 64K may follow
+const ZIP_END_LEN: u64 = 22;
+/// Extra field holding a Unix modification time, more precise than the DOS one
+const ZIP_EXTENDED_TIMESTAMP: u16 = 0x5455;
+const ZIP_STORED: u16 = 0;
+const ZIP_DEFLATED: u16 = 8;
+
+/// Where an entry's bytes are in the archive file
+#[derive(Debug, Clone, Copy)]
+enum Location {
+    /// A directory, which has none
+    Directory,
+    /// A tar member, stored as is from `offset`
+    Tar { offset: u64 },
+    /// A zip member behind the local header at `header_offset`
+    Zip { header_offset: u64, compressed_size: u64, method: u16, crc: u32 },
+}
+
+/// A file or directory in the archive
+#[derive(Debug, Clone)]
+pub struct Entry {
+    /// Path below the archive's root, without leading or trailing slashes,
+    /// e.g. `css/site.css`. Empty for the root itself.
+    pub path: String,
+    pub is_dir: bool,
+    /// Size of the file once extracted
+    pub size: u64,
+    pub modified: Option<SystemTime>,
+    location: Location,
+}
+
+/// A tar or zip file serving as the document root, for `--root-archive`.
+///
+/// The entries are indexed once, when the archive is opened; serving one
+/// reads just its bytes back out of the file. Names are taken relative to
+/// the root even when stored absolute, and entries whose names climb out of
+/// it with `..` are left out of the index, as are links, so lookups can't
+/// leave the archive's own tree.
+pub struct Archive {
+    path: PathBuf,
+    entries: BTreeMap<String, Entry>,
+}
+
+impl Archive {
+    /// Opens and indexes the archive at `path`, telling tar from zip by
+    /// their contents
+    pub fn open(path: &Path) -> io::Result<Archive> {
+        let mut file = File::open(path)?;
+        let mut magic = [0u8; TAR_BLOCK as usize];
+        let read = read_up_to(&mut file, &mut magic)?;
+        let entries = if magic[..read].starts_with(b"PK") {
+            index_zip(&mut file)?
+        } else if read == magic.len() && &magic[257..262] == b"ustar" {
+            index_tar(&mut file)?
+        } else {
+            return Err(io::Error::new(ErrorKind::InvalidData, "not a tar or zip archive"));
+        };
+
+        let mut index = BTreeMap::new();
+        index.insert(String::new(), directory(String::new(), None));
+        for entry in entries {
+            // Directories often aren't archived themselves, only implied by their files
+            let mut parent = entry.path.as_str();
+            while let Some((ancestor, _)) = parent.rsplit_once('/') {
+                index.entry(ancestor.to_string()).or_insert_with(|| directory(ancestor.to_string(), None));
+                parent = ancestor;
+            }
+            index.insert(entry.path.clone(), entry);
+        }
+        Ok(Archive { path: path.to_path_buf(), entries: index })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Number of files (not directories) in the archive
+    pub fn file_count(&self) -> usize {
+        self.entries.values().filter(|entry| !entry.is_dir).count()
+    }
+
+    /// Looks up a decoded URL path. Returns `Err` for paths that `safe_join`
+    /// would refuse on disk, so both kinds of root answer them alike.
+    pub fn find(&self, url_path: &str) -> Result<Option<&Entry>, ()> {
+        let relative = paths::safe_join(Path::new(""), url_path).ok_or(())?;
+        let key: Vec<&str> = relative.iter().filter_map(|segment| segment.to_str()).collect();
+        log_info!("Attempting to serve {} from {}", key.join("/"), self.path.display());
+        Ok(self.entries.get(&key.join("/")))
+    }
+
+    /// The entries directly inside `dir`, sorted by name, for a listing
+    pub fn list(&self, dir: &Entry) -> Vec<listing::Entry> {
+        let prefix = if dir.path.is_empty() { String::new() } else { format!("{}/", dir.path) };
+        self.entries
+            .range(prefix.clone()..)
+            .skip_while(|(path, _)| path.is_empty())
+            .take_while(|(path, _)| path.starts_with(&prefix))
+            .filter_map(|(path, entry)| {
+                let name = &path[prefix.len()..];
+                (!name.contains('/')).then(|| listing::Entry {
+                    name: name.to_string(),
+                    is_dir: entry.is_dir,
+                    size: entry.size,
+                    modified: entry.modified,
+                })
+            })
+            .collect()
+    }
+
+    /// Reads a file's contents out of the archive, inflating and checking
+    /// them if they were compressed
+    pub fn read(&self, entry: &Entry) -> io::Result<Vec<u8>> {
+        let invalid = |reason: &str| io::Error::new(ErrorKind::InvalidData, format!("{}: {}", entry.path, reason));
+        let mut file = File::open(&self.path)?;
+        match entry.location {
+            Location::Directory => Err(no_contents()),
+            Location::Tar { offset } => read_at(&mut file, offset, entry.size),
+            Location::Zip { header_offset, compressed_size, method, crc } => {
+                let data_offset = zip_data_offset(&mut file, entry, header_offset)?;
+                let stored = read_at(&mut file, data_offset, compressed_size)?;
+                let contents = match method {
+                    ZIP_STORED => stored,
+                    _ => compress::inflate(&stored, entry.size as usize).ok_or_else(|| invalid("corrupt data"))?,
+                };
+                if contents.len() as u64 != entry.size || compress::crc32(&contents) != crc {
+                    return Err(invalid("checksum mismatch"));
+                }
+                Ok(contents)
+            }
+        }
+    }
+
+    /// Opens a file's contents from `skip` bytes in, to be read straight out
+    /// of the archive rather than loaded into memory. Returns `None` for a
+    /// deflated entry, which only `read` can inflate. A stored zip entry's CRC
+    /// isn't checked this way, as that would take reading all of it before
+    /// sending the first byte.
+    pub fn stream(&self, entry: &Entry, skip: u64) -> io::Result<Option<io::Take<File>>> {
+        let mut file = File::open(&self.path)?;
+        let data_offset = match entry.location {
+            Location::Directory => return Err(no_contents()),
+            Location::Tar { offset } => offset,
+            Location::Zip { header_offset, method: ZIP_STORED, .. } => {
+                zip_data_offset(&mut file, entry, header_offset)?
+            }
+            Location::Zip { .. } => return Ok(None),
+        };
+        let skip = skip.min(entry.size);
+        file.seek(SeekFrom::Start(data_offset + skip))?;
+        Ok(Some(file.take(entry.size - skip)))
+    }
+}
+
+fn no_contents() -> io::Error {
+    io::Error::new(ErrorKind::InvalidInput, "a directory has no contents")
+}
+
+/// Where a zip entry's data starts, past the local header at `header_offset`
+fn zip_data_offset(file: &mut File, entry: &Entry, header_offset: u64) -> io::Result<u64> {
+    let header = read_at(file, header_offset, 30)?;
+    if u32_at(&header, 0) != ZIP_LOCAL_HEADER {
+        return Err(io::Error::new(ErrorKind::InvalidData, format!("{}: missing local header", entry.path)));
+    }
+    // The local header's name and extra field may differ in length from the central one's
+    Ok(header_offset + 30 + u64::from(u16_at(&header, 26)) + u64::from(u16_at(&header, 28)))
+}
+
+fn directory(path: String, modified: Option<SystemTime>) -> Entry {
+    Entry { path, is_dir: true, size: 0, modified, location: Location::Directory }
+}
+
+/// The index key for a name as stored in the archive, or `None` for names
+/// that would climb out of it. The root itself, e.g. `./`, is the empty key.
+fn normalize(name: &str) -> Option<String> {
+    let mut segments = Vec::new();
+    for segment in name.split('/') {
+        match segment {
+            "" | "." => continue,
+            ".." => return None,
+            _ if segment.contains('\\') || segment.contains('\0') => return None,
+            _ => segments.push(segment),
+        }
+    }
+    Some(segments.join("/"))
+}
+
+/// Adds an archived file or directory to `entries`, unless its name is unsafe
+fn push_entry(entries: &mut Vec<Entry>, name: &str, entry: Entry) {
+    match normalize(name) {
+        // The root is always indexed already
+        Some(path) if path.is_empty() => {}
+        Some(path) => entries.push(Entry { path, ..entry }),
+        None => log_error!("Warning: skipping archive entry {}, it would leave the archive's root", name),
+    }
+}
+
+fn index_tar(file: &mut File) -> io::Result<Vec<Entry>> {
+    let len = file.metadata()?.len();
+    let mut entries = Vec::new();
+    let mut offset = 0;
+    // A GNU long name or pax path applies to the member right after it
+    let mut next_name: Option<String> = None;
+    loop {
+        let header = match read_at(file, offset, TAR_BLOCK) {
+            Ok(header) => header,
+            // Some writers leave off the two empty blocks that should end the archive
+            Err(e) if e.kind() == ErrorKind::UnexpectedEof => break,
+            Err(e) => return Err(e),
+        };
+        if header.iter().all(|&byte| byte == 0) {
+            break;
+        }
+        if !tar_checksum_matches(&header) {
+            return Err(io::Error::new(ErrorKind::InvalidData, format!("bad tar header at offset {}", offset)));
+        }
+        let bad_size = || io::Error::new(ErrorKind::InvalidData, format!("bad tar size at offset {}", offset));
+        let size = tar_number(&header[124..136]).ok_or_else(bad_size)?;
+        let modified = tar_number(&header[136..148])
+            .and_then(|secs| UNIX_EPOCH.checked_add(Duration::from_secs(secs)));
+        let data = offset + TAR_BLOCK;
+        // A size is only believed if the member fits in what's left of the file
+        let next = size.div_ceil(TAR_BLOCK).checked_mul(TAR_BLOCK).and_then(|padded| data.checked_add(padded));
+        offset = next.filter(|_| size <= len.saturating_sub(data)).ok_or_else(bad_size)?;
+
+        let name = match next_name.take() {
+            Some(name) => name,
+            None if &header[257..262] == b"ustar" && header[345] != 0 => {
+                format!("{}/{}", tar_string(&header[345..500]), tar_string(&header[..100]))
+            }
+            None => tar_string(&header[..100]),
+        };
+        match header[156] {
+            b'0' | 0 | b'7' => {
+                let location = Location::Tar { offset: data };
+                let entry = Entry { path: String::new(), is_dir: false, size, modified, location };
+                push_entry(&mut entries, &name, entry);
+            }
+            b'5' => push_entry(&mut entries, &name, directory(String::new(), modified)),
+            b'L' => next_name = Some(tar_string(&read_at(file, data, size)?)),
+            b'x' => next_name = pax_path(&read_at(file, data, size)?),
+            // Global pax headers, and links, which could point anywhere
+            _ => {}
+        }
+    }
+    Ok(entries)
+}
+
+/// A header's checksum is the sum of its bytes, counting its own field as spaces
+fn tar_checksum_matches(header: &[u8]) -> bool {
+    let sum: u64 = header
+        .iter()
+        .enumerate()
+        .map(|(i, &byte)| if (148..156).contains(&i) { u64::from(b' ') } else { u64::from(byte) })
+        .sum();
+    tar_number(&header[148..156]) == Some(sum)
+}
+
+/// An octal number field, or a base-256 one (for sizes past 8G) when its
+/// first bit is set. `None` if it doesn't fit in a `u64`.
+fn tar_number(field: &[u8]) -> Option<u64> {
+    if field.first().is_some_and(|&byte| byte & 0x80 != 0) {
+        let first = u64::from(field[0] & 0x7f);
+        return field[1..].iter().try_fold(first, |value, &byte| Some(value.checked_mul(256)? | u64::from(byte)));
+    }
+    let text = std::str::from_utf8(field).ok()?;
+    let digits = text.trim_matches(|c: char| c == '\0' || c == ' ');
+    if digits.is_empty() {
+        return Some(0);
+    }
+    u64::from_str_radix(digits, 8).ok()
+}
+
+fn tar_string(field: &[u8]) -> String {
+    let end = field.iter().position(|&byte| byte == 0).unwrap_or(field.len());
+    String::from_utf8_lossy(&field[..end]).into_owned()
+}
+
+/// The `path` record of a pax extended header, whose records look like `27 path=some/long/name\n`
+fn pax_path(data: &[u8]) -> Option<String> {
+    let mut rest = data;
+    while !rest.is_empty() {
+        let space = rest.iter().position(|&byte| byte == b' ')?;
+        let len: usize = std::str::from_utf8(&rest[..space]).ok()?.parse().ok()?;
+        let record = rest.get(space + 1..len)?;
+        if let Some(value) = record.strip_prefix(b"path=") {
+            return Some(String::from_utf8_lossy(value.strip_suffix(b"\n").unwrap_or(value)).into_owned());
+        }
+        rest = &rest[len..];
+    }
+    None
+}
+
+fn index_zip(file: &mut File) -> io::Result<Vec<Entry>> {
+    let invalid = |reason: &str| io::Error::new(ErrorKind::InvalidData, reason.to_string());
+    // The end record is last, after a comment of unknown length
+    let len = file.seek(SeekFrom::End(0))?;
+    let tail_start = len.saturating_sub(ZIP_END_LEN + u64::from(u16::MAX));
+    let tail = read_at(file, tail_start, len - tail_start)?;
+    let end = (0..tail.len().saturating_sub(ZIP_END_LEN as usize - 1))
+        .rev()
+        .find(|&at| u32_at(&tail, at) == ZIP_END_OF_DIRECTORY)
+        .ok_or_else(|| invalid("no zip end of central directory record"))?;
+    let count = u16_at(&tail, end + 10);
+    let directory_size = u32_at(&tail, end + 12);
+    let directory_offset = u32_at(&tail, end + 16);
+    if count == u16::MAX || directory_offset == u32::MAX {
+        return Err(invalid("zip64 archives are not supported"));
+    }
+
+    let central = read_at(file, u64::from(directory_offset), u64::from(directory_size))?;
+    let mut entries = Vec::new();
+    let mut at = 0;
+    for _ in 0..count {
+        if central.len() < at + 46 || u32_at(&central, at) != ZIP_CENTRAL_HEADER {
+            return Err(invalid("truncated zip central directory"));
+        }
+        let flags = u16_at(&central, at + 8);
+        let method = u16_at(&central, at + 10);
+        let name_len = usize::from(u16_at(&central, at + 28));
+        let extra_len = usize::from(u16_at(&central, at + 30));
+        let comment_len = usize::from(u16_at(&central, at + 32));
+        let name = central.get(at + 46..at + 46 + name_len).ok_or_else(|| invalid("truncated zip entry name"))?;
+        let name = String::from_utf8_lossy(name).into_owned();
+        let extra = central.get(at + 46 + name_len..at + 46 + name_len + extra_len).unwrap_or_default();
+        let dos_time = (u16_at(&central, at + 14), u16_at(&central, at + 12));
+        let modified = unix_mtime(extra).or_else(|| dos_datetime(dos_time.0, dos_time.1));
+        let location = Location::Zip {
+            header_offset: u64::from(u32_at(&central, at + 42)),
+            compressed_size: u64::from(u32_at(&central, at + 20)),
+            method,
+            crc: u32_at(&central, at + 16),
+        };
+        let size = u64::from(u32_at(&central, at + 24));
+        at += 46 + name_len + extra_len + comment_len;
+
+        if name.ends_with('/') {
+            push_entry(&mut entries, &name, directory(String::new(), modified));
+        } else if flags & 1 != 0 {
+            log_error!("Warning: skipping archive entry {}, it is encrypted", name);
+        } else if method != ZIP_STORED && method != ZIP_DEFLATED {
+            log_error!("Warning: skipping archive entry {}, compression method {} is not supported", name, method);
+        } else {
+            let entry = Entry { path: String::new(), is_dir: false, size, modified, location };
+            push_entry(&mut entries, &name, entry);
+        }
+    }
+    Ok(entries)
+}
+
+/// The modification time from an extended timestamp extra field, if there is one
+fn unix_mtime(mut extra: &[u8]) -> Option<SystemTime> {
+    while extra.len() >= 4 {
+        let (id, len) = (u16_at(extra, 0), usize::from(u16_at(extra, 2)));
+        let data = extra.get(4..4 + len)?;
+        if id == ZIP_EXTENDED_TIMESTAMP && data.len() >= 5 && data[0] & 1 != 0 {
+            let secs = u32::from_le_bytes([data[1], data[2], data[3], data[4]]);
+            return Some(UNIX_EPOCH + Duration::from_secs(u64::from(secs)));
+        }
+        extra = &extra[4 + len..];
+    }
+    None
+}
+
+/// An MS-DOS date and time, which has two-second resolution and no time
+/// zone; it's taken to be UTC
+fn dos_datetime(date: u16, time: u16) -> Option<SystemTime> {
+    let year = 1980 + i64::from(date >> 9);
+    let (month, day) = (u32::from((date >> 5) & 0x0f), u32::from(date & 0x1f));
+    if !(1..=12).contains(&month) || day == 0 {
+        return None;
+    }
+    let seconds = u64::from(time >> 11) * 3600 + u64::from((time >> 5) & 0x3f) * 60 + u64::from(time & 0x1f) * 2;
+    http_date::from_utc(year, month, day, seconds)
+}
+
+fn u16_at(bytes: &[u8], at: usize) -> u16 {
+    u16::from_le_bytes([bytes[at], bytes[at + 1]])
+}
+
+fn u32_at(bytes: &[u8], at: usize) -> u32 {
+    u32::from_le_bytes([bytes[at], bytes[at + 1], bytes[at + 2], bytes[at + 3]])
+}
+
+fn read_at(file: &mut File, offset: u64, len: u64) -> io::Result<Vec<u8>> {
+    file.seek(SeekFrom::Start(offset))?;
+    let mut bytes = Vec::new();
+    file.take(len).read_to_end(&mut bytes)?;
+    if (bytes.len() as u64) < len {
+        return Err(io::Error::new(ErrorKind::UnexpectedEof, "the archive ends early"));
+    }
+    Ok(bytes)
+}
+
+/// Fills as much of `buf` as the file has, returning how much that was
+fn read_up_to(file: &mut File, buf: &mut [u8]) -> io::Result<usize> {
+    let mut filled = 0;
+    while filled < buf.len() {
+        match file.read(&mut buf[filled..])? {
+            0 => break,
+            read => filled += read,
+        }
+    }
+    Ok(filled)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{TempDir, TestServer};
+
+    /// A zip of `files`, each stored or deflated, put together the way zip tools do
+    fn zip(files: &[(&str, &[u8], bool)]) -> Vec<u8> {
+        let mut archive = Vec::new();
+        let mut central = Vec::new();
+        // 1 January 2024, midnight
+        let (time, date) = (0u16, (2024 - 1980) << 9 | 1 << 5 | 1u16);
+        for &(name, contents, deflate) in files {
+            let (method, data) = if deflate {
+                let gzip = compress::Encoding::Gzip.encode(contents, 6);
+                (ZIP_DEFLATED, gzip[10..gzip.len() - 8].to_vec())
+            } else {
+                (ZIP_STORED, contents.to_vec())
+            };
+            let mut common = Vec::new();
+            for field in [20, 0, method, time, date] {
+                common.extend_from_slice(&u16::to_le_bytes(field));
+            }
+            for field in [compress::crc32(contents), data.len() as u32, contents.len() as u32] {
+                common.extend_from_slice(&field.to_le_bytes());
+            }
+            common.extend_from_slice(&(name.len() as u16).to_le_bytes());
+            common.extend_from_slice(&[0, 0]);
+
+            central.extend_from_slice(&ZIP_CENTRAL_HEADER.to_le_bytes());
+            central.extend_from_slice(&[20, 3]);
+            central.extend_from_slice(&common);
+            // No comment, disk 0, no attributes, then where the local header is
+            central.extend_from_slice(&[0; 10]);
+            central.extend_from_slice(&(archive.len() as u32).to_le_bytes());
+            central.extend_from_slice(name.as_bytes());
+
+            archive.extend_from_slice(&ZIP_LOCAL_HEADER.to_le_bytes());
+            archive.extend_from_slice(&common);
+            archive.extend_from_slice(name.as_bytes());
+            archive.extend_from_slice(&data);
+        }
+        let offset = archive.len() as u32;
+        archive.extend_from_slice(&central);
+        archive.extend_from_slice(&ZIP_END_OF_DIRECTORY.to_le_bytes());
+        archive.extend_from_slice(&[0; 4]);
+        let count = (files.len() as u16).to_le_bytes();
+        archive.extend_from_slice(&[count, count].concat());
+        archive.extend_from_slice(&(central.len() as u32).to_le_bytes());
+        archive.extend_from_slice(&offset.to_le_bytes());
+        archive.extend_from_slice(&[0, 0]);
+        archive
+    }
+
+    /// A ustar header for a file called `name`, with `size` already encoded
+    fn tar_header(name: &str, size: [u8; 12]) -> Vec<u8> {
+        let mut header = vec![0; TAR_BLOCK as usize];
+        header[..name.len()].copy_from_slice(name.as_bytes());
+        header[100..108].copy_from_slice(b"0000644\0");
+        header[124..136].copy_from_slice(&size);
+        header[136..148].copy_from_slice(b"14540000000\0");
+        header[156] = b'0';
+        header[257..263].copy_from_slice(b"ustar\0");
+        header[263..265].copy_from_slice(b"00");
+        header[148..156].copy_from_slice(b"        ");
+        let sum: u32 = header.iter().map(|&byte| u32::from(byte)).sum();
+        header[148..156].copy_from_slice(format!("{:06o}\0 ", sum).as_bytes());
+        header
+    }
+
+    fn tar(files: &[(&str, &[u8])]) -> Vec<u8> {
+        let mut archive = Vec::new();
+        for (name, contents) in files {
+            let size: [u8; 12] = format!("{:011o}\0", contents.len()).into_bytes().try_into().unwrap();
+            archive.extend(tar_header(name, size));
+            archive.extend_from_slice(contents);
+            archive.resize(archive.len().next_multiple_of(TAR_BLOCK as usize), 0);
+        }
+        archive.extend_from_slice(&[0; 2 * TAR_BLOCK as usize]);
+        archive
+    }
+
+    #[test]
+    fn files_are_served_from_a_small_zip() {
+        let dir = TempDir::new();
+        let page = b"<!doctype html><p>from the zip</p>".repeat(20);
+        let archive = dir.write("site.zip", zip(&[
+            ("index.html", &page, true),
+            ("css/site.css", b"body { color: red }", false),
+            ("LICENSE", b"Do what you like", false),
+        ]));
+        let server = TestServer::with_args(&["--root-archive", archive.to_str().unwrap(), "--dir-listing"]);
+
+        let index = server.get("/");
+        assert_eq!(index.header("Content-Type"), Some("text/html; charset=utf-8"));
+        assert_eq!((index.status, index.body), (200, page));
+
+        // A stored entry goes out of the file as it is, with its length up front
+        let css = server.get("/css/site.css");
+        assert_eq!((css.status, css.body.as_slice()), (200, &b"body { color: red }"[..]));
+        assert_eq!(css.header("Content-Length"), Some("19"));
+        assert_eq!(css.header("Transfer-Encoding"), None);
+        assert!(css.header("Last-Modified").unwrap().contains("01 Jan 2024"));
+        let license = server.get("/LICENSE");
+        assert_eq!(license.header("Content-Type"), Some("text/plain; charset=utf-8"));
+
+        let part = server.request("GET", "/css/site.css", &[("Range", "bytes=7-11")]);
+        assert_eq!((part.status, part.body.as_slice()), (206, &b"color"[..]));
+        assert_eq!(part.header("Content-Range"), Some("bytes 7-11/19"));
+        let far = server.request("GET", "/css/site.css", &[("Range", "bytes=50-")]);
+        assert_eq!(far.status, 416);
+
+        let listing = String::from_utf8(server.get("/css/").body).unwrap();
+        assert!(listing.contains("site.css"), "{}", listing);
+        assert_eq!(server.get("/missing.txt").status, 404);
+        assert_eq!(server.get("/css").status, 301);
+    }
+
+    #[test]
+    fn head_answers_from_the_index_without_reading_the_entry() {
+        let dir = TempDir::new();
+        let contents = b"x".repeat(4000);
+        let path = dir.write("site.zip", zip(&[("big.bin", &contents, false), ("packed.bin", &contents, true)]));
+        let server = TestServer::with_args(&["--root-archive", path.to_str().unwrap()]);
+        let archive = Archive::open(&path).unwrap();
+
+        // Truncating the file leaves the index in the server intact but the data gone
+        let bytes = std::fs::read(&path).unwrap();
+        std::fs::write(&path, &bytes[..64]).unwrap();
+        for name in ["big.bin", "packed.bin"] {
+            let head = server.request("HEAD", &format!("/{}", name), &[]);
+            assert_eq!(head.status, 200, "{}", name);
+            assert_eq!(head.header("Content-Length"), Some("4000"), "{}", name);
+            assert!(head.body.is_empty());
+            let ranged = server.request("HEAD", &format!("/{}", name), &[("Range", "bytes=0-99")]);
+            assert_eq!((ranged.status, ranged.header("Content-Length")), (206, Some("100")), "{}", name);
+        }
+        assert!(archive.read(archive.find("/packed.bin").unwrap().unwrap()).is_err());
+        // A GET finds the data missing after the headers went out, and stops short
+        let cut = server.get("/big.bin");
+        assert_eq!((cut.status, cut.header("Content-Length")), (200, Some("4000")));
+        assert!(cut.body.len() < 4000);
+    }
+
+    #[test]
+    fn tar_members_are_indexed_and_read_back() {
+        let dir = TempDir::new();
+        let path = dir.write("site.tar", tar(&[("./docs/readme.txt", b"read me"), ("../escape.txt", b"no")]));
+        let archive = Archive::open(&path).unwrap();
+        assert_eq!(archive.file_count(), 1);
+        let entry = archive.find("/docs/readme.txt").unwrap().unwrap();
+        assert_eq!(archive.read(entry).unwrap(), b"read me");
+        let mut streamed = String::new();
+        archive.stream(entry, 5).unwrap().unwrap().read_to_string(&mut streamed).unwrap();
+        assert_eq!(streamed, "me");
+        assert!(archive.find("/docs").unwrap().unwrap().is_dir);
+        assert!(archive.find("/../escape.txt").is_err());
+    }
+
+    #[test]
+    fn sizes_that_overflow_or_overrun_the_file_are_rejected() {
+        let dir = TempDir::new();
+        // Base-256 sizes: one past what a u64 holds, and one merely larger than the file
+        let mut huge = [0xff; 12];
+        huge[0] = 0x80;
+        let mut large = [0; 12];
+        large[0] = 0x80;
+        large[8] = 1;
+        for size in [huge, large, *b"77777777777\0"] {
+            let mut archive = tar_header("big.bin", size);
+            archive.extend_from_slice(&[0; 2 * TAR_BLOCK as usize]);
+            let path = dir.write("bad.tar", archive);
+            let error = Archive::open(&path).err().expect("the archive should be refused");
+            assert_eq!(error.kind(), ErrorKind::InvalidData);
+        }
+        assert_eq!(tar_number(&huge), None);
+        assert_eq!(tar_number(&large), Some(1 << 24));
+    }
+}
@@ -0,0 +1,208 @@
+use std::io::{Read, Write};
+use std::net::TcpStream;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Options for the `bench` subcommand
+struct BenchOptions {
+    host: String,
+    port: u16,
+    path: String,
+    connections: usize,
+    duration: Duration,
+}
+
+/// Per-thread results collected while hammering the server
+#[derive(Default)]
+struct WorkerResult {
+    latencies: Vec<Duration>,
+    http_errors: usize,
+    io_errors: usize,
+}
+
+/// Entry point for `rust-raw-http-server bench ...`
+pub fn run<I: Iterator<Item = String>>(args: I) -> Result<(), String> {
+    let options = parse_args(args)?;
+
+    println!(
+        "Benchmarking http://{}:{}{} with {} connections for {:?}",
+        options.host, options.port, options.path, options.connections, options.duration
+    );
+
+    let deadline = Instant::now() + options.duration;
+    let started = Instant::now();
+
+    // Spawn one thread per simulated connection
+    let workers: Vec<_> = (0..options.connections)
+        .map(|_| {
+            let address = format!("{}:{}", options.host, options.port);
+            let request = format!(
+                "GET {} HTTP/1.1\r\nHost: {}\r\nUser-Agent: rust-raw-http-server-bench\r\nConnection: close\r\n\r\n",
+                options.path, options.host
+            );
+            thread::spawn(move || worker(&address, request.as_bytes(), deadline))
+        })
+        .collect();
+
+    let mut total = WorkerResult::default();
+    for handle in workers {
+        let result = handle.join().map_err(|_| "benchmark worker panicked".to_string())?;
+        total.latencies.extend(result.latencies);
+        total.http_errors += result.http_errors;
+        total.io_errors += result.io_errors;
+    }
+
+    report(&mut total, started.elapsed());
+    Ok(())
+}
+
+/// Sends requests back to back until the deadline passes
+fn worker(address: &str, request: &[u8], deadline: Instant) -> WorkerResult {
+    let mut result = WorkerResult::default();
+
+    while Instant::now() < deadline {
+        let start = Instant::now();
+        match send_request(address, request) {
+            Ok(status) => {
+                result.latencies.push(start.elapsed());
+                if status >= 400 {
+                    result.http_errors += 1;
+                }
+            }
+            Err(_) => result.io_errors += 1,
+        }
+    }
+
+    result
+}
+
+/// Performs a single request and returns the response status code
+fn send_request(address: &str, request: &[u8]) -> std::io::Result<u16> {
+    let mut stream = TcpStream::connect(address)?;
+    stream.write_all(request)?;
+
+    // The server closes the connection after each response, so read until EOF
+    let mut response = Vec::new();
+    stream.read_to_end(&mut response)?;
+
+    parse_status(&response).ok_or_else(|| {
+        std::io::Error::new(std::io::ErrorKind::InvalidData, "malformed response status line")
+    })
+}
+
+/// Extracts the status code from the first line of an HTTP response
+fn parse_status(response: &[u8]) -> Option<u16> {
+    let line_end = response.iter().position(|&b| b == b'\n')?;
+    let status_line = std::str::from_utf8(&response[..line_end]).ok()?;
+    let mut parts = status_line.split_whitespace();
+    if !parts.next()?.starts_with("HTTP/") {
+        return None;
+    }
+    parts.next()?.parse().ok()
+}
+
+/// Prints throughput, latency percentiles and error counts
+fn report(total: &mut WorkerResult, elapsed: Duration) {
+    total.latencies.sort();
+    let completed = total.latencies.len();
+    let seconds = elapsed.as_secs_f64();
+
+    println!();
+    println!("Requests completed: {}", completed);
+    println!("Duration:           {:.2}s", seconds);
+    println!("Requests/sec:       {:.1}", completed as f64 / seconds);
+    println!("HTTP errors (4xx/5xx): {}", total.http_errors);
+    println!("IO errors:             {}", total.io_errors);
+
+    if completed > 0 {
+        println!();
+        println!("Latency:");
+        for (label, percentile) in [("p50", 50.0), ("p90", 90.0), ("p99", 99.0)] {
+            println!("  {}: {:?}", label, percentile_of(&total.latencies, percentile));
+        }
+        println!("  max: {:?}", total.latencies[completed - 1]);
+    }
+}
+
+/// Returns the given percentile of an already-sorted list of latencies
+fn percentile_of(sorted: &[Duration], percentile: f64) -> Duration {
+    let rank = ((percentile / 100.0) * (sorted.len() - 1) as f64).round() as usize;
+    sorted[rank.min(sorted.len() - 1)]
+}
+
+/// Parses `--url`, `--connections` and `--duration`
+fn parse_args<I: Iterator<Item = String>>(mut args: I) -> Result<BenchOptions, String> {
+    let mut url = "http://127.0.0.1:8080/".to_string();
+    let mut connections = 10;
+    let mut duration = Duration::from_secs(10);
+
+    while let Some(arg) = args.next() {
+        let mut value = |name: &str| args.next().ok_or(format!("{} requires a value", name));
+        match arg.as_str() {
+            "--url" => url = value("--url")?,
+            "--connections" | "-c" => {
+                connections = value("--connections")?
+                    .parse()
+                    .map_err(|_| "--connections must be a positive number".to_string())?;
+            }
+            "--duration" | "-d" => duration = parse_duration(&value("--duration")?)?,
+            "-h" | "--help" => return Err(usage()),
+            other => return Err(format!("Unknown bench argument: {}\n\n{}", other, usage())),
+        }
+    }
+
+    if connections == 0 {
+        return Err("--connections must be at least 1".to_string());
+    }
+
+    let (host, port, path) = parse_url(&url)?;
+    Ok(BenchOptions { host, port, path, connections, duration })
+}
+
+/// Splits an `http://host[:port][/path]` URL into its parts
+fn parse_url(url: &str) -> Result<(String, u16, String), String> {
+    let rest = url
+        .strip_prefix("http://")
+        .ok_or("only http:// URLs are supported")?;
+
+    let (authority, path) = match rest.find('/') {
+        Some(index) => (&rest[..index], &rest[index..]),
+        None => (rest, "/"),
+    };
+
+    let (host, port) = match authority.rsplit_once(':') {
+        Some((host, port)) => (host, port.parse().map_err(|_| format!("invalid port in {}", url))?),
+        None => (authority, 80),
+    };
+
+    if host.is_empty() {
+        return Err(format!("missing host in {}", url));
+    }
+
+    Ok((host.to_string(), port, path.to_string()))
+}
+
+/// Parses durations like `10s`, `500ms`, `2m` or a bare number of seconds
+fn parse_duration(value: &str) -> Result<Duration, String> {
+    let invalid = || format!("invalid duration: {}", value);
+
+    if let Some(ms) = value.strip_suffix("ms") {
+        return ms.parse().map(Duration::from_millis).map_err(|_| invalid());
+    }
+    if let Some(minutes) = value.strip_suffix('m') {
+        return minutes.parse::<u64>().map(|m| Duration::from_secs(m * 60)).map_err(|_| invalid());
+    }
+    let seconds = value.strip_suffix('s').unwrap_or(value);
+    seconds.parse().map(Duration::from_secs).map_err(|_| invalid())
+}
+
+/// Returns the usage text for the bench subcommand
+fn usage() -> String {
+    "Usage: rust-raw-http-server bench [OPTIONS]\n\
+     \n\
+     Options:\n  \
+       --url <url>            URL to request (default http://127.0.0.1:8080/)\n  \
+       -c, --connections <n>  Number of concurrent connections (default 10)\n  \
+       -d, --duration <time>  How long to run, e.g. 10s, 500ms, 2m (default 10s)"
+        .to_string()
+}
@@ -0,0 +1,454 @@
+use std::error::Error;
+use std::fmt;
+use std::io::{self, ErrorKind, Read, Write};
+
+use crate::HTTP_VERSION;
+use crate::http::{HttpRequest, StatusCode};
+
+/// Bytes read from the socket at a time
+const READ_SIZE: usize = 8192;
+/// Longest chunk-size line accepted in a chunked body, extensions included
+const MAX_CHUNK_LINE: usize = 1024;
+/// Largest trailer section accepted after the last chunk
+const MAX_TRAILER_BYTES: usize = 8 * 1024;
+/// Fields a client may not send as trailers: they frame, route or modify the
+/// request, authenticate it or describe its content, and all of that has to be
+/// known before the body is read
+const FORBIDDEN_TRAILERS: &[&str] = &[
+    "Authorization",
+    "Cache-Control",
+    "Connection",
+    "Content-Encoding",
+    "Content-Length",
+    "Content-Range",
+    "Content-Type",
+    "Cookie",
+    "Expect",
+    "Host",
+    "If-Match",
+    "If-Modified-Since",
+    "If-None-Match",
+    "If-Range",
+    "If-Unmodified-Since",
+    "Keep-Alive",
+    "Max-Forwards",
+    "Pragma",
+    "Proxy-Authorization",
+    "Range",
+    "TE",
+    "Trailer",
+    "Transfer-Encoding",
+    "Upgrade",
+];
+
+/// Why a request body couldn't be read
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BodyError {
+    InvalidLength,
+    MissingLength,
+    UnsupportedEncoding,
+    InvalidChunk,
+    ForbiddenTrailer,
+    TooLarge,
+    Incomplete,
+}
+
+impl BodyError {
+    pub fn status(self) -> StatusCode {
+        match self {
+            BodyError::InvalidLength
+            | BodyError::InvalidChunk
+            | BodyError::ForbiddenTrailer
+            | BodyError::Incomplete => StatusCode::BAD_REQUEST,
+            BodyError::MissingLength => StatusCode::LENGTH_REQUIRED,
+            BodyError::UnsupportedEncoding => StatusCode::NOT_IMPLEMENTED,
+            BodyError::TooLarge => StatusCode::CONTENT_TOO_LARGE,
+        }
+    }
+
+    pub fn message(self) -> &'static str {
+        match self {
+            BodyError::InvalidLength => "Invalid Content-Length",
+            BodyError::MissingLength => "A Content-Length header is required",
+            BodyError::UnsupportedEncoding => "Only the chunked transfer coding is supported",
+            BodyError::InvalidChunk => "The chunked request body is malformed",
+            BodyError::ForbiddenTrailer => "The request trailers include a field that is not allowed there",
+            BodyError::TooLarge => "The request body is too large",
+            BodyError::Incomplete => "The request body was incomplete",
+        }
+    }
+}
+
+impl fmt::Display for BodyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.message())
+    }
+}
+
+impl Error for BodyError {}
+
+/// A connection's stream, as seen by a body reader: reads bring in the body,
+/// writes send `100 Continue`
+pub trait Stream: Read + Write {}
+
+impl<T: Read + Write + ?Sized> Stream for T {}
+
+/// Where a chunked body's reader is
+#[derive(Debug, Clone, Copy)]
+enum Chunk {
+    /// At a chunk-size line
+    Size,
+    /// Inside a chunk with this many bytes left
+    Data(u64),
+    /// At the CRLF that ends a chunk's data
+    DataEnd,
+    /// Past the last chunk and its trailers
+    Done,
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Framing {
+    /// `Content-Length`, with this many bytes left
+    Length(u64),
+    Chunked(Chunk),
+}
+
+/// A request body read straight off the connection as the handler asks for
+/// it, so large uploads never have to sit in memory whole.
+///
+/// Bytes that arrived along with the head are used first. Anything read past
+/// the end of the body stays in the connection's buffer for the next request.
+/// The `max_body` limit is checked as bytes are read, which also covers
+/// chunked bodies, whose size isn't known up front.
+pub struct RequestBody<'a> {
+    stream: &'a mut dyn Stream,
+    /// Received bytes not yet consumed, shared with the connection
+    buffer: &'a mut Vec<u8>,
+    framing: Framing,
+    max_body: u64,
+    /// Body bytes handed out so far
+    received: u64,
+    /// The client sent `Expect: 100-continue` and hasn't been answered yet
+    awaiting_continue: bool,
+    /// The first error that cut the body short
+    failure: Option<BodyError>,
+    /// Fields sent after a chunked body, in the order received
+    trailers: Vec<(String, String)>,
+}
+
+impl<'a> RequestBody<'a> {
+    /// Prepares to read `request`'s body. Framing the server can't follow and
+    /// a declared length over `max_body` are refused before anything is read.
+    pub fn new(
+        request: &HttpRequest,
+        stream: &'a mut dyn Stream,
+        buffer: &'a mut Vec<u8>,
+        max_body: u64,
+    ) -> Result<Self, BodyError> {
+        let framing = match (request.header("Transfer-Encoding"), request.header("Content-Length")) {
+            (Some(coding), _) if coding.trim().eq_ignore_ascii_case("chunked") => Framing::Chunked(Chunk::Size),
+            (Some(_), _) => return Err(BodyError::UnsupportedEncoding),
+            (None, Some(value)) => {
+                let length: u64 = value.trim().parse().map_err(|_| BodyError::InvalidLength)?;
+                if length > max_body {
+                    return Err(BodyError::TooLarge);
+                }
+                Framing::Length(length)
+            }
+            (None, None) => return Err(BodyError::MissingLength),
+        };
+
+        // A client that has started sending the body isn't waiting any more
+        let awaiting_continue = expects_continue(request) && buffer.is_empty();
+        Ok(RequestBody {
+            stream,
+            buffer,
+            framing,
+            max_body,
+            received: 0,
+            awaiting_continue,
+            failure: None,
+            trailers: Vec::new(),
+        })
+    }
+
+    /// A body with nothing in it, for requests whose method carries none
+    pub fn empty(stream: &'a mut dyn Stream, buffer: &'a mut Vec<u8>) -> Self {
+        RequestBody {
+            stream,
+            buffer,
+            framing: Framing::Length(0),
+            max_body: 0,
+            received: 0,
+            awaiting_continue: false,
+            failure: None,
+            trailers: Vec::new(),
+        }
+    }
+
+    /// True once the whole body has been read, leaving the connection at the
+    /// start of the next request
+    pub fn is_complete(&self) -> bool {
+        matches!(self.framing, Framing::Length(0) | Framing::Chunked(Chunk::Done))
+    }
+
+    /// The error that cut the body short, if reading it failed
+    pub fn failure(&self) -> Option<BodyError> {
+        self.failure
+    }
+
+    /// Trailer fields that followed a chunked body. Empty until the whole body
+    /// has been read.
+    pub fn trailers(&self) -> &[(String, String)] {
+        &self.trailers
+    }
+
+    /// Reads the rest of the body into memory
+    pub fn read_all(&mut self) -> Result<Vec<u8>, BodyError> {
+        let mut body = Vec::new();
+        match self.read_to_end(&mut body) {
+            Ok(_) => Ok(body),
+            Err(_) => Err(self.failure.unwrap_or(BodyError::Incomplete)),
+        }
+    }
+
+    /// Reads the rest of the body and throws it away
+    pub fn discard(&mut self) -> Result<u64, BodyError> {
+        io::copy(self, &mut io::sink()).map_err(|_| self.failure.unwrap_or(BodyError::Incomplete))
+    }
+
+    /// Records `error` as the reason the body stopped and returns it as an IO error
+    fn fail(&mut self, error: BodyError) -> io::Error {
+        let error = *self.failure.get_or_insert(error);
+        io::Error::new(ErrorKind::InvalidData, error)
+    }
+
+    /// Reads more bytes from the stream into the buffer.
+    ///
+    /// A client that sent `Expect: 100-continue` is waiting for our go-ahead,
+    /// which it gets the first time the handler wants bytes that haven't
+    /// arrived, so a body refused up front is never transmitted. That interim
+    /// `100 Continue` isn't the request's response: it never reaches the access
+    /// log, the stats or the keep-alive decision, all of which see only the
+    /// final status.
+    fn read_more(&mut self) -> io::Result<()> {
+        if self.awaiting_continue {
+            self.awaiting_continue = false;
+            if let Err(e) = self.stream.write_all(format!("{} 100 Continue\r\n\r\n", HTTP_VERSION).as_bytes()) {
+                log_info!("Failed to send 100 Continue: {}", e);
+                return Err(self.fail(BodyError::Incomplete));
+            }
+            log_info!("Sent 100 Continue");
+        }
+
+        let mut chunk = [0; READ_SIZE];
+        loop {
+            match self.stream.read(&mut chunk) {
+                Ok(0) => return Err(self.fail(BodyError::Incomplete)),
+                Ok(size) => {
+                    self.buffer.extend_from_slice(&chunk[..size]);
+                    return Ok(());
+                }
+                Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+                Err(e) => {
+                    log_info!("Failed to read request body: {}", e);
+                    return Err(self.fail(BodyError::Incomplete));
+                }
+            }
+        }
+    }
+
+    /// Takes one CRLF-terminated line of chunked framing off the buffer,
+    /// without the CRLF. Lines longer than `limit` are malformed.
+    fn read_line(&mut self, limit: usize) -> io::Result<String> {
+        loop {
+            if let Some(end) = self.buffer.windows(2).position(|pair| pair == b"\r\n") {
+                let line: Vec<u8> = self.buffer.drain(..end + 2).collect();
+                if end > limit {
+                    return Err(self.fail(BodyError::InvalidChunk));
+                }
+                return Ok(String::from_utf8_lossy(&line[..end]).into_owned());
+            }
+            if self.buffer.len() > limit + 1 {
+                return Err(self.fail(BodyError::InvalidChunk));
+            }
+            self.read_more()?;
+        }
+    }
+
+    /// Reads the trailer section that follows the last chunk. Each line is a
+    /// `Name: value` field, parsed like a header; fields that only make sense
+    /// before the body refuse the request.
+    fn read_trailers(&mut self) -> io::Result<()> {
+        let mut total = 0;
+        loop {
+            let line = self.read_line(MAX_TRAILER_BYTES)?;
+            if line.is_empty() {
+                return Ok(());
+            }
+            total += line.len() + 2;
+            if total > MAX_TRAILER_BYTES {
+                return Err(self.fail(BodyError::InvalidChunk));
+            }
+            let Some((name, value)) = line.split_once(':') else {
+                return Err(self.fail(BodyError::InvalidChunk));
+            };
+            let name = name.trim();
+            if name.is_empty() || name.contains(char::is_whitespace) {
+                return Err(self.fail(BodyError::InvalidChunk));
+            }
+            if FORBIDDEN_TRAILERS.iter().any(|forbidden| forbidden.eq_ignore_ascii_case(name)) {
+                log_info!("Refusing a request trailer: {}", name);
+                return Err(self.fail(BodyError::ForbiddenTrailer));
+            }
+            self.trailers.push((name.to_string(), value.trim().to_string()));
+        }
+    }
+}
+
+impl Read for RequestBody<'_> {
+    fn read(&mut self, out: &mut [u8]) -> io::Result<usize> {
+        if let Some(error) = self.failure {
+            return Err(io::Error::new(ErrorKind::InvalidData, error));
+        }
+
+        // Step through the framing until there are data bytes to hand out
+        let remaining = loop {
+            match self.framing {
+                Framing::Length(0) | Framing::Chunked(Chunk::Done) => return Ok(0),
+                Framing::Length(remaining) | Framing::Chunked(Chunk::Data(remaining)) => break remaining,
+                Framing::Chunked(Chunk::Size) => {
+                    let line = self.read_line(MAX_CHUNK_LINE)?;
+                    let Some(size) = parse_chunk_size(&line) else {
+                        return Err(self.fail(BodyError::InvalidChunk));
+                    };
+                    if size == 0 {
+                        self.read_trailers()?;
+                        self.framing = Framing::Chunked(Chunk::Done);
+                    } else {
+                        self.framing = Framing::Chunked(Chunk::Data(size));
+                    }
+                }
+                Framing::Chunked(Chunk::DataEnd) => {
+                    if !self.read_line(0)?.is_empty() {
+                        return Err(self.fail(BodyError::InvalidChunk));
+                    }
+                    self.framing = Framing::Chunked(Chunk::Size);
+                }
+            }
+        };
+
+        if self.buffer.is_empty() {
+            self.read_more()?;
+        }
+        let size = (remaining.min(self.buffer.len() as u64) as usize).min(out.len());
+        if self.received + size as u64 > self.max_body {
+            log_info!("Request body exceeded the {} byte limit", self.max_body);
+            return Err(self.fail(BodyError::TooLarge));
+        }
+        out[..size].copy_from_slice(&self.buffer[..size]);
+        self.buffer.drain(..size);
+        self.received += size as u64;
+
+        let remaining = remaining - size as u64;
+        self.framing = match self.framing {
+            Framing::Length(_) => Framing::Length(remaining),
+            _ if remaining == 0 => Framing::Chunked(Chunk::DataEnd),
+            _ => Framing::Chunked(Chunk::Data(remaining)),
+        };
+        Ok(size)
+    }
+}
+
+/// Whether `request` declares a body at all. Requests without either framing
+/// header have none, whatever their method.
+pub fn has_body(request: &HttpRequest) -> bool {
+    request.header("Transfer-Encoding").is_some() || request.header("Content-Length").is_some()
+}
+
+/// Parses a chunk-size line: hex digits, optionally followed by `;` extensions
+fn parse_chunk_size(line: &str) -> Option<u64> {
+    let size = line.split(';').next().unwrap_or("").trim();
+    if size.is_empty() || size.len() > 16 || !size.bytes().all(|byte| byte.is_ascii_hexdigit()) {
+        return None;
+    }
+    u64::from_str_radix(size, 16).ok()
+}
+
+/// Whether the client is holding back its body until told to continue.
+/// HTTP/1.0 clients don't know interim responses, so they never get one.
+fn expects_continue(request: &HttpRequest) -> bool {
+    request.is_http_1_1()
+        && request.header("Expect").is_some_and(|value| value.trim().eq_ignore_ascii_case("100-continue"))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+    use crate::http::{self, ParseLimits};
+
+    /// Reads the body of a POST framed by `framing`, allowing `max_body` bytes
+    fn read_body(framing: &str, sent: &[u8], max_body: u64) -> Result<Vec<u8>, BodyError> {
+        let head = format!("POST / HTTP/1.1\r\nHost: a\r\n{}\r\n\r\n", framing);
+        let request = http::parse_request(head.as_bytes(), ParseLimits::default()).unwrap();
+        let mut stream = Cursor::new(Vec::new());
+        let mut buffer = sent.to_vec();
+        RequestBody::new(&request, &mut stream, &mut buffer, max_body)?.read_all()
+    }
+
+    fn read_chunked(encoded: &[u8], max_body: u64) -> Result<Vec<u8>, BodyError> {
+        read_body("Transfer-Encoding: chunked", encoded, max_body)
+    }
+
+    #[test]
+    fn chunk_sizes_are_plain_hex_that_fits() {
+        assert_eq!(parse_chunk_size("1a;name=value"), Some(26));
+        assert_eq!(parse_chunk_size("ffffffffffffffff"), Some(u64::MAX));
+        for invalid in ["", "fffffffffffffffff", "+1", "-1", "0x10", "g", " "] {
+            assert_eq!(parse_chunk_size(invalid), None, "{:?}", invalid);
+        }
+    }
+
+    #[test]
+    fn crafted_chunked_bodies_fail_cleanly() {
+        assert_eq!(read_chunked(b"5\r\nhello\r\n0\r\n\r\n", 64).unwrap(), b"hello");
+        // A chunk claiming all of u64 runs into the body limit, not an overflow
+        assert_eq!(read_chunked(b"ffffffffffffffff\r\nabc", 2), Err(BodyError::TooLarge));
+        assert_eq!(read_chunked(b"fffffffffffffffff\r\n", 64), Err(BodyError::InvalidChunk));
+        assert_eq!(read_chunked(b"5\r\nhelloXX0\r\n\r\n", 64), Err(BodyError::InvalidChunk));
+        assert_eq!(read_chunked(b"5\r\nhel", 64), Err(BodyError::Incomplete));
+    }
+
+    #[test]
+    fn trailers_after_the_last_chunk_are_kept() {
+        let head = b"POST / HTTP/1.1\r\nHost: a\r\nTransfer-Encoding: chunked\r\n\r\n";
+        let request = http::parse_request(head, ParseLimits::default()).unwrap();
+        let mut stream = Cursor::new(Vec::new());
+        let mut buffer = b"4\r\ndata\r\n0\r\nX-Checksum: sha256=abc\r\nX-Parts:  2 \r\n\r\n".to_vec();
+        let mut body = RequestBody::new(&request, &mut stream, &mut buffer, 64).unwrap();
+        assert_eq!(body.read_all().unwrap(), b"data");
+        let expected = [("X-Checksum", "sha256=abc"), ("X-Parts", "2")].map(|(n, v)| (n.to_string(), v.to_string()));
+        assert_eq!(body.trailers(), expected);
+    }
+
+    #[test]
+    fn trailers_that_belong_in_the_head_are_refused() {
+        for field in ["Content-Length: 4", "transfer-encoding: chunked", "Host: b", "Authorization: x"] {
+            let encoded = format!("4\r\ndata\r\n0\r\n{}\r\n\r\n", field);
+            assert_eq!(read_chunked(encoded.as_bytes(), 64), Err(BodyError::ForbiddenTrailer), "{}", field);
+        }
+        for line in ["no colon", ": empty name", "Bad Name: x"] {
+            let encoded = format!("4\r\ndata\r\n0\r\n{}\r\n\r\n", line);
+            assert_eq!(read_chunked(encoded.as_bytes(), 64), Err(BodyError::InvalidChunk), "{}", line);
+        }
+    }
+
+    #[test]
+    fn content_length_past_u64_is_invalid() {
+        let framing = "Content-Length: 99999999999999999999999";
+        assert_eq!(read_body(framing, b"", u64::MAX), Err(BodyError::InvalidLength));
+        assert_eq!(read_body("Content-Length: 3", b"abc", 2), Err(BodyError::TooLarge));
+    }
+}
@@ -0,0 +1,285 @@
+use std::collections::HashMap;
+use std::fs::{self, File, Metadata};
+use std::io::{self, Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
+
+/// A file's contents as of a given size and modification time
+struct Entry {
+    contents: Arc<Vec<u8>>,
+    len: u64,
+    modified: Option<SystemTime>,
+    /// Value of the cache's use counter when this entry was last read
+    last_used: u64,
+}
+
+struct State {
+    entries: HashMap<PathBuf, Entry>,
+    /// Sum of the cached contents' lengths
+    bytes: u64,
+    uses: u64,
+}
+
+/// In-memory cache of file contents, bounded by total size.
+///
+/// Entries are checked against the file's current metadata on every read,
+/// so a file changed on disk is read again rather than served stale. Since
+/// validators come from that same metadata, a cached entry always matches the
+/// ETag that range requests are checked against, and ranges are sliced
+/// straight out of the cached bytes. When the cache is full the least
+/// recently used entries make room.
+pub struct FileCache {
+    max_bytes: u64,
+    state: Mutex<State>,
+}
+
+impl FileCache {
+    /// Creates a cache holding at most `max_bytes`; zero disables caching
+    pub fn new(max_bytes: u64) -> Self {
+        FileCache {
+            max_bytes,
+            state: Mutex::new(State { entries: HashMap::new(), bytes: 0, uses: 0 }),
+        }
+    }
+
+    /// Returns the contents of `path`, whose current metadata is `metadata`,
+    /// from memory when possible and from disk otherwise
+    pub fn read(&self, path: &Path, metadata: &Metadata) -> io::Result<Arc<Vec<u8>>> {
+        if self.max_bytes == 0 {
+            return fs::read(path).map(Arc::new);
+        }
+
+        let modified = metadata.modified().ok();
+        {
+            let mut state = self.state.lock().unwrap();
+            state.uses += 1;
+            let uses = state.uses;
+            if let Some(entry) = state.entries.get_mut(path)
+                && entry.len == metadata.len()
+                && entry.modified == modified
+            {
+                entry.last_used = uses;
+                return Ok(Arc::clone(&entry.contents));
+            }
+        }
+
+        let contents = Arc::new(fs::read(path)?);
+        // Callers derive ETag and Last-Modified (and so If-Range) from `metadata`.
+        // If the file changed since it was taken, the bytes belong to another
+        // version and mustn't be cached under these validators.
+        if contents.len() as u64 == metadata.len() {
+            self.insert(path, &contents, modified, true);
+        }
+        Ok(contents)
+    }
+
+    /// Opens `count` bytes of `path` from `start`, to be read as they're sent:
+    /// shared out of the cache when the file fits in it, straight from disk
+    /// when it doesn't, so a file is never loaded only to be copied out again
+    pub fn open(&self, path: &Path, metadata: &Metadata, start: u64, count: u64) -> io::Result<Box<dyn Read>> {
+        if metadata.len() > self.max_bytes {
+            let mut file = File::open(path)?;
+            file.seek(SeekFrom::Start(start))?;
+            return Ok(Box::new(file.take(count)));
+        }
+        let mut cursor = io::Cursor::new(Shared(self.read(path, metadata)?));
+        cursor.set_position(start);
+        Ok(Box::new(cursor.take(count)))
+    }
+
+    /// Reads every regular file under `roots` into the cache, smallest first,
+    /// stopping once the cache is full. Returns the files and bytes loaded.
+    pub fn preload(&self, roots: &[PathBuf]) -> (usize, u64) {
+        let mut files = Vec::new();
+        for root in roots {
+            collect_files(root, &mut files);
+        }
+        // Small files are the cheapest to keep and the most likely to be
+        // requested together (pages, scripts, styles), so they go first
+        files.sort_by_key(|(_, metadata)| metadata.len());
+
+        let (mut count, mut bytes) = (0, 0);
+        for (path, metadata) in files {
+            if bytes + metadata.len() > self.max_bytes {
+                break;
+            }
+            // With several roots, only the first copy of a path is ever served
+            if self.state.lock().unwrap().entries.contains_key(&path) {
+                continue;
+            }
+            match fs::read(&path) {
+                Ok(contents) => {
+                    let len = contents.len() as u64;
+                    if self.insert(&path, &Arc::new(contents), metadata.modified().ok(), false) {
+                        count += 1;
+                        bytes += len;
+                    }
+                }
+                Err(e) => log_error!("Failed to preload {}: {}", path.display(), e),
+            }
+        }
+        (count, bytes)
+    }
+
+    /// Stores `contents` for `path`, evicting old entries if `evict` allows it.
+    /// Returns false when the contents didn't fit.
+    fn insert(&self, path: &Path, contents: &Arc<Vec<u8>>, modified: Option<SystemTime>, evict: bool) -> bool {
+        let len = contents.len() as u64;
+        if len > self.max_bytes {
+            return false;
+        }
+
+        let mut state = self.state.lock().unwrap();
+        if let Some(old) = state.entries.remove(path) {
+            state.bytes -= old.len;
+        }
+        while state.bytes + len > self.max_bytes {
+            if !evict {
+                return false;
+            }
+            let Some(oldest) = state.entries.iter().min_by_key(|(_, entry)| entry.last_used).map(|(p, _)| p.clone())
+            else {
+                break;
+            };
+            if let Some(old) = state.entries.remove(&oldest) {
+                state.bytes -= old.len;
+            }
+        }
+
+        state.bytes += len;
+        let last_used = state.uses;
+        state.entries.insert(path.to_path_buf(), Entry { contents: Arc::clone(contents), len, modified, last_used });
+        true
+    }
+}
+
+/// Cached contents read through a cursor without copying them
+struct Shared(Arc<Vec<u8>>);
+
+impl AsRef<[u8]> for Shared {
+    fn as_ref(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+/// Appends every regular file below `dir` to `files`, following symlinks
+fn collect_files(dir: &Path, files: &mut Vec<(PathBuf, Metadata)>) {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) => {
+            log_error!("Failed to read directory {} for preloading: {}", dir.display(), e);
+            return;
+        }
+    };
+    for entry in entries.flatten() {
+        let path = entry.path();
+        match fs::metadata(&path) {
+            Ok(metadata) if metadata.is_dir() => collect_files(&path, files),
+            Ok(metadata) if metadata.is_file() => files.push((path, metadata)),
+            _ => {}
+        }
+    }
+}
+
+/// A control file parsed into `T`, as of a given size and modification time
+struct Parsed<T> {
+    value: Arc<T>,
+    len: u64,
+    modified: Option<SystemTime>,
+}
+
+/// Small per-directory control files (such as `.mime`) kept parsed in memory.
+/// Like `FileCache`, every lookup checks the file's current metadata, so an
+/// edited file is parsed again and a deleted one stops applying.
+pub struct ParsedCache<T> {
+    entries: Mutex<HashMap<PathBuf, Parsed<T>>>,
+}
+
+impl<T> ParsedCache<T> {
+    pub fn new() -> Self {
+        ParsedCache { entries: Mutex::new(HashMap::new()) }
+    }
+
+    /// Returns `path` parsed by `parse`, or `None` when there is no such file
+    pub fn get(&self, path: &Path, parse: impl FnOnce(&str, &Path) -> T) -> Option<Arc<T>> {
+        let metadata = match fs::metadata(path) {
+            Ok(metadata) if metadata.is_file() => metadata,
+            _ => {
+                self.entries.lock().unwrap().remove(path);
+                return None;
+            }
+        };
+
+        let modified = metadata.modified().ok();
+        if let Some(entry) = self.entries.lock().unwrap().get(path)
+            && entry.len == metadata.len()
+            && entry.modified == modified
+        {
+            return Some(Arc::clone(&entry.value));
+        }
+
+        let contents = match fs::read_to_string(path) {
+            Ok(contents) => contents,
+            Err(e) => {
+                log_error!("Warning: ignoring {}, it could not be read: {}", path.display(), e);
+                return None;
+            }
+        };
+        let value = Arc::new(parse(&contents, path));
+        let entry = Parsed { value: Arc::clone(&value), len: metadata.len(), modified };
+        self.entries.lock().unwrap().insert(path.to_path_buf(), entry);
+        Some(value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::File;
+
+    use super::*;
+    use crate::testing::{TempDir, TestServer};
+
+    /// Rewrites `path` behind the cache's back: new bytes of the same length
+    /// and the same modification time, so only a disk read would notice
+    fn replace_keeping_metadata(path: &Path, contents: &[u8]) {
+        let modified = fs::metadata(path).unwrap().modified().unwrap();
+        fs::write(path, contents).unwrap();
+        File::options().write(true).open(path).unwrap().set_modified(modified).unwrap();
+    }
+
+    #[test]
+    fn cached_reads_come_from_memory() {
+        let dir = TempDir::new();
+        let path = dir.write("data.bin", "0123456789");
+        let cache = FileCache::new(1024);
+        let metadata = fs::metadata(&path).unwrap();
+        assert_eq!(*cache.read(&path, &metadata).unwrap(), b"0123456789");
+
+        replace_keeping_metadata(&path, b"abcdefghij");
+        assert_eq!(*cache.read(&path, &fs::metadata(&path).unwrap()).unwrap(), b"0123456789");
+        // A disabled cache always goes to disk
+        assert_eq!(*FileCache::new(0).read(&path, &metadata).unwrap(), b"abcdefghij");
+    }
+
+    #[test]
+    fn range_on_a_cached_file_is_sliced_from_memory() {
+        let root = TempDir::new();
+        let path = root.write("data.txt", "0123456789");
+        let server = TestServer::with_args(&["--root", root.path().to_str().unwrap(), "--cache-size", "1M"]);
+
+        let full = server.get("/data.txt");
+        assert_eq!(full.body, b"0123456789");
+        let etag = full.header("ETag").unwrap().to_string();
+        replace_keeping_metadata(&path, b"abcdefghij");
+
+        let partial = server.request("GET", "/data.txt", &[("Range", "bytes=2-4"), ("If-Range", &etag)]);
+        assert_eq!(partial.status, 206);
+        assert_eq!(partial.header("Content-Range"), Some("bytes 2-4/10"));
+        assert_eq!(partial.body, b"234");
+        // A stale If-Range gets the whole file instead
+        let stale = server.request("GET", "/data.txt", &[("Range", "bytes=2-4"), ("If-Range", "\"other\"")]);
+        assert_eq!(stale.status, 200);
+        assert_eq!(stale.body, b"0123456789");
+    }
+}
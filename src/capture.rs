@@ -0,0 +1,68 @@
+use std::io::{self, Read, Write};
+use std::time::Duration;
+
+/// Wraps a stream and keeps a copy of every byte read from and written to it
+pub struct TeeStream<S> {
+    inner: S,
+    pub received: Vec<u8>,
+    pub sent: Vec<u8>,
+}
+
+impl<S> TeeStream<S> {
+    pub fn new(inner: S) -> Self {
+        TeeStream { inner, received: Vec::new(), sent: Vec::new() }
+    }
+}
+
+impl<S: crate::ReadTimeout> crate::ReadTimeout for TeeStream<S> {
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        self.inner.set_read_timeout(timeout)
+    }
+}
+
+impl<S: Read> Read for TeeStream<S> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let size = self.inner.read(buf)?;
+        self.received.extend_from_slice(&buf[..size]);
+        Ok(size)
+    }
+}
+
+impl<S: Write> Write for TeeStream<S> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let size = self.inner.write(buf)?;
+        self.sent.extend_from_slice(&buf[..size]);
+        Ok(size)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Formats bytes as a classic hex dump: offset, 16 hex bytes, then ASCII
+pub fn hex_dump(bytes: &[u8]) -> String {
+    let mut out = String::new();
+
+    for (line, chunk) in bytes.chunks(16).enumerate() {
+        out.push_str(&format!("{:08x}  ", line * 16));
+
+        for i in 0..16 {
+            match chunk.get(i) {
+                Some(byte) => out.push_str(&format!("{:02x} ", byte)),
+                None => out.push_str("   "),
+            }
+            if i == 7 {
+                out.push(' ');
+            }
+        }
+
+        out.push_str(" |");
+        for &byte in chunk {
+            out.push(if byte.is_ascii_graphic() || byte == b' ' { byte as char } else { '.' });
+        }
+        out.push_str("|\n");
+    }
+
+    out
+}
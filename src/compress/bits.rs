@@ -0,0 +1,96 @@
+/// Packs values into bytes least-significant bit first, the bit order
+/// shared by DEFLATE and brotli
+pub struct BitWriter {
+    out: Vec<u8>,
+    pending: u64,
+    pending_bits: u32,
+}
+
+impl BitWriter {
+    pub fn new() -> Self {
+        BitWriter { out: Vec::new(), pending: 0, pending_bits: 0 }
+    }
+
+    /// Appends the low `bits` bits of `value` (at most 32)
+    pub fn write(&mut self, value: u32, bits: u32) {
+        debug_assert!(bits <= 32);
+        let mask = (1u64 << bits) - 1;
+        self.pending |= (u64::from(value) & mask) << self.pending_bits;
+        self.pending_bits += bits;
+        while self.pending_bits >= 8 {
+            self.out.push(self.pending as u8);
+            self.pending >>= 8;
+            self.pending_bits -= 8;
+        }
+    }
+
+    /// Pads with zero bits up to the next byte boundary
+    pub fn align(&mut self) {
+        if self.pending_bits > 0 {
+            self.out.push(self.pending as u8);
+            self.pending = 0;
+            self.pending_bits = 0;
+        }
+    }
+
+    /// Appends raw bytes; only valid on a byte boundary
+    pub fn write_bytes(&mut self, bytes: &[u8]) {
+        debug_assert_eq!(self.pending_bits, 0);
+        self.out.extend_from_slice(bytes);
+    }
+
+    pub fn finish(mut self) -> Vec<u8> {
+        self.align();
+        self.out
+    }
+}
+
+/// Reads values back out of a `BitWriter`-style stream, least-significant bit first
+pub struct BitReader<'a> {
+    data: &'a [u8],
+    /// Next byte not yet loaded into `pending`
+    position: usize,
+    pending: u64,
+    pending_bits: u32,
+}
+
+impl<'a> BitReader<'a> {
+    pub fn new(data: &'a [u8]) -> Self {
+        BitReader { data, position: 0, pending: 0, pending_bits: 0 }
+    }
+
+    /// The next `bits` bits (at most 32) as a number, or `None` past the end
+    pub fn read(&mut self, bits: u32) -> Option<u32> {
+        debug_assert!(bits <= 32);
+        while self.pending_bits < bits {
+            let byte = *self.data.get(self.position)?;
+            self.pending |= u64::from(byte) << self.pending_bits;
+            self.position += 1;
+            self.pending_bits += 8;
+        }
+        let value = (self.pending & ((1u64 << bits) - 1)) as u32;
+        self.pending >>= bits;
+        self.pending_bits -= bits;
+        Some(value)
+    }
+
+    /// Skips to the next byte boundary
+    pub fn align(&mut self) {
+        let partial = self.pending_bits % 8;
+        self.pending >>= partial;
+        self.pending_bits -= partial;
+    }
+
+    /// The next `len` raw bytes; only valid on a byte boundary
+    pub fn read_bytes(&mut self, len: usize) -> Option<&'a [u8]> {
+        debug_assert_eq!(self.pending_bits % 8, 0);
+        // Whole bytes still buffered come first
+        let buffered = (self.pending_bits / 8) as usize;
+        let start = self.position - buffered;
+        let bytes = self.data.get(start..start + len)?;
+        self.position = start + len;
+        self.pending = 0;
+        self.pending_bits = 0;
+        Some(bytes)
+    }
+}
@@ -0,0 +1,514 @@
+use super::bits::BitWriter;
+use super::huffman;
+use super::lz77::{self, Token};
+
+/// Input bytes per meta-block; must stay below brotli's 16 MiB limit
+const META_BLOCK_SIZE: usize = 1 << 20;
+
+const LITERAL_ALPHABET: usize = 256;
+const COMMAND_ALPHABET: usize = 704;
+/// 16 short codes plus 48 distance buckets, with NPOSTFIX = NDIRECT = 0
+const DISTANCE_ALPHABET: usize = 64;
+
+const INSERT_BASE: [u32; 24] = [
+    0, 1, 2, 3, 4, 5, 6, 8, 10, 14, 18, 26, 34, 50, 66, 98, 130, 194, 322, 578, 1090, 2114, 6210, 22594,
+];
+const INSERT_EXTRA: [u8; 24] = [0, 0, 0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 7, 8, 9, 10, 12, 14, 24];
+const COPY_BASE: [u32; 24] = [
+    2, 3, 4, 5, 6, 7, 8, 9, 10, 12, 14, 18, 22, 30, 38, 54, 70, 102, 134, 198, 326, 582, 1094, 2118,
+];
+const COPY_EXTRA: [u8; 24] = [0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 7, 8, 9, 10, 24];
+
+/// Order in which code length code lengths are transmitted
+const CODE_LENGTH_ORDER: [usize; 18] = [1, 2, 3, 4, 0, 5, 17, 6, 16, 7, 8, 9, 10, 11, 12, 13, 14, 15];
+/// Fixed prefix code for code length code lengths 0-5, as (value, bits)
+const CODE_LENGTH_LENGTH_CODES: [(u32, u32); 6] = [(0, 2), (7, 4), (3, 3), (2, 2), (1, 2), (15, 4)];
+/// Code length symbols 16 and 17 repeat the previous length or zero
+const REPEAT_PREVIOUS: u8 = 16;
+const REPEAT_ZERO: u8 = 17;
+
+/// Compresses `data` into a brotli stream (RFC 7932).
+///
+/// This is a deliberately simple encoder: the same LZ77 matcher as gzip,
+/// one prefix code per alphabet per meta-block and no context modeling or
+/// static dictionary, so output is roughly gzip-sized. For the same reason
+/// `level` uses gzip's 0-9 scale of matcher effort.
+pub fn compress(data: &[u8], level: u8) -> Vec<u8> {
+    let mut writer = BitWriter::new();
+    // WBITS = 16: a 64 KiB window is plenty for our 32 KiB matches
+    writer.write(0, 1);
+
+    for chunk in data.chunks(META_BLOCK_SIZE) {
+        write_meta_block(&mut writer, chunk, level);
+    }
+
+    // ISLAST, ISLASTEMPTY
+    writer.write(1, 1);
+    writer.write(1, 1);
+    writer.finish()
+}
+
+/// An insert-and-copy command: `insert` literals followed by a copy of
+/// `copy` bytes from `distance` back. The final command may be insert-only.
+struct Command {
+    insert: Vec<u8>,
+    copy: u32,
+    distance: u32,
+}
+
+fn to_commands(tokens: &[Token]) -> Vec<Command> {
+    let mut commands = Vec::new();
+    let mut pending = Vec::new();
+    for token in tokens {
+        match *token {
+            Token::Literal(byte) => pending.push(byte),
+            Token::Match { length, distance } => commands.push(Command {
+                insert: std::mem::take(&mut pending),
+                copy: u32::from(length),
+                distance: u32::from(distance),
+            }),
+        }
+    }
+    if !pending.is_empty() {
+        commands.push(Command { insert: pending, copy: 0, distance: 0 });
+    }
+    commands
+}
+
+fn insert_code(length: u32) -> usize {
+    INSERT_BASE.iter().rposition(|&base| base <= length).unwrap_or(0)
+}
+
+fn copy_code(length: u32) -> usize {
+    COPY_BASE.iter().rposition(|&base| base <= length).unwrap_or(0)
+}
+
+/// Combines insert and copy length codes into a command symbol that is
+/// followed by an explicit distance
+fn command_symbol(insert_code: usize, copy_code: usize) -> usize {
+    let cell = match (insert_code >> 3, copy_code >> 3) {
+        (0, 0) => 128,
+        (0, 1) => 192,
+        (0, _) => 384,
+        (1, 0) => 256,
+        (1, 1) => 320,
+        (1, _) => 512,
+        (_, 0) => 448,
+        (_, 1) => 576,
+        (_, _) => 640,
+    };
+    cell + ((insert_code & 7) << 3) + (copy_code & 7)
+}
+
+/// Returns the distance symbol plus its extra bits as (value, count)
+fn distance_symbol(distance: u32) -> (usize, u32, u32) {
+    // With NPOSTFIX = NDIRECT = 0, distance d lands in a bucket of `dist = d + 3`
+    let dist = distance + 3;
+    let bucket = dist.ilog2() - 1;
+    let prefix = (dist >> bucket) & 1;
+    let offset = (2 + prefix) << bucket;
+    let symbol = 16 + 2 * (bucket - 1) + prefix;
+    (symbol as usize, dist - offset, bucket)
+}
+
+/// A prefix code ready for writing symbols
+struct PrefixCode {
+    lengths: Vec<u8>,
+    codes: Vec<u16>,
+}
+
+impl PrefixCode {
+    fn write(&self, writer: &mut BitWriter, symbol: usize) {
+        writer.write(u32::from(self.codes[symbol]), u32::from(self.lengths[symbol]));
+    }
+}
+
+/// Builds a prefix code for the given frequencies and writes its description
+fn write_prefix_code(writer: &mut BitWriter, freqs: &[u32]) -> PrefixCode {
+    let alphabet_bits = (freqs.len() - 1).ilog2() + 1;
+    let lengths = huffman::code_lengths(freqs, 15);
+    let used: Vec<usize> = (0..freqs.len()).filter(|&symbol| lengths[symbol] > 0).collect();
+
+    // A single symbol (or none at all) is sent as a simple prefix code;
+    // it then takes zero bits per occurrence
+    if used.len() <= 1 {
+        writer.write(1, 2);
+        writer.write(0, 2);
+        writer.write(used.first().copied().unwrap_or(0) as u32, alphabet_bits);
+        return PrefixCode { lengths: vec![0; freqs.len()], codes: vec![0; freqs.len()] };
+    }
+
+    let encoded = run_length_encode(&lengths);
+    let mut code_length_freqs = [0u32; 18];
+    for &(symbol, _) in &encoded {
+        code_length_freqs[symbol as usize] += 1;
+    }
+    let code_length_lengths = huffman::code_lengths(&code_length_freqs, 5);
+    let distinct = code_length_lengths.iter().filter(|&&length| length > 0).count();
+
+    // HSKIP = 0. The decoder stops reading code length code lengths once the
+    // code is complete, so trailing zeros are only sent for a one-symbol code.
+    writer.write(0, 2);
+    let stored = if distinct > 1 {
+        1 + CODE_LENGTH_ORDER.iter().rposition(|&s| code_length_lengths[s] > 0).unwrap_or(0)
+    } else {
+        CODE_LENGTH_ORDER.len()
+    };
+    for &symbol in &CODE_LENGTH_ORDER[..stored] {
+        let (value, bits) = CODE_LENGTH_LENGTH_CODES[code_length_lengths[symbol] as usize];
+        writer.write(value, bits);
+    }
+
+    // With a single code length symbol, the decoder reads zero bits per symbol
+    let code_length_code = if distinct > 1 {
+        PrefixCode { codes: huffman::canonical_codes(&code_length_lengths), lengths: code_length_lengths }
+    } else {
+        PrefixCode { lengths: vec![0; 18], codes: vec![0; 18] }
+    };
+    for &(symbol, extra) in &encoded {
+        code_length_code.write(writer, symbol as usize);
+        match symbol {
+            REPEAT_PREVIOUS => writer.write(extra, 2),
+            REPEAT_ZERO => writer.write(extra, 3),
+            _ => {}
+        }
+    }
+
+    let codes = huffman::canonical_codes(&lengths);
+    PrefixCode { lengths, codes }
+}
+
+/// Run-length encodes code lengths into (symbol, extra bits value) pairs.
+/// Consecutive repeat codes multiply in brotli, so the runs are spelled out
+/// the same way the reference encoder does it.
+fn run_length_encode(lengths: &[u8]) -> Vec<(u8, u32)> {
+    // Trailing zeros are implied once the code is complete
+    let end = lengths.iter().rposition(|&length| length > 0).map_or(0, |i| i + 1);
+    let lengths = &lengths[..end];
+
+    let mut encoded = Vec::new();
+    let mut previous = 8;
+    let mut i = 0;
+    while i < lengths.len() {
+        let value = lengths[i];
+        let run = lengths[i..].iter().take_while(|&&length| length == value).count();
+        i += run;
+
+        if value == 0 {
+            encode_zero_run(&mut encoded, run);
+        } else {
+            encode_value_run(&mut encoded, previous, value, run);
+            previous = value;
+        }
+    }
+    encoded
+}
+
+fn encode_zero_run(encoded: &mut Vec<(u8, u32)>, mut run: usize) {
+    if run == 11 {
+        encoded.push((0, 0));
+        run -= 1;
+    }
+    if run < 3 {
+        encoded.extend(std::iter::repeat_n((0, 0), run));
+        return;
+    }
+
+    let start = encoded.len();
+    run -= 3;
+    loop {
+        encoded.push((REPEAT_ZERO, (run & 7) as u32));
+        run >>= 3;
+        if run == 0 {
+            break;
+        }
+        run -= 1;
+    }
+    encoded[start..].reverse();
+}
+
+fn encode_value_run(encoded: &mut Vec<(u8, u32)>, previous: u8, value: u8, mut run: usize) {
+    if previous != value {
+        encoded.push((value, 0));
+        run -= 1;
+    }
+    if run == 7 {
+        encoded.push((value, 0));
+        run -= 1;
+    }
+    if run < 3 {
+        encoded.extend(std::iter::repeat_n((value, 0), run));
+        return;
+    }
+
+    let start = encoded.len();
+    run -= 3;
+    loop {
+        encoded.push((REPEAT_PREVIOUS, (run & 3) as u32));
+        run >>= 2;
+        if run == 0 {
+            break;
+        }
+        run -= 1;
+    }
+    encoded[start..].reverse();
+}
+
+fn write_meta_block(writer: &mut BitWriter, data: &[u8], level: u8) {
+    let commands = to_commands(&lz77::tokenize(data, lz77::Effort::for_level(level)));
+
+    let mut literal_freqs = vec![0u32; LITERAL_ALPHABET];
+    let mut command_freqs = vec![0u32; COMMAND_ALPHABET];
+    let mut distance_freqs = vec![0u32; DISTANCE_ALPHABET];
+    for command in &commands {
+        for &byte in &command.insert {
+            literal_freqs[byte as usize] += 1;
+        }
+        let symbol = command_symbol(insert_code(command.insert.len() as u32), copy_code(command.copy));
+        command_freqs[symbol] += 1;
+        if command.copy > 0 {
+            distance_freqs[distance_symbol(command.distance).0] += 1;
+        }
+    }
+
+    // ISLAST = 0, then MLEN - 1 in the fewest nibbles that hold it
+    writer.write(0, 1);
+    let length = data.len() as u32 - 1;
+    let nibbles = if length < 1 << 16 { 4 } else if length < 1 << 20 { 5 } else { 6 };
+    writer.write(nibbles - 4, 2);
+    writer.write(length, nibbles * 4);
+    // ISUNCOMPRESSED = 0
+    writer.write(0, 1);
+
+    // One block type per category, no postfix or direct distance codes,
+    // context mode LSB6, one literal and one distance tree
+    writer.write(0, 1);
+    writer.write(0, 1);
+    writer.write(0, 1);
+    writer.write(0, 2);
+    writer.write(0, 4);
+    writer.write(0, 2);
+    writer.write(0, 1);
+    writer.write(0, 1);
+
+    let literals = write_prefix_code(writer, &literal_freqs);
+    let command_code = write_prefix_code(writer, &command_freqs);
+    let distances = write_prefix_code(writer, &distance_freqs);
+
+    for command in &commands {
+        let insert_length = command.insert.len() as u32;
+        let insert = insert_code(insert_length);
+        let copy = copy_code(command.copy);
+        command_code.write(writer, command_symbol(insert, copy));
+        writer.write(insert_length - INSERT_BASE[insert], u32::from(INSERT_EXTRA[insert]));
+        if command.copy > 0 {
+            writer.write(command.copy - COPY_BASE[copy], u32::from(COPY_EXTRA[copy]));
+        }
+
+        for &byte in &command.insert {
+            literals.write(writer, byte as usize);
+        }
+
+        // An insert-only command ends the meta-block; the decoder stops
+        // before reading a distance
+        if command.copy > 0 {
+            let (symbol, extra, extra_bits) = distance_symbol(command.distance);
+            distances.write(writer, symbol);
+            writer.write(extra, extra_bits);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compress::MAX_LEVEL;
+    use crate::compress::bits::BitReader;
+    use crate::compress::inflate::Decoder;
+    use crate::compress::tests::round_trip_inputs;
+
+    /// A prefix code as read back: a single symbol takes no bits at all
+    enum Code {
+        Single(u16),
+        Tree(Decoder),
+    }
+
+    impl Code {
+        fn decode(&self, reader: &mut BitReader) -> Option<u16> {
+            match self {
+                Code::Single(symbol) => Some(*symbol),
+                Code::Tree(decoder) => decoder.decode(reader),
+            }
+        }
+
+        fn from_lengths(lengths: &[u8]) -> Option<Code> {
+            let mut used = (0..lengths.len()).filter(|&symbol| lengths[symbol] > 0);
+            match (used.next(), used.next()) {
+                (Some(symbol), None) => Some(Code::Single(symbol as u16)),
+                _ => Decoder::new(lengths).map(Code::Tree),
+            }
+        }
+    }
+
+    /// Reads one code length code length, in the fixed code of RFC 7932 section 3.5
+    fn read_code_length_length(reader: &mut BitReader) -> Option<u8> {
+        Some(match reader.read(2)? {
+            0 => 0,
+            1 => 4,
+            2 => 3,
+            _ if reader.read(1)? == 0 => 2,
+            _ if reader.read(1)? == 0 => 1,
+            _ => 5,
+        })
+    }
+
+    /// Reads a prefix code description for an `alphabet`-symbol alphabet.
+    /// Simple codes are only supported with one symbol, the only kind written.
+    fn read_prefix_code(reader: &mut BitReader, alphabet: usize) -> Option<Code> {
+        let alphabet_bits = (alphabet - 1).ilog2() + 1;
+        let hskip = reader.read(2)? as usize;
+        if hskip == 1 {
+            if reader.read(2)? != 0 {
+                return None;
+            }
+            return Some(Code::Single(reader.read(alphabet_bits)? as u16));
+        }
+
+        let mut code_length_lengths = [0u8; 18];
+        let mut space = 32i32;
+        for &symbol in &CODE_LENGTH_ORDER[hskip..] {
+            let length = read_code_length_length(reader)?;
+            code_length_lengths[symbol] = length;
+            if length > 0 {
+                space -= 32 >> length;
+                if space <= 0 {
+                    break;
+                }
+            }
+        }
+        let code_length_code = Code::from_lengths(&code_length_lengths)?;
+
+        let mut lengths = vec![0u8; alphabet];
+        let (mut symbol, mut previous, mut space) = (0, 8, 32768i32);
+        let (mut repeat, mut repeat_length) = (0usize, 0u8);
+        while symbol < alphabet && space > 0 {
+            let code = code_length_code.decode(reader)? as u8;
+            if code < REPEAT_PREVIOUS {
+                lengths[symbol] = code;
+                symbol += 1;
+                repeat = 0;
+                if code > 0 {
+                    previous = code;
+                    space -= 32768 >> code;
+                }
+                continue;
+            }
+            let (extra_bits, length) = if code == REPEAT_PREVIOUS { (2, previous) } else { (3, 0) };
+            if repeat_length != length {
+                repeat = 0;
+                repeat_length = length;
+            }
+            let old = repeat;
+            if repeat > 0 {
+                repeat = (repeat - 2) << extra_bits;
+            }
+            repeat += reader.read(extra_bits)? as usize + 3;
+            let run = repeat - old;
+            lengths.get_mut(symbol..symbol + run)?.fill(length);
+            symbol += run;
+            if length > 0 {
+                space -= (run as i32) * (32768 >> length);
+            }
+        }
+        Code::from_lengths(&lengths)
+    }
+
+    /// The insert and copy length codes of a command symbol, and whether it
+    /// reuses the last distance instead of coding one
+    fn command_codes(symbol: usize) -> (usize, usize, bool) {
+        const CELLS: [(usize, usize); 11] =
+            [(0, 0), (0, 8), (0, 0), (0, 8), (8, 0), (8, 8), (0, 16), (16, 0), (8, 16), (16, 8), (16, 16)];
+        let (insert_base, copy_base) = CELLS[symbol >> 6];
+        (insert_base + ((symbol >> 3) & 7), copy_base + (symbol & 7), symbol < 128)
+    }
+
+    /// Decodes the subset of brotli this encoder writes: a 64 KiB window,
+    /// one block type and tree per category, no postfix or direct distance
+    /// codes and explicit distances. Anything else is `None`.
+    fn decompress(data: &[u8]) -> Option<Vec<u8>> {
+        let mut reader = BitReader::new(data);
+        let mut out = Vec::new();
+        if reader.read(1)? != 0 {
+            return None;
+        }
+        loop {
+            let last = reader.read(1)? == 1;
+            if last && reader.read(1)? == 1 {
+                return Some(out);
+            }
+            let nibbles = reader.read(2)? + 4;
+            if nibbles == 7 {
+                return None;
+            }
+            let length = reader.read(nibbles * 4)? as usize + 1;
+            if !last && reader.read(1)? != 0 {
+                return None;
+            }
+            // Block types, NPOSTFIX and NDIRECT, then the literal context mode and tree counts
+            let header = [reader.read(1)?, reader.read(1)?, reader.read(1)?, reader.read(2)?, reader.read(4)?];
+            let _context_mode = reader.read(2)?;
+            if header != [0; 5] || reader.read(1)? != 0 || reader.read(1)? != 0 {
+                return None;
+            }
+
+            let literals = read_prefix_code(&mut reader, LITERAL_ALPHABET)?;
+            let commands = read_prefix_code(&mut reader, COMMAND_ALPHABET)?;
+            let distances = read_prefix_code(&mut reader, DISTANCE_ALPHABET)?;
+            let end = out.len() + length;
+            while out.len() < end {
+                let (insert, copy, implicit_distance) = command_codes(usize::from(commands.decode(&mut reader)?));
+                let insert_length = INSERT_BASE[insert] + reader.read(u32::from(INSERT_EXTRA[insert]))?;
+                let copy_length = COPY_BASE[copy] + reader.read(u32::from(COPY_EXTRA[copy]))?;
+                for _ in 0..insert_length {
+                    out.push(literals.decode(&mut reader)? as u8);
+                }
+                if out.len() >= end {
+                    break;
+                }
+                let symbol = u32::from(distances.decode(&mut reader)?);
+                if implicit_distance || symbol < 16 {
+                    return None;
+                }
+                let bits = 1 + ((symbol - 16) >> 1);
+                let offset = ((2 + ((symbol - 16) & 1)) << bits) - 4;
+                let distance = (offset + reader.read(bits)? + 1) as usize;
+                let start = out.len().checked_sub(distance)?;
+                for i in 0..copy_length as usize {
+                    out.push(out[start + i]);
+                }
+            }
+            if out.len() != end {
+                return None;
+            }
+            if last {
+                return Some(out);
+            }
+        }
+    }
+
+    #[test]
+    fn round_trips_at_every_level() {
+        for input in round_trip_inputs() {
+            for level in 0..=MAX_LEVEL {
+                let decoded = decompress(&compress(&input, level)).expect("the brotli stream should decode");
+                assert!(decoded == input, "level {} changed a {}-byte input", level, input.len());
+            }
+        }
+    }
+
+    #[test]
+    fn inputs_over_a_meta_block_round_trip() {
+        let input: Vec<u8> = (0..META_BLOCK_SIZE + 1000).map(|i| (i % 251) as u8).collect();
+        assert!(decompress(&compress(&input, 1)) == Some(input));
+    }
+}
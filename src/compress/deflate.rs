@@ -0,0 +1,278 @@
+use super::bits::BitWriter;
+use super::huffman;
+use super::lz77::{self, Token};
+
+/// Tokens per block; each block gets its own Huffman codes
+const BLOCK_TOKENS: usize = 16 * 1024;
+/// Largest payload of a single stored block
+const MAX_STORED: usize = 65535;
+pub(super) const END_OF_BLOCK: usize = 256;
+
+pub(super) const LENGTH_BASE: [u16; 29] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115, 131, 163, 195, 227, 258,
+];
+pub(super) const LENGTH_EXTRA: [u8; 29] =
+    [0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0];
+pub(super) const DISTANCE_BASE: [u16; 30] = [
+    1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537, 2049, 3073, 4097,
+    6145, 8193, 12289, 16385, 24577,
+];
+pub(super) const DISTANCE_EXTRA: [u8; 30] =
+    [0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13, 13];
+/// Order in which code length code lengths are transmitted
+pub(super) const CODE_LENGTH_ORDER: [usize; 19] = [16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15];
+
+/// Compresses `data` into a raw DEFLATE stream (RFC 1951) at `level` (0-9)
+pub fn compress(data: &[u8], level: u8) -> Vec<u8> {
+    let tokens = lz77::tokenize(data, lz77::Effort::for_level(level));
+    let mut writer = BitWriter::new();
+
+    if tokens.is_empty() {
+        write_block(&mut writer, &[], &[], true);
+        return writer.finish();
+    }
+
+    let mut offset = 0;
+    let block_count = tokens.len().div_ceil(BLOCK_TOKENS);
+    for (index, block) in tokens.chunks(BLOCK_TOKENS).enumerate() {
+        let covered: usize = block.iter().map(token_len).sum();
+        write_block(&mut writer, block, &data[offset..offset + covered], index + 1 == block_count);
+        offset += covered;
+    }
+
+    writer.finish()
+}
+
+fn token_len(token: &Token) -> usize {
+    match *token {
+        Token::Literal(_) => 1,
+        Token::Match { length, .. } => length as usize,
+    }
+}
+
+fn length_code(length: u16) -> usize {
+    LENGTH_BASE.iter().rposition(|&base| base <= length).unwrap_or(0)
+}
+
+fn distance_code(distance: u16) -> usize {
+    DISTANCE_BASE.iter().rposition(|&base| base <= distance).unwrap_or(0)
+}
+
+/// Huffman codes for one alphabet
+struct Code {
+    lengths: Vec<u8>,
+    codes: Vec<u16>,
+}
+
+impl Code {
+    fn from_lengths(lengths: Vec<u8>) -> Self {
+        let codes = huffman::canonical_codes(&lengths);
+        Code { lengths, codes }
+    }
+
+    fn write(&self, writer: &mut BitWriter, symbol: usize) {
+        writer.write(u32::from(self.codes[symbol]), u32::from(self.lengths[symbol]));
+    }
+
+    fn cost(&self, freqs: &[u32]) -> u64 {
+        freqs.iter().zip(&self.lengths).map(|(&freq, &length)| u64::from(freq) * u64::from(length)).sum()
+    }
+}
+
+/// Builds length-limited code lengths, making sure at least two symbols get
+/// a code so the result is complete (inflate implementations reject a
+/// single-symbol code in some places)
+fn complete_lengths(freqs: &[u32], max_bits: u8) -> Vec<u8> {
+    let mut lengths = huffman::code_lengths(freqs, max_bits);
+    if lengths.iter().filter(|&&length| length > 0).count() < 2 {
+        let used = lengths.iter().position(|&length| length > 0).unwrap_or(0);
+        lengths[used] = 1;
+        lengths[if used == 0 { 1 } else { 0 }] = 1;
+    }
+    lengths
+}
+
+fn fixed_codes() -> (Code, Code) {
+    let mut literal_lengths = vec![8u8; 288];
+    literal_lengths[144..256].fill(9);
+    literal_lengths[256..280].fill(7);
+    (Code::from_lengths(literal_lengths), Code::from_lengths(vec![5u8; 30]))
+}
+
+/// Run-length encodes the code lengths of a dynamic block header into
+/// (symbol, extra bits value) pairs, using symbols 16-18 for repeats
+fn run_length_encode(lengths: &[u8]) -> Vec<(u8, u8)> {
+    let mut encoded = Vec::new();
+    let mut i = 0;
+    while i < lengths.len() {
+        let value = lengths[i];
+        let mut run = lengths[i..].iter().take_while(|&&length| length == value).count();
+        i += run;
+
+        if value == 0 {
+            while run >= 11 {
+                let n = run.min(138);
+                encoded.push((18, (n - 11) as u8));
+                run -= n;
+            }
+            if run >= 3 {
+                encoded.push((17, (run - 3) as u8));
+                run = 0;
+            }
+        } else {
+            encoded.push((value, 0));
+            run -= 1;
+            while run >= 3 {
+                let n = run.min(6);
+                encoded.push((16, (n - 3) as u8));
+                run -= n;
+            }
+        }
+        encoded.extend(std::iter::repeat_n((value, 0), run));
+    }
+    encoded
+}
+
+fn code_length_extra_bits(symbol: u8) -> u32 {
+    match symbol {
+        16 => 2,
+        17 => 3,
+        18 => 7,
+        _ => 0,
+    }
+}
+
+/// Everything needed to write a dynamic block header
+struct DynamicHeader {
+    literal_count: usize,
+    distance_count: usize,
+    code_length_count: usize,
+    encoded_lengths: Vec<(u8, u8)>,
+    code_length_code: Code,
+}
+
+impl DynamicHeader {
+    fn new(literals: &Code, distances: &Code) -> Self {
+        let literal_count = 257 + literals.lengths[257..].iter().rposition(|&l| l > 0).map_or(0, |i| i + 1);
+        let distance_count = 1 + distances.lengths[1..].iter().rposition(|&l| l > 0).map_or(0, |i| i + 1);
+
+        let mut all_lengths = literals.lengths[..literal_count].to_vec();
+        all_lengths.extend_from_slice(&distances.lengths[..distance_count]);
+        let encoded_lengths = run_length_encode(&all_lengths);
+
+        let mut freqs = [0u32; 19];
+        for &(symbol, _) in &encoded_lengths {
+            freqs[symbol as usize] += 1;
+        }
+        let code_length_code = Code::from_lengths(complete_lengths(&freqs, 7));
+        let code_length_count =
+            4.max(1 + CODE_LENGTH_ORDER.iter().rposition(|&s| code_length_code.lengths[s] > 0).unwrap_or(0));
+
+        DynamicHeader { literal_count, distance_count, code_length_count, encoded_lengths, code_length_code }
+    }
+
+    fn cost(&self) -> u64 {
+        let lengths: u64 = self
+            .encoded_lengths
+            .iter()
+            .map(|&(symbol, _)| {
+                u64::from(self.code_length_code.lengths[symbol as usize]) + u64::from(code_length_extra_bits(symbol))
+            })
+            .sum();
+        14 + 3 * self.code_length_count as u64 + lengths
+    }
+
+    fn write(&self, writer: &mut BitWriter) {
+        writer.write((self.literal_count - 257) as u32, 5);
+        writer.write((self.distance_count - 1) as u32, 5);
+        writer.write((self.code_length_count - 4) as u32, 4);
+        for &symbol in &CODE_LENGTH_ORDER[..self.code_length_count] {
+            writer.write(u32::from(self.code_length_code.lengths[symbol]), 3);
+        }
+        for &(symbol, extra) in &self.encoded_lengths {
+            self.code_length_code.write(writer, symbol as usize);
+            writer.write(u32::from(extra), code_length_extra_bits(symbol));
+        }
+    }
+}
+
+/// Writes one block using whichever of stored, fixed or dynamic Huffman
+/// encoding comes out smallest
+fn write_block(writer: &mut BitWriter, tokens: &[Token], raw: &[u8], last: bool) {
+    let mut literal_freqs = [0u32; 286];
+    let mut distance_freqs = [0u32; 30];
+    for token in tokens {
+        match *token {
+            Token::Literal(byte) => literal_freqs[byte as usize] += 1,
+            Token::Match { length, distance } => {
+                literal_freqs[257 + length_code(length)] += 1;
+                distance_freqs[distance_code(distance)] += 1;
+            }
+        }
+    }
+    literal_freqs[END_OF_BLOCK] += 1;
+
+    // Extra bits cost the same whichever Huffman codes are used
+    let extra_bits: u64 = literal_freqs[257..]
+        .iter()
+        .zip(LENGTH_EXTRA)
+        .chain(distance_freqs.iter().zip(DISTANCE_EXTRA))
+        .map(|(&freq, extra)| u64::from(freq) * u64::from(extra))
+        .sum();
+
+    let dynamic_literals = Code::from_lengths(complete_lengths(&literal_freqs, 15));
+    let dynamic_distances = Code::from_lengths(complete_lengths(&distance_freqs, 15));
+    let header = DynamicHeader::new(&dynamic_literals, &dynamic_distances);
+    let dynamic_cost =
+        header.cost() + dynamic_literals.cost(&literal_freqs) + dynamic_distances.cost(&distance_freqs) + extra_bits;
+
+    let (fixed_literals, fixed_distances) = fixed_codes();
+    let fixed_cost = fixed_literals.cost(&literal_freqs) + fixed_distances.cost(&distance_freqs) + extra_bits;
+
+    let stored_cost = (raw.len().div_ceil(MAX_STORED).max(1) * 40 + raw.len() * 8) as u64;
+
+    if stored_cost < dynamic_cost.min(fixed_cost) {
+        write_stored(writer, raw, last);
+    } else if dynamic_cost < fixed_cost {
+        writer.write(u32::from(last), 1);
+        writer.write(2, 2);
+        header.write(writer);
+        write_tokens(writer, tokens, &dynamic_literals, &dynamic_distances);
+    } else {
+        writer.write(u32::from(last), 1);
+        writer.write(1, 2);
+        write_tokens(writer, tokens, &fixed_literals, &fixed_distances);
+    }
+}
+
+fn write_stored(writer: &mut BitWriter, raw: &[u8], last: bool) {
+    let chunks: Vec<&[u8]> = if raw.is_empty() { vec![raw] } else { raw.chunks(MAX_STORED).collect() };
+    let chunk_count = chunks.len();
+    for (index, chunk) in chunks.into_iter().enumerate() {
+        writer.write(u32::from(last && index + 1 == chunk_count), 1);
+        writer.write(0, 2);
+        writer.align();
+        let length = chunk.len() as u16;
+        writer.write_bytes(&length.to_le_bytes());
+        writer.write_bytes(&(!length).to_le_bytes());
+        writer.write_bytes(chunk);
+    }
+}
+
+fn write_tokens(writer: &mut BitWriter, tokens: &[Token], literals: &Code, distances: &Code) {
+    for token in tokens {
+        match *token {
+            Token::Literal(byte) => literals.write(writer, byte as usize),
+            Token::Match { length, distance } => {
+                let code = length_code(length);
+                literals.write(writer, 257 + code);
+                writer.write(u32::from(length - LENGTH_BASE[code]), u32::from(LENGTH_EXTRA[code]));
+
+                let code = distance_code(distance);
+                distances.write(writer, code);
+                writer.write(u32::from(distance - DISTANCE_BASE[code]), u32::from(DISTANCE_EXTRA[code]));
+            }
+        }
+    }
+    literals.write(writer, END_OF_BLOCK);
+}
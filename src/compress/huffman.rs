@@ -0,0 +1,92 @@
+/// Computes Huffman code lengths for the given symbol frequencies, with no
+/// code longer than `max_bits`, using the package-merge algorithm.
+///
+/// Unused symbols get length 0. A lone used symbol gets length 1, which
+/// leaves the code incomplete; callers decide how their format handles that.
+pub fn code_lengths(freqs: &[u32], max_bits: u8) -> Vec<u8> {
+    let mut lengths = vec![0u8; freqs.len()];
+
+    let mut leaves: Vec<(u64, usize)> = freqs
+        .iter()
+        .enumerate()
+        .filter(|&(_, &freq)| freq > 0)
+        .map(|(symbol, &freq)| (u64::from(freq), symbol))
+        .collect();
+    if leaves.len() <= 1 {
+        if let Some(&(_, symbol)) = leaves.first() {
+            lengths[symbol] = 1;
+        }
+        return lengths;
+    }
+    leaves.sort_unstable();
+    debug_assert!(leaves.len() <= 1 << max_bits);
+
+    // Each item is a total weight plus the leaves it was built from
+    let leaf_items: Vec<(u64, Vec<usize>)> = leaves.iter().map(|&(weight, symbol)| (weight, vec![symbol])).collect();
+    let mut items = leaf_items.clone();
+    for _ in 1..max_bits {
+        let packages = items.chunks_exact(2).map(|pair| {
+            let mut symbols = pair[0].1.clone();
+            symbols.extend_from_slice(&pair[1].1);
+            (pair[0].0 + pair[1].0, symbols)
+        });
+        items = merge_by_weight(leaf_items.iter().cloned(), packages);
+    }
+
+    // A leaf's code length is the number of selected items it appears in
+    for (_, symbols) in items.iter().take(2 * leaves.len() - 2) {
+        for &symbol in symbols {
+            lengths[symbol] += 1;
+        }
+    }
+    lengths
+}
+
+/// Merges two weight-sorted sequences into one sorted list
+fn merge_by_weight<A, B>(a: A, b: B) -> Vec<(u64, Vec<usize>)>
+where
+    A: Iterator<Item = (u64, Vec<usize>)>,
+    B: Iterator<Item = (u64, Vec<usize>)>,
+{
+    let mut a = a.peekable();
+    let mut b = b.peekable();
+    let mut merged = Vec::new();
+    loop {
+        let take_a = match (a.peek(), b.peek()) {
+            (Some(x), Some(y)) => x.0 <= y.0,
+            (Some(_), None) => true,
+            (None, Some(_)) => false,
+            (None, None) => return merged,
+        };
+        merged.extend(if take_a { a.next() } else { b.next() });
+    }
+}
+
+/// Assigns canonical codes to the given lengths. The codes come back
+/// bit-reversed, ready to be written least-significant bit first.
+pub fn canonical_codes(lengths: &[u8]) -> Vec<u16> {
+    let max_bits = lengths.iter().copied().max().unwrap_or(0) as usize;
+    let mut count_per_length = vec![0u16; max_bits + 1];
+    for &length in lengths.iter().filter(|&&length| length > 0) {
+        count_per_length[length as usize] += 1;
+    }
+
+    let mut next_code = vec![0u16; max_bits + 1];
+    let mut code = 0u16;
+    for bits in 1..=max_bits {
+        code = (code + count_per_length[bits - 1]) << 1;
+        next_code[bits] = code;
+    }
+
+    lengths
+        .iter()
+        .map(|&length| {
+            if length == 0 {
+                return 0;
+            }
+            let code = next_code[length as usize];
+            next_code[length as usize] += 1;
+            code.reverse_bits() >> (16 - length)
+        })
+        .collect()
+}
@@ -0,0 +1,179 @@
+use super::bits::BitReader;
+use super::deflate::{
+    CODE_LENGTH_ORDER, DISTANCE_BASE, DISTANCE_EXTRA, END_OF_BLOCK, LENGTH_BASE, LENGTH_EXTRA,
+};
+
+/// Longest code DEFLATE allows
+const MAX_BITS: usize = 15;
+
+/// Decodes canonical Huffman codes one bit at a time, from the number of
+/// codes of each length and the symbols sorted by code
+pub(super) struct Decoder {
+    counts: [u16; MAX_BITS + 1],
+    symbols: Vec<u16>,
+}
+
+impl Decoder {
+    /// `None` for lengths that describe more codes than fit. Incomplete
+    /// codes are allowed, since a block using a single distance has one.
+    pub(super) fn new(lengths: &[u8]) -> Option<Decoder> {
+        let mut counts = [0u16; MAX_BITS + 1];
+        for &length in lengths {
+            counts[usize::from(length)] += 1;
+        }
+        counts[0] = 0;
+
+        let mut left: i32 = 1;
+        for &count in &counts[1..] {
+            left = (left << 1) - i32::from(count);
+            if left < 0 {
+                return None;
+            }
+        }
+
+        let mut offsets = [0u16; MAX_BITS + 2];
+        for length in 1..=MAX_BITS {
+            offsets[length + 1] = offsets[length] + counts[length];
+        }
+        let mut symbols = vec![0; usize::from(offsets[MAX_BITS + 1])];
+        for (symbol, &length) in lengths.iter().enumerate() {
+            if length != 0 {
+                let slot = &mut offsets[usize::from(length)];
+                symbols[usize::from(*slot)] = symbol as u16;
+                *slot += 1;
+            }
+        }
+        Some(Decoder { counts, symbols })
+    }
+
+    pub(super) fn decode(&self, reader: &mut BitReader) -> Option<u16> {
+        // Codes of each length are consecutive, starting at `first`
+        let (mut code, mut first, mut index) = (0i32, 0i32, 0i32);
+        for &count in &self.counts[1..] {
+            code |= reader.read(1)? as i32;
+            let count = i32::from(count);
+            if code - first < count {
+                return self.symbols.get((index + code - first) as usize).copied();
+            }
+            index += count;
+            first = (first + count) << 1;
+            code <<= 1;
+        }
+        None
+    }
+}
+
+/// Decompresses a raw DEFLATE stream (RFC 1951). Returns `None` if it's
+/// malformed, truncated, or would come to more than `limit` bytes.
+pub fn decompress(data: &[u8], limit: usize) -> Option<Vec<u8>> {
+    let mut reader = BitReader::new(data);
+    let mut out = Vec::new();
+    loop {
+        let last = reader.read(1)? == 1;
+        match reader.read(2)? {
+            0 => {
+                reader.align();
+                let header = reader.read_bytes(4)?;
+                let len = u16::from_le_bytes([header[0], header[1]]);
+                let complement = u16::from_le_bytes([header[2], header[3]]);
+                if len != !complement {
+                    return None;
+                }
+                out.extend_from_slice(reader.read_bytes(usize::from(len))?);
+            }
+            1 => {
+                let (literals, distances) = fixed_decoders();
+                inflate_block(&mut reader, &mut out, &literals, &distances, limit)?;
+            }
+            2 => {
+                let (literals, distances) = dynamic_decoders(&mut reader)?;
+                inflate_block(&mut reader, &mut out, &literals, &distances, limit)?;
+            }
+            _ => return None,
+        }
+        if out.len() > limit {
+            return None;
+        }
+        if last {
+            return Some(out);
+        }
+    }
+}
+
+fn fixed_decoders() -> (Decoder, Decoder) {
+    let mut lengths = [0u8; 288];
+    lengths[..144].fill(8);
+    lengths[144..256].fill(9);
+    lengths[256..280].fill(7);
+    lengths[280..].fill(8);
+    let literals = Decoder::new(&lengths).expect("the fixed literal code is complete");
+    let distances = Decoder::new(&[5; 30]).expect("the fixed distance code is complete");
+    (literals, distances)
+}
+
+fn dynamic_decoders(reader: &mut BitReader) -> Option<(Decoder, Decoder)> {
+    let literal_count = reader.read(5)? as usize + 257;
+    let distance_count = reader.read(5)? as usize + 1;
+    let code_length_count = reader.read(4)? as usize + 4;
+
+    let mut code_length_lengths = [0u8; 19];
+    for &symbol in &CODE_LENGTH_ORDER[..code_length_count] {
+        code_length_lengths[symbol] = reader.read(3)? as u8;
+    }
+    let code_lengths = Decoder::new(&code_length_lengths)?;
+
+    let mut lengths = Vec::with_capacity(literal_count + distance_count);
+    while lengths.len() < literal_count + distance_count {
+        let (value, repeat) = match code_lengths.decode(reader)? {
+            symbol @ 0..=15 => (symbol as u8, 1),
+            16 => (*lengths.last()?, 3 + reader.read(2)?),
+            17 => (0, 3 + reader.read(3)?),
+            18 => (0, 11 + reader.read(7)?),
+            _ => return None,
+        };
+        lengths.extend(std::iter::repeat_n(value, repeat as usize));
+    }
+    // A repeat may not run on from the literal lengths past the distance ones
+    if lengths.len() != literal_count + distance_count || lengths[END_OF_BLOCK] == 0 {
+        return None;
+    }
+    let (literal_lengths, distance_lengths) = lengths.split_at(literal_count);
+    Some((Decoder::new(literal_lengths)?, Decoder::new(distance_lengths)?))
+}
+
+/// Decodes one compressed block onto `out`, whose earlier bytes back
+/// references may copy from
+fn inflate_block(
+    reader: &mut BitReader,
+    out: &mut Vec<u8>,
+    literals: &Decoder,
+    distances: &Decoder,
+    limit: usize,
+) -> Option<()> {
+    loop {
+        let symbol = usize::from(literals.decode(reader)?);
+        if symbol < END_OF_BLOCK {
+            out.push(symbol as u8);
+        } else if symbol == END_OF_BLOCK {
+            return Some(());
+        } else {
+            let index = symbol - END_OF_BLOCK - 1;
+            let length = usize::from(*LENGTH_BASE.get(index)?)
+                + reader.read(u32::from(LENGTH_EXTRA[index]))? as usize;
+            let index = usize::from(distances.decode(reader)?);
+            let distance = usize::from(*DISTANCE_BASE.get(index)?)
+                + reader.read(u32::from(DISTANCE_EXTRA[index]))? as usize;
+            if distance > out.len() {
+                return None;
+            }
+            // The copy may overlap what it produces, so it goes byte by byte
+            let start = out.len() - distance;
+            for offset in 0..length {
+                out.push(out[start + offset]);
+            }
+        }
+        if out.len() > limit {
+            return None;
+        }
+    }
+}
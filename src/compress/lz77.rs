@@ -0,0 +1,149 @@
+/// How far back a match may reach
+pub const WINDOW_SIZE: usize = 32 * 1024;
+pub const MIN_MATCH: usize = 3;
+pub const MAX_MATCH: usize = 258;
+
+const HASH_BITS: u32 = 15;
+const NO_POSITION: u32 = u32::MAX;
+
+/// How hard the matcher searches, set by the compression level
+#[derive(Debug, Clone, Copy)]
+pub struct Effort {
+    /// Candidates examined per position before settling for the best so far
+    max_chain: usize,
+    /// A match this long is good enough to stop searching
+    nice_match: usize,
+    /// Whether to check the next position for a longer match before taking one
+    lazy: bool,
+}
+
+impl Effort {
+    /// The effort for a level from 0 (no matching at all) to 9 (slowest),
+    /// after zlib's table. Levels above 9 search as hard as 9.
+    pub fn for_level(level: u8) -> Effort {
+        let (max_chain, nice_match, lazy) = match level {
+            0 => (0, 0, false),
+            1 => (4, 8, false),
+            2 => (8, 16, false),
+            3 => (32, 32, false),
+            4 => (16, 16, true),
+            5 => (32, 32, true),
+            6 => (128, 128, true),
+            7 => (256, 128, true),
+            8 => (1024, MAX_MATCH, true),
+            _ => (4096, MAX_MATCH, true),
+        };
+        Effort { max_chain, nice_match, lazy }
+    }
+}
+
+/// One step of the LZ77 parse: either a byte copied as-is or a reference
+/// to earlier output
+#[derive(Debug, Clone, Copy)]
+pub enum Token {
+    Literal(u8),
+    Match { length: u16, distance: u16 },
+}
+
+/// Parses `data` into literals and back-references using hash chains,
+/// with one step of lazy matching unless `effort` rules it out
+pub fn tokenize(data: &[u8], effort: Effort) -> Vec<Token> {
+    let mut matcher = Matcher {
+        data,
+        effort,
+        head: vec![NO_POSITION; 1 << HASH_BITS],
+        prev: vec![NO_POSITION; WINDOW_SIZE],
+    };
+    let mut tokens = Vec::with_capacity(data.len() / 2);
+
+    let mut pos = 0;
+    while pos < data.len() {
+        let (length, distance) = matcher.longest_match(pos);
+        matcher.insert(pos);
+
+        // If the next position starts a longer match, emit a literal instead
+        // and take that one on the next round
+        let lazy = effort.lazy && length < effort.nice_match;
+        if length < MIN_MATCH || (lazy && matcher.longest_match(pos + 1).0 > length) {
+            tokens.push(Token::Literal(data[pos]));
+            pos += 1;
+            continue;
+        }
+
+        tokens.push(Token::Match { length: length as u16, distance: distance as u16 });
+        for covered in pos + 1..pos + length {
+            matcher.insert(covered);
+        }
+        pos += length;
+    }
+
+    tokens
+}
+
+struct Matcher<'a> {
+    data: &'a [u8],
+    effort: Effort,
+    /// Most recent position for each hash of three bytes
+    head: Vec<u32>,
+    /// Previous position with the same hash, indexed by position within the window
+    prev: Vec<u32>,
+}
+
+impl Matcher<'_> {
+    fn hash(&self, pos: usize) -> usize {
+        let bytes = &self.data[pos..pos + MIN_MATCH];
+        let key = u32::from(bytes[0]) << 16 | u32::from(bytes[1]) << 8 | u32::from(bytes[2]);
+        (key.wrapping_mul(0x9E37_79B1) >> (32 - HASH_BITS)) as usize
+    }
+
+    fn insert(&mut self, pos: usize) {
+        if pos + MIN_MATCH > self.data.len() {
+            return;
+        }
+        let hash = self.hash(pos);
+        self.prev[pos % WINDOW_SIZE] = self.head[hash];
+        self.head[hash] = pos as u32;
+    }
+
+    /// Finds the longest earlier match for the bytes at `pos`, as (length, distance)
+    fn longest_match(&self, pos: usize) -> (usize, usize) {
+        if pos + MIN_MATCH > self.data.len() {
+            return (0, 0);
+        }
+        let limit = MAX_MATCH.min(self.data.len() - pos);
+        let current = &self.data[pos..pos + limit];
+
+        let mut best = (0, 0);
+        let mut candidate = self.head[self.hash(pos)];
+        let mut chain = self.effort.max_chain;
+        while candidate != NO_POSITION && chain > 0 {
+            let start = candidate as usize;
+            // Positions are only inserted after being searched, so `start < pos`
+            let distance = pos - start;
+            if distance > WINDOW_SIZE {
+                break;
+            }
+
+            let earlier = &self.data[start..start + limit];
+            if earlier[best.0.min(limit - 1)] == current[best.0.min(limit - 1)] {
+                let length = earlier.iter().zip(current).take_while(|(a, b)| a == b).count();
+                if length > best.0 {
+                    best = (length, distance);
+                    if length >= self.effort.nice_match.min(limit) {
+                        break;
+                    }
+                }
+            }
+
+            // The slot may have been reused by a newer position; then the chain ends here
+            let next = self.prev[start % WINDOW_SIZE];
+            if next == NO_POSITION || next as usize >= start {
+                break;
+            }
+            candidate = next;
+            chain -= 1;
+        }
+
+        best
+    }
+}
@@ -0,0 +1,524 @@
+//! Response body compression, negotiated from `Accept-Encoding`
+
+mod bits;
+#[cfg(feature = "brotli")]
+mod brotli;
+mod deflate;
+mod gzip;
+mod huffman;
+mod inflate;
+mod lz77;
+
+use crate::http::{HttpRequest, HttpResponse, StatusCode};
+use crate::negotiate::{self, MediaRange};
+
+/// Bodies smaller than this gain too little to be worth compressing
+const MIN_COMPRESS_SIZE: usize = 1024;
+/// Compression level used unless `--compress-level` says otherwise
+pub const DEFAULT_LEVEL: u8 = 6;
+/// Highest compression level; higher ones search no harder
+pub const MAX_LEVEL: u8 = 9;
+
+/// A content coding the server can produce
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Encoding {
+    #[cfg(feature = "brotli")]
+    Brotli,
+    Gzip,
+}
+
+impl Encoding {
+    /// Encodings in order of preference when a client accepts several
+    const PREFERRED: &[Encoding] = &[
+        #[cfg(feature = "brotli")]
+        Encoding::Brotli,
+        Encoding::Gzip,
+    ];
+
+    /// The token used in `Accept-Encoding` and `Content-Encoding`
+    pub fn token(self) -> &'static str {
+        match self {
+            #[cfg(feature = "brotli")]
+            Encoding::Brotli => "br",
+            Encoding::Gzip => "gzip",
+        }
+    }
+
+    /// Suffix of a precompressed sibling holding a file in this encoding,
+    /// e.g. `app.js.gz` next to `app.js`
+    pub fn file_suffix(self) -> &'static str {
+        match self {
+            #[cfg(feature = "brotli")]
+            Encoding::Brotli => ".br",
+            Encoding::Gzip => ".gz",
+        }
+    }
+
+    /// Every encoding the server knows, in order of preference
+    pub fn all() -> &'static [Encoding] {
+        Encoding::PREFERRED
+    }
+
+    /// Encodes `data` at `level`, from 0 (fastest) to `MAX_LEVEL` (smallest).
+    /// Brotli shares the scale, so its usual 10 and 11 are the same as 9.
+    pub fn encode(self, data: &[u8], level: u8) -> Vec<u8> {
+        let level = level.min(MAX_LEVEL);
+        match self {
+            #[cfg(feature = "brotli")]
+            Encoding::Brotli => brotli::compress(data, level),
+            Encoding::Gzip => gzip::compress(data, level),
+        }
+    }
+}
+
+/// Decompresses a raw DEFLATE stream, the way zip archives store files.
+/// Returns `None` if it's malformed or would come to more than `limit` bytes.
+pub fn inflate(data: &[u8], limit: usize) -> Option<Vec<u8>> {
+    inflate::decompress(data, limit)
+}
+
+/// The CRC-32 that gzip and zip check their contents with
+pub fn crc32(data: &[u8]) -> u32 {
+    gzip::crc32(data)
+}
+
+/// Media types compressed unless `--compress-types` adds more: text and the
+/// common text-based application formats
+pub const DEFAULT_COMPRESSIBLE_TYPES: &[&str] =
+    &["text/*", "application/javascript", "application/json", "application/xml"];
+
+/// Media types whose payload is already compressed; encoding them again only burns CPU
+const INCOMPRESSIBLE_TYPES: &[&str] = &[
+    "image/*",
+    "video/*",
+    "audio/*",
+    "font/woff",
+    "font/woff2",
+    "application/zip",
+    "application/gzip",
+    "application/x-gzip",
+    "application/x-bzip2",
+    "application/x-xz",
+    "application/x-7z-compressed",
+    "application/x-rar-compressed",
+    "application/zstd",
+    "application/pdf",
+];
+
+/// Leading bytes of common compressed formats, for bodies whose type is mislabeled
+/// (e.g. an extensionless PNG that got sniffed as text)
+const COMPRESSED_SIGNATURES: &[&[u8]] = &[
+    b"\x89PNG",
+    b"\xff\xd8\xff",
+    b"GIF8",
+    b"PK\x03\x04",
+    b"\x1f\x8b",
+    b"BZh",
+    b"\xfd7zXZ",
+    b"%PDF",
+];
+
+fn essence(content_type: &str) -> String {
+    content_type.split(';').next().unwrap_or("").trim().to_ascii_lowercase()
+}
+
+/// Returns true for content types worth compressing: those matching one of
+/// the `compressible` patterns, which are lowercase and may hold one `*`
+/// (`text/*`, `application/*+json`). A compressed-already type is only
+/// compressed when a pattern names it without a wildcard, such as
+/// `image/svg+xml`, so `image/*` or `application/*` can't drag PNGs and zips in.
+pub fn is_compressible(content_type: &str, compressible: &[String]) -> bool {
+    let mime = essence(content_type);
+    compressible.contains(&mime)
+        || (!is_incompressible(&mime) && compressible.iter().any(|pattern| type_matches(pattern, &mime)))
+}
+
+/// Matches a lowercase media type against a pattern with at most one `*`,
+/// which stands for any run of characters
+fn type_matches(pattern: &str, mime: &str) -> bool {
+    match pattern.split_once('*') {
+        Some((prefix, suffix)) => {
+            mime.len() >= prefix.len() + suffix.len() && mime.starts_with(prefix) && mime.ends_with(suffix)
+        }
+        None => pattern == mime,
+    }
+}
+
+/// Returns true for content types that are compressed already
+fn is_incompressible(content_type: &str) -> bool {
+    let mime = essence(content_type);
+    INCOMPRESSIBLE_TYPES.iter().any(|pattern| match pattern.strip_suffix("/*") {
+        Some(kind) => mime.split('/').next() == Some(kind),
+        None => mime == *pattern,
+    })
+}
+
+fn looks_compressed(body: &[u8]) -> bool {
+    COMPRESSED_SIGNATURES.iter().any(|signature| body.starts_with(signature))
+}
+
+/// The q-value `Accept-Encoding` gives `coding`: its own entry, else the `*`
+/// entry. `None` when neither is listed.
+fn coding_quality(ranges: &[MediaRange], coding: &str) -> Option<f32> {
+    let find = |name: &str| ranges.iter().find(|range| range.value.eq_ignore_ascii_case(name));
+    find(coding).or_else(|| find("*")).map(|range| range.quality)
+}
+
+/// Picks the encoding to use under an `Accept-Encoding` header: the supported
+/// coding with the highest q-value, ties going to our own preference order.
+/// Returns `None` when nothing is acceptable or the client rates identity higher.
+pub fn negotiate(accept_encoding: &str) -> Option<Encoding> {
+    negotiate_among(accept_encoding, Encoding::PREFERRED)
+}
+
+/// Like `negotiate`, choosing only from `available` (given in preference order)
+pub fn negotiate_among(accept_encoding: &str, available: &[Encoding]) -> Option<Encoding> {
+    let ranges = negotiate::parse_header(accept_encoding);
+    let mut best: Option<(Encoding, f32)> = None;
+    for &encoding in available {
+        let quality = coding_quality(&ranges, encoding.token()).unwrap_or(0.0);
+        if quality > 0.0 && best.is_none_or(|(_, best_quality)| quality > best_quality) {
+            best = Some((encoding, quality));
+        }
+    }
+
+    // Unlisted identity is always acceptable, but only wins when rated higher
+    let identity = coding_quality(&ranges, "identity").unwrap_or(0.0);
+    best.filter(|&(_, quality)| quality >= identity).map(|(encoding, _)| encoding)
+}
+
+/// Whether an unencoded body is acceptable: it is unless ruled out by
+/// `identity;q=0`, or by `*;q=0` without an `identity` entry
+pub fn identity_acceptable(accept_encoding: &str) -> bool {
+    coding_quality(&negotiate::parse_header(accept_encoding), "identity").is_none_or(|quality| quality > 0.0)
+}
+
+/// Whether `encode_response` might encode a `len`-byte body of `content_type`
+/// for `request`, compressing the `compressible` types. When it can't, a HEAD response can report the unencoded
+/// length without producing the body; when it might, only encoding the body
+/// gives the length the GET response would have.
+pub fn may_encode(request: &HttpRequest, content_type: &str, len: u64, compressible: &[String]) -> bool {
+    let Some(accept_encoding) = request.header("Accept-Encoding") else {
+        return false;
+    };
+    if !identity_acceptable(accept_encoding) {
+        return true;
+    }
+    negotiate(accept_encoding).is_some()
+        && len >= MIN_COMPRESS_SIZE as u64
+        && is_compressible(content_type, compressible)
+}
+
+/// Compresses the response body when it has one of the `compressible` types,
+/// is large enough to be worth it, and the client accepts an encoding we support.
+/// A client that refuses identity gets the body encoded regardless, or a 406
+/// when that isn't possible. `level` trades CPU for size, as in `Encoding::encode`.
+pub fn encode_response(
+    request: &HttpRequest,
+    response: HttpResponse,
+    level: u8,
+    compressible: &[String],
+) -> HttpResponse {
+    let accept_encoding = request.header("Accept-Encoding");
+    let identity_allowed = accept_encoding.is_none_or(identity_acceptable);
+    // A 304 or 204 has no body to encode
+    if response.header("Content-Encoding").is_some() || !response.status.allows_body() {
+        return response;
+    }
+
+    // Already-compressed formats are skipped before anything else
+    let compressible =
+        response.header("Content-Type").is_some_and(|content_type| is_compressible(content_type, compressible));
+    // Caches must key on Accept-Encoding even when this client gets identity
+    let response = if compressible { with_vary(response) } else { response };
+    // Ranges win over compression: Content-Range counts identity bytes, so a
+    // partial body is always sent as it is. Streamed bodies aren't in memory to compress.
+    if response.status == StatusCode::PARTIAL_CONTENT || response.stream.is_some() {
+        return unencoded(request, response, identity_allowed);
+    }
+    if !compressible && identity_allowed {
+        return response;
+    }
+    let response = with_vary(response);
+    let Some(encoding) = accept_encoding.and_then(negotiate) else {
+        return unencoded(request, response, identity_allowed);
+    };
+    // A HEAD response has the length but not the bytes to encode
+    if response.content_length() != response.body.len() as u64 {
+        return response;
+    }
+    // Without identity as an option, even bodies not worth compressing get encoded
+    if identity_allowed && (response.body.len() < MIN_COMPRESS_SIZE || looks_compressed(&response.body)) {
+        return response;
+    }
+
+    let encoded = encoding.encode(&response.body, level);
+    if identity_allowed && encoded.len() >= response.body.len() {
+        return response;
+    }
+    let mut response = response;
+    response.body = encoded;
+    weaken_etag(&mut response);
+    without_ranges(response).with_header("Content-Encoding", encoding.token())
+}
+
+/// Replaces `Accept-Ranges` with `none`. Offsets into an encoded body mean
+/// nothing to the server, which only serves ranges of the identity bytes, so
+/// a client resuming an encoded download mustn't be invited to try.
+pub fn without_ranges(mut response: HttpResponse) -> HttpResponse {
+    response.headers.retain(|(name, _)| !name.eq_ignore_ascii_case("Accept-Ranges"));
+    response.with_header("Accept-Ranges", "none")
+}
+
+/// Adds `Vary: Accept-Encoding` unless the response already has it
+pub fn with_vary(response: HttpResponse) -> HttpResponse {
+    let varies = response
+        .headers
+        .iter()
+        .any(|(name, value)| name.eq_ignore_ascii_case("Vary") && value.eq_ignore_ascii_case("Accept-Encoding"));
+    if varies { response } else { response.with_header("Vary", "Accept-Encoding") }
+}
+
+/// Makes a strong ETag weak. Encoded bytes aren't the file's bytes, so a
+/// strong ETag would be a lie; a weak one with the same tag still lets
+/// If-None-Match revalidate against the original, whichever encoding the
+/// cache holds.
+pub fn weaken_etag(response: &mut HttpResponse) {
+    for (name, value) in response.headers.iter_mut() {
+        if name.eq_ignore_ascii_case("ETag") && !value.starts_with("W/") {
+            value.insert_str(0, "W/");
+        }
+    }
+}
+
+/// Sends the response without a content coding, or a 406 in its place when
+/// the client refused identity for a successful response with a body
+fn unencoded(request: &HttpRequest, response: HttpResponse, identity_allowed: bool) -> HttpResponse {
+    let has_body = matches!(response.status.as_u16(), 200 | 206) && response.content_length() > 0;
+    if identity_allowed || !has_body {
+        return response;
+    }
+    log_info!("The client refuses identity and accepts no coding we can apply, sending 406");
+    crate::error_response(request, StatusCode::NOT_ACCEPTABLE, "None of the accepted content codings is available")
+        .with_header("Vary", "Accept-Encoding")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{TempDir, TestServer};
+
+    fn default_types() -> Vec<String> {
+        DEFAULT_COMPRESSIBLE_TYPES.iter().map(|mime| mime.to_string()).collect()
+    }
+
+    /// Inputs every encoder must round-trip: empty, tiny, repetitive,
+    /// text-like, incompressible, and longer than the match window
+    pub(super) fn round_trip_inputs() -> Vec<Vec<u8>> {
+        // A fixed LCG keeps the "random" bytes the same on every run
+        let mut state = 0x2545_f491_u32;
+        let mut next = move || {
+            state = state.wrapping_mul(1_664_525).wrapping_add(1_013_904_223);
+            (state >> 24) as u8
+        };
+        let words = ["the ", "quick ", "brown ", "fox ", "jumps ", "over ", "lazy ", "dog\n"];
+        let text: Vec<u8> = (0..20_000).flat_map(|_| words[usize::from(next() % 8)].bytes()).collect();
+        let noise: Vec<u8> = (0..5000).map(|_| next()).collect();
+        vec![Vec::new(), b"a".to_vec(), b"abcabcabcabcabc".to_vec(), vec![0; 100_000], text, noise]
+    }
+
+    #[test]
+    fn gzip_round_trips_at_every_level() {
+        for input in round_trip_inputs() {
+            for level in 0..=MAX_LEVEL {
+                let encoded = Encoding::Gzip.encode(&input, level);
+                assert_eq!(&encoded[..3], &[0x1f, 0x8b, 8]);
+                let (deflated, trailer) = encoded[10..].split_at(encoded.len() - 18);
+                let decoded = inflate(deflated, usize::MAX).expect("the deflate stream should decode");
+                assert!(decoded == input, "level {} changed a {}-byte input", level, input.len());
+                assert_eq!(trailer[..4], crc32(&input).to_le_bytes());
+                assert_eq!(trailer[4..], (input.len() as u32).to_le_bytes());
+            }
+        }
+    }
+
+    #[test]
+    fn higher_levels_never_compress_worse_on_text() {
+        let text = &round_trip_inputs()[4];
+        let sizes: Vec<usize> = [1, 6, 9].iter().map(|&level| Encoding::Gzip.encode(text, level).len()).collect();
+        assert!(sizes.windows(2).all(|pair| pair[1] <= pair[0]), "{:?}", sizes);
+        assert!(sizes[2] < text.len() / 2, "{:?}", sizes);
+    }
+
+    #[test]
+    fn compressed_formats_are_never_compressible() {
+        let types = default_types();
+        assert!(is_compressible("text/html; charset=utf-8", &types));
+        assert!(is_compressible("application/json", &types));
+        for mime in ["image/png", "image/jpeg", "video/mp4", "application/zip", "application/gzip", "application/pdf"] {
+            assert!(!is_compressible(mime, &types), "{} should be skipped", mime);
+            // Not even a catch-all pattern drags them in
+            assert!(!is_compressible(mime, &["*/*".to_string()]), "{} should be skipped", mime);
+        }
+    }
+
+    #[test]
+    fn png_is_not_gzipped_even_when_accepted() {
+        let root = TempDir::new();
+        // Highly compressible bytes behind the PNG signature
+        let mut png = b"\x89PNG\r\n\x1a\n".to_vec();
+        png.resize(16 * 1024, 0);
+        root.write("big.png", &png);
+        root.write("big.txt", vec![b'a'; 16 * 1024]);
+        let server = TestServer::with_args(&["--root", root.path().to_str().unwrap()]);
+
+        let image = server.request("GET", "/big.png", &[("Accept-Encoding", "gzip, br")]);
+        assert_eq!(image.status, 200);
+        assert_eq!(image.header("Content-Encoding"), None);
+        assert_eq!(image.body, png);
+        // The same client does get text compressed
+        let text = server.request("GET", "/big.txt", &[("Accept-Encoding", "gzip")]);
+        assert_eq!(text.header("Content-Encoding"), Some("gzip"));
+    }
+
+    #[test]
+    fn highest_q_value_wins() {
+        assert_eq!(negotiate("gzip;q=0.5, identity;q=0.4"), Some(Encoding::Gzip));
+        assert_eq!(negotiate("gzip;q=0"), None);
+        assert_eq!(negotiate_among("*;q=0.3", &[Encoding::Gzip]), Some(Encoding::Gzip));
+        // Identity rated above every coding keeps the body as it is
+        assert_eq!(negotiate("identity, gzip;q=0.5"), None);
+        assert_eq!(negotiate_among("br, gzip;q=0.5", &[Encoding::Gzip]), Some(Encoding::Gzip));
+    }
+
+    #[cfg(feature = "brotli")]
+    #[test]
+    fn q_values_order_brotli_and_gzip() {
+        assert_eq!(negotiate("gzip;q=0.5, br;q=1.0, identity;q=0"), Some(Encoding::Brotli));
+        assert_eq!(negotiate("gzip;q=1.0, br;q=0.5"), Some(Encoding::Gzip));
+        // Ties go to our own preference
+        assert_eq!(negotiate("gzip, br"), Some(Encoding::Brotli));
+        assert_eq!(negotiate("br;q=0, *"), Some(Encoding::Gzip));
+    }
+
+    #[test]
+    fn identity_is_refused_only_by_a_zero_q_value() {
+        assert!(identity_acceptable(""));
+        assert!(identity_acceptable("gzip"));
+        assert!(identity_acceptable("*;q=0, identity;q=0.1"));
+        assert!(!identity_acceptable("gzip, identity;q=0"));
+        assert!(!identity_acceptable("*;q=0"));
+    }
+
+    #[test]
+    fn refusing_identity_gets_an_encoding_or_406() {
+        let root = TempDir::new();
+        root.write("page.txt", vec![b'a'; 4096]);
+        root.write("photo.png", b"\x89PNG\r\n\x1a\n");
+        let server = TestServer::with_args(&["--root", root.path().to_str().unwrap()]);
+
+        let text = server.request("GET", "/page.txt", &[("Accept-Encoding", "gzip;q=0.5, identity;q=0")]);
+        assert_eq!(text.status, 200);
+        assert_eq!(text.header("Content-Encoding"), Some("gzip"));
+        // Not worth compressing, but identity isn't an option
+        let image = server.request("GET", "/photo.png", &[("Accept-Encoding", "gzip, identity;q=0")]);
+        assert_eq!(image.header("Content-Encoding"), Some("gzip"));
+        // No coding the client takes is one we can produce
+        let refused = server.request("GET", "/page.txt", &[("Accept-Encoding", "deflate, identity;q=0")]);
+        assert_eq!(refused.status, 406);
+        assert!(refused.headers.iter().any(|(name, value)| name == "Vary" && value == "Accept-Encoding"));
+    }
+
+    #[test]
+    fn head_and_get_agree_on_the_encoded_length() {
+        let root = TempDir::new();
+        let text: Vec<u8> = (0..2000).flat_map(|i| format!("line {}\n", i).into_bytes()).collect();
+        root.write("log.txt", &text);
+        let server = TestServer::with_args(&["--root", root.path().to_str().unwrap()]);
+
+        for accept in ["gzip", "identity"] {
+            let headers = [("Accept-Encoding", accept)];
+            let get = server.request("GET", "/log.txt", &headers);
+            let head = server.request("HEAD", "/log.txt", &headers);
+            assert_eq!(head.header("Content-Encoding"), get.header("Content-Encoding"), "{}", accept);
+            assert_eq!(head.header("Content-Length"), get.header("Content-Length"), "{}", accept);
+            assert_eq!(get.header("Content-Length"), Some(get.body.len().to_string().as_str()), "{}", accept);
+            assert!(head.body.is_empty());
+        }
+        let gzipped = server.request("HEAD", "/log.txt", &[("Accept-Encoding", "gzip")]);
+        assert_ne!(gzipped.header("Content-Length"), Some(text.len().to_string().as_str()));
+    }
+
+    #[test]
+    fn range_requests_are_never_encoded() {
+        let root = TempDir::new();
+        let text = "0123456789".repeat(500);
+        root.write("digits.txt", &text);
+        root.write("app.js", &text);
+        root.write("app.js.gz", Encoding::Gzip.encode(text.as_bytes(), 6));
+        let root = root.path().to_str().unwrap();
+        for args in [vec!["--root", root], vec!["--root", root, "--precompressed"]] {
+            let server = TestServer::with_args(&args);
+            for path in ["/digits.txt", "/app.js"] {
+                let ranged = server.request("GET", path, &[("Accept-Encoding", "gzip"), ("Range", "bytes=100-109")]);
+                assert_eq!(ranged.status, 206, "{} {:?}", path, args);
+                assert_eq!(ranged.header("Content-Encoding"), None, "{} {:?}", path, args);
+                assert_eq!(ranged.header("Content-Range"), Some("bytes 100-109/5000"));
+                assert_eq!(ranged.body, b"0123456789");
+
+                // Without a range the body is encoded and ranges into it aren't offered
+                let whole = server.request("GET", path, &[("Accept-Encoding", "gzip")]);
+                assert_eq!(whole.header("Content-Encoding"), Some("gzip"), "{} {:?}", path, args);
+                assert_eq!(whole.header("Accept-Ranges"), Some("none"));
+            }
+        }
+    }
+
+    #[test]
+    fn compressible_types_take_wildcards_but_not_over_compressed_formats() {
+        let mut types = default_types();
+        for mime in ["text/html; charset=utf-8", "Application/JSON", "application/xml"] {
+            assert!(is_compressible(mime, &types), "{}", mime);
+        }
+        for mime in ["image/svg+xml", "application/geo+json", "application/octet-stream", "image/png"] {
+            assert!(!is_compressible(mime, &types), "{}", mime);
+        }
+
+        types.extend(["image/svg+xml", "application/*+json", "application/*", "image/*"].map(String::from));
+        for mime in ["image/svg+xml", "application/geo+json", "application/vnd.api+json", "application/wasm"] {
+            assert!(is_compressible(mime, &types), "{}", mime);
+        }
+        // Named exactly, a compressed-already type counts; through a wildcard it doesn't
+        for mime in ["image/png", "application/zip", "font/woff2"] {
+            assert!(!is_compressible(mime, &types), "{}", mime);
+        }
+    }
+
+    #[test]
+    fn configured_types_are_gzipped_and_unlisted_binaries_are_not() {
+        let root = TempDir::new();
+        let json = r#"{"type":"FeatureCollection","features":[]}"#.repeat(100);
+        root.write("map.geojson", &json);
+        root.write("data.thing", &json);
+        root.write("logo.svg", format!("<svg>{}</svg>", "<g/>".repeat(500)));
+        root.write(".mime", "application/geo+json geojson;\napplication/x-thing thing;\n");
+        let root = root.path().to_str().unwrap();
+        let gzip = [("Accept-Encoding", "gzip")];
+
+        let server = TestServer::with_args(&["--root", root, "--compress-types", "image/svg+xml, application/*+json"]);
+        for path in ["/map.geojson", "/logo.svg"] {
+            let response = server.request("GET", path, &gzip);
+            assert_eq!(response.header("Content-Encoding"), Some("gzip"), "{}", path);
+            assert_eq!(response.header("Vary"), Some("Accept-Encoding"), "{}", path);
+        }
+        let binary = server.request("GET", "/data.thing", &gzip);
+        assert_eq!(binary.header("Content-Type"), Some("application/x-thing"));
+        assert_eq!((binary.header("Content-Encoding"), binary.body.len()), (None, json.len()));
+
+        // Without the option neither is a compressible type
+        let plain = TestServer::with_args(&["--root", root]);
+        for path in ["/map.geojson", "/logo.svg"] {
+            assert_eq!(plain.request("GET", path, &gzip).header("Content-Encoding"), None, "{}", path);
+        }
+    }
+}
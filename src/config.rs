@@ -0,0 +1,777 @@
+use std::fs;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::time::Duration;
+
+use crate::access_log::LogFormat;
+use crate::acl::MethodRule;
+use crate::compress;
+use crate::cors::CorsRule;
+use crate::http::{HeaderCase, SimpleRequestPolicy};
+use crate::logger::Level;
+use crate::rewrite::RewriteRule;
+use crate::thread_pool::OverflowPolicy;
+use crate::transform::Transform;
+
+/// Runtime configuration assembled from command-line arguments
+pub struct Config {
+    /// Addresses and ports to listen on, all serving the same content;
+    /// port 0 picks a free one
+    pub addresses: Vec<String>,
+    /// Document roots searched in order; the first one containing a path serves it
+    pub roots: Vec<PathBuf>,
+    /// A tar or zip file serving as the document root in place of `roots`
+    pub root_archive: Option<PathBuf>,
+    /// Optional nginx-style mime.types file merged over the built-in table
+    pub mime_types: Option<PathBuf>,
+    /// Generate an HTML listing for directories without an index file
+    pub dir_listing: bool,
+    /// Answer `/` with a built-in welcome page while the document roots are empty
+    pub welcome: bool,
+    /// Serve files reached through symlinks, as long as they stay inside the root
+    pub follow_symlinks: bool,
+    /// Number of worker threads handling connections
+    pub workers: usize,
+    /// Prefix of the server's thread names, e.g. `rawrust` for `rawrust-w0`
+    pub thread_name: String,
+    /// Maximum number of connections being handled or queued at once;
+    /// anything beyond this gets an immediate 503
+    pub max_connections: usize,
+    /// What happens to a new connection when every worker is busy and the
+    /// work queue is full
+    pub overflow_policy: OverflowPolicy,
+    /// Seconds sent in `Retry-After` when shedding load with 503
+    pub retry_after: u64,
+    /// Requests per second allowed from one client IP before answering 429
+    pub rate_limit: Option<u32>,
+    /// Believe `Forwarded` and `X-Forwarded-*` about the client's address and scheme,
+    /// for servers that are only reachable through a proxy
+    pub trust_proxy: bool,
+    /// Answer every plain http request with a 301 to the same URL over https
+    pub redirect_https: bool,
+    /// Ports from `--tls-bind` that TLS and plain HTTP share: handshakes are
+    /// passed on to `tls_backend` and plain requests redirected to https
+    pub tls_addresses: Vec<String>,
+    /// `host:port` that `--tls-bind` ports pass TLS connections to
+    pub tls_backend: Option<String>,
+    /// Most bytes per second sent in one response body
+    pub rate_limit_bps: Option<u64>,
+    /// Format of the per-request access log lines
+    pub log_format: LogFormat,
+    /// Write an access log line per request
+    pub access_log: bool,
+    /// Count responses for the status endpoint, which is only served when on
+    pub stats: bool,
+    /// Write logs to this file instead of stdout/stderr
+    pub log_file: Option<PathBuf>,
+    /// Least severe log lines written: `Info` for everything, `Error` for errors only
+    pub log_level: Level,
+    /// Rotate the log file once it reaches this many bytes
+    pub log_max_size: Option<u64>,
+    /// Serve a single connection, dump the raw bytes exchanged and exit
+    pub capture: bool,
+    /// Print the routes and static file setup, then exit without serving
+    pub list_routes: bool,
+    /// How long an idle keep-alive connection may wait for its next request
+    pub keepalive_timeout: Duration,
+    /// How long a request that has started arriving (or the first request on a
+    /// connection) may take to finish arriving
+    pub request_timeout: Duration,
+    /// Maximum number of requests served on one connection
+    pub keepalive_max: usize,
+    /// Most bytes one connection may transfer, both ways, before it's closed
+    pub max_connection_bytes: Option<u64>,
+    /// Most keep-alive connections allowed to sit idle between requests
+    pub max_keepalive_connections: Option<usize>,
+    /// `SO_SNDBUF` asked for on every accepted connection; the OS default when unset
+    pub send_buffer: Option<usize>,
+    /// `SO_RCVBUF` asked for on every accepted connection; the OS default when unset
+    pub recv_buffer: Option<usize>,
+    /// Directory that PUT and DELETE operate on; writes are disabled when unset
+    pub writable: Option<PathBuf>,
+    /// Largest request body accepted, in bytes
+    pub max_body: u64,
+    /// Longest request target (path plus query) accepted, in bytes
+    pub max_target_len: usize,
+    /// Most header lines accepted in a request before answering 431
+    pub max_headers: usize,
+    /// Longest single header value accepted before answering 431
+    pub max_header_value: usize,
+    /// Generate an `X-Request-Id` for requests that don't bring one
+    pub request_ids: bool,
+    /// Leave out every header that would differ between two identical
+    /// requests, so their responses are byte-for-byte equal
+    pub deterministic: bool,
+    /// Compress text responses for clients that accept gzip (or brotli)
+    pub compression: bool,
+    /// How hard on-the-fly compression works, from 0 (fastest) to 9 (smallest)
+    pub compress_level: u8,
+    /// Content types compressed on the fly, lowercase, each with at most one `*`
+    pub compress_types: Vec<String>,
+    /// Match file extensions to MIME types case-sensitively, so `PHOTO.JPG`
+    /// is only a JPEG if a mapping says `JPG`
+    pub strict_extensions: bool,
+    /// Serve `file.br` or `file.gz` in place of `file` to clients that accept them
+    pub precompressed: bool,
+    /// Inject a script into served HTML that reloads the page when its file changes
+    pub live_reload: bool,
+    /// Watch the document roots and push reloads to open HTML pages as files change
+    pub watch: bool,
+    /// Rewrites of served file contents, run in order after `--live-reload`'s.
+    /// Only settable from the library API, and kept across reloads.
+    pub transforms: Vec<Transform>,
+    /// File served instead of a 404 for extensionless paths browsers ask for,
+    /// so a single-page app's client-side router can handle them
+    pub spa_fallback: Option<PathBuf>,
+    /// Most bytes of file contents kept in memory; zero disables the cache
+    pub cache_size: u64,
+    /// Read the document roots into the cache at startup
+    pub preload: bool,
+    /// Spelling of response header names
+    pub header_case: HeaderCase,
+    /// Include the internal cause of 5xx errors in the response body
+    pub verbose_errors: bool,
+    /// Rules applied in order to the decoded path before looking up a file
+    pub rewrites: Vec<RewriteRule>,
+    /// Methods allowed per path prefix; when empty, every method is left to routing
+    pub method_rules: Vec<MethodRule>,
+    /// Server-wide CORS rule from `--cors`; cross-origin requests get no
+    /// CORS headers when unset, unless a route rule covers them
+    pub cors: Option<CorsRule>,
+    /// Per-route CORS rules from `--cors-route`, as (path pattern, rule)
+    pub cors_routes: Vec<(String, CorsRule)>,
+    /// Path prefixes forwarded to another server by `--proxy`, as (prefix, `host:port`)
+    pub proxies: Vec<(String, String)>,
+    /// Longest wait for a connection to a `--proxy` upstream
+    pub proxy_connect_timeout: Duration,
+    /// Longest wait for any single read from a `--proxy` upstream
+    pub proxy_read_timeout: Duration,
+    /// Longest a `--proxy` upstream may take over its whole answer
+    pub proxy_timeout: Duration,
+    /// Largest `--proxy` answer buffered before giving up with 502
+    pub proxy_max_response: usize,
+    /// Further attempts to connect to an upstream after a failure, for idempotent methods
+    pub proxy_retries: u32,
+    /// Headers added to every response that doesn't set them itself
+    pub headers: Vec<(String, String)>,
+    /// Static files that never change once published, by path pattern (`/assets/*`)
+    /// or extension (`*.woff2`), which browsers are told never to revalidate
+    pub immutable: Vec<String>,
+    /// How HTTP/0.9 requests (a request line without a version) are answered
+    pub simple_requests: SimpleRequestPolicy,
+    /// File of options read by `--config`, re-read on SIGHUP
+    pub config_file: Option<PathBuf>,
+    /// The arguments this configuration was parsed from, replayed on reload
+    pub args: Vec<String>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            addresses: vec![crate::SERVER_ADDRESS.to_string()],
+            roots: vec![PathBuf::from(crate::DOCUMENT_ROOT)],
+            root_archive: None,
+            mime_types: None,
+            dir_listing: false,
+            welcome: true,
+            follow_symlinks: true,
+            workers: 4,
+            thread_name: "rawrust".to_string(),
+            max_connections: 256,
+            overflow_policy: OverflowPolicy::Block,
+            retry_after: crate::RETRY_AFTER_SECS,
+            rate_limit: None,
+            trust_proxy: false,
+            redirect_https: false,
+            tls_addresses: Vec::new(),
+            tls_backend: None,
+            rate_limit_bps: None,
+            log_format: LogFormat::Common,
+            log_file: None,
+            log_level: Level::Info,
+            log_max_size: None,
+            capture: false,
+            list_routes: false,
+            keepalive_timeout: Duration::from_secs(5),
+            request_timeout: Duration::from_secs(30),
+            keepalive_max: 100,
+            max_connection_bytes: None,
+            max_keepalive_connections: None,
+            send_buffer: None,
+            recv_buffer: None,
+            writable: None,
+            max_body: 10 * 1024 * 1024,
+            max_target_len: 8 * 1024,
+            max_headers: 100,
+            max_header_value: 8 * 1024,
+            request_ids: true,
+            deterministic: false,
+            compression: true,
+            compress_level: compress::DEFAULT_LEVEL,
+            compress_types: compress::DEFAULT_COMPRESSIBLE_TYPES.iter().map(|mime| mime.to_string()).collect(),
+            precompressed: false,
+            live_reload: false,
+            watch: false,
+            transforms: Vec::new(),
+            strict_extensions: false,
+            access_log: true,
+            stats: true,
+            spa_fallback: None,
+            cache_size: 0,
+            preload: false,
+            header_case: HeaderCase::Canonical,
+            verbose_errors: false,
+            rewrites: Vec::new(),
+            method_rules: Vec::new(),
+            cors: None,
+            cors_routes: Vec::new(),
+            proxies: Vec::new(),
+            proxy_connect_timeout: Duration::from_secs(5),
+            proxy_read_timeout: Duration::from_secs(30),
+            proxy_timeout: Duration::from_secs(60),
+            proxy_max_response: 64 * 1024 * 1024,
+            proxy_retries: 0,
+            headers: Vec::new(),
+            immutable: Vec::new(),
+            simple_requests: SimpleRequestPolicy::Reject,
+            config_file: None,
+            args: Vec::new(),
+        }
+    }
+}
+
+impl Config {
+    /// Parses the process arguments (excluding the program name). Options
+    /// from a `--config` file apply where it appears among them, so later
+    /// arguments override the file.
+    pub fn from_args<I: Iterator<Item = String>>(args: I) -> Result<Config, String> {
+        let args: Vec<String> = args.collect();
+        let mut config_file = None;
+        let mut expanded = Vec::new();
+        let mut iter = args.iter();
+        while let Some(arg) = iter.next() {
+            if arg == "--config" {
+                let path = PathBuf::from(iter.next().ok_or("--config requires a file path")?);
+                expanded.extend(read_config_file(&path)?);
+                config_file = Some(path);
+            } else {
+                expanded.push(arg.clone());
+            }
+        }
+
+        let mut config = Config::parse(expanded.into_iter())?;
+        config.config_file = config_file;
+        config.args = args;
+        Ok(config)
+    }
+
+    /// Parses the arguments this configuration came from again, picking up
+    /// any changes to its `--config` file
+    pub fn reload(&self) -> Result<Config, String> {
+        let mut config = Config::from_args(self.args.iter().cloned())?;
+        config.transforms = self.transforms.clone();
+        Ok(config)
+    }
+
+    /// The options that differ in `new` but can't change without a restart,
+    /// because they were used up at startup (sockets, threads, caches)
+    pub fn restart_required(&self, new: &Config) -> Vec<&'static str> {
+        let changes = [
+            ("--bind", self.addresses != new.addresses),
+            ("--tls-bind", self.tls_addresses != new.tls_addresses),
+            ("--workers", self.workers != new.workers),
+            ("--thread-name", self.thread_name != new.thread_name),
+            ("--overflow-policy", self.overflow_policy != new.overflow_policy),
+            ("--rate-limit", self.rate_limit != new.rate_limit),
+            ("--log-file", self.log_file != new.log_file),
+            ("--log-max-size", self.log_max_size != new.log_max_size),
+            ("--cache-size", self.cache_size != new.cache_size),
+            ("--preload", self.preload != new.preload),
+            ("--watch", self.watch != new.watch),
+        ];
+        changes.into_iter().filter(|(_, changed)| *changed).map(|(option, _)| option).collect()
+    }
+
+    fn parse<I: Iterator<Item = String>>(mut args: I) -> Result<Config, String> {
+        let mut config = Config::default();
+        let mut roots = Vec::new();
+        let mut addresses = Vec::new();
+
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--bind" => {
+                    let value = args.next().ok_or("--bind requires an address")?;
+                    addresses.extend(value.split(',').filter(|address| !address.is_empty()).map(str::to_string));
+                }
+                "--root" => {
+                    let value = args.next().ok_or("--root requires a directory")?;
+                    roots.extend(value.split(',').filter(|root| !root.is_empty()).map(PathBuf::from));
+                }
+                "--root-archive" => {
+                    let value = args.next().ok_or("--root-archive requires a tar or zip file")?;
+                    config.root_archive = Some(PathBuf::from(value));
+                }
+                "--mime-types" => {
+                    let value = args.next().ok_or("--mime-types requires a file path")?;
+                    config.mime_types = Some(PathBuf::from(value));
+                }
+                "--dir-listing" => config.dir_listing = true,
+                "--no-welcome" => config.welcome = false,
+                "--no-follow-symlinks" => config.follow_symlinks = false,
+                "--workers" => config.workers = parse_value(&mut args, "--workers")?,
+                "--thread-name" => {
+                    let value = args.next().ok_or("--thread-name requires a prefix")?;
+                    if value.is_empty() || value.contains('\0') {
+                        return Err(format!("Invalid value for --thread-name: {:?}", value));
+                    }
+                    config.thread_name = value;
+                }
+                "--max-connections" => {
+                    config.max_connections = parse_value(&mut args, "--max-connections")?
+                }
+                "--overflow-policy" => {
+                    let value = args.next().ok_or("--overflow-policy requires a value")?;
+                    config.overflow_policy = OverflowPolicy::parse(&value).ok_or(format!(
+                        "Invalid value for --overflow-policy: {} (expected block, reject or grow)",
+                        value
+                    ))?;
+                }
+                "--retry-after" => config.retry_after = parse_value(&mut args, "--retry-after")?,
+                "--rate-limit" => config.rate_limit = Some(parse_value(&mut args, "--rate-limit")?),
+                "--trust-proxy" => config.trust_proxy = true,
+                "--redirect-https" => config.redirect_https = true,
+                "--tls-bind" => {
+                    let value = args.next().ok_or("--tls-bind requires an address")?;
+                    let tls_addresses = value.split(',').filter(|address| !address.is_empty()).map(str::to_string);
+                    config.tls_addresses.extend(tls_addresses);
+                }
+                "--tls-backend" => {
+                    config.tls_backend = Some(args.next().ok_or("--tls-backend requires an address")?);
+                }
+                "--rate-limit-bps" => {
+                    let value = args.next().ok_or("--rate-limit-bps requires a value")?;
+                    let rate = parse_size(&value)
+                        .filter(|&rate| rate > 0)
+                        .ok_or(format!("Invalid value for --rate-limit-bps: {}", value))?;
+                    config.rate_limit_bps = Some(rate);
+                }
+                "--log-level" => {
+                    let value = args.next().ok_or("--log-level requires a value")?;
+                    config.log_level = Level::parse(&value)
+                        .ok_or(format!("Invalid value for --log-level: {} (expected info or error)", value))?;
+                }
+                "--log-format" => {
+                    let value = args.next().ok_or("--log-format requires a value")?;
+                    config.log_format = LogFormat::parse(&value)
+                        .ok_or(format!("Invalid value for --log-format: {} (expected clf or json)", value))?;
+                }
+                "--log-file" => {
+                    let value = args.next().ok_or("--log-file requires a file path")?;
+                    config.log_file = Some(PathBuf::from(value));
+                }
+                "--log-max-size" => {
+                    let value = args.next().ok_or("--log-max-size requires a value")?;
+                    config.log_max_size = Some(
+                        parse_size(&value).ok_or(format!("Invalid value for --log-max-size: {}", value))?,
+                    );
+                }
+                "--capture" => config.capture = true,
+                "--list-routes" => config.list_routes = true,
+                "--keepalive-timeout" => {
+                    config.keepalive_timeout = Duration::from_secs(parse_value(&mut args, "--keepalive-timeout")?)
+                }
+                "--request-timeout" => {
+                    config.request_timeout = Duration::from_secs(parse_value(&mut args, "--request-timeout")?)
+                }
+                "--keepalive-max" => config.keepalive_max = parse_value(&mut args, "--keepalive-max")?,
+                "--max-connection-bytes" => {
+                    let value = args.next().ok_or("--max-connection-bytes requires a value")?;
+                    config.max_connection_bytes = Some(
+                        parse_size(&value).ok_or(format!("Invalid value for --max-connection-bytes: {}", value))?,
+                    );
+                }
+                "--max-keepalive-connections" => {
+                    config.max_keepalive_connections =
+                        Some(parse_value(&mut args, "--max-keepalive-connections")?)
+                }
+                "--send-buffer" => {
+                    let value = args.next().ok_or("--send-buffer requires a value")?;
+                    config.send_buffer = Some(buffer_size(&value, "--send-buffer")?);
+                }
+                "--recv-buffer" => {
+                    let value = args.next().ok_or("--recv-buffer requires a value")?;
+                    config.recv_buffer = Some(buffer_size(&value, "--recv-buffer")?);
+                }
+                "--writable" => {
+                    let value = args.next().ok_or("--writable requires a directory")?;
+                    config.writable = Some(PathBuf::from(value));
+                }
+                "--max-body" => {
+                    let value = args.next().ok_or("--max-body requires a value")?;
+                    config.max_body = parse_size(&value).ok_or(format!("Invalid value for --max-body: {}", value))?;
+                }
+                "--max-uri-length" => {
+                    let value = args.next().ok_or("--max-uri-length requires a value")?;
+                    config.max_target_len = parse_size(&value)
+                        .and_then(|size| usize::try_from(size).ok())
+                        .ok_or(format!("Invalid value for --max-uri-length: {}", value))?;
+                }
+                "--max-headers" => config.max_headers = parse_value(&mut args, "--max-headers")?,
+                "--max-header-value" => {
+                    let value = args.next().ok_or("--max-header-value requires a value")?;
+                    config.max_header_value = parse_size(&value)
+                        .and_then(|size| usize::try_from(size).ok())
+                        .ok_or(format!("Invalid value for --max-header-value: {}", value))?;
+                }
+                "--no-request-id" => config.request_ids = false,
+                "--deterministic" => {
+                    config.deterministic = true;
+                    config.request_ids = false;
+                }
+                "--no-compress" => config.compression = false,
+                "--compress-types" => {
+                    let value = args.next().ok_or("--compress-types requires a list of content types")?;
+                    for mime in value.split(',').map(str::trim).filter(|mime| !mime.is_empty()) {
+                        if !mime.contains('/') || mime.matches('*').count() > 1 {
+                            return Err(format!("Invalid content type for --compress-types: {}", mime));
+                        }
+                        config.compress_types.push(mime.to_ascii_lowercase());
+                    }
+                }
+                "--compress-level" => {
+                    let level = parse_value(&mut args, "--compress-level")?;
+                    if level > compress::MAX_LEVEL {
+                        return Err(format!("--compress-level must be between 0 and {}", compress::MAX_LEVEL));
+                    }
+                    config.compress_level = level;
+                }
+                "--precompressed" => config.precompressed = true,
+                "--live-reload" => config.live_reload = true,
+                "--watch" => config.watch = true,
+                "--strict-extensions" => config.strict_extensions = true,
+                "--no-access-log" => config.access_log = false,
+                "--no-stats" => config.stats = false,
+                "--spa-fallback" => {
+                    let value = args.next().ok_or("--spa-fallback requires a file path")?;
+                    config.spa_fallback = Some(PathBuf::from(value));
+                }
+                "--cache-size" => {
+                    let value = args.next().ok_or("--cache-size requires a value")?;
+                    config.cache_size =
+                        parse_size(&value).ok_or(format!("Invalid value for --cache-size: {}", value))?;
+                }
+                "--preload" => config.preload = true,
+                "--lowercase-headers" => config.header_case = HeaderCase::Lower,
+                "--verbose-errors" => config.verbose_errors = true,
+                "--rewrite" => {
+                    let value = args.next().ok_or("--rewrite requires a rule")?;
+                    let rule = RewriteRule::parse_prefix(&value)
+                        .ok_or(format!("Invalid value for --rewrite: {} (expected /from=/to)", value))?;
+                    config.rewrites.push(rule);
+                }
+                "--allow-methods" => {
+                    let value = args.next().ok_or("--allow-methods requires a rule")?;
+                    let rule = MethodRule::parse(&value)
+                        .ok_or(format!("Invalid value for --allow-methods: {} (expected /prefix=GET,PUT)", value))?;
+                    config.method_rules.push(rule);
+                }
+                "--cors" => {
+                    let value = args.next().ok_or("--cors requires a rule")?;
+                    config.cors = Some(CorsRule::parse(&value).ok_or(format!("Invalid value for --cors: {}", value))?);
+                }
+                "--cors-route" => {
+                    let value = args.next().ok_or("--cors-route requires a rule")?;
+                    let invalid =
+                        || format!("Invalid value for --cors-route: {} (expected /path;methods=GET,POST)", value);
+                    let (pattern, rule) = value.split_once(';').unwrap_or((&value, ""));
+                    if !pattern.starts_with('/') {
+                        return Err(invalid());
+                    }
+                    let rule = CorsRule::parse(rule).ok_or_else(invalid)?;
+                    config.cors_routes.push((pattern.to_string(), rule));
+                }
+                "--proxy" => {
+                    let value = args.next().ok_or("--proxy requires a rule")?;
+                    let invalid = || format!("Invalid value for --proxy: {} (expected /prefix=host:port)", value);
+                    let (prefix, address) = value.split_once('=').ok_or_else(invalid)?;
+                    if !prefix.starts_with('/') || address.is_empty() {
+                        return Err(invalid());
+                    }
+                    config.proxies.push((prefix.trim_end_matches('/').to_string(), address.to_string()));
+                }
+                "--proxy-connect-timeout" => {
+                    let secs = parse_value(&mut args, "--proxy-connect-timeout")?;
+                    config.proxy_connect_timeout = Duration::from_secs(secs);
+                }
+                "--proxy-read-timeout" => {
+                    config.proxy_read_timeout = Duration::from_secs(parse_value(&mut args, "--proxy-read-timeout")?)
+                }
+                "--proxy-timeout" => {
+                    config.proxy_timeout = Duration::from_secs(parse_value(&mut args, "--proxy-timeout")?);
+                }
+                "--proxy-max-response" => {
+                    let value = args.next().ok_or("--proxy-max-response requires a size")?;
+                    config.proxy_max_response = parse_size(&value)
+                        .and_then(|size| usize::try_from(size).ok())
+                        .filter(|&size| size > 0)
+                        .ok_or(format!("Invalid value for --proxy-max-response: {}", value))?;
+                }
+                "--proxy-retries" => config.proxy_retries = parse_value(&mut args, "--proxy-retries")?,
+                "--rewrite-suffix" => {
+                    let value = args.next().ok_or("--rewrite-suffix requires a suffix")?;
+                    let rule = RewriteRule::parse_suffix(&value)
+                        .ok_or(format!("Invalid value for --rewrite-suffix: {}", value))?;
+                    config.rewrites.push(rule);
+                }
+                "--header" => {
+                    let value = args.next().ok_or("--header requires a \"Name: value\" pair")?;
+                    let header = parse_header(&value).ok_or(format!("Invalid value for --header: {}", value))?;
+                    config.headers.push(header);
+                }
+                "--immutable" => {
+                    let value = args.next().ok_or("--immutable requires a pattern")?;
+                    for pattern in value.split(',').map(str::trim).filter(|pattern| !pattern.is_empty()) {
+                        if !(pattern.starts_with('/') || pattern.starts_with("*.")) {
+                            let expected = "expected /path/* or *.ext";
+                            return Err(format!("Invalid value for --immutable: {} ({})", pattern, expected));
+                        }
+                        config.immutable.push(pattern.to_string());
+                    }
+                }
+                "--http09" => {
+                    let value = args.next().ok_or("--http09 requires a policy")?;
+                    config.simple_requests = SimpleRequestPolicy::parse(&value)
+                        .ok_or(format!("Invalid value for --http09: {} (expected reject, 505 or serve)", value))?;
+                }
+                "-h" | "--help" => return Err(usage()),
+                other => return Err(format!("Unknown argument: {}\n\n{}", other, usage())),
+            }
+        }
+
+        // Any --root replaces the default document root, and any --bind the default address.
+        // An archive replaces the directories altogether.
+        if config.root_archive.is_some() {
+            if !roots.is_empty() {
+                return Err("--root-archive can't be combined with --root".to_string());
+            }
+            config.roots = Vec::new();
+        } else if !roots.is_empty() {
+            config.roots = roots;
+        }
+        if !addresses.is_empty() {
+            config.addresses = addresses;
+        }
+
+        if !config.tls_addresses.is_empty() && config.tls_backend.is_none() {
+            return Err("--tls-bind requires --tls-backend to pass TLS connections to".to_string());
+        }
+        if config.workers == 0 {
+            return Err("--workers must be at least 1".to_string());
+        }
+        if config.keepalive_timeout.is_zero() {
+            return Err("--keepalive-timeout must be at least 1 second".to_string());
+        }
+        let proxy_timeouts = [config.proxy_connect_timeout, config.proxy_read_timeout, config.proxy_timeout];
+        if proxy_timeouts.iter().any(Duration::is_zero) {
+            let options = "--proxy-connect-timeout, --proxy-read-timeout and --proxy-timeout";
+            return Err(format!("{} must be at least 1 second", options));
+        }
+        if config.request_timeout.is_zero() {
+            return Err("--request-timeout must be at least 1 second".to_string());
+        }
+        if config.keepalive_max == 0 {
+            return Err("--keepalive-max must be at least 1".to_string());
+        }
+        if config.max_target_len == 0 {
+            return Err("--max-uri-length must be at least 1".to_string());
+        }
+        if config.preload && config.cache_size == 0 {
+            return Err("--preload requires --cache-size".to_string());
+        }
+        if config.rate_limit == Some(0) {
+            return Err("--rate-limit must be at least 1".to_string());
+        }
+        if config.max_connections == 0 {
+            return Err("--max-connections must be at least 1".to_string());
+        }
+
+        Ok(config)
+    }
+}
+
+/// Turns a config file into arguments. Each line holds one option without
+/// its leading dashes, then its value if it takes one, e.g. `root /srv/www`
+/// or `dir-listing`; `#` starts a comment.
+fn read_config_file(path: &PathBuf) -> Result<Vec<String>, String> {
+    let contents =
+        fs::read_to_string(path).map_err(|e| format!("Failed to read config file {}: {}", path.display(), e))?;
+
+    let mut args = Vec::new();
+    for line in contents.lines() {
+        let line = line.split('#').next().unwrap_or("").trim();
+        if line.is_empty() {
+            continue;
+        }
+        let (option, value) = match line.split_once(char::is_whitespace) {
+            Some((option, value)) => (option, Some(value.trim())),
+            None => (line, None),
+        };
+        let option = option.trim_start_matches('-');
+        if option == "config" {
+            return Err(format!("{}: a config file can't include another", path.display()));
+        }
+        args.push(format!("--{}", option));
+        args.extend(value.map(str::to_string));
+    }
+    Ok(args)
+}
+
+/// Reads and parses the value following an option
+fn parse_value<T: FromStr, I: Iterator<Item = String>>(args: &mut I, name: &str) -> Result<T, String> {
+    let value = args.next().ok_or(format!("{} requires a value", name))?;
+    value.parse().map_err(|_| format!("Invalid value for {}: {}", name, value))
+}
+
+/// Parses a `--header` value such as `X-Frame-Options: DENY`. Headers the
+/// server writes itself to frame or date the response can't be overridden.
+fn parse_header(value: &str) -> Option<(String, String)> {
+    let (name, value) = value.split_once(':')?;
+    let name = name.trim();
+    let value = value.trim();
+    let valid_name = !name.is_empty() && name.bytes().all(|byte| byte.is_ascii_alphanumeric() || b"-_".contains(&byte));
+    let framing = ["Content-Length", "Transfer-Encoding", "Connection", "Keep-Alive", "Trailer", "Date"]
+        .iter()
+        .any(|reserved| name.eq_ignore_ascii_case(reserved));
+    if !valid_name || framing || value.is_empty() || value.bytes().any(|byte| byte.is_ascii_control()) {
+        return None;
+    }
+    Some((name.to_string(), value.to_string()))
+}
+
+/// A socket buffer size: non-zero, and small enough to hand to `setsockopt`
+fn buffer_size(value: &str, option: &str) -> Result<usize, String> {
+    parse_size(value)
+        .filter(|&size| size > 0 && size <= i32::MAX as u64)
+        .map(|size| size as usize)
+        .ok_or(format!("Invalid value for {}: {}", option, value))
+}
+
+/// Parses a byte count with an optional `K`, `M` or `G` suffix, e.g. `10M`
+fn parse_size(value: &str) -> Option<u64> {
+    let (digits, multiplier) = match value.chars().last()?.to_ascii_uppercase() {
+        'K' => (&value[..value.len() - 1], 1024),
+        'M' => (&value[..value.len() - 1], 1024 * 1024),
+        'G' => (&value[..value.len() - 1], 1024 * 1024 * 1024),
+        _ => (value, 1),
+    };
+    digits.parse::<u64>().ok()?.checked_mul(multiplier)
+}
+
+/// Returns the command-line usage text
+pub fn usage() -> String {
+    "Usage: rust-raw-http-server [OPTIONS]\n       \
+     rust-raw-http-server bench [BENCH OPTIONS]\n\
+     \n\
+     Options:\n  \
+       --config <file>       Read options from a file, one per line (e.g. `root public`); re-read on SIGHUP\n  \
+       --bind <addr>         Address to listen on; repeat (or comma-separate) for several (default 127.0.0.1:8080)\n  \
+       --root <dir>          Document root; repeat (or comma-separate) to search several in order\n  \
+       --root-archive <file> Serve the contents of a tar or zip file instead of a directory\n  \
+       --mime-types <file>   Merge an nginx-style mime.types file over the built-in types\n  \
+       --strict-extensions   Match extensions to MIME types case-sensitively (PHOTO.JPG isn't a JPEG)\n  \
+       --dir-listing         List directories that have no index.html\n  \
+       --no-welcome          Don't show the built-in welcome page for / while the document root is empty\n  \
+       --no-follow-symlinks  Refuse any path through a symlink (by default only those leaving the root)\n  \
+       --workers <n>         Number of worker threads (default 4)\n  \
+       --thread-name <prefix> Prefix of thread names: <prefix>-w0, <prefix>-accept... (default rawrust)\n  \
+       --max-connections <n> Connections allowed in flight before answering 503 (default 256)\n  \
+       --overflow-policy <p> When the work queue is full: block (default), reject with 503, or grow\n  \
+       --retry-after <secs>  Retry-After sent with 503 when over the limit (default 1)\n  \
+       --rate-limit <n>      Requests per second allowed per client IP before answering 429\n  \
+       --trust-proxy         Take the client IP and scheme from Forwarded or X-Forwarded-For/-Proto\n  \
+       --redirect-https      Answer plain http requests with a 301 to the https URL\n  \
+       --tls-bind <addr>     Port shared by TLS and plain HTTP; plain requests are redirected to https\n  \
+       --tls-backend <addr>  Where --tls-bind ports pass TLS connections, e.g. a local TLS terminator\n  \
+       --rate-limit-bps <size> Pace each response body to at most this many bytes per second, e.g. 512K\n  \
+       --log-format <fmt>    Access log format: clf (default) or json\n  \
+       --log-level <level>   info (default) for everything, or error for errors only\n  \
+       --log-file <path>     Append logs to a file (reopened on SIGHUP)\n  \
+       --log-max-size <size> Rotate the log file at this size, e.g. 10M\n  \
+       --capture             Serve one connection, hex-dump the raw exchange and exit\n  \
+       --list-routes         Print the registered routes and document roots, then exit\n  \
+       --keepalive-timeout <secs> Close idle keep-alive connections after this long (default 5)\n  \
+       --request-timeout <secs> Time allowed to finish receiving a request once it starts (default 30)\n  \
+       --keepalive-max <n>   Requests served per connection before closing (default 100)\n  \
+       --max-connection-bytes <size> Close a connection once it has transferred this much (default off)\n  \
+       --max-keepalive-connections <n> Idle keep-alive connections allowed; past it, close after responding\n  \
+       --send-buffer <size>  Ask for this SO_SNDBUF on every connection, e.g. 256K (default: the OS's)\n  \
+       --recv-buffer <size>  Ask for this SO_RCVBUF on every connection (default: the OS's)\n  \
+       --writable <dir>      Enable PUT and DELETE for files under this directory\n  \
+       --proxy <p=host:port> Forward requests under a path prefix to another server, e.g. '/api=127.0.0.1:3000'\n  \
+       --proxy-connect-timeout <secs> Longest wait to connect to a --proxy upstream (default 5)\n  \
+       --proxy-read-timeout <secs> Longest silence from a --proxy upstream before answering 504 (default 30)\n  \
+       --proxy-timeout <secs> Longest a --proxy upstream may take over its whole answer (default 60)\n  \
+       --proxy-max-response <size> Largest --proxy answer buffered before answering 502 (default 64M)\n  \
+       --proxy-retries <n>   Reconnect attempts after a failed upstream connection, idempotent requests only\n  \
+       --max-body <size>     Largest accepted request body, e.g. 10M (default 10M)\n  \
+       --max-uri-length <size> Longest request target before answering 414 (default 8K)\n  \
+       --max-headers <n>     Most header lines in a request before answering 431 (default 100)\n  \
+       --max-header-value <size> Longest single header value before answering 431 (default 8K)\n  \
+       --no-request-id       Don't generate X-Request-Id (incoming IDs are still reused)\n  \
+       --deterministic       Freeze volatile headers so identical requests get byte-identical responses\n  \
+       --no-compress         Never gzip/brotli-encode responses\n  \
+       --compress-types <list> Also compress these content types, e.g. 'image/svg+xml,application/*+json'\n  \
+       --compress-level <n>  Compression effort from 0 (fastest) to 9 (smallest output) (default 6)\n  \
+       --precompressed       Serve file.br/file.gz siblings in place of file to clients that accept them\n  \
+       --live-reload         Reload HTML pages in the browser when their files change\n  \
+       --watch               Watch the roots and push reloads to open pages over /__livereload\n  \
+       --no-access-log       Don't write access log lines (for benchmarking)\n  \
+       --no-stats            Don't count responses or serve /__status (for benchmarking)\n  \
+       --spa-fallback <file> Serve this file for missing extensionless paths requested as HTML\n  \
+       --cache-size <size>   Keep up to this much file content in memory, e.g. 64M (default off)\n  \
+       --preload             Read the document roots into the cache at startup\n  \
+       --lowercase-headers   Send response header names in lowercase\n  \
+       --verbose-errors      Show the internal cause of 5xx errors in the body (for development)\n  \
+       --rewrite <from=to>   Serve paths starting with <from> from <to> instead, e.g. /docs/=/manual/\n  \
+       --rewrite-suffix <s>  Try <s> appended to extensionless paths, e.g. .html for clean URLs\n  \
+       --allow-methods <p=M> Only allow these methods under a prefix, e.g. /upload/=PUT; others get GET, HEAD\n  \
+       --cors <rule>         Allow cross-origin requests, e.g. '*' or 'origin=https://a.example;methods=GET,POST'\n  \
+       --cors-route <p;rule> CORS rule for one route pattern, e.g. '/api/*;methods=GET,PUT;max-age=600'\n  \
+       --header <h>          Add \"Name: value\" to every response that doesn't set it; repeatable\n  \
+       --immutable <pattern> Cache matching files for a year without revalidating, e.g. '/assets/*' or '*.woff2'\n  \
+       --http09 <policy>     Answer versionless HTTP/0.9 requests with reject (400, default), 505 or serve\n  \
+       -h, --help            Show this help"
+        .to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn header_option_takes_a_name_and_a_value() {
+        assert_eq!(parse_header("X-Frame-Options: DENY"), Some(("X-Frame-Options".to_string(), "DENY".to_string())));
+        assert_eq!(
+            parse_header("Strict-Transport-Security:max-age=63072000; includeSubDomains"),
+            Some(("Strict-Transport-Security".to_string(), "max-age=63072000; includeSubDomains".to_string()))
+        );
+        for invalid in ["X-Empty:", "no colon", ": value", "Bad Name: x", "X-Control: a\u{7}b"] {
+            assert_eq!(parse_header(invalid), None, "{:?}", invalid);
+        }
+        // Framing is the server's own business
+        assert_eq!(parse_header("content-length: 5"), None);
+        assert_eq!(parse_header("Connection: close"), None);
+        assert_eq!(parse_header("Date: Thu, 01 Jan 1970 00:00:00 GMT"), None);
+    }
+
+    #[test]
+    fn compress_level_shares_gzips_0_to_9_scale() {
+        let parse = |level: &str| Config::from_args(["--compress-level", level].map(String::from).into_iter());
+        assert_eq!(parse("0").unwrap().compress_level, 0);
+        assert_eq!(parse("9").unwrap().compress_level, 9);
+        // Brotli's own 10 and 11 aren't accepted rather than quietly meaning 9
+        for level in ["10", "11", "-1", "fast"] {
+            assert!(parse(level).is_err(), "{}", level);
+        }
+    }
+}
@@ -0,0 +1,654 @@
+use std::io::{self, ErrorKind, Read, Write};
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::sync::atomic::Ordering;
+use std::time::{Duration, Instant, SystemTime};
+
+use crate::access_log::AccessLogEntry;
+use crate::http::{self, HttpRequest, HttpResponse, Method, ParseLimits, SimpleRequestPolicy, StatusCode};
+use crate::{acl, compress, cors, forwarded, logger, request_id, tls_port};
+use crate::{ConnectionGuard, Context, CountingWriter, Disposition, LiveContext, ReadTimeout, Sent, MAX_HEAD_BYTES};
+
+/// Outcome of waiting for the next request on a connection
+enum ReadOutcome {
+    /// A complete request head (request line and headers)
+    Head(Vec<u8>),
+    /// The client closed the connection, went away, or stayed idle too long
+    Closed,
+    /// The request head grew past `MAX_HEAD_BYTES` without ending
+    TooLarge,
+    /// Part of a request head arrived, then the request timeout ran out
+    TimedOut,
+}
+
+/// A stream that counts the bytes going each way, for `--max-connection-bytes`
+struct Metered<S> {
+    inner: S,
+    /// Bytes read and written so far
+    bytes: u64,
+}
+
+impl<S: Read> Read for Metered<S> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let size = self.inner.read(buf)?;
+        self.bytes += size as u64;
+        Ok(size)
+    }
+}
+
+impl<S: Write> Write for Metered<S> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let size = self.inner.write(buf)?;
+        self.bytes += size as u64;
+        Ok(size)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+impl<S: ReadTimeout> ReadTimeout for Metered<S> {
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        self.inner.set_read_timeout(timeout)
+    }
+}
+
+/// One client connection and the bookkeeping that spans its requests
+pub(crate) struct Connection<'a, S> {
+    stream: Metered<S>,
+    peer: Option<SocketAddr>,
+    live: &'a LiveContext,
+    /// The configuration the current request is served with, picked up
+    /// afresh as each request arrives so a reload applies to the next one
+    context: Arc<Context>,
+    /// Bytes received but not yet consumed; a pipelining client may send
+    /// the start of the next request along with the current one
+    buffer: Vec<u8>,
+    requests_served: usize,
+    /// When the current request's head finished arriving
+    request_started: Instant,
+    /// Answer every request with a redirect to https, as for plaintext
+    /// that arrived on a `--tls-bind` port
+    redirect_https: bool,
+}
+
+impl<'a, S: Read + Write + ReadTimeout> Connection<'a, S> {
+    pub fn new(stream: S, peer: Option<SocketAddr>, live: &'a LiveContext) -> Self {
+        Connection {
+            stream: Metered { inner: stream, bytes: 0 },
+            peer,
+            live,
+            context: live.load(),
+            buffer: Vec::new(),
+            requests_served: 0,
+            request_started: Instant::now(),
+            redirect_https: false,
+        }
+    }
+
+    /// Redirects every request to https, whatever `--redirect-https` says
+    pub fn redirecting_to_https(mut self) -> Self {
+        self.redirect_https = true;
+        self
+    }
+
+    /// Serves requests until the connection should close (client asked,
+    /// limit reached, timeout or error)
+    pub fn serve(mut self) {
+        while let Some(head) = self.next_request() {
+            if !self.handle(&head) {
+                return;
+            }
+        }
+    }
+
+    /// Waits for the next request head. Returns `None` once the connection
+    /// is done, after answering a head too large to read with 431 or one that
+    /// stalled partway with 408.
+    fn next_request(&mut self) -> Option<Vec<u8>> {
+        match self.read_head() {
+            ReadOutcome::Head(head) => {
+                self.context = self.live.load();
+                self.request_started = Instant::now();
+                self.requests_served += 1;
+                Some(head)
+            }
+            ReadOutcome::Closed => None,
+            ReadOutcome::TooLarge => {
+                let response = HttpResponse::text(431, "Request header fields too large");
+                let context = Arc::clone(&self.context);
+                crate::send_response(&mut self.stream, response, Disposition::Close, false, &context.config);
+                None
+            }
+            ReadOutcome::TimedOut => {
+                let response = HttpResponse::text(StatusCode::REQUEST_TIMEOUT, "The request took too long to arrive");
+                let context = Arc::clone(&self.context);
+                crate::send_response(&mut self.stream, response, Disposition::Close, false, &context.config);
+                None
+            }
+        }
+    }
+
+    /// Parses and answers one request. Returns whether the connection stays open.
+    fn handle(&mut self, head: &[u8]) -> bool {
+        let context = Arc::clone(&self.context);
+        let config = &context.config;
+
+        // Parse the request line and headers
+        let limits = ParseLimits {
+            max_target_len: config.max_target_len,
+            max_headers: config.max_headers,
+            max_header_value: config.max_header_value,
+        };
+        let peer_ip = self.peer.map(|addr| addr.ip());
+        let parsed = http::parse_request(head, limits).map(|mut parsed| {
+            parsed.request_id = crate::assign_request_id(&parsed, config);
+            forwarded::identify_client(&mut parsed, peer_ip, config.trust_proxy);
+            parsed
+        });
+
+        // Tag every log line written while handling this request with its ID
+        let request_id = match &parsed {
+            Ok(parsed) => parsed.request_id.clone(),
+            Err(_) => config.request_ids.then(request_id::generate),
+        };
+        let _log_scope = logger::RequestIdScope::enter(request_id.clone());
+
+        log_info!("Request: \n{}", String::from_utf8_lossy(head));
+        let (response, reusable) = match &parsed {
+            // HTTP/0.9 only survives in scanners and ancient clients; most get refused
+            Ok(request) if is_simple(request) && config.simple_requests != SimpleRequestPolicy::Serve => {
+                log_info!("HTTP/0.9 request for {}, refusing it", request.path);
+                let response = match config.simple_requests {
+                    SimpleRequestPolicy::Unsupported => HttpResponse::text(505, "HTTP/0.9 is not supported"),
+                    _ => HttpResponse::text(400, "HTTP/0.9 requests are not supported"),
+                };
+                (response, false)
+            }
+            Ok(request) => {
+                log_info!("Method: {}, Path: {}, Version: {}", request.method, request.path, request.version);
+                // `OPTIONS *` isn't about any path, so no prefix rule covers it. A CORS
+                // preflight asks about another method, which is checked when it comes.
+                let exempt = request.is_server_wide() || cors::is_preflight(request);
+                let denied = acl::allowed_methods(&config.method_rules, &request.path)
+                    .filter(|methods| !exempt && !methods.contains(&request.method));
+                // Refused requests never had their body read, so the connection can't be reused
+                if let Some(wait) = crate::rate_limit_wait(&context, request.client_ip) {
+                    (crate::too_many_requests(request, wait), false)
+                } else if (config.redirect_https || self.redirect_https)
+                    && request.scheme == "http"
+                    && !request.is_server_wide()
+                {
+                    (crate::redirect_to_https(request), false)
+                } else if let Some(methods) = denied {
+                    log_info!("{} is not allowed for {}", request.method, request.path);
+                    (crate::method_not_allowed(request, methods), false)
+                } else {
+                    crate::respond(request, &mut self.stream, &mut self.buffer, &context)
+                }
+            },
+            // Invalid request format or an over-long target
+            Err(error) => (HttpResponse::text(error.status(), error.message()), false),
+        };
+        let parsed = parsed.ok();
+        let response = match &parsed {
+            Some(request) => {
+                let response = crate::apply_error_page(request, response, &config.roots);
+                let response = crate::apply_cors(request, response, &context);
+                let response =
+                    if config.verbose_errors { crate::show_error_detail(request, response) } else { response };
+                if config.compression {
+                    compress::encode_response(request, response, config.compress_level, &config.compress_types)
+                } else {
+                    response
+                }
+            }
+            None => response,
+        };
+
+        self.respond(response, parsed.as_ref(), request_id.as_deref(), reusable)
+    }
+
+    /// Sends the final response to `request` (`None` if it couldn't be
+    /// parsed), then logs and counts it. `reusable` is false when the stream
+    /// is no longer at a request boundary. Returns whether the connection stays open.
+    fn respond(
+        &mut self,
+        response: HttpResponse,
+        request: Option<&HttpRequest>,
+        request_id: Option<&str>,
+        reusable: bool,
+    ) -> bool {
+        let context = Arc::clone(&self.context);
+        let config = &context.config;
+
+        // Chunked encoding is HTTP/1.1 only: older clients lose the trailers,
+        // and a streamed body of unknown length goes out as it is, ended by closing
+        // the connection. It isn't read into memory first, as it may never end (an event stream).
+        let mut response = response;
+        if request.is_none_or(|request| !request.is_http_1_1()) {
+            response.trailers.clear();
+            response.close_delimited = response.stream.is_some() && response.content_length.is_none();
+        }
+        if request.is_none_or(|request| request.method != Method::Head)
+            && let Err(e) = response.prime_stream()
+        {
+            // Nothing has been sent yet, so a stream that fails before its
+            // first byte still gets a proper 500
+            log_error!("Failed to start the response body: {}", e);
+            response = HttpResponse::text(500, "Failed to generate the response");
+        }
+
+        // The status line echoes the client's version rather than claiming 1.1
+        if let Some(request) = request {
+            response.version = Some(http::response_version(&request.version));
+        }
+
+        // While draining, keep-alive clients are told to move to a new connection
+        let draining = context.draining.load(Ordering::SeqCst);
+        if draining && reusable {
+            log_info!("Draining for shutdown, closing the connection after this response");
+        }
+        // HEAD gets exactly the GET response's headers, without its body
+        let head_only = request.is_some_and(|request| request.method == Method::Head);
+
+        // Past its byte budget, a connection gets this response and no more.
+        // A streamed body's size may not be known, so only what's known counts.
+        let unknown = response.stream.is_some() && response.content_length.is_none();
+        let body_bytes = if head_only || unknown { 0 } else { response.content_length() };
+        let limit = config.max_connection_bytes.filter(|&limit| self.stream.bytes + body_bytes > limit);
+        if let Some(limit) = limit && reusable {
+            log_info!("Connection is reaching its {} byte limit, closing it after this response", limit);
+        }
+        let over_budget = limit.is_some();
+
+        // Idle connections each hold a worker, so past the cap this one
+        // closes instead of joining them. Busy connections are never cut off.
+        let idle = context.idle_connections.load(Ordering::SeqCst);
+        let idle_full = config.max_keepalive_connections.is_some_and(|max| idle >= max);
+        if idle_full && reusable {
+            log_info!("{} keep-alive connections are idle already, closing this one after the response", idle);
+        }
+
+        // An HTTP/0.9 body ends where the connection does, as does a close-delimited one
+        let ends_with_close = request.is_some_and(is_simple) || response.close_delimited;
+        let reusable = reusable && !draining && !over_budget && !idle_full && !ends_with_close;
+        let disposition = Disposition::decide(request, response.status, reusable, self.requests_served, config);
+        let response = match request_id {
+            Some(id) => response.with_header("X-Request-Id", id),
+            None => response,
+        };
+
+        let status = response.status;
+        // Refused HTTP/0.9 requests get a normal response, which at least explains itself
+        let simple = request.is_some_and(is_simple) && config.simple_requests == SimpleRequestPolicy::Serve;
+        let sent = if simple {
+            self.send_body_only(response)
+        } else {
+            crate::send_response(&mut self.stream, response, disposition, head_only, config)
+        };
+
+        // Write one access log line per request
+        if config.access_log {
+            let entry = AccessLogEntry {
+                timestamp: SystemTime::now(),
+                client_ip: request.and_then(|request| request.client_ip).or(self.peer.map(|addr| addr.ip())),
+                method: request.map(|request| request.method.as_str()),
+                path: request.map(|request| request.path.as_str()),
+                version: request.map(|request| request.version.as_str()),
+                user_agent: request.and_then(|request| request.header("User-Agent")),
+                status,
+                bytes: sent.body_bytes,
+                duration: self.request_started.elapsed(),
+                request_id,
+            };
+            logger::write_access(&entry.format(config.log_format));
+        }
+        if config.stats {
+            context.stats.record(status, sent.body_bytes);
+        }
+
+        disposition != Disposition::Close && sent.complete
+    }
+
+    /// Answers an HTTP/0.9 request the way it expects: the body and nothing
+    /// else, with closing the connection marking its end
+    fn send_body_only(&mut self, mut response: HttpResponse) -> Sent {
+        let mut stream = CountingWriter { inner: &mut self.stream, written: 0 };
+        let result = match response.stream.take() {
+            Some(mut source) => io::copy(&mut source, &mut stream).map(|_| ()),
+            None => stream.write_all(&response.body),
+        };
+        let result = result.and_then(|_| stream.flush());
+        match &result {
+            Ok(()) => {}
+            Err(e) if crate::is_disconnect(e) => log_info!("Client disconnected during the HTTP/0.9 response: {}", e),
+            Err(e) => log_error!("Failed to send HTTP/0.9 response: {}", e),
+        }
+        Sent { body_bytes: stream.written, complete: result.is_ok() }
+    }
+
+    /// Reads from the stream until the buffer holds a complete request head,
+    /// then splits the head off and returns it. Clients may deliver the head in
+    /// any number of pieces; nothing is parsed until the blank line has arrived.
+    ///
+    /// A keep-alive connection waiting for its next request may sit idle for
+    /// `keepalive_timeout`; once a request has started arriving (and for the
+    /// first request on a connection) the rest of it must come within `request_timeout`.
+    fn read_head(&mut self) -> ReadOutcome {
+        let context = Arc::clone(&self.context);
+        let config = &context.config;
+        let first_request = self.requests_served == 0;
+        let buffer = &mut self.buffer;
+        let mut idle = !first_request && buffer.is_empty();
+        // Counted among the idle connections until the next request starts arriving
+        let mut idle_guard = idle.then(|| ConnectionGuard::new(&context.idle_connections));
+        let timeout = if idle { config.keepalive_timeout } else { config.request_timeout };
+        self.stream.apply_read_timeout(timeout);
+        let mut chunk = [0; 1024];
+        // Bytes already searched for the terminator, so a client trickling in
+        // one byte at a time doesn't make us rescan the whole buffer each read
+        let mut scanned = 0;
+        // The request timeout counts from the request's first byte, however
+        // steadily the rest trickles in, so one slow client can't hold a worker
+        let mut deadline = (!buffer.is_empty()).then(|| Instant::now() + config.request_timeout);
+
+        loop {
+            if let Some(end) = http::find_simple_request_end(buffer) {
+                let rest = buffer.split_off(end);
+                return ReadOutcome::Head(std::mem::replace(buffer, rest));
+            }
+            if let Some(end) = http::find_head_end(&buffer[scanned..]) {
+                let rest = buffer.split_off(scanned + end);
+                return ReadOutcome::Head(std::mem::replace(buffer, rest));
+            }
+            // The blank line (`\n\n` or `\n\r\n`) may straddle two reads, so keep its start in view
+            scanned = buffer.len().saturating_sub(3);
+            if buffer.len() > MAX_HEAD_BYTES {
+                return ReadOutcome::TooLarge;
+            }
+
+            if let Some(deadline) = deadline {
+                let left = deadline.saturating_duration_since(Instant::now());
+                if left.is_zero() {
+                    log_info!("Request head not complete within {:?}, sending 408", config.request_timeout);
+                    return ReadOutcome::TimedOut;
+                }
+                self.stream.apply_read_timeout(left);
+            }
+
+            match self.stream.read(&mut chunk) {
+                Ok(0) => {
+                    // The client closed the connection. Before any request data this is
+                    // normal: health checkers and browsers do it all the time.
+                    if buffer.is_empty() && first_request {
+                        log_info!("Client closed connection before sending a request");
+                    } else if !buffer.is_empty() {
+                        log_info!("Client closed connection in the middle of a request");
+                    }
+                    return ReadOutcome::Closed;
+                },
+                Ok(size) => {
+                    log_info!("Received {} bytes", size);
+                    buffer.extend_from_slice(&chunk[..size]);
+                    // An https:// URL pointed at this plaintext port. Any HTTP answer
+                    // would only surface as a cryptic TLS error, so just hang up.
+                    if first_request && tls_port::classify(buffer) == tls_port::Protocol::Tls {
+                        log_info!("Client began a TLS handshake on this plaintext port, closing");
+                        return ReadOutcome::Closed;
+                    }
+                    // The request has started; the rest of it has to arrive within the request timeout
+                    if idle {
+                        idle = false;
+                        drop(idle_guard.take());
+                    }
+                    deadline.get_or_insert_with(|| Instant::now() + config.request_timeout);
+                },
+                Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+                Err(e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => {
+                    // With nothing received there's no request to answer: it was an
+                    // idle probe or a keep-alive connection that had nothing more to ask
+                    if idle {
+                        log_info!("Keep-alive connection idle for too long, closing");
+                    } else if buffer.is_empty() {
+                        log_info!("Connection timed out waiting for a request, closing");
+                    } else {
+                        log_info!("Connection timed out in the middle of a request, sending 408");
+                        return ReadOutcome::TimedOut;
+                    }
+                    return ReadOutcome::Closed;
+                },
+                Err(e) if crate::is_disconnect(&e) => {
+                    log_info!("Client disconnected while reading: {}", e);
+                    return ReadOutcome::Closed;
+                },
+                Err(e) => {
+                    log_error!("Failed to read from connection: {}", e);
+                    return ReadOutcome::Closed;
+                }
+            }
+        }
+    }
+}
+
+/// Whether `request` is an HTTP/0.9 simple request
+fn is_simple(request: &HttpRequest) -> bool {
+    request.version == http::SIMPLE_REQUEST_VERSION
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::TcpStream;
+    use std::{fs, thread};
+
+    use super::*;
+    use crate::testing::{self, Response, TempDir, TestServer, read_response, read_to_close};
+
+    fn server_with_file() -> (TempDir, TestServer) {
+        let root = TempDir::new();
+        root.write("x.txt", "A");
+        let server = TestServer::with_args(&["--root", root.path().to_str().unwrap()]);
+        (root, server)
+    }
+
+    #[test]
+    fn request_split_across_reads_is_parsed_once() {
+        let (_root, server) = server_with_file();
+        let mut stream = server.connect();
+        stream.write_all(b"GET /x.txt HTTP/1.1\r\n").unwrap();
+        thread::sleep(Duration::from_millis(100));
+        stream.write_all(b"Host: a\r\nConnection: close\r\n\r\n").unwrap();
+
+        let received = read_to_close(&mut stream);
+        let response = Response::parse(&received);
+        assert_eq!(response.status, 200);
+        assert_eq!(response.body, b"A");
+        // One request, so exactly one response
+        assert_eq!(received.windows(9).filter(|w| w == b"HTTP/1.1 ").count(), 1);
+    }
+
+    #[test]
+    fn terminator_split_between_reads_is_found() {
+        let (_root, server) = server_with_file();
+        let mut stream = server.connect();
+        // The blank line arrives over two reads, as `\r\n\r` and `\n`
+        stream.write_all(b"GET /x.txt HTTP/1.1\r\nHost: a\r\nConnection: close\r\n\r").unwrap();
+        thread::sleep(Duration::from_millis(100));
+        stream.write_all(b"\n").unwrap();
+        assert_eq!(Response::parse(&read_to_close(&mut stream)).status, 200);
+    }
+
+    #[test]
+    fn interim_100_is_neither_logged_nor_counted() {
+        let root = TempDir::new();
+        let logs = TempDir::new();
+        let log_file = logs.path().join("access.log");
+        let root = root.path().to_str().unwrap();
+        let log_file_arg = log_file.to_str().unwrap();
+        let mut config = testing::config(&["--root", root, "--writable", root, "--log-file", log_file_arg]);
+        config.access_log = true;
+        let server = TestServer::start(config);
+
+        let mut stream = server.connect();
+        let head = "PUT /continued.txt HTTP/1.1\r\nHost: a\r\nConnection: close\r\n\
+                    Expect: 100-continue\r\nContent-Length: 5\r\n\r\n";
+        stream.write_all(head.as_bytes()).unwrap();
+        let mut interim = [0; 25];
+        stream.read_exact(&mut interim).unwrap();
+        assert_eq!(&interim, b"HTTP/1.1 100 Continue\r\n\r\n");
+        stream.write_all(b"hello").unwrap();
+        assert_eq!(Response::parse(&read_to_close(&mut stream)).status, 201);
+
+        let status = String::from_utf8(server.get("/__status").body).unwrap();
+        assert!(status.contains("\"201\":1"), "{}", status);
+        assert!(!status.contains("\"100\""), "{}", status);
+        drop(server);
+
+        // Other tests may log to the same file meanwhile, so only this request's line counts
+        let log = fs::read_to_string(&log_file).unwrap();
+        let lines: Vec<&str> = log.lines().filter(|line| line.contains("PUT /continued.txt")).collect();
+        assert_eq!(lines.len(), 1, "{}", log);
+        assert!(lines[0].contains("\" 201 "), "{}", lines[0]);
+    }
+
+    #[test]
+    fn versionless_requests_follow_the_http09_policy() {
+        let root = TempDir::new();
+        root.write("x.txt", "A");
+        let root = root.path().to_str().unwrap();
+
+        let rejected = TestServer::with_args(&["--root", root]).send(b"GET /x.txt\r\n");
+        assert_eq!(rejected.status, 400);
+        let unsupported = TestServer::with_args(&["--root", root, "--http09", "505"]).send(b"GET /x.txt\r\n");
+        assert_eq!(unsupported.status, 505);
+        // Served the HTTP/0.9 way: the body alone, ended by closing the connection
+        let served = TestServer::with_args(&["--root", root, "--http09", "serve"]).exchange(b"GET /x.txt\r\n");
+        assert_eq!(served, b"A");
+    }
+
+    #[test]
+    fn unread_body_on_get_is_drained_before_the_next_request() {
+        let (_root, server) = server_with_file();
+        let mut stream = server.connect();
+        // The body looks like a request of its own, which must never be answered
+        let smuggled = "GET /nope HTTP/1.1\r\nHost: a\r\n\r\n";
+        let first = format!("GET /x.txt HTTP/1.1\r\nHost: a\r\nContent-Length: {}\r\n\r\n", smuggled.len());
+        let first = first + smuggled;
+        stream.write_all(first.as_bytes()).unwrap();
+        stream.write_all(b"HEAD /x.txt HTTP/1.1\r\nHost: a\r\nContent-Length: 3\r\n\r\nabc").unwrap();
+        stream.write_all(b"GET /x.txt HTTP/1.1\r\nHost: a\r\nConnection: close\r\n\r\n").unwrap();
+
+        let received = read_to_close(&mut stream);
+        let status_lines = received.windows(12).filter(|window| window.starts_with(b"HTTP/1.1 "));
+        let statuses: Vec<&[u8]> = status_lines.map(|line| &line[9..]).collect();
+        assert_eq!(statuses, [b"200", b"200", b"200"]);
+    }
+
+    #[test]
+    fn past_the_idle_cap_connections_close_after_responding() {
+        let root = TempDir::new();
+        root.write("x.txt", "A");
+        let root = root.path().to_str().unwrap();
+        let server = TestServer::with_args(&["--root", root, "--max-keepalive-connections", "1"]);
+        let request = b"GET /x.txt HTTP/1.1\r\nHost: a\r\n\r\n";
+
+        let mut idle = server.connect();
+        idle.write_all(request).unwrap();
+        assert_eq!(read_response(&mut idle).header("Connection"), Some("keep-alive"));
+        thread::sleep(Duration::from_millis(100));
+
+        // The one idle slot is taken, so this connection is served and then closed
+        let mut second = server.connect();
+        second.write_all(request).unwrap();
+        let response = Response::parse(&read_to_close(&mut second));
+        assert_eq!(response.status, 200);
+        assert_eq!(response.header("Connection"), Some("close"));
+
+        // The idle connection itself is still usable
+        idle.write_all(request).unwrap();
+        assert_eq!(read_response(&mut idle).status, 200);
+    }
+
+    #[test]
+    fn body_over_the_limit_on_get_closes_the_connection() {
+        let root = TempDir::new();
+        root.write("x.txt", "A");
+        let server = TestServer::with_args(&["--root", root.path().to_str().unwrap(), "--max-body", "16"]);
+        let mut stream = server.connect();
+        let body = "x".repeat(64);
+        let request = format!("GET /x.txt HTTP/1.1\r\nHost: a\r\nContent-Length: 64\r\n\r\n{}", body);
+        stream.write_all(request.as_bytes()).unwrap();
+        let response = Response::parse(&read_to_close(&mut stream));
+        assert_eq!(response.header("Connection"), Some("close"));
+    }
+
+    #[test]
+    fn a_head_that_stalls_partway_gets_408_and_an_idle_one_just_closes() {
+        let root = TempDir::new();
+        root.write("x.txt", "A");
+        let server = TestServer::with_args(&[
+            "--root", root.path().to_str().unwrap(),
+            "--request-timeout", "1",
+            "--keepalive-timeout", "1",
+        ]);
+
+        let mut stalled = server.connect();
+        stalled.write_all(b"GET /x.txt HTTP/1.1\r\nHost: a\r\n").unwrap();
+        let started = Instant::now();
+        let response = Response::parse(&read_to_close(&mut stalled));
+        assert_eq!(response.status, 408);
+        assert_eq!(response.header("Connection"), Some("close"));
+        assert!(started.elapsed() < Duration::from_secs(5), "took {:?}", started.elapsed());
+
+        // Nor does a head that arrives partly, then keeps trickling, get to go on for ever
+        let mut trickled = server.connect();
+        trickled.write_all(b"GET /x.txt HTTP/1.1\r\n").unwrap();
+        let started = Instant::now();
+        let rest = b"Host: a\r\nX-Padding: aaaaaaaaaaaaaaaaaaaa\r\n\r\n";
+        let writer = trickle(trickled.try_clone().unwrap(), rest, Duration::from_millis(200));
+        let response = Response::parse(&read_to_close(&mut trickled));
+        assert_eq!((response.status, response.header("Connection")), (408, Some("close")));
+        assert!(started.elapsed() < Duration::from_secs(3), "took {:?}", started.elapsed());
+        writer.join().unwrap();
+
+        // Nothing sent at all is a probe, not a request to answer
+        let mut idle = server.connect();
+        assert!(read_to_close(&mut idle).is_empty());
+
+        // Nor is a kept-alive connection with nothing more to ask
+        let mut kept = server.connect();
+        kept.write_all(b"GET /x.txt HTTP/1.1\r\nHost: a\r\n\r\n").unwrap();
+        assert_eq!(read_response(&mut kept).status, 200);
+        assert!(read_to_close(&mut kept).is_empty());
+    }
+
+    /// Sends `head` a byte at a time, `gap` apart, until it's all out or the server hangs up
+    fn trickle(mut stream: TcpStream, head: &'static [u8], gap: Duration) -> thread::JoinHandle<()> {
+        thread::spawn(move || {
+            for byte in head {
+                if stream.write_all(&[*byte]).is_err() {
+                    return;
+                }
+                thread::sleep(gap);
+            }
+        })
+    }
+
+    #[test]
+    fn a_trickled_head_runs_out_of_time_however_steady_it_is() {
+        let root = TempDir::new();
+        root.write("x.txt", "A");
+        let server = TestServer::with_args(&["--root", root.path().to_str().unwrap(), "--request-timeout", "1"]);
+        let mut stream = server.connect();
+        let head = b"GET /x.txt HTTP/1.1\r\nHost: a\r\nUser-Agent: slow\r\nConnection: close\r\n\r\n";
+        let started = Instant::now();
+        // Each byte arrives well inside the timeout, but the head as a whole doesn't
+        let writer = trickle(stream.try_clone().unwrap(), head, Duration::from_millis(200));
+        let response = Response::parse(&read_to_close(&mut stream));
+        let elapsed = started.elapsed();
+        assert_eq!(response.status, 408);
+        assert!(elapsed >= Duration::from_millis(900) && elapsed < Duration::from_secs(3), "took {:?}", elapsed);
+        writer.join().unwrap();
+    }
+}
@@ -0,0 +1,199 @@
+use crate::http::{HttpRequest, HttpResponse, Method, StatusCode};
+
+/// Methods a CORS rule allows when it doesn't list any
+const DEFAULT_METHODS: &[Method] = &[Method::Get, Method::Head];
+
+/// Cross-origin settings, from `--cors` for the whole server or
+/// `--cors-route` for one route. Fields a route rule leaves out are taken
+/// from the server-wide rule.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CorsRule {
+    /// Origins allowed to read responses; `*` allows any
+    origins: Option<Vec<String>>,
+    /// Methods a preflight may be approved for
+    methods: Option<Vec<Method>>,
+    /// Request headers a preflight may be approved for
+    headers: Option<Vec<String>>,
+    /// How long a browser may cache a preflight answer, in seconds
+    max_age: Option<u64>,
+}
+
+impl CorsRule {
+    /// Parses `;`-separated `key=value` fields: `origin`, `methods`, `headers`
+    /// and `max-age`, the first three being comma-separated lists, e.g.
+    /// `origin=https://app.example;methods=GET,POST;max-age=600`. A field
+    /// without `=` is an origin list, so `*` alone allows any origin.
+    pub fn parse(value: &str) -> Option<CorsRule> {
+        let mut rule = CorsRule::default();
+        for field in value.split(';').map(str::trim).filter(|field| !field.is_empty()) {
+            let (key, list) = field.split_once('=').unwrap_or(("origin", field));
+            let items = || list.split(',').map(str::trim).filter(|item| !item.is_empty());
+            match key.trim() {
+                "origin" => rule.origins = Some(items().map(str::to_string).collect()),
+                "methods" => {
+                    let mut methods = Vec::new();
+                    for token in items() {
+                        if !token.bytes().all(|byte| byte.is_ascii_alphabetic()) {
+                            return None;
+                        }
+                        let method = Method::parse(&token.to_ascii_uppercase());
+                        if !methods.contains(&method) {
+                            methods.push(method);
+                        }
+                    }
+                    rule.methods = Some(methods);
+                }
+                "headers" => rule.headers = Some(items().map(str::to_string).collect()),
+                "max-age" => rule.max_age = Some(list.trim().parse().ok()?),
+                _ => return None,
+            }
+        }
+        Some(rule)
+    }
+
+    /// This rule with the fields it leaves out taken from `base`
+    pub fn over(&self, base: Option<&CorsRule>) -> CorsRule {
+        let Some(base) = base else {
+            return self.clone();
+        };
+        CorsRule {
+            origins: self.origins.clone().or_else(|| base.origins.clone()),
+            methods: self.methods.clone().or_else(|| base.methods.clone()),
+            headers: self.headers.clone().or_else(|| base.headers.clone()),
+            max_age: self.max_age.or(base.max_age),
+        }
+    }
+
+    /// The `Access-Control-Allow-Origin` value for `origin`, if it's allowed
+    fn allow_origin(&self, origin: &str) -> Option<String> {
+        let origins = self.origins.as_deref().unwrap_or_default();
+        if origins.is_empty() || origins.iter().any(|allowed| allowed == "*") {
+            return Some("*".to_string());
+        }
+        origins.iter().any(|allowed| allowed.eq_ignore_ascii_case(origin)).then(|| origin.to_string())
+    }
+
+    fn methods(&self) -> &[Method] {
+        self.methods.as_deref().filter(|methods| !methods.is_empty()).unwrap_or(DEFAULT_METHODS)
+    }
+}
+
+/// Whether `request` is a CORS preflight: an `OPTIONS` from a browser asking
+/// whether it may send the real request
+pub fn is_preflight(request: &HttpRequest) -> bool {
+    request.method == Method::Options
+        && request.header("Origin").is_some()
+        && request.header("Access-Control-Request-Method").is_some()
+}
+
+/// Answers a preflight under `rule`. A request the rule doesn't allow still
+/// gets a 204, just without the `Access-Control-Allow-*` headers, which is
+/// how browsers are told no.
+pub fn preflight(request: &HttpRequest, rule: &CorsRule) -> HttpResponse {
+    let response = with_vary_origin(HttpResponse::new(StatusCode::NO_CONTENT), rule);
+    let origin = request.header("Origin").unwrap_or_default();
+    let Some(allow_origin) = rule.allow_origin(origin) else {
+        log_info!("CORS preflight from {} refused: origin not allowed", origin);
+        return response;
+    };
+    let requested = Method::parse(request.header("Access-Control-Request-Method").unwrap_or_default().trim());
+    if !rule.methods().contains(&requested) {
+        log_info!("CORS preflight from {} refused: {} not allowed", origin, requested);
+        return response;
+    }
+
+    let methods: Vec<&str> = rule.methods().iter().map(Method::as_str).collect();
+    let mut response = response
+        .with_header("Access-Control-Allow-Origin", &allow_origin)
+        .with_header("Access-Control-Allow-Methods", &methods.join(", "));
+    if let Some(headers) = rule.headers.as_ref().filter(|headers| !headers.is_empty()) {
+        response = response.with_header("Access-Control-Allow-Headers", &headers.join(", "));
+    }
+    if let Some(max_age) = rule.max_age {
+        response = response.with_header("Access-Control-Max-Age", &max_age.to_string());
+    }
+    response
+}
+
+/// Lets the requesting origin read `response`, if `rule` allows it
+pub fn apply(request: &HttpRequest, response: HttpResponse, rule: &CorsRule) -> HttpResponse {
+    let Some(origin) = request.header("Origin") else {
+        return response;
+    };
+    let response = with_vary_origin(response, rule);
+    match rule.allow_origin(origin) {
+        Some(allow_origin) => response.with_header("Access-Control-Allow-Origin", &allow_origin),
+        None => response,
+    }
+}
+
+/// Adds `Vary: Origin` when the answer depends on the origin, which it does
+/// unless every origin is allowed
+fn with_vary_origin(response: HttpResponse, rule: &CorsRule) -> HttpResponse {
+    if rule.allow_origin("").is_some_and(|allow_origin| allow_origin == "*") {
+        response
+    } else {
+        response.with_header("Vary", "Origin")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{TempDir, TestServer};
+
+    #[test]
+    fn route_rules_fill_their_gaps_from_the_global_rule() {
+        let global = CorsRule::parse("origin=https://app.example;max-age=60").unwrap();
+        let route = CorsRule::parse("methods=get,PUT;headers=Authorization").unwrap().over(Some(&global));
+        assert_eq!(route.methods(), &[Method::Get, Method::Put]);
+        assert_eq!(route.allow_origin("https://APP.example").as_deref(), Some("https://APP.example"));
+        assert_eq!(route.allow_origin("https://evil.example"), None);
+        assert_eq!(route.max_age, Some(60));
+        assert_eq!(CorsRule::parse("*").unwrap().allow_origin("https://any.example").as_deref(), Some("*"));
+        assert_eq!(CorsRule::parse("*").unwrap().methods(), DEFAULT_METHODS);
+        for invalid in ["methods=GET,P-UT", "max-age=soon", "colour=blue"] {
+            assert_eq!(CorsRule::parse(invalid), None, "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn each_route_answers_preflights_with_its_own_methods() {
+        let root = TempDir::new();
+        let server = TestServer::with_args(&[
+            "--root",
+            root.path().to_str().unwrap(),
+            "--cors",
+            "*;max-age=600",
+            "--cors-route",
+            "/api/users/*;methods=GET,POST",
+            "--cors-route",
+            "/api/admin/*;methods=GET,DELETE;headers=Authorization",
+        ]);
+        let preflight = |path: &str, method: &str| {
+            let headers = [("Origin", "https://app.example"), ("Access-Control-Request-Method", method)];
+            server.request("OPTIONS", path, &headers)
+        };
+
+        let users = preflight("/api/users/7", "POST");
+        assert_eq!(users.status, 204);
+        assert_eq!(users.header("Access-Control-Allow-Methods"), Some("GET, POST"));
+        assert_eq!(users.header("Access-Control-Allow-Headers"), None);
+        assert_eq!(users.header("Access-Control-Max-Age"), Some("600"));
+
+        let admin = preflight("/api/admin/7", "DELETE");
+        assert_eq!(admin.header("Access-Control-Allow-Methods"), Some("GET, DELETE"));
+        assert_eq!(admin.header("Access-Control-Allow-Headers"), Some("Authorization"));
+
+        // A method only the other route allows is refused
+        let refused = preflight("/api/users/7", "DELETE");
+        assert_eq!(refused.status, 204);
+        assert_eq!(refused.header("Access-Control-Allow-Methods"), None);
+        assert_eq!(refused.header("Access-Control-Allow-Origin"), None);
+
+        // Unlisted paths fall back to --cors alone
+        let elsewhere = preflight("/index.html", "GET");
+        assert_eq!(elsewhere.header("Access-Control-Allow-Methods"), Some("GET, HEAD"));
+        assert_eq!(elsewhere.header("Access-Control-Allow-Origin"), Some("*"));
+    }
+}
@@ -0,0 +1,30 @@
+use std::fmt;
+use std::io;
+
+/// An internal failure behind a 500 response. Its text is always logged, but
+/// only sent to the client with `--verbose-errors`, since it can name paths
+/// and system details.
+#[derive(Debug)]
+pub struct ServerError {
+    /// What the server was doing, e.g. `writing public/a.txt`
+    action: String,
+    source: io::Error,
+}
+
+impl ServerError {
+    pub fn io(action: impl Into<String>, source: io::Error) -> Self {
+        ServerError { action: action.into(), source }
+    }
+}
+
+impl fmt::Display for ServerError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.action, self.source)
+    }
+}
+
+impl std::error::Error for ServerError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        Some(&self.source)
+    }
+}
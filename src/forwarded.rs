@@ -0,0 +1,153 @@
+use std::net::IpAddr;
+
+use crate::http::HttpRequest;
+
+/// What a proxy in front of us reports about the client
+#[derive(Debug, Default, PartialEq, Eq)]
+struct Forwarded {
+    client_ip: Option<IpAddr>,
+    /// Lowercase scheme the client used, e.g. `https`
+    proto: Option<String>,
+}
+
+/// Fills in `request.client_ip` and `request.scheme`. They come from the
+/// connection, unless `trust_proxy` says the peer is a proxy whose headers
+/// can be believed, in which case whatever those headers report wins.
+pub fn identify_client(request: &mut HttpRequest, peer: Option<IpAddr>, trust_proxy: bool) {
+    request.client_ip = peer;
+    if !trust_proxy {
+        return;
+    }
+    let forwarded = from_headers(request);
+    if forwarded == Forwarded::default() {
+        return;
+    }
+    if let Some(ip) = forwarded.client_ip {
+        request.client_ip = Some(ip);
+    }
+    if let Some(proto) = forwarded.proto {
+        request.scheme = proto;
+    }
+    let client = request.client_ip.map_or("an unknown address".to_string(), |ip| ip.to_string());
+    log_info!("The proxy reports a client at {} using {}", client, request.scheme);
+}
+
+/// Reads the proxy headers. `Forwarded` (RFC 7239) wins whenever it's there;
+/// only without it are `X-Forwarded-For` and `X-Forwarded-Proto` consulted.
+///
+/// Each proxy appends its own entry, and only the last one was written by the
+/// proxy we trust, so that's the one used: anything before it came from the
+/// client or proxies we know nothing about.
+fn from_headers(request: &HttpRequest) -> Forwarded {
+    if let Some(value) = request.header("Forwarded") {
+        return parse_forwarded(value);
+    }
+    let last = |name| request.header(name).and_then(|value: &str| value.rsplit(',').next()).map(str::trim);
+    Forwarded {
+        client_ip: last("X-Forwarded-For").and_then(parse_node),
+        proto: last("X-Forwarded-Proto").and_then(parse_proto),
+    }
+}
+
+/// Parses the last element of a `Forwarded` value such as
+/// `for=192.0.2.60;proto=http, for="[2001:db8:cafe::17]:4711";proto=https`
+fn parse_forwarded(value: &str) -> Forwarded {
+    let mut forwarded = Forwarded::default();
+    let element = value.rsplit(',').next().unwrap_or_default();
+    for pair in element.split(';') {
+        let Some((key, value)) = pair.split_once('=') else {
+            continue;
+        };
+        let value = value.trim();
+        let value = value.strip_prefix('"').and_then(|value| value.strip_suffix('"')).unwrap_or(value);
+        match key.trim().to_ascii_lowercase().as_str() {
+            "for" => forwarded.client_ip = parse_node(value),
+            "proto" => forwarded.proto = parse_proto(value),
+            _ => {}
+        }
+    }
+    forwarded
+}
+
+/// An address with an optional port: `192.0.2.43`, `192.0.2.43:47011`,
+/// `[2001:db8::1]` or `[2001:db8::1]:4711`. Obfuscated identifiers such as
+/// `unknown` or `_hidden` name no address.
+fn parse_node(node: &str) -> Option<IpAddr> {
+    if let Some(bracketed) = node.strip_prefix('[') {
+        let (ip, _port) = bracketed.split_once(']')?;
+        return ip.parse().ok();
+    }
+    // X-Forwarded-For sometimes carries a bare IPv6 address
+    if let Ok(ip) = node.parse() {
+        return Some(ip);
+    }
+    let (ip, _port) = node.split_once(':')?;
+    ip.parse().ok()
+}
+
+fn parse_proto(proto: &str) -> Option<String> {
+    let proto = proto.trim().to_ascii_lowercase();
+    let valid = proto.starts_with(|c: char| c.is_ascii_alphabetic())
+        && proto.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '+' | '-' | '.'));
+    valid.then_some(proto)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::http;
+    use crate::testing::{TempDir, TestServer};
+
+    fn request(headers: &str) -> HttpRequest {
+        let head = format!("GET / HTTP/1.1\r\nHost: example.com\r\n{}\r\n", headers);
+        http::parse_request(head.as_bytes(), Default::default()).expect("test request should parse")
+    }
+
+    #[test]
+    fn the_last_forwarded_element_names_the_client() {
+        let forwarded = parse_forwarded(r#"for=192.0.2.60;proto=http, For="[2001:db8:cafe::17]:4711";Proto=HTTPS"#);
+        assert_eq!(forwarded.client_ip, Some("2001:db8:cafe::17".parse().unwrap()));
+        assert_eq!(forwarded.proto.as_deref(), Some("https"));
+
+        let forwarded = parse_forwarded("for=198.51.100.17:8080;host=example.com;by=203.0.113.43");
+        assert_eq!(forwarded.client_ip, Some("198.51.100.17".parse().unwrap()));
+        assert_eq!(forwarded.proto, None);
+        for hidden in ["for=unknown", "for=_hidden, for=\"_proxy\"", "proto=\"h t t p\""] {
+            assert_eq!(parse_forwarded(hidden), Forwarded::default(), "{}", hidden);
+        }
+    }
+
+    #[test]
+    fn forwarded_wins_over_x_forwarded_and_only_when_trusted() {
+        let peer = Some("127.0.0.1".parse().unwrap());
+        let headers = "Forwarded: for=\"[2001:db8::1]\";proto=https\r\n\
+                       X-Forwarded-For: 203.0.113.9, 192.0.2.1\r\nX-Forwarded-Proto: http\r\n";
+        let mut trusted = request(headers);
+        identify_client(&mut trusted, peer, true);
+        assert_eq!(trusted.client_ip, Some("2001:db8::1".parse().unwrap()));
+        assert_eq!(trusted.scheme, "https");
+
+        let mut legacy = request("X-Forwarded-For: 203.0.113.9, 192.0.2.1\r\nX-Forwarded-Proto: https\r\n");
+        identify_client(&mut legacy, peer, true);
+        assert_eq!(legacy.client_ip, Some("192.0.2.1".parse().unwrap()));
+        assert_eq!(legacy.scheme, "https");
+
+        let mut untrusted = request(headers);
+        identify_client(&mut untrusted, peer, false);
+        assert_eq!((untrusted.client_ip, untrusted.scheme.as_str()), (peer, "http"));
+    }
+
+    #[test]
+    fn the_forwarded_scheme_decides_https_redirects() {
+        let root = TempDir::new();
+        root.write("index.html", "home");
+        let root = root.path().to_str().unwrap();
+        let server = TestServer::with_args(&["--root", root, "--redirect-https", "--trust-proxy"]);
+        let secure = server.request("GET", "/index.html", &[("Forwarded", "for=\"[2001:db8::1]:4711\";proto=https")]);
+        assert_eq!((secure.status, secure.body), (200, b"home".to_vec()));
+
+        let plain = server.request("GET", "/index.html", &[("Forwarded", "for=192.0.2.1;proto=http")]);
+        assert_eq!(plain.status, 301);
+        assert_eq!(plain.header("Location"), Some("https://test/index.html"));
+    }
+}
@@ -0,0 +1,1022 @@
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::fmt;
+use std::io::Read;
+use std::net::IpAddr;
+
+/// HTTP request methods the server knows about
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Method {
+    Get,
+    Head,
+    Post,
+    Put,
+    Delete,
+    Options,
+    Patch,
+    /// Any other token, kept verbatim so we can report it
+    Other(String),
+}
+
+impl Method {
+    /// Converts a request-line method token into a `Method`
+    pub fn parse(token: &str) -> Method {
+        match token {
+            "GET" => Method::Get,
+            "HEAD" => Method::Head,
+            "POST" => Method::Post,
+            "PUT" => Method::Put,
+            "DELETE" => Method::Delete,
+            "OPTIONS" => Method::Options,
+            "PATCH" => Method::Patch,
+            other => Method::Other(other.to_string()),
+        }
+    }
+
+    /// Returns the method as it appears on the wire
+    pub fn as_str(&self) -> &str {
+        match self {
+            Method::Get => "GET",
+            Method::Head => "HEAD",
+            Method::Post => "POST",
+            Method::Put => "PUT",
+            Method::Delete => "DELETE",
+            Method::Options => "OPTIONS",
+            Method::Patch => "PATCH",
+            Method::Other(token) => token,
+        }
+    }
+}
+
+impl fmt::Display for Method {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// A parsed HTTP request head (request line plus headers)
+#[derive(Debug, Clone)]
+pub struct HttpRequest {
+    pub method: Method,
+    /// The percent-decoded path component of the target
+    pub path: String,
+    /// The raw query string, without the leading `?`
+    pub query: Option<String>,
+    pub version: String,
+    /// Header names are stored lowercased; repeated headers are joined with `, `
+    pub headers: HashMap<String, String>,
+    /// Identifier used to correlate this request's log lines; echoed as `X-Request-Id`
+    pub request_id: Option<String>,
+    /// The client's address: the connection's peer, or what a trusted proxy reports
+    pub client_ip: Option<IpAddr>,
+    /// `http` unless a trusted proxy says the client used something else
+    pub scheme: String,
+}
+
+impl HttpRequest {
+    /// Looks up a header value by (case-insensitive) name
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers.get(&name.to_ascii_lowercase()).map(|value| value.as_str())
+    }
+
+    /// True for `OPTIONS *`, which asks about the server rather than a resource
+    pub fn is_server_wide(&self) -> bool {
+        self.path == "*"
+    }
+
+    /// True when the client speaks HTTP/1.1 or a later 1.x, and so knows
+    /// chunked coding, persistent connections and interim responses
+    pub fn is_http_1_1(&self) -> bool {
+        at_least_1_1(&self.version)
+    }
+}
+
+/// Why a request head couldn't be turned into an `HttpRequest`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ParseError {
+    /// The request line is unusable
+    Malformed,
+    /// The request target is longer than the configured limit
+    TargetTooLong,
+    /// The headers allow more than one reading of where the body ends
+    /// (conflicting `Content-Length`/`Transfer-Encoding`), a request smuggling vector
+    AmbiguousFraming,
+    /// More header lines than the configured limit
+    TooManyHeaders,
+    /// A single header value longer than the configured limit
+    HeaderValueTooLong,
+    /// An HTTP/1.1 (or later) request without the `Host` header it must carry
+    MissingHost,
+    /// A well-formed version the server doesn't speak, such as `HTTP/2.0`
+    UnsupportedVersion,
+}
+
+impl ParseError {
+    /// The status code to answer with
+    pub fn status(self) -> StatusCode {
+        match self {
+            ParseError::Malformed | ParseError::AmbiguousFraming | ParseError::MissingHost => {
+                StatusCode::BAD_REQUEST
+            }
+            ParseError::TargetTooLong => StatusCode::URI_TOO_LONG,
+            ParseError::UnsupportedVersion => StatusCode::HTTP_VERSION_NOT_SUPPORTED,
+            ParseError::TooManyHeaders | ParseError::HeaderValueTooLong => {
+                StatusCode::REQUEST_HEADER_FIELDS_TOO_LARGE
+            }
+        }
+    }
+
+    pub fn message(self) -> &'static str {
+        match self {
+            ParseError::Malformed => "Invalid request format",
+            ParseError::TargetTooLong => "The request target is too long",
+            ParseError::AmbiguousFraming => "Conflicting or invalid Content-Length and Transfer-Encoding headers",
+            ParseError::TooManyHeaders => "Too many header fields",
+            ParseError::HeaderValueTooLong => "A header field value is too long",
+            ParseError::MissingHost => "HTTP/1.1 requests must include a Host header",
+            ParseError::UnsupportedVersion => "Only HTTP/1.x is supported",
+        }
+    }
+}
+
+/// Version given to an HTTP/0.9 simple request, which doesn't name one
+pub const SIMPLE_REQUEST_VERSION: &str = "HTTP/0.9";
+
+/// What to do with an HTTP/0.9 simple request (`GET /path` with no version)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SimpleRequestPolicy {
+    /// Answer `400 Bad Request`
+    Reject,
+    /// Answer `505 HTTP Version Not Supported`
+    Unsupported,
+    /// Send the body alone, with no status line or headers, as HTTP/0.9 did
+    Serve,
+}
+
+impl SimpleRequestPolicy {
+    pub fn parse(value: &str) -> Option<SimpleRequestPolicy> {
+        match value {
+            "reject" => Some(SimpleRequestPolicy::Reject),
+            "505" => Some(SimpleRequestPolicy::Unsupported),
+            "serve" => Some(SimpleRequestPolicy::Serve),
+            _ => None,
+        }
+    }
+}
+
+/// Limits on what a request head may contain, beyond its total size
+#[derive(Debug, Clone, Copy)]
+pub struct ParseLimits {
+    /// Longest request target (path plus query), in bytes
+    pub max_target_len: usize,
+    /// Most header lines
+    pub max_headers: usize,
+    /// Longest value of a single header line, in bytes
+    pub max_header_value: usize,
+}
+
+impl Default for ParseLimits {
+    fn default() -> Self {
+        ParseLimits { max_target_len: 8 * 1024, max_headers: 100, max_header_value: 8 * 1024 }
+    }
+}
+
+/// Parses the request line and headers from the start of a request.
+/// Targets over the length limit are rejected before any decoding, and a
+/// head with too many header lines before any of them is stored. A header
+/// value over its limit is refused before it's stored.
+///
+/// `head` comes straight off the wire, so this has to cope with any bytes at
+/// all: every failure is a `ParseError`, never a panic. Bytes that aren't
+/// UTF-8 are replaced rather than refused, which only ever changes header
+/// values and targets that would then fail to match anything.
+pub fn parse_request(head: &[u8], limits: ParseLimits) -> Result<HttpRequest, ParseError> {
+    let request = String::from_utf8_lossy(head);
+    // `lines` ends lines at LF with or without a CR before it
+    let mut lines = request.lines();
+
+    // The first line is exactly `method SP target SP version`. Anything looser
+    // (a missing target, doubled spaces) would let the version pass for the path.
+    // The one exception is HTTP/0.9's `GET target`, which comes without a version
+    // or headers; it is recognized so the caller can apply its policy to it.
+    let request_line = lines.next().unwrap_or("");
+    let parts: Vec<&str> = request_line.split(' ').collect();
+    let (method, target, version) = match *parts.as_slice() {
+        [method, target, version] => (method, target, version),
+        ["GET", target] if target.starts_with('/') => ("GET", target, SIMPLE_REQUEST_VERSION),
+        _ => return Err(ParseError::Malformed),
+    };
+    if method.is_empty() {
+        return Err(ParseError::Malformed);
+    }
+    // Only 1.x is spoken, besides the version given to HTTP/0.9 requests above.
+    // Something that isn't a version at all means this isn't HTTP.
+    match version_number(version) {
+        Some((1, _)) => {}
+        Some(_) if parts.len() == 2 => {}
+        Some(_) => return Err(ParseError::UnsupportedVersion),
+        None => return Err(ParseError::Malformed),
+    }
+    if target.len() > limits.max_target_len {
+        return Err(ParseError::TargetTooLong);
+    }
+    let method = Method::parse(method);
+    // The asterisk form (`OPTIONS * HTTP/1.1`) addresses the whole server,
+    // and is only defined for OPTIONS
+    if target == "*" && method != Method::Options {
+        return Err(ParseError::Malformed);
+    }
+    let target = origin_form(target).ok_or(ParseError::Malformed)?;
+
+    let (raw_path, query) = match target.split_once('?') {
+        Some((path, query)) => (path, Some(query.to_string())),
+        None => (target.as_ref(), None),
+    };
+    let path = percent_decode(raw_path).ok_or(ParseError::Malformed)?;
+
+    // Every following line up to the blank line is a `Name: value` header.
+    // Count them first: thousands of tiny headers fit in the size limit but
+    // would make every request expensive to look at.
+    let lines: Vec<&str> = lines.take_while(|line| !line.is_empty()).collect();
+    if lines.len() > limits.max_headers {
+        return Err(ParseError::TooManyHeaders);
+    }
+    let mut headers: HashMap<String, String> = HashMap::new();
+    for line in lines {
+        // Folded continuation lines are obsolete, and intermediaries disagree on them
+        if line.starts_with([' ', '\t']) {
+            return Err(ParseError::Malformed);
+        }
+        // A line without a colon isn't a header, and dropping it would leave
+        // us reading the request differently from whoever passed it on
+        let Some((name, value)) = line.split_once(':') else {
+            return Err(ParseError::Malformed);
+        };
+        // Whitespace before the colon is forbidden for the same reason
+        if name.is_empty() || name.ends_with([' ', '\t']) {
+            return Err(ParseError::Malformed);
+        }
+        let value = value.trim();
+        // One huge value (a crafted Cookie, say) could take the whole head budget
+        if value.len() > limits.max_header_value {
+            return Err(ParseError::HeaderValueTooLong);
+        }
+        let name = name.to_ascii_lowercase();
+        headers
+            .entry(name)
+            .and_modify(|existing| {
+                existing.push_str(", ");
+                existing.push_str(value);
+            })
+            .or_insert_with(|| value.to_string());
+    }
+
+    check_framing(&mut headers)?;
+    if requires_host(version) && !headers.contains_key("host") {
+        return Err(ParseError::MissingHost);
+    }
+
+    Ok(HttpRequest {
+        method,
+        path,
+        query,
+        version: version.to_string(),
+        headers,
+        request_id: None,
+        client_ip: None,
+        scheme: "http".to_string(),
+    })
+}
+
+/// The `(major, minor)` of a version such as `HTTP/1.1`. Each is a single
+/// digit, so `HTTP/01.1` or `HTTP/+1.1` aren't versions.
+fn version_number(version: &str) -> Option<(u32, u32)> {
+    let (major, minor) = version.strip_prefix("HTTP/")?.split_once('.')?;
+    let digit = |part: &str| match part.as_bytes() {
+        &[digit @ b'0'..=b'9'] => Some(u32::from(digit - b'0')),
+        _ => None,
+    };
+    Some((digit(major)?, digit(minor)?))
+}
+
+/// Whether `version` is HTTP/1.1 or later. Versions are compared as
+/// numbers, so an `HTTP/1.2` client gets what an HTTP/1.1 one would.
+fn at_least_1_1(version: &str) -> bool {
+    version_number(version).is_some_and(|number| number >= (1, 1))
+}
+
+/// Whether `version` must name the host. HTTP/1.0 predates the header, and
+/// HTTP/0.9 has no headers at all.
+fn requires_host(version: &str) -> bool {
+    at_least_1_1(version)
+}
+
+/// The version to put in the status line of a response to a request made
+/// with `request_version`: the client's own, so a strict HTTP/1.0 client or
+/// proxy isn't told about a version it didn't ask for, but never more than
+/// the HTTP/1.1 the server speaks. HTTP/0.9 has no status line, so a
+/// refusal that needs one gets the lowest there is.
+pub fn response_version(request_version: &str) -> &'static str {
+    if at_least_1_1(request_version) { "HTTP/1.1" } else { "HTTP/1.0" }
+}
+
+/// The path-and-query part of a request target. Origin-form (`/path?query`)
+/// and `*` are returned as they are; the absolute form a proxy would send
+/// (`http://host/path`) loses its scheme and authority. Anything else is invalid.
+fn origin_form(target: &str) -> Option<Cow<'_, str>> {
+    if target.starts_with('/') || target == "*" {
+        return Some(Cow::Borrowed(target));
+    }
+    let (scheme, rest) = target.split_once("://")?;
+    if !scheme.eq_ignore_ascii_case("http") && !scheme.eq_ignore_ascii_case("https") {
+        return None;
+    }
+    let (authority, path) = match rest.find(['/', '?']) {
+        Some(index) => rest.split_at(index),
+        None => (rest, ""),
+    };
+    if authority.is_empty() {
+        return None;
+    }
+    // `http://host` and `http://host?query` address the root
+    if path.starts_with('/') { Some(Cow::Borrowed(path)) } else { Some(Cow::Owned(format!("/{}", path))) }
+}
+
+/// Rejects requests whose body length could be read more than one way, so
+/// a proxy in front of us can't be made to disagree about where the next
+/// request starts. Repeated identical `Content-Length` values collapse to one.
+fn check_framing(headers: &mut HashMap<String, String>) -> Result<(), ParseError> {
+    let Some(content_length) = headers.get("content-length") else {
+        return Ok(());
+    };
+    if headers.contains_key("transfer-encoding") {
+        return Err(ParseError::AmbiguousFraming);
+    }
+
+    let mut values = content_length.split(',').map(str::trim);
+    let first = values.next().unwrap_or("");
+    let valid = !first.is_empty() && first.bytes().all(|byte| byte.is_ascii_digit());
+    if !valid || values.any(|value| value != first) {
+        return Err(ParseError::AmbiguousFraming);
+    }
+    let first = first.to_string();
+    headers.insert("content-length".to_string(), first);
+    Ok(())
+}
+
+/// Length of the HTTP/0.9 simple request (`GET /path` and a line ending) at
+/// the start of `buffer`, if that's what it holds. Such a request is complete
+/// after its first line, since it has no headers and no blank line to end them.
+pub fn find_simple_request_end(buffer: &[u8]) -> Option<usize> {
+    let end = buffer.iter().position(|&byte| byte == b'\n')? + 1;
+    let line = buffer[..end].strip_suffix(b"\n")?;
+    let line = line.strip_suffix(b"\r").unwrap_or(line);
+    let target = line.strip_prefix(b"GET /")?;
+    (!target.contains(&b' ')).then_some(end)
+}
+
+/// Returns the length of the request head (up to and including the blank
+/// line that ends the headers), if the buffer contains a complete one.
+///
+/// Lines may end in a bare LF as well as CRLF, as some minimal clients send
+/// them, so the blank line is an LF followed by an optional CR and an LF.
+/// `parse_request` splits lines the same way; responses always use CRLF.
+pub fn find_head_end(buffer: &[u8]) -> Option<usize> {
+    buffer.iter().enumerate().filter(|&(_, &byte)| byte == b'\n').find_map(|(index, _)| {
+        match buffer[index + 1..] {
+            [b'\n', ..] => Some(index + 2),
+            [b'\r', b'\n', ..] => Some(index + 3),
+            _ => None,
+        }
+    })
+}
+
+/// Decodes `%XX` escapes in a URL path. Returns `None` for invalid escapes
+/// or if the result isn't valid UTF-8.
+pub fn percent_decode(input: &str) -> Option<String> {
+    let bytes = input.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+
+    while i < bytes.len() {
+        if bytes[i] == b'%' {
+            // `from_str_radix` alone would also take a sign, as in `%+1`
+            let hex = input.get(i + 1..i + 3).filter(|hex| hex.bytes().all(|byte| byte.is_ascii_hexdigit()))?;
+            decoded.push(u8::from_str_radix(hex, 16).ok()?);
+            i += 3;
+        } else {
+            decoded.push(bytes[i]);
+            i += 1;
+        }
+    }
+
+    String::from_utf8(decoded).ok()
+}
+
+/// Encodes a path segment so it can be used safely inside an `href`
+pub fn percent_encode(input: &str) -> String {
+    let mut encoded = String::with_capacity(input.len());
+    for byte in input.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => {
+                encoded.push(byte as char)
+            }
+            _ => encoded.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    encoded
+}
+
+/// How header names are spelled on the wire
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HeaderCase {
+    /// The conventional form, e.g. `Content-Type` and `ETag`
+    Canonical,
+    /// All lowercase, as HTTP/2 requires
+    Lower,
+}
+
+impl HeaderCase {
+    /// Spells `name` in this case
+    pub fn apply(self, name: &str) -> String {
+        match self {
+            HeaderCase::Canonical => canonical_header_name(name),
+            HeaderCase::Lower => name.to_ascii_lowercase(),
+        }
+    }
+}
+
+/// Names whose conventional spelling isn't simply capitalized words
+const IRREGULAR_HEADER_NAMES: &[&str] = &["ETag", "TE", "WWW-Authenticate", "Content-MD5", "DNT", "X-XSS-Protection"];
+
+/// Capitalizes each dash-separated word of a header name, e.g.
+/// `content-type` becomes `Content-Type`
+pub fn canonical_header_name(name: &str) -> String {
+    if let Some(irregular) = IRREGULAR_HEADER_NAMES.iter().find(|irregular| irregular.eq_ignore_ascii_case(name)) {
+        return irregular.to_string();
+    }
+
+    let mut canonical = String::with_capacity(name.len());
+    let mut word_start = true;
+    for c in name.chars() {
+        canonical.push(if word_start { c.to_ascii_uppercase() } else { c.to_ascii_lowercase() });
+        word_start = c == '-';
+    }
+    canonical
+}
+
+/// An HTTP response waiting to be written to the client
+pub struct HttpResponse {
+    pub status: StatusCode,
+    /// Extra headers in the order they should be sent. Names are respelled
+    /// in the configured case when written.
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+    /// Length advertised in `Content-Length` when the body itself is left
+    /// out, as for a HEAD of a file, or streamed with its length known up
+    /// front; `None` means the length of `body`
+    pub content_length: Option<u64>,
+    /// Fields sent after the body. A response with trailers goes out with
+    /// chunked encoding and a `Trailer` header naming them.
+    pub trailers: Vec<(String, String)>,
+    /// A body produced while the response is being sent, for output too large
+    /// to build in memory. When set, `body` is ignored and the response goes
+    /// out with chunked encoding, unless `content_length` gives its length.
+    pub stream: Option<Box<dyn Read>>,
+    /// Send `stream` as it is, without chunks or a length, and close the
+    /// connection to end it: how a streamed body reaches an HTTP/1.0 client
+    pub close_delimited: bool,
+    /// For a 5xx, the message with the internal cause spelled out. It
+    /// replaces the generic body only when `--verbose-errors` is on.
+    pub error_detail: Option<String>,
+    /// Version named in the status line, see `response_version`. `None`
+    /// means the HTTP/1.1 the server speaks, for requests that couldn't be parsed.
+    pub version: Option<&'static str>,
+}
+
+impl HttpResponse {
+    /// Creates an empty response with the given status code
+    pub fn new(status: impl Into<StatusCode>) -> Self {
+        HttpResponse {
+            status: status.into(),
+            headers: Vec::new(),
+            body: Vec::new(),
+            content_length: None,
+            trailers: Vec::new(),
+            stream: None,
+            close_delimited: false,
+            error_detail: None,
+            version: None,
+        }
+    }
+
+    /// Convenience constructor for a plain-text response
+    pub fn text(status: impl Into<StatusCode>, body: &str) -> Self {
+        HttpResponse::new(status).with_body(body.as_bytes().to_vec(), "text/plain; charset=utf-8")
+    }
+
+    /// Sets the body along with its Content-Type
+    pub fn with_body(mut self, body: Vec<u8>, content_type: &str) -> Self {
+        self.body = body;
+        self.with_header("Content-Type", content_type)
+    }
+
+    /// Describes a body of `len` bytes without carrying it, for HEAD
+    pub fn with_omitted_body(mut self, len: u64, content_type: &str) -> Self {
+        self.body.clear();
+        self.content_length = Some(len);
+        self.with_header("Content-Type", content_type)
+    }
+
+    /// Sets a body that is read from `reader` as the response is sent
+    pub fn with_stream(mut self, reader: impl Read + 'static, content_type: &str) -> Self {
+        self.body.clear();
+        self.stream = Some(Box::new(reader));
+        self.with_header("Content-Type", content_type)
+    }
+
+    /// Sets a body of exactly `len` bytes that is read from `reader` as the
+    /// response is sent, such as a file too large to load
+    pub fn with_sized_stream(mut self, reader: impl Read + 'static, len: u64, content_type: &str) -> Self {
+        self.body.clear();
+        self.stream = Some(Box::new(reader));
+        self.content_length = Some(len);
+        self.with_header("Content-Type", content_type)
+    }
+
+    /// True when the body has to go out with chunked encoding: it's streamed
+    /// with no length known, or trailers have to follow it
+    pub fn is_chunked(&self) -> bool {
+        (self.stream.is_some() && self.content_length.is_none() && !self.close_delimited) || !self.trailers.is_empty()
+    }
+
+    /// Reads the first piece of a streamed body ahead of the headers, so a
+    /// stream that fails straight away does so while the status can still
+    /// change. The piece is put back in front of the rest of the stream.
+    pub fn prime_stream(&mut self) -> std::io::Result<()> {
+        let Some(mut stream) = self.stream.take() else {
+            return Ok(());
+        };
+        let mut first = vec![0; 16 * 1024];
+        let size = loop {
+            match stream.read(&mut first) {
+                Ok(size) => break size,
+                Err(e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e),
+            }
+        };
+        first.truncate(size);
+        self.stream = Some(Box::new(std::io::Cursor::new(first).chain(stream)));
+        Ok(())
+    }
+
+    /// Drops the body, trailers and any framing headers of a response whose
+    /// status doesn't allow a body, in case a handler set them anyway.
+    /// Returns true if there was anything to drop.
+    pub fn discard_body(&mut self) -> bool {
+        let had_body = !self.body.is_empty() || self.stream.is_some() || !self.trailers.is_empty();
+        self.body.clear();
+        self.stream = None;
+        self.trailers.clear();
+        self.content_length = None;
+        self.headers.retain(|(name, _)| {
+            !name.eq_ignore_ascii_case("Content-Length") && !name.eq_ignore_ascii_case("Transfer-Encoding")
+        });
+        had_body
+    }
+
+    /// The value for the `Content-Length` header
+    pub fn content_length(&self) -> u64 {
+        self.content_length.unwrap_or(self.body.len() as u64)
+    }
+
+    /// Appends a header to the response
+    pub fn with_header(mut self, name: &str, value: &str) -> Self {
+        self.headers.push((name.to_string(), value.to_string()));
+        self
+    }
+
+    /// Appends a trailer field, e.g. a checksum of the body
+    pub fn with_trailer(mut self, name: &str, value: &str) -> Self {
+        self.trailers.push((name.to_string(), value.to_string()));
+        self
+    }
+
+    /// Looks up the first header with this (case-insensitive) name
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(existing, _)| existing.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+/// An HTTP status code. The standard codes have named constants; any other
+/// code can be made with `StatusCode::from`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct StatusCode(u16);
+
+impl StatusCode {
+    pub const OK: StatusCode = StatusCode(200);
+    pub const CREATED: StatusCode = StatusCode(201);
+    pub const NO_CONTENT: StatusCode = StatusCode(204);
+    pub const PARTIAL_CONTENT: StatusCode = StatusCode(206);
+    pub const MOVED_PERMANENTLY: StatusCode = StatusCode(301);
+    pub const FOUND: StatusCode = StatusCode(302);
+    pub const NOT_MODIFIED: StatusCode = StatusCode(304);
+    pub const BAD_REQUEST: StatusCode = StatusCode(400);
+    pub const FORBIDDEN: StatusCode = StatusCode(403);
+    pub const NOT_FOUND: StatusCode = StatusCode(404);
+    pub const METHOD_NOT_ALLOWED: StatusCode = StatusCode(405);
+    pub const NOT_ACCEPTABLE: StatusCode = StatusCode(406);
+    pub const REQUEST_TIMEOUT: StatusCode = StatusCode(408);
+    pub const CONFLICT: StatusCode = StatusCode(409);
+    pub const LENGTH_REQUIRED: StatusCode = StatusCode(411);
+    pub const PRECONDITION_FAILED: StatusCode = StatusCode(412);
+    pub const CONTENT_TOO_LARGE: StatusCode = StatusCode(413);
+    pub const URI_TOO_LONG: StatusCode = StatusCode(414);
+    pub const RANGE_NOT_SATISFIABLE: StatusCode = StatusCode(416);
+    pub const TOO_MANY_REQUESTS: StatusCode = StatusCode(429);
+    pub const REQUEST_HEADER_FIELDS_TOO_LARGE: StatusCode = StatusCode(431);
+    pub const INTERNAL_SERVER_ERROR: StatusCode = StatusCode(500);
+    pub const NOT_IMPLEMENTED: StatusCode = StatusCode(501);
+    pub const BAD_GATEWAY: StatusCode = StatusCode(502);
+    pub const SERVICE_UNAVAILABLE: StatusCode = StatusCode(503);
+    pub const GATEWAY_TIMEOUT: StatusCode = StatusCode(504);
+    pub const HTTP_VERSION_NOT_SUPPORTED: StatusCode = StatusCode(505);
+
+    pub fn as_u16(self) -> u16 {
+        self.0
+    }
+
+    /// 1xx
+    pub fn is_informational(self) -> bool {
+        (100..200).contains(&self.0)
+    }
+
+    /// 2xx
+    pub fn is_success(self) -> bool {
+        (200..300).contains(&self.0)
+    }
+
+    /// 3xx
+    pub fn is_redirect(self) -> bool {
+        (300..400).contains(&self.0)
+    }
+
+    /// 4xx
+    pub fn is_client_error(self) -> bool {
+        (400..500).contains(&self.0)
+    }
+
+    /// 5xx
+    pub fn is_server_error(self) -> bool {
+        (500..600).contains(&self.0)
+    }
+
+    /// Whether a response with this status may carry a body. 1xx, 204 and
+    /// 304 responses end with their headers, so they get no framing either.
+    pub fn allows_body(self) -> bool {
+        !(self.is_informational() || self == StatusCode::NO_CONTENT || self == StatusCode::NOT_MODIFIED)
+    }
+
+    /// Returns the standard reason phrase, if this is a code we know
+    pub fn canonical_reason(self) -> Option<&'static str> {
+        let reason = match self.0 {
+            200 => "OK",
+            201 => "Created",
+            204 => "No Content",
+            206 => "Partial Content",
+            301 => "Moved Permanently",
+            302 => "Found",
+            304 => "Not Modified",
+            400 => "Bad Request",
+            403 => "Forbidden",
+            404 => "Not Found",
+            405 => "Method Not Allowed",
+            406 => "Not Acceptable",
+            408 => "Request Timeout",
+            409 => "Conflict",
+            411 => "Length Required",
+            412 => "Precondition Failed",
+            413 => "Content Too Large",
+            414 => "URI Too Long",
+            416 => "Range Not Satisfiable",
+            429 => "Too Many Requests",
+            431 => "Request Header Fields Too Large",
+            500 => "Internal Server Error",
+            501 => "Not Implemented",
+            502 => "Bad Gateway",
+            503 => "Service Unavailable",
+            504 => "Gateway Timeout",
+            505 => "HTTP Version Not Supported",
+            _ => return None,
+        };
+        Some(reason)
+    }
+}
+
+impl From<u16> for StatusCode {
+    fn from(code: u16) -> Self {
+        StatusCode(code)
+    }
+}
+
+impl From<StatusCode> for u16 {
+    fn from(status: StatusCode) -> Self {
+        status.0
+    }
+}
+
+impl fmt::Display for StatusCode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{TempDir, TestServer};
+
+    fn parse(head: &str) -> Result<HttpRequest, ParseError> {
+        parse_request(head.as_bytes(), ParseLimits::default())
+    }
+
+    #[test]
+    fn ambiguous_body_framing_is_rejected() {
+        let with = |headers: &str| parse(&format!("POST /upload HTTP/1.1\r\nHost: a\r\n{}\r\n", headers));
+        let rejected = [
+            "Content-Length: 5\r\nTransfer-Encoding: chunked\r\n",
+            "Transfer-Encoding: chunked\r\nContent-Length: 5\r\n",
+            "Content-Length: 5\r\nContent-Length: 6\r\n",
+            "Content-Length: 5, 6\r\n",
+            "Content-Length: -1\r\n",
+            "Content-Length: +5\r\n",
+            "Content-Length: 5 5\r\n",
+            "Content-Length: 0x10\r\n",
+            "Content-Length: \r\n",
+        ];
+        for headers in rejected {
+            let error = with(headers).unwrap_err();
+            assert_eq!(error, ParseError::AmbiguousFraming, "{:?}", headers);
+            assert_eq!(error.status(), StatusCode::BAD_REQUEST);
+        }
+
+        // Repeating the same length is harmless, and is folded into one value
+        let request = with("Content-Length: 5\r\nContent-Length: 5\r\n").unwrap();
+        assert_eq!(request.header("Content-Length"), Some("5"));
+    }
+
+    #[test]
+    fn malformed_header_lines_are_rejected() {
+        let with = |line: &str| parse(&format!("GET / HTTP/1.1\r\nHost: a\r\n{}\r\n\r\n", line));
+        for line in ["X-Foo bar", "Content-Length : 5", "Content-Length\t: 5", ": empty name", " folded"] {
+            assert_eq!(with(line).unwrap_err(), ParseError::Malformed, "{:?}", line);
+        }
+        assert!(with("X-Foo: bar").is_ok());
+    }
+
+    #[test]
+    fn fuzz_seeds_parse_without_panicking() {
+        let seeds = std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("fuzz/seeds/parse_request");
+        for entry in std::fs::read_dir(seeds).unwrap() {
+            let input = std::fs::read(entry.unwrap().path()).unwrap();
+            let _ = parse_request(&input, ParseLimits::default());
+            let _ = find_head_end(&input);
+            let _ = find_simple_request_end(&input);
+        }
+    }
+
+    #[test]
+    fn crafted_inputs_are_errors_not_panics() {
+        let malformed: [&[u8]; 9] = [
+            b"",
+            b"\n\n",
+            b"GET /abc% HTTP/1.1\r\nHost: a\r\n\r\n",
+            b"GET /%4 HTTP/1.1\r\nHost: a\r\n\r\n",
+            b"GET /%+1 HTTP/1.1\r\nHost: a\r\n\r\n",
+            // A `%` right before a multi-byte character once sliced through it
+            "GET /%\u{e9}x HTTP/1.1\r\nHost: a\r\n\r\n".as_bytes(),
+            // Decodes to bytes that aren't UTF-8
+            b"GET /%C3%28 HTTP/1.1\r\nHost: a\r\n\r\n",
+            b"GET http:// HTTP/1.1\r\nHost: a\r\n\r\n",
+            b"GET http:///x HTTP/1.1\r\nHost: a\r\n\r\n",
+        ];
+        for input in malformed {
+            let result = parse_request(input, ParseLimits::default());
+            assert_eq!(result.unwrap_err(), ParseError::Malformed, "{:?}", String::from_utf8_lossy(input));
+        }
+
+        // Raw bytes that aren't UTF-8 are replaced, which fails to match anything later
+        let lossy = parse_request(b"GET /\xff HTTP/1.1\r\nHost: \xc0\r\n\r\n", ParseLimits::default()).unwrap();
+        assert_eq!(lossy.path, "/\u{fffd}");
+        assert_eq!(find_head_end(b"\n"), None);
+        assert_eq!(find_head_end(b"\n\n"), Some(2));
+    }
+
+    #[test]
+    fn header_count_is_capped_before_any_are_stored() {
+        let head = |count: usize| {
+            let headers: String = (0..count).map(|i| format!("X-{}: {}\r\n", i, i)).collect();
+            format!("GET / HTTP/1.1\r\nHost: a\r\n{}\r\n", headers)
+        };
+        let max = ParseLimits::default().max_headers;
+        assert!(parse(&head(max - 1)).is_ok());
+        let error = parse(&head(max)).unwrap_err();
+        assert_eq!(error, ParseError::TooManyHeaders);
+        assert_eq!(error.status(), StatusCode::REQUEST_HEADER_FIELDS_TOO_LARGE);
+    }
+
+    #[test]
+    fn five_hundred_headers_get_431_promptly() {
+        let server = TestServer::with_args(&[]);
+        let headers: String = (0..500).map(|i| format!("X-Flood-{}: {}\r\n", i, i)).collect();
+        let request = format!("GET / HTTP/1.1\r\nHost: a\r\n{}Connection: close\r\n\r\n", headers);
+        let started = std::time::Instant::now();
+        assert_eq!(server.send(request).status, 431);
+        assert!(started.elapsed() < std::time::Duration::from_secs(2));
+        // A lower configured limit applies too
+        let strict = TestServer::with_args(&["--max-headers", "3"]);
+        let few = "GET / HTTP/1.1\r\nHost: a\r\nA: 1\r\nB: 2\r\nConnection: close\r\n\r\n";
+        assert_eq!(strict.send(few).status, 431);
+    }
+
+    #[test]
+    fn one_enormous_header_value_is_refused_before_it_is_stored() {
+        let limit = ParseLimits::default().max_header_value;
+        let cookie = |len: usize| format!("GET / HTTP/1.1\r\nHost: a\r\nCookie: {}\r\n\r\n", "c".repeat(len));
+        assert!(parse(&cookie(limit)).is_ok());
+        let error = parse(&cookie(limit + 1)).unwrap_err();
+        assert_eq!(error, ParseError::HeaderValueTooLong);
+        assert_eq!(error.status(), StatusCode::REQUEST_HEADER_FIELDS_TOO_LARGE);
+
+        // Well inside the head limit, but far past the value limit
+        let server = TestServer::with_args(&[]);
+        let huge = format!("GET / HTTP/1.1\r\nHost: a\r\nCookie: {}\r\nConnection: close\r\n\r\n", "c".repeat(40_000));
+        assert_eq!(server.send(huge).status, 431);
+        let strict = TestServer::with_args(&["--max-header-value", "16"]);
+        let request = "GET / HTTP/1.1\r\nHost: a\r\nX-Long: 0123456789abcdefg\r\nConnection: close\r\n\r\n";
+        assert_eq!(strict.send(request).status, 431);
+    }
+
+    #[test]
+    fn bare_lf_line_endings_are_accepted() {
+        assert_eq!(find_head_end(b"GET / HTTP/1.1\nHost: a\n\nbody"), Some(24));
+        assert_eq!(find_head_end(b"GET / HTTP/1.1\r\nHost: a\n\r\nbody"), Some(26));
+        assert_eq!(find_head_end(b"GET / HTTP/1.1\r\nHost: a\r\n\r\nbody"), Some(27));
+        assert_eq!(find_head_end(b"GET / HTTP/1.1\nHost: a\n"), None);
+        assert_eq!(find_head_end(b"GET / HTTP/1.1\r\nHost: a\r\n\r"), None);
+
+        let request = parse("GET /a?b=c HTTP/1.1\nHost: example\nX-Mixed: yes\r\nAccept: */*\n\n").unwrap();
+        assert_eq!(request.path, "/a");
+        assert_eq!(request.version, "HTTP/1.1");
+        assert_eq!(request.header("host"), Some("example"));
+        assert_eq!(request.header("x-mixed"), Some("yes"));
+        assert_eq!(request.header("accept"), Some("*/*"));
+
+        // Two LF-only requests on one connection, each answered with CRLF lines
+        let root = TempDir::new();
+        root.write("a.txt", "A");
+        let server = TestServer::with_args(&["--root", root.path().to_str().unwrap()]);
+        let pipelined = "GET /a.txt HTTP/1.1\nHost: a\n\nGET /a.txt HTTP/1.1\nHost: a\nConnection: close\n\n";
+        let raw = server.exchange(pipelined);
+        let text = String::from_utf8_lossy(&raw);
+        assert_eq!(text.matches("HTTP/1.1 200 OK\r\n").count(), 2, "{}", text);
+        assert!(!text.replace("\r\n", "").contains('\n'), "{}", text);
+    }
+
+    #[test]
+    fn bodiless_statuses_drop_the_body_and_its_framing() {
+        for status in [100, 101, 204, 304] {
+            assert!(!StatusCode::from(status).allows_body(), "{}", status);
+        }
+        for status in [200, 201, 206, 404, 500] {
+            assert!(StatusCode::from(status).allows_body(), "{}", status);
+        }
+        let mut response = HttpResponse::text(204, "stray")
+            .with_header("Content-Length", "5")
+            .with_header("transfer-encoding", "chunked")
+            .with_header("ETag", "\"a\"")
+            .with_trailer("X-Sum", "1");
+        assert!(response.discard_body());
+        assert!(response.body.is_empty() && response.trailers.is_empty() && !response.is_chunked());
+        assert_eq!(response.header("Content-Length"), None);
+        assert_eq!(response.header("Transfer-Encoding"), None);
+        assert_eq!(response.header("ETag"), Some("\"a\""));
+        assert!(!HttpResponse::new(204).discard_body());
+    }
+
+    #[test]
+    fn header_without_colon_gets_400() {
+        let server = TestServer::with_args(&[]);
+        let response = server.send(b"GET / HTTP/1.1\r\nHost: a\r\nX-Foo bar\r\nConnection: close\r\n\r\n");
+        assert_eq!(response.status, 400);
+    }
+
+    #[test]
+    fn over_long_targets_are_414() {
+        let limit = ParseLimits::default().max_target_len;
+        let at_limit = format!("/{}", "a".repeat(limit - 1));
+        assert!(parse(&format!("GET {} HTTP/1.1\r\nHost: a\r\n\r\n", at_limit)).is_ok());
+
+        let over = format!("/{}", "a".repeat(limit));
+        let error = parse(&format!("GET {} HTTP/1.1\r\nHost: a\r\n\r\n", over)).unwrap_err();
+        assert_eq!(error, ParseError::TargetTooLong);
+        assert_eq!(error.status(), StatusCode::URI_TOO_LONG);
+    }
+
+    #[test]
+    fn server_answers_414_for_the_configured_limit() {
+        let server = TestServer::with_args(&["--max-uri-length", "64"]);
+        let response = server.get(&format!("/{}", "x".repeat(64)));
+        assert_eq!(response.status, 414);
+        assert_eq!(response.header("Connection"), Some("close"));
+        assert_eq!(server.get("/short").status, 404);
+    }
+
+    #[test]
+    fn request_line_needs_three_tokens_and_a_target() {
+        let missing_target = ["GET  HTTP/1.1", "GET HTTP/1.1"];
+        let extra_whitespace = ["GET /  HTTP/1.1", "GET  / HTTP/1.1", " GET / HTTP/1.1", "GET / HTTP/1.1 x"];
+        for line in missing_target.into_iter().chain(extra_whitespace) {
+            let error = parse(&format!("{}\r\nHost: a\r\n\r\n", line)).unwrap_err();
+            assert_eq!(error, ParseError::Malformed, "{:?}", line);
+        }
+        assert_eq!(parse("GET x HTTP/1.1\r\nHost: a\r\n\r\n").unwrap_err(), ParseError::Malformed);
+        assert_eq!(parse("OPTIONS * HTTP/1.1\r\nHost: a\r\n\r\n").unwrap().path, "*");
+        assert_eq!(parse("GET http://a/x?y HTTP/1.1\r\nHost: a\r\n\r\n").unwrap().path, "/x");
+    }
+
+    #[test]
+    fn versions_must_parse_and_be_1_x() {
+        let with = |version: &str| parse(&format!("GET /x {}\r\nHost: a\r\n\r\n", version));
+        for version in ["HTTP/foo", "HTTP/1", "HTTP/1.1.1", "HTTP/+1.1", "HTTP/01.1", "http/1.1", "HTTP/1.x"] {
+            assert_eq!(with(version).unwrap_err(), ParseError::Malformed, "{:?}", version);
+        }
+        for version in ["HTTP/2.0", "HTTP/0.9", "HTTP/3.0"] {
+            let error = with(version).unwrap_err();
+            assert_eq!(error, ParseError::UnsupportedVersion, "{:?}", version);
+            assert_eq!(error.status(), StatusCode::HTTP_VERSION_NOT_SUPPORTED);
+        }
+        assert_eq!(with("HTTP/1.0").unwrap().version, "HTTP/1.0");
+        assert_eq!(parse("GET /x\r\n").unwrap().version, SIMPLE_REQUEST_VERSION);
+    }
+
+    #[test]
+    fn server_answers_400_for_bad_versions_and_505_for_others() {
+        let server = TestServer::with_args(&[]);
+        assert_eq!(server.send(b"GET /x HTTP/foo\r\nHost: a\r\n\r\n").status, 400);
+        assert_eq!(server.send(b"GET /x HTTP/2.0\r\nHost: a\r\n\r\n").status, 505);
+    }
+
+    #[test]
+    fn versionless_get_is_a_complete_simple_request() {
+        assert_eq!(find_simple_request_end(b"GET /index.html\r\n"), Some(17));
+        assert_eq!(find_simple_request_end(b"GET /\nleftover"), Some(6));
+        assert_eq!(find_simple_request_end(b"GET /index.html"), None);
+        assert_eq!(find_simple_request_end(b"GET / HTTP/1.1\r\n"), None);
+        assert_eq!(find_simple_request_end(b"POST /\r\n"), None);
+
+        let request = parse("GET /index.html\r\n").unwrap();
+        assert_eq!((request.method, request.path.as_str()), (Method::Get, "/index.html"));
+        assert_eq!(request.version, SIMPLE_REQUEST_VERSION);
+        assert_eq!(parse("HEAD /index.html\r\n").unwrap_err(), ParseError::Malformed);
+    }
+
+    #[test]
+    fn http_1_1_and_later_must_name_the_host() {
+        for version in ["HTTP/1.1", "HTTP/1.2"] {
+            let missing = parse(&format!("GET / {}\r\nAccept: */*\r\n\r\n", version));
+            assert_eq!(missing.unwrap_err(), ParseError::MissingHost, "{}", version);
+        }
+        assert!(parse("GET / HTTP/1.1\r\nhOsT: example.com\r\n\r\n").is_ok());
+        assert!(parse("GET / HTTP/1.0\r\nAccept: */*\r\n\r\n").is_ok());
+        assert_eq!(ParseError::MissingHost.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[test]
+    fn hostless_http_1_1_gets_400_and_http_1_0_is_served() {
+        let root = TempDir::new();
+        root.write("x.txt", "A");
+        let server = TestServer::with_args(&["--root", root.path().to_str().unwrap()]);
+        let hostless = server.send(b"GET /x.txt HTTP/1.1\r\nConnection: close\r\n\r\n");
+        assert_eq!(hostless.status, 400);
+        let old = server.send(b"GET /x.txt HTTP/1.0\r\n\r\n");
+        assert_eq!((old.status, old.body), (200, b"A".to_vec()));
+    }
+
+    #[test]
+    fn versions_compare_as_numbers() {
+        for version in ["HTTP/1.1", "HTTP/1.2", "HTTP/2.0"] {
+            assert!(at_least_1_1(version), "{}", version);
+        }
+        for version in ["HTTP/1.0", SIMPLE_REQUEST_VERSION, "HTTP/1.x", "HTTP/1.10"] {
+            assert!(!at_least_1_1(version), "{}", version);
+        }
+        assert!(parse("GET / HTTP/1.2\r\nHost: a\r\n\r\n").unwrap().is_http_1_1());
+        assert!(!parse("GET / HTTP/1.0\r\n\r\n").unwrap().is_http_1_1());
+        let answered = ["HTTP/1.0", "HTTP/1.1", "HTTP/1.2", SIMPLE_REQUEST_VERSION].map(response_version);
+        assert_eq!(answered, ["HTTP/1.0", "HTTP/1.1", "HTTP/1.1", "HTTP/1.0"]);
+    }
+}
@@ -0,0 +1,130 @@
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+const DAY_NAMES: [&str; 7] = ["Thu", "Fri", "Sat", "Sun", "Mon", "Tue", "Wed"];
+const MONTH_NAMES: [&str; 12] = [
+    "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+];
+
+/// A point in time broken down into UTC calendar fields
+struct DateTime {
+    days: i64,
+    year: i64,
+    month: u32,
+    day: u32,
+    hour: u64,
+    minute: u64,
+    second: u64,
+}
+
+impl DateTime {
+    fn from_system_time(time: SystemTime) -> Self {
+        let secs = time.duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+        let days = (secs / 86_400) as i64;
+        let seconds_of_day = secs % 86_400;
+        let (year, month, day) = civil_from_days(days);
+
+        DateTime {
+            days,
+            year,
+            month,
+            day,
+            hour: seconds_of_day / 3600,
+            minute: (seconds_of_day / 60) % 60,
+            second: seconds_of_day % 60,
+        }
+    }
+}
+
+/// Formats a time as an IMF-fixdate, e.g. `Sun, 06 Nov 1994 08:49:37 GMT`
+pub fn format_http_date(time: SystemTime) -> String {
+    let t = DateTime::from_system_time(time);
+    format!(
+        "{}, {:02} {} {} {:02}:{:02}:{:02} GMT",
+        DAY_NAMES[(t.days % 7) as usize],
+        t.day,
+        MONTH_NAMES[(t.month - 1) as usize],
+        t.year,
+        t.hour,
+        t.minute,
+        t.second
+    )
+}
+
+/// Formats a time the way Common Log Format expects, e.g. `06/Nov/1994:08:49:37 +0000`
+pub fn format_clf_date(time: SystemTime) -> String {
+    let t = DateTime::from_system_time(time);
+    format!(
+        "{:02}/{}/{}:{:02}:{:02}:{:02} +0000",
+        t.day,
+        MONTH_NAMES[(t.month - 1) as usize],
+        t.year,
+        t.hour,
+        t.minute,
+        t.second
+    )
+}
+
+/// Formats a time as RFC 3339 in UTC, e.g. `1994-11-06T08:49:37Z`
+pub fn format_rfc3339(time: SystemTime) -> String {
+    let t = DateTime::from_system_time(time);
+    format!(
+        "{}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+        t.year, t.month, t.day, t.hour, t.minute, t.second
+    )
+}
+
+/// Parses an IMF-fixdate back into a `SystemTime`
+pub fn parse_http_date(value: &str) -> Option<SystemTime> {
+    // "Sun, 06 Nov 1994 08:49:37 GMT" -> ["06", "Nov", "1994", "08:49:37", "GMT"]
+    let (_, rest) = value.trim().split_once(", ")?;
+    let parts: Vec<&str> = rest.split(' ').collect();
+    if parts.len() != 5 || parts[4] != "GMT" {
+        return None;
+    }
+
+    let day: u32 = parts[0].parse().ok()?;
+    let month = MONTH_NAMES.iter().position(|&name| name == parts[1])? as u32 + 1;
+    let year: i64 = parts[2].parse().ok()?;
+
+    let time: Vec<u64> = parts[3].split(':').map(|part| part.parse().ok()).collect::<Option<_>>()?;
+    if time.len() != 3 || time[0] > 23 || time[1] > 59 || time[2] > 60 || !(1..=31).contains(&day) {
+        return None;
+    }
+
+    from_utc(year, month, day, time[0] * 3600 + time[1] * 60 + time[2])
+}
+
+/// The time at `seconds` into a UTC calendar day, or `None` before 1970
+pub fn from_utc(year: i64, month: u32, day: u32, seconds: u64) -> Option<SystemTime> {
+    let days = days_from_civil(year, month, day);
+    if days < 0 {
+        return None;
+    }
+    Some(UNIX_EPOCH + Duration::from_secs(days as u64 * 86_400 + seconds))
+}
+
+/// Converts days since 1970-01-01 into a (year, month, day) triple.
+/// This is Howard Hinnant's well-known `civil_from_days` algorithm.
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
+
+/// The inverse of `civil_from_days`
+fn days_from_civil(year: i64, month: u32, day: u32) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let yoe = year.rem_euclid(400);
+    let mp = (month as i64 + 9) % 12;
+    let doy = (153 * mp + 2) / 5 + day as i64 - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146_097 + doe - 719_468
+}
@@ -0,0 +1,2739 @@
+#[macro_use]
+mod logger;
+
+mod access_log;
+mod acl;
+mod archive;
+pub mod bench;
+mod body;
+mod cache;
+mod capture;
+mod compress;
+pub mod config;
+mod connection;
+mod cors;
+mod error;
+mod forwarded;
+pub mod http;
+mod http_date;
+mod listing;
+mod mime;
+mod negotiate;
+mod paths;
+mod proxy;
+mod range;
+mod rate_limit;
+mod request_id;
+mod rewrite;
+mod router;
+mod signals;
+mod sockopt;
+mod stats;
+mod thread_pool;
+mod tls_port;
+mod throttle;
+mod transform;
+mod validators;
+mod watch;
+mod writable;
+
+#[cfg(test)]
+mod testing;
+
+use std::borrow::Cow;
+use std::io::{self, ErrorKind, Read, Write};
+use std::net::{IpAddr, SocketAddr, TcpListener, TcpStream};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
+
+use archive::Archive;
+use body::RequestBody;
+use cache::{FileCache, ParsedCache};
+use compress::Encoding;
+use error::ServerError;
+use capture::TeeStream;
+pub use config::Config;
+pub use transform::Transform;
+use connection::Connection;
+use http::{HeaderCase, HttpRequest, HttpResponse, Method, StatusCode};
+use mime::MimeTypes;
+use paths::Root;
+use range::RangeRequest;
+use rate_limit::RateLimiter;
+use router::{Handler, Router};
+use stats::Stats;
+use thread_pool::ThreadPool;
+use throttle::Throttle;
+use validators::Validators;
+use watch::Watcher;
+
+// Constants for our HTTP server
+/// Address bound when `--bind` isn't given
+const SERVER_ADDRESS: &str = "127.0.0.1:8080";
+const HTTP_VERSION: &str = "HTTP/1.1";
+const SERVER_NAME: &str = "RustRawHTTP/1.0";
+/// Document root used when no `--root` is given
+const DOCUMENT_ROOT: &str = "public";
+/// `Retry-After` sent with 503 unless `--retry-after` says otherwise
+const RETRY_AFTER_SECS: u64 = 1;
+/// Largest file read whole into memory to be transformed or compressed.
+/// Larger files are only streamed, so each request holds a bounded amount.
+const MAX_BUFFERED_FILE: u64 = 32 * 1024 * 1024;
+/// `Cache-Control` for files matching `--immutable`: a year, the most HTTP
+/// caches honour, and never revalidated in the meantime
+const IMMUTABLE_CACHE_CONTROL: &str = "public, max-age=31536000, immutable";
+/// Index file served for directories without an `.index` file of their own
+const DEFAULT_INDEX: &str = "index.html";
+/// Per-directory file naming the directory's index files
+const DIRECTORY_INDEX_FILE: &str = ".index";
+/// Path of the built-in endpoint reporting server counters as JSON
+const STATUS_PATH: &str = "/__status";
+/// Bounds for the accept-loop backoff when descriptors run out
+const MIN_ACCEPT_BACKOFF: Duration = Duration::from_millis(10);
+const MAX_ACCEPT_BACKOFF: Duration = Duration::from_secs(1);
+/// How often `run_until` checks its shutdown flag while no connections arrive
+const SHUTDOWN_POLL_INTERVAL: Duration = Duration::from_millis(50);
+/// Largest request head (request line plus headers) we're willing to buffer
+const MAX_HEAD_BYTES: usize = 64 * 1024;
+/// Largest chunk written when a body goes out with chunked encoding
+const CHUNK_SIZE: usize = 16 * 1024;
+/// Largest single write of a response. A write cut short by a disconnect
+/// reports nothing of what it sent, so this bounds how far off the logged
+/// progress of an abandoned download can be.
+const MAX_WRITE: usize = 256 * 1024;
+
+/// A bound server, ready to accept connections
+pub struct Server {
+    /// One listener per configured address; all feed the same worker pool
+    listeners: Vec<TcpListener>,
+    /// Listeners from this index on are `--tls-bind` ports
+    tls_from: usize,
+    context: Context,
+}
+
+impl Server {
+    /// Sets up logging and the MIME table from `config`, then binds the listening sockets
+    pub fn bind(config: Config) -> io::Result<Server> {
+        // Set up logging before anything else gets logged
+        logger::set_level(config.log_level);
+        if let Some(path) = &config.log_file {
+            logger::init_file(path, config.log_max_size).map_err(|e| {
+                io::Error::new(e.kind(), format!("failed to open log file {}: {}", path.display(), e))
+            })?;
+        }
+        // SIGHUP reopens the log file and re-reads the config file
+        if config.log_file.is_some() || config.config_file.is_some() {
+            signals::install_handlers();
+        }
+
+        // Create a TCP listener bound to each specified address
+        // This is the core networking functionality that allows our program to accept connections
+        if config.addresses.is_empty() {
+            return Err(io::Error::new(ErrorKind::InvalidInput, "no address to listen on"));
+        }
+        let mut listeners = Vec::new();
+        let mut bound = Vec::new();
+        for address in &config.addresses {
+            let listener = TcpListener::bind(address)
+                .map_err(|e| io::Error::new(e.kind(), format!("failed to bind {}: {}", address, e)))?;
+            bound.push(listener.local_addr()?);
+            listeners.push(listener);
+        }
+        let tls_from = listeners.len();
+        for address in &config.tls_addresses {
+            let listener = TcpListener::bind(address)
+                .map_err(|e| io::Error::new(e.kind(), format!("failed to bind {}: {}", address, e)))?;
+            bound.push(listener.local_addr()?);
+            listeners.push(listener);
+        }
+        log_info!("{}", describe_config(&config, &bound[..tls_from], &bound[tls_from..]));
+
+        let cache = FileCache::new(config.cache_size);
+        if config.preload {
+            let started = Instant::now();
+            let (files, bytes) = cache.preload(&config.roots);
+            log_info!("Preloaded {} files ({} bytes) into the cache in {:?}", files, bytes, started.elapsed());
+        }
+
+        let rate_limiter = config.rate_limit.map(|rate| Arc::new(RateLimiter::new(rate)));
+        let stats = Arc::new(Stats::new(config.workers));
+        let active_connections = Arc::new(AtomicUsize::new(0));
+        let watcher = config.watch.then(|| Arc::new(Watcher::new()));
+        let archive = match &config.root_archive {
+            Some(path) => Some(Arc::new(open_archive(path)?)),
+            None => None,
+        };
+        let context = Context {
+            router: build_router(&config, &stats, &active_connections, watcher.as_ref()),
+            mime_types: load_mime_types(&config),
+            roots: config.roots.iter().map(|root| Root::new(root)).collect(),
+            archive,
+            transforms: load_transforms(&config, watcher.is_some()),
+            watcher,
+            config,
+            cache: Arc::new(cache),
+            directory_types: ParsedCache::new(),
+            directory_indexes: ParsedCache::new(),
+            rate_limiter,
+            stats,
+            active_connections,
+            idle_connections: Arc::new(AtomicUsize::new(0)),
+            draining: Arc::new(AtomicBool::new(false)),
+        };
+        log_info!("{}", describe_routes(&context.config, &context.router));
+        Ok(Server { listeners, tls_from, context })
+    }
+
+    /// The first address actually bound, e.g. to learn the port picked for `127.0.0.1:0`
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.listeners[0].local_addr()
+    }
+
+    /// Every address actually bound, in the order they were configured,
+    /// `--tls-bind` ones after the rest
+    pub fn local_addrs(&self) -> io::Result<Vec<SocketAddr>> {
+        self.listeners.iter().map(TcpListener::local_addr).collect()
+    }
+
+    /// Serves connections until the process exits.
+    /// Only returns once every listening socket has failed.
+    pub fn run(self) -> io::Result<()> {
+        // In capture mode, handle exactly one connection on this thread, dump it and exit
+        if self.context.config.capture {
+            capture_one(&self.listeners[0], &LiveContext::new(self.context));
+            return Ok(());
+        }
+        self.serve(None)
+    }
+
+    /// Serves connections until `shutdown` is set, then stops accepting on
+    /// every listener and waits for the connections already in progress to finish
+    pub fn run_until(self, shutdown: &AtomicBool) -> io::Result<()> {
+        // Poll instead of blocking in accept so the flag gets noticed
+        for listener in &self.listeners {
+            listener.set_nonblocking(true)?;
+        }
+        self.serve(Some(shutdown))
+    }
+
+    fn serve(self, shutdown: Option<&AtomicBool>) -> io::Result<()> {
+        // Share the configuration with the worker threads
+        let config = &self.context.config;
+        let pool = ThreadPool::new(config.workers, config.overflow_policy, &config.thread_name);
+        let thread_name = config.thread_name.clone();
+        let watch_config = config.config_file.is_some();
+        let watcher = self.context.watcher.clone();
+        let live = Arc::new(LiveContext::new(self.context));
+        let stopped = AtomicBool::new(false);
+
+        // Each listener gets its own accept thread; the first error to end one is reported
+        log_info!("Waiting for connections...");
+        let results: Vec<io::Result<()>> = thread::scope(|scope| {
+            // Named threads are easy to pick out in `top -H`, a debugger or a panic message
+            let spawn_named = |name: String| thread::Builder::new().name(name);
+            if watch_config {
+                spawn_named(format!("{}-reload", thread_name))
+                    .spawn_scoped(scope, || watch_for_reload(&live, &stopped))
+                    .expect("failed to spawn the reload thread");
+            }
+            if let Some(watcher) = &watcher {
+                spawn_named(format!("{}-watch", thread_name))
+                    .spawn_scoped(scope, || watcher.run(|| live.load().config.roots.clone(), &stopped))
+                    .expect("failed to spawn the watch thread");
+            }
+            let loops: Vec<_> = self
+                .listeners
+                .iter()
+                .enumerate()
+                .map(|(i, listener)| {
+                    let tls = i >= self.tls_from;
+                    let (live, pool) = (&live, &pool);
+                    spawn_named(format!("{}-accept{}", thread_name, i))
+                        .spawn_scoped(scope, move || accept_loop(listener, tls, live, pool, shutdown))
+                        .expect("failed to spawn an accept thread")
+                })
+                .collect();
+            let results = loops.into_iter().map(|handle| handle.join().unwrap_or(Ok(()))).collect();
+            stopped.store(true, Ordering::SeqCst);
+            results
+        });
+
+        // Dropping the pool waits for the workers to finish their connections
+        drop(pool);
+        log_info!("Server stopped");
+        results.into_iter().collect()
+    }
+}
+
+/// Accepts connections on one listener and hands them to the pool until
+/// `shutdown` is set or the listener fails. Connections on a `tls` port
+/// are sorted into TLS and plaintext first.
+fn accept_loop(
+    listener: &TcpListener,
+    tls: bool,
+    live: &Arc<LiveContext>,
+    pool: &ThreadPool,
+    shutdown: Option<&AtomicBool>,
+) -> io::Result<()> {
+    // Listen for incoming connections until told to stop
+    let mut backoff = Duration::ZERO;
+    let mut result = Ok(());
+    for stream in listener.incoming() {
+        if shutdown.is_some_and(|flag| flag.load(Ordering::SeqCst)) {
+            log_info!("Shutdown requested, no longer accepting connections");
+            live.load().draining.store(true, Ordering::SeqCst);
+            break;
+        }
+
+        match stream {
+            Ok(mut stream) => {
+                backoff = Duration::ZERO;
+
+                // Successfully accepted a connection, handle it.
+                // The peer may already have gone away, so don't unwrap its address.
+                match stream.peer_addr() {
+                    Ok(addr) => log_info!("New connection: {}", addr),
+                    Err(_) => continue,
+                }
+
+                // Accepted sockets may inherit non-blocking mode from the listener
+                if shutdown.is_some() && let Err(e) = stream.set_nonblocking(false) {
+                    log_error!("Failed to make connection blocking: {}", e);
+                    continue;
+                }
+
+                // The connection keeps the configuration current when it was accepted
+                let context = live.load();
+                let config = &context.config;
+
+                // Count the connection now; the guard decrements it when dropped
+                let guard = ConnectionGuard::new(&context.active_connections);
+                if guard.count > config.max_connections {
+                    // Shed load right away instead of letting the queue grow without bound
+                    log_info!("Connection limit of {} reached, sending 503", config.max_connections);
+                    let response = HttpResponse::text(503, "The server is too busy, please retry shortly")
+                        .with_header("Retry-After", &config.retry_after.to_string());
+                    send_response(&mut stream, response, Disposition::Close, false, config);
+                    continue;
+                }
+
+                apply_buffer_sizes(&stream, config);
+
+                // The first request must arrive within the request timeout
+                stream.apply_read_timeout(config.request_timeout);
+
+                let live = Arc::clone(live);
+                let queued = pool.execute((stream, guard), move |(stream, _guard)| {
+                    let peer = stream.peer_addr().ok();
+                    if tls {
+                        tls_port::serve(stream, peer, &live);
+                    } else {
+                        Connection::new(stream, peer, &live).serve();
+                    }
+                });
+                // Only the reject policy hands the connection back
+                if let Err((mut stream, _guard)) = queued {
+                    log_info!("Work queue full, sending 503");
+                    let response = HttpResponse::text(503, "The server is too busy, please retry shortly")
+                        .with_header("Retry-After", &config.retry_after.to_string());
+                    send_response(&mut stream, response, Disposition::Close, false, config);
+                }
+            },
+            // Nothing to accept right now; only happens while polling for shutdown
+            Err(e) if e.kind() == ErrorKind::WouldBlock => thread::sleep(SHUTDOWN_POLL_INTERVAL),
+            Err(e) => match classify_accept_error(&e) {
+                AcceptError::Transient => {
+                    // Only this connection failed (e.g. the client gave up during the handshake)
+                    log_info!("Connection failed: {}", e);
+                },
+                AcceptError::ResourceExhausted => {
+                    // Retrying immediately would just fail again and spin a core,
+                    // so wait for workers to release descriptors/memory
+                    backoff = (backoff * 2).clamp(MIN_ACCEPT_BACKOFF, MAX_ACCEPT_BACKOFF);
+                    log_error!("Cannot accept connections ({}), backing off for {:?}", e, backoff);
+                    thread::sleep(backoff);
+                },
+                AcceptError::Fatal => {
+                    let address = listener.local_addr().map_or("?".to_string(), |addr| addr.to_string());
+                    log_error!("Listener on {} failed, no longer accepting on it: {}", address, e);
+                    result = Err(e);
+                    break;
+                }
+            }
+        }
+    }
+
+    result
+}
+
+/// How the accept loop should react to an error from `accept`
+enum AcceptError {
+    /// Affects a single connection only; keep accepting
+    Transient,
+    /// The process is out of descriptors or memory; back off before retrying
+    ResourceExhausted,
+    /// The listening socket itself is broken
+    Fatal,
+}
+
+/// Sizes the connection's socket buffers as `--send-buffer` and
+/// `--recv-buffer` ask, logging what the kernel actually granted
+fn apply_buffer_sizes(stream: &TcpStream, config: &Config) {
+    let wanted = [(sockopt::Buffer::Send, config.send_buffer), (sockopt::Buffer::Receive, config.recv_buffer)];
+    for (buffer, size) in wanted {
+        let Some(size) = size else {
+            continue;
+        };
+        match sockopt::set_buffer_size(stream, buffer, size) {
+            Ok(effective) => log_info!("{}: asked for {} bytes, got {}", buffer.option_name(), size, effective),
+            Err(e) => log_error!("Warning: failed to set {}: {}", buffer.option_name(), e),
+        }
+    }
+}
+
+fn classify_accept_error(error: &io::Error) -> AcceptError {
+    // errno values shared by Linux, macOS and the BSDs
+    const EBADF: i32 = 9;
+    const ENOMEM: i32 = 12;
+    const ENFILE: i32 = 23;
+    const EMFILE: i32 = 24;
+    #[cfg(target_os = "linux")]
+    const ENOBUFS: i32 = 105;
+    #[cfg(not(target_os = "linux"))]
+    const ENOBUFS: i32 = 55;
+
+    match error.raw_os_error() {
+        Some(ENOMEM | ENFILE | EMFILE | ENOBUFS) => return AcceptError::ResourceExhausted,
+        Some(EBADF) => return AcceptError::Fatal,
+        _ => {}
+    }
+
+    match error.kind() {
+        ErrorKind::OutOfMemory => AcceptError::ResourceExhausted,
+        ErrorKind::InvalidInput | ErrorKind::Unsupported | ErrorKind::PermissionDenied => AcceptError::Fatal,
+        _ => AcceptError::Transient,
+    }
+}
+
+/// Everything a worker needs to handle requests, shared between threads
+struct Context {
+    config: Config,
+    mime_types: MimeTypes,
+    router: Router,
+    /// `config.roots`, resolved once when the context is built
+    roots: Vec<Root>,
+    /// The `--root-archive` served instead of the roots, indexed when opened
+    archive: Option<Arc<Archive>>,
+    /// Rewrites applied to served file contents
+    transforms: Vec<Transform>,
+    /// Pushes reloads to open pages, when `--watch` is set
+    watcher: Option<Arc<Watcher>>,
+    cache: Arc<FileCache>,
+    /// Parsed `.mime` overrides, keyed by the file's path
+    directory_types: ParsedCache<MimeTypes>,
+    /// Parsed `.index` files, keyed by the file's path
+    directory_indexes: ParsedCache<Vec<String>>,
+    /// Per-client request budget, when `--rate-limit` is set
+    rate_limiter: Option<Arc<RateLimiter>>,
+    /// Response counters served at `STATUS_PATH`
+    stats: Arc<Stats>,
+    /// Connections accepted and not yet closed
+    active_connections: Arc<AtomicUsize>,
+    /// Keep-alive connections waiting for their next request
+    idle_connections: Arc<AtomicUsize>,
+    /// Set once shutdown starts: requests still get answered, but every
+    /// connection closes after its current response
+    draining: Arc<AtomicBool>,
+}
+
+impl Context {
+    /// A context for `config`, keeping the cache, the rate limiter, the
+    /// watcher and the counters, none of which a reload changes
+    fn reconfigured(&self, config: Config) -> Context {
+        Context {
+            router: build_router(&config, &self.stats, &self.active_connections, self.watcher.as_ref()),
+            mime_types: load_mime_types(&config),
+            roots: config.roots.iter().map(|root| Root::new(root)).collect(),
+            archive: self.reopened_archive(&config),
+            transforms: load_transforms(&config, self.watcher.is_some()),
+            watcher: self.watcher.clone(),
+            config,
+            cache: Arc::clone(&self.cache),
+            directory_types: ParsedCache::new(),
+            directory_indexes: ParsedCache::new(),
+            rate_limiter: self.rate_limiter.clone(),
+            stats: Arc::clone(&self.stats),
+            active_connections: Arc::clone(&self.active_connections),
+            idle_connections: Arc::clone(&self.idle_connections),
+            draining: Arc::clone(&self.draining),
+        }
+    }
+
+    /// The archive `config` asks for: the one already open when the path is
+    /// unchanged, since it was indexed then. One that fails to open keeps the
+    /// old archive in service.
+    fn reopened_archive(&self, config: &Config) -> Option<Arc<Archive>> {
+        let path = config.root_archive.as_ref()?;
+        if let Some(current) = &self.archive
+            && current.path() == path
+        {
+            return Some(Arc::clone(current));
+        }
+        match open_archive(path) {
+            Ok(archive) => Some(Arc::new(archive)),
+            Err(e) => {
+                log_error!("Keeping the current document root, the new one is unusable: {}", e);
+                self.archive.clone()
+            }
+        }
+    }
+}
+
+/// Opens and indexes the `--root-archive`
+fn open_archive(path: &Path) -> io::Result<Archive> {
+    let started = Instant::now();
+    let archive = Archive::open(path)
+        .map_err(|e| io::Error::new(e.kind(), format!("failed to open archive {}: {}", path.display(), e)))?;
+    log_info!("Indexed {} files in {} in {:?}", archive.file_count(), path.display(), started.elapsed());
+    Ok(archive)
+}
+
+/// The context new requests are served with. A reload swaps in a whole new
+/// one; requests already running keep the `Arc` they started with.
+struct LiveContext {
+    current: RwLock<Arc<Context>>,
+}
+
+impl LiveContext {
+    fn new(context: Context) -> Self {
+        LiveContext { current: RwLock::new(Arc::new(context)) }
+    }
+
+    fn load(&self) -> Arc<Context> {
+        Arc::clone(&self.current.read().unwrap())
+    }
+
+    /// Re-reads the config file. Options that only take effect at startup
+    /// keep their old values until a restart, which is logged.
+    fn reload(&self) {
+        let current = self.load();
+        let config = match current.config.reload() {
+            Ok(config) => config,
+            Err(e) => {
+                // Parse errors come with the usage text, which doesn't belong in the log
+                let reason = e.lines().next().unwrap_or_default();
+                log_error!("Failed to reload the configuration, keeping the current one: {}", reason);
+                return;
+            }
+        };
+        for option in current.config.restart_required(&config) {
+            log_error!("Warning: the change to {} takes effect after a restart", option);
+        }
+        let path = config.config_file.clone().unwrap_or_default();
+        logger::set_level(config.log_level);
+        let context = current.reconfigured(config);
+        log_info!("Configuration reloaded from {}", path.display());
+        log_info!("{}", describe_routes(&context.config, &context.router));
+        *self.current.write().unwrap() = Arc::new(context);
+    }
+}
+
+/// Reloads the configuration whenever SIGHUP asks for it, until `stopped` is set
+fn watch_for_reload(live: &LiveContext, stopped: &AtomicBool) {
+    while !stopped.load(Ordering::SeqCst) {
+        if signals::take_reload_request() {
+            live.reload();
+        }
+        thread::sleep(SHUTDOWN_POLL_INTERVAL);
+    }
+}
+
+/// Builds the MIME table, merging any user-supplied overrides
+fn load_mime_types(config: &Config) -> MimeTypes {
+    let mut mime_types = MimeTypes::new(!config.strict_extensions);
+    if let Some(path) = &config.mime_types {
+        match mime_types.load_file(path) {
+            Ok(count) => log_info!("Loaded {} MIME type mappings from {}", count, path.display()),
+            Err(e) => log_error!("Failed to read MIME types file {}: {}", path.display(), e),
+        }
+    }
+    mime_types
+}
+
+/// The content transforms in effect: the live-reload script, then the
+/// library's. While `watching`, pages listen for pushed reloads instead of
+/// polling.
+fn load_transforms(config: &Config, watching: bool) -> Vec<Transform> {
+    let live_reload = if watching {
+        Some(Transform::inject_before_body("live-reload-events", watch::CLIENT_SNIPPET))
+    } else {
+        config.live_reload.then(Transform::live_reload)
+    };
+    live_reload.into_iter().chain(config.transforms.iter().cloned()).collect()
+}
+
+/// The startup banner: the settings in effect once defaults, `--config` and
+/// the command line have been merged, listening on the `bound` addresses
+/// and the `tls_bound` ones. Written at the info level, so `--log-level
+/// error` leaves it out.
+fn describe_config(config: &Config, bound: &[SocketAddr], tls_bound: &[SocketAddr]) -> String {
+    let on_off = |on: bool| if on { "on" } else { "off" };
+    let bound: Vec<String> = bound.iter().map(SocketAddr::to_string).collect();
+    let mut lines = vec![
+        format!("Listening on:   {} (plain HTTP, no TLS)", bound.join(", ")),
+        format!("Document roots: {}", describe_roots(config)),
+        format!(
+            "Workers:        {} (up to {} connections, on overflow: {})",
+            config.workers,
+            config.max_connections,
+            config.overflow_policy.as_str()
+        ),
+        format!(
+            "Keep-alive:     {}s idle, {} requests per connection, {}s per request",
+            config.keepalive_timeout.as_secs(),
+            config.keepalive_max,
+            config.request_timeout.as_secs()
+        ),
+        if config.compression {
+            let precompressed = on_off(config.precompressed);
+            format!("Compression:    on, level {} (precompressed files {})", config.compress_level, precompressed)
+        } else {
+            "Compression:    off".to_string()
+        },
+        format!(
+            "Logging:        {} to {} (access log {}, {})",
+            config.log_level.as_str(),
+            config.log_file.as_ref().map_or("the terminal".to_string(), |path| path.display().to_string()),
+            on_off(config.access_log),
+            config.log_format.as_str()
+        ),
+        match config.cache_size {
+            0 => "Cache:          off".to_string(),
+            size => format!("Cache:          {} bytes{}", size, if config.preload { ", preloaded" } else { "" }),
+        },
+    ];
+    if let (false, Some(backend)) = (tls_bound.is_empty(), &config.tls_backend) {
+        let tls_bound: Vec<String> = tls_bound.iter().map(SocketAddr::to_string).collect();
+        let ports = tls_bound.join(", ");
+        lines.push(format!("TLS ports:      {} (TLS passed to {}, plain HTTP redirected)", ports, backend));
+    }
+    if let Some(root) = &config.writable {
+        lines.push(format!("Writable:       {} (PUT and DELETE)", root.display()));
+    }
+    for (prefix, address) in &config.proxies {
+        lines.push(format!("Proxy:          {}/* to {}", prefix, address));
+    }
+    if let Some(path) = &config.config_file {
+        lines.push(format!("Config file:    {}", path.display()));
+    }
+    format!("Effective configuration:\n  {}", lines.join("\n  "))
+}
+
+/// Describes what `config` serves: every route `build_router` registers for
+/// it, then the static files that requests matching no route fall through to.
+/// This is what `--list-routes` prints.
+pub fn route_summary(config: &Config) -> String {
+    let watcher = config.watch.then(|| Arc::new(Watcher::new()));
+    let stats = Arc::new(Stats::new(config.workers));
+    let router = build_router(config, &stats, &Arc::new(AtomicUsize::new(0)), watcher.as_ref());
+    describe_routes(config, &router)
+}
+
+/// Lists `router`'s table, in matching order, followed by the static file setup
+fn describe_routes(config: &Config, router: &Router) -> String {
+    let mut summary = String::from("Routes:\n");
+    for (method, pattern, handler) in router.routes() {
+        let streaming = if matches!(handler, Handler::Streaming(_)) { " (streams the body)" } else { "" };
+        summary.push_str(&format!("  {:<7} {}{}\n", method.as_str(), pattern, streaming));
+    }
+
+    summary.push_str(&format!("Static files for anything else: {}\n", describe_roots(config)));
+    summary.push_str(&format!("  Directory listings: {}\n", if config.dir_listing { "on" } else { "off" }));
+    match &config.spa_fallback {
+        Some(path) => summary.push_str(&format!("  SPA fallback: {}", path.display())),
+        None => summary.push_str("  SPA fallback: off"),
+    }
+    summary
+}
+
+/// The document roots, or the archive standing in for them
+fn describe_roots(config: &Config) -> String {
+    match &config.root_archive {
+        Some(path) => format!("{} (archive)", path.display()),
+        None => config.roots.iter().map(|root| root.display().to_string()).collect::<Vec<_>>().join(", "),
+    }
+}
+
+/// Registers the built-in dynamic routes enabled by the configuration
+fn build_router(
+    config: &Config,
+    stats: &Arc<Stats>,
+    active_connections: &Arc<AtomicUsize>,
+    watcher: Option<&Arc<Watcher>>,
+) -> Router {
+    let mut router = Router::new();
+
+    // With --no-stats the counters are never incremented, and serving them would only mislead
+    if config.stats {
+        for method in [Method::Get, Method::Head] {
+            let stats = Arc::clone(stats);
+            let active_connections = Arc::clone(active_connections);
+            router.route(method, STATUS_PATH, move |_, _| {
+                let json = stats.to_json(active_connections.load(Ordering::SeqCst));
+                HttpResponse::new(StatusCode::OK)
+                    .with_body(json.into_bytes(), "application/json")
+                    .with_header("Cache-Control", "no-store")
+            });
+        }
+    }
+
+    if let Some(watcher) = watcher {
+        let watcher = Arc::clone(watcher);
+        router.route(Method::Get, watch::EVENTS_PATH, move |_, _| {
+            HttpResponse::new(StatusCode::OK)
+                .with_stream(watcher.subscribe(), "text/event-stream")
+                .with_header("Cache-Control", "no-store")
+        });
+    }
+
+    for (pattern, rule) in &config.cors_routes {
+        router.cors(pattern, rule.clone());
+    }
+
+    for (prefix, address) in &config.proxies {
+        let upstream = Arc::new(proxy::Upstream {
+            address: address.clone(),
+            connect_timeout: config.proxy_connect_timeout,
+            read_timeout: config.proxy_read_timeout,
+            timeout: config.proxy_timeout,
+            max_response: config.proxy_max_response,
+            retries: config.proxy_retries,
+        });
+        let pattern = format!("{}/*", prefix);
+        for method in [Method::Get, Method::Head, Method::Post, Method::Put, Method::Delete, Method::Patch] {
+            let upstream = Arc::clone(&upstream);
+            router.route(method, &pattern, move |request, body| proxy::forward(request, body, &upstream));
+        }
+        router.route(Method::Options, &pattern, move |request, body| proxy::forward(request, body, &upstream));
+    }
+
+    if let Some(root) = &config.writable {
+        let put_root = root.clone();
+        let delete_root = root.clone();
+        router
+            .route_streaming(Method::Put, "/*", move |request, body| writable::handle_put(request, body, &put_root))
+            .route(Method::Delete, "/*", move |request, _| writable::handle_delete(request, &delete_root));
+    }
+
+    router
+}
+
+/// Accepts a single connection, serves it through a recording stream and
+/// logs the raw request and response bytes
+fn capture_one(listener: &TcpListener, live: &LiveContext) {
+    log_info!("Capture mode: waiting for one connection...");
+
+    let (stream, peer) = match listener.accept() {
+        Ok(accepted) => accepted,
+        Err(e) => {
+            log_error!("Connection failed: {}", e);
+            return;
+        }
+    };
+    log_info!("New connection: {}", peer);
+    stream.apply_read_timeout(live.load().config.request_timeout);
+
+    let mut tee = TeeStream::new(stream);
+    Connection::new(&mut tee, Some(peer), live).serve();
+
+    log_info!("----- Raw request ({} bytes) -----\n{}", tee.received.len(), capture::hex_dump(&tee.received));
+    log_info!("----- Raw response ({} bytes) -----\n{}", tee.sent.len(), capture::hex_dump(&tee.sent));
+    log_info!("Capture complete, shutting down");
+}
+
+/// Tracks one in-flight connection in the shared counter
+struct ConnectionGuard {
+    counter: Arc<AtomicUsize>,
+    /// Number of in-flight connections including this one
+    count: usize,
+}
+
+impl ConnectionGuard {
+    fn new(counter: &Arc<AtomicUsize>) -> Self {
+        let count = counter.fetch_add(1, Ordering::SeqCst) + 1;
+        ConnectionGuard { counter: Arc::clone(counter), count }
+    }
+}
+
+impl Drop for ConnectionGuard {
+    fn drop(&mut self) {
+        self.counter.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// Streams whose read timeout can change between the phases of a connection.
+/// Stream types that can't time out reads keep the default, which reports
+/// `Unsupported`.
+trait ReadTimeout {
+    fn set_read_timeout(&self, _timeout: Option<Duration>) -> io::Result<()> {
+        Err(io::Error::from(ErrorKind::Unsupported))
+    }
+
+    /// Sets the timeout, carrying on without it if the stream refuses. A
+    /// missing timeout only means a stalled client holds its worker longer.
+    fn apply_read_timeout(&self, timeout: Duration) {
+        if let Err(e) = self.set_read_timeout(Some(timeout)) {
+            log_error!("Warning: could not set a {:?} read timeout, continuing without one: {}", timeout, e);
+        }
+    }
+}
+
+impl ReadTimeout for TcpStream {
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        TcpStream::set_read_timeout(self, timeout)
+    }
+}
+
+impl<S: ReadTimeout> ReadTimeout for &mut S {
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        (**self).set_read_timeout(timeout)
+    }
+}
+
+/// Charges the request to the client's rate limit. Returns how long the
+/// client must wait when it's over the limit.
+fn rate_limit_wait(context: &Context, client_ip: Option<IpAddr>) -> Option<Duration> {
+    let limiter = context.rate_limiter.as_ref()?;
+    limiter.check(client_ip?).err()
+}
+
+/// A 405 for a method `--allow-methods` doesn't allow, listing the ones it does
+fn method_not_allowed(request: &HttpRequest, methods: &[Method]) -> HttpResponse {
+    let allow: Vec<&str> = methods.iter().map(Method::as_str).collect();
+    let allow = allow.join(", ");
+    error_response(request, 405, &format!("Supported methods: {}", allow)).with_header("Allow", &allow)
+}
+
+/// A 429 telling the client when its next request will be accepted
+fn too_many_requests(request: &HttpRequest, wait: Duration) -> HttpResponse {
+    log_info!("Rate limit exceeded, next request allowed in {:?}", wait);
+    // Retry-After only has whole seconds; rounding down would invite an early retry
+    let seconds = wait.as_secs_f64().ceil().max(1.0) as u64;
+    error_response(request, StatusCode::TOO_MANY_REQUESTS, "Too many requests, please slow down")
+        .with_header("Retry-After", &seconds.to_string())
+}
+
+/// A 301 sending `request` to the same host, path and query over https.
+/// The port is dropped along with the scheme, since the one the client used
+/// is this plaintext one; https gets its default.
+fn redirect_to_https(request: &HttpRequest) -> HttpResponse {
+    let host = request.header("Host").map(strip_port).filter(|host| is_valid_host(host));
+    let Some(host) = host else {
+        log_info!("No usable Host header to redirect {} to https", request.path);
+        return error_response(request, 400, "A valid Host header is needed to redirect to https");
+    };
+    let path: Vec<String> = request.path.split('/').map(http::percent_encode).collect();
+    let location = match &request.query {
+        Some(query) => format!("https://{}{}?{}", host, path.join("/"), query),
+        None => format!("https://{}{}", host, path.join("/")),
+    };
+    log_info!("Redirecting to {}", location);
+    error_response(request, 301, "Moved Permanently").with_header("Location", &location)
+}
+
+/// `example.com:8080` without its port. IPv6 literals keep their brackets.
+fn strip_port(host: &str) -> &str {
+    match host.rfind(':') {
+        Some(colon) if !host[colon..].contains(']') => &host[..colon],
+        _ => host,
+    }
+}
+
+/// Whether `host` is a name or address that can sit in a URL as is, so a
+/// crafted header can't smuggle a path or credentials into the `Location`
+fn is_valid_host(host: &str) -> bool {
+    !host.is_empty()
+        && host.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '.' | '_' | ':' | '[' | ']'))
+}
+
+/// Dispatches a parsed request to the right handler. The second value is
+/// false when the connection can't be reused afterwards (e.g. an unread body).
+fn respond<S: Read + Write>(
+    request: &HttpRequest,
+    stream: &mut S,
+    buffer: &mut Vec<u8>,
+    context: &Context,
+) -> (HttpResponse, bool) {
+    let config = &context.config;
+    // Only methods that carry a payload have a body to read
+    let takes_body = matches!(request.method, Method::Post | Method::Put | Method::Patch);
+    let handler = context.router.find(&request.method, &request.path);
+
+    // Some clients send a body even where it means nothing, like on a GET. Only
+    // routed payload methods read it, so everywhere else it's read and dropped
+    // here; left in the buffer, it would be parsed as the next request.
+    if !(takes_body && handler.is_some()) && body::has_body(request) {
+        let discarded = RequestBody::new(request, stream, buffer, config.max_body).and_then(|mut body| body.discard());
+        match discarded {
+            Ok(bytes) => log_info!("Discarded a {} byte body the {} request doesn't use", bytes, request.method),
+            Err(error) => return (error_response(request, error.status(), error.message()), false),
+        }
+    }
+
+    // `OPTIONS *` asks what the server as a whole supports
+    if request.is_server_wide() {
+        let allow = allow_header(["GET", "HEAD", "OPTIONS"], context.router.all_methods());
+        return (HttpResponse::new(StatusCode::NO_CONTENT).with_header("Allow", &allow), true);
+    }
+
+    // Preflights are answered from the CORS rule alone, before any handler runs
+    if cors::is_preflight(request) && let Some(rule) = cors_rule(context, &request.path) {
+        log_info!("Answering a CORS preflight for {}", request.path);
+        return (cors::preflight(request, &rule), true);
+    }
+
+    // Registered routes take priority over static files
+    if let Some(handler) = handler {
+        let (response, reusable) = match handler {
+            Handler::Buffered(handler) if !takes_body => (handler(request, &[]), true),
+            Handler::Buffered(handler) => {
+                let body = RequestBody::new(request, stream, buffer, config.max_body);
+                match body.and_then(|mut body| body.read_all()) {
+                    Ok(body) => (handler(request, &body), true),
+                    Err(error) => return (error_response(request, error.status(), error.message()), false),
+                }
+            }
+            Handler::Streaming(handler) => {
+                let body = if takes_body {
+                    RequestBody::new(request, stream, buffer, config.max_body)
+                } else {
+                    Ok(RequestBody::empty(stream, buffer))
+                };
+                let mut body = match body {
+                    Ok(body) => body,
+                    Err(error) => return (error_response(request, error.status(), error.message()), false),
+                };
+                let response = handler(request, &mut body);
+                // A body cut short decides the answer, whatever the handler made of it
+                if let Some(error) = body.failure() {
+                    return (error_response(request, error.status(), error.message()), false);
+                }
+                // A handler that stopped reading early leaves the rest of the body in the way
+                (response, body.is_complete())
+            }
+        };
+        // Generated responses can't be served in pieces
+        let response = if response.header("Accept-Ranges").is_none() {
+            response.with_header("Accept-Ranges", "none")
+        } else {
+            response
+        };
+        return (response, reusable);
+    }
+
+    if matches!(request.method, Method::Get | Method::Head) {
+        return (serve_file(&rewrite_request(request, context), context), true);
+    }
+
+    // Method not supported
+    let allow = allow_header(["GET", "HEAD"], context.router.allowed_methods(&request.path));
+    let message = format!("Supported methods: {}", allow);
+    (error_response(request, 405, &message).with_header("Allow", &allow), true)
+}
+
+/// The CORS rule for `path`: the route's own, filled in from `--cors`, or
+/// `--cors` alone for paths no route rule covers
+fn cors_rule(context: &Context, path: &str) -> Option<cors::CorsRule> {
+    let global = context.config.cors.as_ref();
+    match context.router.find_cors(path) {
+        Some(rule) => Some(rule.over(global)),
+        None => global.cloned(),
+    }
+}
+
+/// Lets a cross-origin client read `response` when a CORS rule allows it.
+/// Preflight answers already carry their headers.
+fn apply_cors(request: &HttpRequest, response: HttpResponse, context: &Context) -> HttpResponse {
+    match cors_rule(context, &request.path) {
+        Some(rule) if !cors::is_preflight(request) => cors::apply(request, response, &rule),
+        _ => response,
+    }
+}
+
+/// Builds an `Allow` value from the built-in methods plus routed ones, without duplicates
+fn allow_header<const N: usize>(built_in: [&str; N], routed: Vec<&Method>) -> String {
+    let mut allowed: Vec<&str> = built_in.to_vec();
+    for method in routed {
+        if !allowed.contains(&method.as_str()) {
+            allowed.push(method.as_str());
+        }
+    }
+    allowed.join(", ")
+}
+
+/// Picks the request's ID: an incoming `X-Request-Id` is reused when it looks
+/// sane, otherwise a fresh one is generated unless generation is disabled
+fn assign_request_id(request: &HttpRequest, config: &Config) -> Option<String> {
+    let incoming = request.header("X-Request-Id").and_then(request_id::sanitize_incoming);
+    match incoming {
+        Some(id) => Some(id.to_string()),
+        None if config.request_ids => Some(request_id::generate()),
+        None => None,
+    }
+}
+
+/// What happens to the connection after the current response
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Disposition {
+    /// Wait for another request, advertising these limits in `Keep-Alive`:
+    /// the idle timeout and how many more requests the connection may carry
+    KeepAlive { timeout: Duration, max: usize },
+    Close,
+}
+
+impl Disposition {
+    /// The single place that decides whether a connection stays open. Both the
+    /// `Connection` header we send and the request loop follow this decision.
+    fn decide(
+        request: Option<&HttpRequest>,
+        status: StatusCode,
+        reusable: bool,
+        requests_served: usize,
+        config: &Config,
+    ) -> Disposition {
+        // After these errors the rest of the stream can't be trusted to start
+        // at a request boundary, so never reuse the connection
+        let fatal_error = matches!(status.as_u16(), 400 | 408 | 411 | 413 | 414 | 431);
+
+        let keep_alive = reusable
+            && !fatal_error
+            && request.is_some_and(wants_keep_alive)
+            && requests_served < config.keepalive_max;
+
+        if keep_alive {
+            // The budget shrinks with every request, so the client can tell
+            // when the server is about to close
+            let max = config.keepalive_max - requests_served;
+            Disposition::KeepAlive { timeout: config.keepalive_timeout, max }
+        } else {
+            Disposition::Close
+        }
+    }
+}
+
+/// Decides whether the client asked to keep the connection open.
+/// HTTP/1.1 defaults to keep-alive; HTTP/1.0 must ask for it explicitly.
+fn wants_keep_alive(request: &HttpRequest) -> bool {
+    let connection = request.header("Connection").unwrap_or("").to_ascii_lowercase();
+    let has_token = |token: &str| connection.split(',').any(|t| t.trim() == token);
+
+    if request.is_http_1_1() {
+        !has_token("close")
+    } else {
+        has_token("keep-alive")
+    }
+}
+
+/// Applies the configured rewrite rules to the request's path. The result
+/// still goes through the traversal checks in `serve_file`.
+fn rewrite_request<'a>(request: &'a HttpRequest, context: &Context) -> Cow<'a, HttpRequest> {
+    let config = &context.config;
+    if config.rewrites.is_empty() {
+        return Cow::Borrowed(request);
+    }
+    let exists = |path: &str| match &context.archive {
+        Some(archive) => matches!(archive.find(path), Ok(Some(entry)) if !entry.is_dir),
+        None => matches!(resolve_in_roots(context, path), Ok(Some((_, metadata))) if metadata.is_file()),
+    };
+    let path = rewrite::rewrite(&config.rewrites, &request.path, &exists);
+    if path == request.path {
+        return Cow::Borrowed(request);
+    }
+    log_info!("Rewrote {} to {}", request.path, path);
+    Cow::Owned(HttpRequest { path, ..request.clone() })
+}
+
+/// Attempts to serve a file (or directory) from the local filesystem
+fn serve_file(request: &HttpRequest, context: &Context) -> HttpResponse {
+    if let Some(archive) = &context.archive {
+        return serve_from_archive(request, archive, context);
+    }
+    let config = &context.config;
+    // Directories (including `/`) are resolved by serve_directory below
+    let path = request.path.as_str();
+
+    // Look the path up in each document root in turn,
+    // refusing anything that tries to climb out of them
+    let (file_path, metadata) = match resolve_in_roots(context, path) {
+        Ok(Some(found)) => found,
+        Err(()) => return error_response(request, 403, "Access to the requested path is forbidden"),
+        // Client-side routes of a single-page app look like missing files
+        Ok(None) => match &config.spa_fallback {
+            Some(fallback) if is_spa_route(request) => {
+                log_info!("No file for {}, serving SPA fallback {}", path, fallback.display());
+                return match fs::metadata(fallback) {
+                    Ok(metadata) => serve_regular_file(request, fallback, &metadata, context),
+                    Err(e) => {
+                        log_error!("Failed to read SPA fallback {}: {}", fallback.display(), e);
+                        error_response(request, 404, "The requested file was not found")
+                    }
+                };
+            }
+            // A document root that hasn't been created yet is as empty as it gets
+            _ if is_welcome(request, context) => return welcome_page(context),
+            // File not found or couldn't be read
+            _ => return error_response(request, 404, "The requested file was not found"),
+        },
+    };
+
+    if metadata.is_dir() {
+        return serve_directory(request, path, &file_path, context);
+    }
+    // Per-directory settings are for the server, not for download
+    if file_path.file_name().is_some_and(|name| name == mime::DIRECTORY_MIME_FILE || name == DIRECTORY_INDEX_FILE) {
+        return error_response(request, 404, "The requested file was not found");
+    }
+
+    serve_regular_file(request, &file_path, &metadata, context)
+}
+
+/// Serves a file or directory out of the `--root-archive`. Lookups get the
+/// same traversal checks as on disk, and files the same content types,
+/// validators, transforms and ranges; the `.mime` and `.index` files that
+/// configure directories on disk are only served as ordinary files.
+fn serve_from_archive(request: &HttpRequest, archive: &Archive, context: &Context) -> HttpResponse {
+    let entry = match archive.find(&request.path) {
+        Ok(Some(entry)) => entry,
+        Ok(None) => return error_response(request, 404, "The requested file was not found"),
+        Err(()) => return error_response(request, 403, "Access to the requested path is forbidden"),
+    };
+    if entry.is_dir {
+        return serve_archive_directory(request, archive, entry, context);
+    }
+
+    let file_path = Path::new(&entry.path);
+    let applied = transform::for_path(&context.transforms, file_path);
+    let mut validators = Validators::for_entry(entry.size, entry.modified);
+    if !applied.is_empty() {
+        validators = validators.with_etag_token(&transform::etag_token(&applied));
+    }
+    if validators::is_not_modified(request, &validators) {
+        return with_immutable(request, validators.apply(HttpResponse::new(304)), &context.config);
+    }
+
+    // Unless it's transformed or compressed, the body needn't be in memory:
+    // HEAD only takes the entry's size, and a stored entry is sent straight
+    // out of the archive file
+    let config = &context.config;
+    if applied.is_empty()
+        && let Some(content_type) = archive_content_type(archive, entry, &context.mime_types)
+        && !encodes_in_memory(request, &content_type, entry.size, config)
+        && let Some(response) = stream_from_archive(request, archive, entry, &validators, &content_type)
+    {
+        return with_immutable(request, response, config);
+    }
+
+    let contents = match archive.read(entry) {
+        Ok(contents) if applied.is_empty() => contents,
+        Ok(contents) => transform::apply_all(&applied, &contents),
+        Err(e) => {
+            let error = ServerError::io(format!("reading {} from {}", entry.path, archive.path().display()), e);
+            return server_error(request, "The requested file could not be read", &error);
+        }
+    };
+    let content_type = get_content_type(file_path, &contents, &context.mime_types);
+    with_immutable(request, contents_response(request, &validators, contents, content_type), &context.config)
+}
+
+/// The content type of an archived file, when it can be told without
+/// inflating it: from its name, or from the first bytes of a stored file
+/// that has no extension
+fn archive_content_type(archive: &Archive, entry: &archive::Entry, mime_types: &MimeTypes) -> Option<String> {
+    let path = Path::new(&entry.path);
+    let mut prefix = Vec::new();
+    if path.extension().is_none() {
+        let reader = archive.stream(entry, 0).ok()??;
+        reader.take(mime::SNIFF_LEN as u64).read_to_end(&mut prefix).ok()?;
+    }
+    Some(get_content_type(path, &prefix, mime_types).to_string())
+}
+
+/// Answers with an archived file, whole or a range of it, without loading
+/// it: just the headers for HEAD, or the body read from the archive as it's
+/// sent. `None` if the entry is deflated and has to be read whole after all.
+fn stream_from_archive(
+    request: &HttpRequest,
+    archive: &Archive,
+    entry: &archive::Entry,
+    validators: &Validators,
+    content_type: &str,
+) -> Option<HttpResponse> {
+    let len = entry.size;
+    let (status, start, count) = match range::evaluate(request, validators, len) {
+        RangeRequest::Full => (200, 0, len),
+        RangeRequest::Partial { start, end } => (206, start, end - start + 1),
+        RangeRequest::Unsatisfiable => {
+            let response = error_response(request, 416, "The requested range is not satisfiable")
+                .with_header("Content-Range", &format!("bytes */{}", len));
+            return Some(response.with_header("Accept-Ranges", "bytes"));
+        }
+    };
+    let response = if request.method == Method::Head {
+        HttpResponse::new(status).with_omitted_body(count, content_type)
+    } else {
+        match archive.stream(entry, start) {
+            Ok(Some(reader)) => HttpResponse::new(status).with_sized_stream(reader.take(count), count, content_type),
+            Ok(None) => return None,
+            Err(e) => {
+                let error = ServerError::io(format!("reading {} from {}", entry.path, archive.path().display()), e);
+                return Some(server_error(request, "The requested file could not be read", &error));
+            }
+        }
+    };
+    let response = validators.apply(response);
+    let response = match status {
+        206 => response.with_header("Content-Range", &format!("bytes {}-{}/{}", start, start + count - 1, len)),
+        _ => response,
+    };
+    Some(response.with_header("Accept-Ranges", "bytes"))
+}
+
+/// An archived directory's `index.html`, or its listing when enabled
+fn serve_archive_directory(
+    request: &HttpRequest,
+    archive: &Archive,
+    dir: &archive::Entry,
+    context: &Context,
+) -> HttpResponse {
+    let url_path = request.path.as_str();
+    if !url_path.ends_with('/') {
+        return redirect_to_slash(request, url_path);
+    }
+    let index_path = format!("{}{}", url_path, DEFAULT_INDEX);
+    if let Ok(Some(index)) = archive.find(&index_path)
+        && !index.is_dir
+    {
+        let index_request = HttpRequest { path: index_path, ..request.clone() };
+        return serve_from_archive(&index_request, archive, context);
+    }
+    if !context.config.dir_listing {
+        return error_response(request, 403, "This directory has no index file and listing is disabled");
+    }
+
+    let entries = archive.list(dir);
+    let validators = listing::validators(dir.modified, &entries);
+    if validators::is_not_modified(request, &validators) {
+        return validators.apply(HttpResponse::new(304));
+    }
+    let html = listing::render(url_path, &entries);
+    validators
+        .apply(HttpResponse::new(200).with_body(html.into_bytes(), "text/html; charset=utf-8"))
+        .with_header("Accept-Ranges", "none")
+}
+
+/// Finds `url_path` in the first document root that has it. Returns `Err` if
+/// the path would escape the roots, by name or through a symlink; the check
+/// is the same for every root.
+fn resolve_in_roots(context: &Context, url_path: &str) -> Result<Option<(PathBuf, fs::Metadata)>, ()> {
+    for root in &context.roots {
+        let file_path = paths::safe_join(root.base(), url_path).ok_or(())?;
+        log_info!("Attempting to serve file: {}", file_path.display());
+        if let Ok(metadata) = fs::metadata(&file_path) {
+            // A symlink may lead out of the root, which is as bad as `..`
+            if !paths::is_contained(root, &file_path, context.config.follow_symlinks) {
+                log_info!("Refusing {}: it leaves the document root through a symlink", file_path.display());
+                return Err(());
+            }
+            return Ok(Some((file_path, metadata)));
+        }
+    }
+    Ok(None)
+}
+
+/// Sends a file's contents (or the requested byte range of them), or a 304 when
+/// the client's copy is still current
+fn serve_regular_file(
+    request: &HttpRequest,
+    file_path: &Path,
+    metadata: &fs::Metadata,
+    context: &Context,
+) -> HttpResponse {
+    // A precompressed sibling holds the file as stored, so it can't stand in
+    // for a transformed body
+    let applied = transform::for_path(&context.transforms, file_path);
+    let siblings = if context.config.precompressed && applied.is_empty() {
+        precompressed_siblings(file_path, metadata, context)
+    } else {
+        Vec::new()
+    };
+    let response = if siblings.is_empty() {
+        serve_file_contents(request, file_path, metadata, &applied, context)
+    } else {
+        // Which file answers depends on Accept-Encoding, whatever this client gets
+        let response = serve_precompressed(request, file_path, metadata, &siblings, context)
+            .unwrap_or_else(|| serve_file_contents(request, file_path, metadata, &[], context));
+        compress::with_vary(response)
+    };
+    with_immutable(request, response, &context.config)
+}
+
+/// Marks a file response cacheable for a year when `--immutable` covers it.
+/// A 304 carries the same caching headers as the full response would.
+fn with_immutable(request: &HttpRequest, response: HttpResponse, config: &Config) -> HttpResponse {
+    let cacheable = matches!(response.status.as_u16(), 200 | 206 | 304);
+    if cacheable && response.header("Cache-Control").is_none() && is_immutable(&request.path, config) {
+        response.with_header("Cache-Control", IMMUTABLE_CACHE_CONTROL)
+    } else {
+        response
+    }
+}
+
+/// Whether `--immutable` covers `path`, by path pattern or, ignoring case, by extension
+fn is_immutable(path: &str, config: &Config) -> bool {
+    config.immutable.iter().any(|pattern| match pattern.strip_prefix('*') {
+        Some(suffix) => {
+            let path = path.as_bytes();
+            path.len() >= suffix.len() && path[path.len() - suffix.len()..].eq_ignore_ascii_case(suffix.as_bytes())
+        }
+        None => router::pattern_matches(pattern, path),
+    })
+}
+
+/// A ready-made compressed copy of a file, e.g. `app.js.br` next to `app.js`
+struct Precompressed {
+    encoding: Encoding,
+    path: PathBuf,
+    metadata: fs::Metadata,
+}
+
+/// Finds the precompressed siblings of `file_path`, in preference order.
+/// Siblings older than the file were made from an earlier version, so they
+/// don't count. Each one is a file of its own, so it has to pass the same
+/// symlink checks as `file_path` did.
+fn precompressed_siblings(file_path: &Path, metadata: &fs::Metadata, context: &Context) -> Vec<Precompressed> {
+    let root = context.roots.iter().find(|root| file_path.starts_with(root.base()));
+    let modified = metadata.modified().ok();
+    let mut siblings = Vec::new();
+    for &encoding in Encoding::all() {
+        let mut path = file_path.as_os_str().to_owned();
+        path.push(encoding.file_suffix());
+        let path = PathBuf::from(path);
+        let Ok(sibling) = fs::metadata(&path) else {
+            continue;
+        };
+        if !sibling.is_file() {
+            continue;
+        }
+        if !root.is_some_and(|root| paths::is_contained(root, &path, context.config.follow_symlinks)) {
+            log_info!("Ignoring {}: it leaves the document root through a symlink", path.display());
+            continue;
+        }
+        if sibling.modified().ok() < modified {
+            log_info!("Ignoring {}: it is older than {}", path.display(), file_path.display());
+            continue;
+        }
+        siblings.push(Precompressed { encoding, path, metadata: sibling });
+    }
+    siblings
+}
+
+/// Answers with the best precompressed sibling the client accepts, or `None`
+/// when it accepts none of them or asks for a range, which is counted in the
+/// file's own bytes.
+///
+/// The sibling only supplies the body. The content type and the validators
+/// come from the original file, so that every encoding of it shares one
+/// ETag (weak here, as with on-the-fly compression, and the same on a 304).
+fn serve_precompressed(
+    request: &HttpRequest,
+    file_path: &Path,
+    metadata: &fs::Metadata,
+    siblings: &[Precompressed],
+    context: &Context,
+) -> Option<HttpResponse> {
+    if request.header("Range").is_some() {
+        return None;
+    }
+    let available: Vec<Encoding> = siblings.iter().map(|sibling| sibling.encoding).collect();
+    let encoding = compress::negotiate_among(request.header("Accept-Encoding")?, &available)?;
+    let sibling = siblings.iter().find(|sibling| sibling.encoding == encoding)?;
+
+    let validators = Validators::for_file(metadata);
+    if validators::is_not_modified(request, &validators) {
+        let mut response = validators.apply(HttpResponse::new(304));
+        compress::weaken_etag(&mut response);
+        return Some(response);
+    }
+
+    let content_type = directory_content_type(file_path, context)
+        .unwrap_or_else(|| head_content_type(file_path, &context.mime_types));
+    let response = if request.method == Method::Head {
+        HttpResponse::new(200).with_omitted_body(sibling.metadata.len(), &content_type)
+    } else {
+        let len = sibling.metadata.len();
+        match context.cache.open(&sibling.path, &sibling.metadata, 0, len) {
+            Ok(reader) => HttpResponse::new(200).with_sized_stream(reader, len, &content_type),
+            Err(e) => return Some(file_error_response(request, &sibling.path, e)),
+        }
+    };
+    log_info!("Serving precompressed {}", sibling.path.display());
+
+    let mut response = validators.apply(response);
+    compress::weaken_etag(&mut response);
+    Some(compress::without_ranges(response).with_header("Content-Encoding", encoding.token()))
+}
+
+/// Serves the file itself: whole, as a range, or just its headers for HEAD
+fn serve_file_contents(
+    request: &HttpRequest,
+    file_path: &Path,
+    metadata: &fs::Metadata,
+    applied: &[&Transform],
+    context: &Context,
+) -> HttpResponse {
+    // Answer conditional requests before reading the file at all
+    let mut validators = Validators::for_file(metadata);
+    if !applied.is_empty() {
+        validators = validators.with_etag_token(&transform::etag_token(applied));
+    }
+    if validators::is_not_modified(request, &validators) {
+        return file_not_modified(request, &validators, file_path, metadata, context);
+    }
+
+    // Unless it's transformed or compressed, the body needn't be in memory:
+    // HEAD only takes the length, which the metadata already has, and GET
+    // reads the file as it's sent. A range is never compressed, so its
+    // headers follow from the metadata alone too. Download managers rely on
+    // this to probe resumability.
+    let len = metadata.len();
+    if applied.is_empty() {
+        let content_type = directory_content_type(file_path, context)
+            .unwrap_or_else(|| head_content_type(file_path, &context.mime_types));
+        let range = range::evaluate(request, &validators, len);
+        if range != RangeRequest::Full || !encodes_in_memory(request, &content_type, len, &context.config) {
+            return stream_file(request, file_path, metadata, &validators, range, &content_type, context);
+        }
+    }
+
+    // Otherwise only building the body gives its length, for HEAD as well,
+    // and that takes the whole file in memory. A file too large to hold is
+    // only ever streamed, which a transformed one can't be.
+    if len > MAX_BUFFERED_FILE {
+        log_error!("Refusing to transform {}: at {} bytes it is too large to hold in memory", file_path.display(), len);
+        return error_response(request, 500, "The requested file is too large to transform");
+    }
+    match context.cache.read(file_path, metadata) {
+        Ok(contents) => {
+            // The cache keeps the file as stored; transforms run on every read.
+            // Bytes the cache holds on to are copied, others are taken over.
+            let contents = match applied {
+                [] => Arc::try_unwrap(contents).unwrap_or_else(|shared| shared.to_vec()),
+                _ => transform::apply_all(applied, &contents),
+            };
+            let content_type = directory_content_type(file_path, context)
+                .unwrap_or_else(|| get_content_type(file_path, &contents, &context.mime_types).to_string());
+            contents_response(request, &validators, contents, &content_type)
+        },
+        Err(e) => file_error_response(request, file_path, e),
+    }
+}
+
+/// Whether a `len`-byte file of `content_type` would be compressed for
+/// `request`, which means reading it into memory first. Files larger than
+/// `MAX_BUFFERED_FILE` are streamed as they are instead.
+fn encodes_in_memory(request: &HttpRequest, content_type: &str, len: u64, config: &Config) -> bool {
+    config.compression
+        && len <= MAX_BUFFERED_FILE
+        && compress::may_encode(request, content_type, len, &config.compress_types)
+}
+
+/// Answers with a file, whole or the `range` of it, without loading it: just
+/// the headers for HEAD, or the body read from the cache or disk as it's sent
+fn stream_file(
+    request: &HttpRequest,
+    file_path: &Path,
+    metadata: &fs::Metadata,
+    validators: &Validators,
+    range: RangeRequest,
+    content_type: &str,
+    context: &Context,
+) -> HttpResponse {
+    let len = metadata.len();
+    let (status, start, count) = match range {
+        RangeRequest::Full => (200, 0, len),
+        RangeRequest::Partial { start, end } => (206, start, end - start + 1),
+        RangeRequest::Unsatisfiable => {
+            return error_response(request, 416, "The requested range is not satisfiable")
+                .with_header("Content-Range", &format!("bytes */{}", len))
+                .with_header("Accept-Ranges", "bytes");
+        }
+    };
+    let response = if request.method == Method::Head {
+        HttpResponse::new(status).with_omitted_body(count, content_type)
+    } else {
+        match context.cache.open(file_path, metadata, start, count) {
+            Ok(reader) => HttpResponse::new(status).with_sized_stream(reader, count, content_type),
+            Err(e) => return file_error_response(request, file_path, e),
+        }
+    };
+    let response = validators.apply(response);
+    let response = match status {
+        206 => response.with_header("Content-Range", &format!("bytes {}-{}/{}", start, start + count - 1, len)),
+        _ => response,
+    };
+    response.with_header("Accept-Ranges", "bytes")
+}
+
+/// The 304 for a file, with the headers its 200 would have had for this
+/// client: `Vary` when its type gets compressed, and the weak ETag when this
+/// client would have been sent it encoded
+fn file_not_modified(
+    request: &HttpRequest,
+    validators: &Validators,
+    file_path: &Path,
+    metadata: &fs::Metadata,
+    context: &Context,
+) -> HttpResponse {
+    let mut response = validators.apply(HttpResponse::new(304));
+    let config = &context.config;
+    if !config.compression {
+        return response;
+    }
+    let content_type = directory_content_type(file_path, context)
+        .unwrap_or_else(|| head_content_type(file_path, &context.mime_types));
+    if !compress::is_compressible(&content_type, &config.compress_types) {
+        return response;
+    }
+    if encodes_in_memory(request, &content_type, metadata.len(), config) {
+        compress::weaken_etag(&mut response);
+    }
+    compress::with_vary(response)
+}
+
+/// Answers with a file's `contents`, whole or the requested range of them
+fn contents_response(
+    request: &HttpRequest,
+    validators: &Validators,
+    contents: Vec<u8>,
+    content_type: &str,
+) -> HttpResponse {
+    let len = contents.len() as u64;
+    let response = match range::evaluate(request, validators, len) {
+        // File found, send it with a 200 OK response
+        RangeRequest::Full => validators.apply(HttpResponse::new(200).with_body(contents, content_type)),
+        RangeRequest::Partial { start, end } => {
+            let part = contents[start as usize..=end as usize].to_vec();
+            validators
+                .apply(HttpResponse::new(206).with_body(part, content_type))
+                .with_header("Content-Range", &format!("bytes {}-{}/{}", start, end, len))
+        }
+        RangeRequest::Unsatisfiable => error_response(request, 416, "The requested range is not satisfiable")
+            .with_header("Content-Range", &format!("bytes */{}", len)),
+    };
+    // Files are seekable, so they are the only responses that advertise ranges
+    response.with_header("Accept-Ranges", "bytes")
+}
+
+/// Maps a failure to read a file that was just found to the status that
+/// explains it, so unreadable files don't masquerade as missing ones
+fn file_error_response(request: &HttpRequest, file_path: &Path, error: io::Error) -> HttpResponse {
+    match error.kind() {
+        // Deleted between the lookup and the read
+        ErrorKind::NotFound => {
+            log_info!("File {} disappeared before it could be read", file_path.display());
+            error_response(request, 404, "The requested file was not found")
+        }
+        ErrorKind::PermissionDenied => {
+            log_error!("Permission denied reading {}: {}", file_path.display(), error);
+            error_response(request, 403, "The server is not allowed to read the requested file")
+        }
+        _ => {
+            let error = ServerError::io(format!("reading {}", file_path.display()), error);
+            server_error(request, "The requested file could not be read", &error)
+        }
+    }
+}
+
+/// The content type given to `file_path` by a `.mime` file in its directory, if any
+fn directory_content_type(file_path: &Path, context: &Context) -> Option<String> {
+    let mime_file = file_path.parent()?.join(mime::DIRECTORY_MIME_FILE);
+    let fold_case = !context.config.strict_extensions;
+    let types = context
+        .directory_types
+        .get(&mime_file, |contents, path| MimeTypes::parse_directory_file(contents, path, fold_case))?;
+    let extension = file_path.extension().and_then(|ext| ext.to_str());
+    types.lookup_override(extension).map(str::to_string)
+}
+
+/// The content type a GET of `file_path` would report, without reading the
+/// whole file. Only extensionless files need their first bytes sniffed.
+fn head_content_type(file_path: &Path, mime_types: &MimeTypes) -> String {
+    let mut prefix = Vec::new();
+    if file_path.extension().is_none()
+        && let Ok(file) = fs::File::open(file_path)
+    {
+        let _ = file.take(mime::SNIFF_LEN as u64).read_to_end(&mut prefix);
+    }
+    get_content_type(file_path, &prefix, mime_types).to_string()
+}
+
+/// A missing path is treated as an app route when it has no file extension
+/// (so broken asset links still 404) and the client explicitly asks for HTML
+fn is_spa_route(request: &HttpRequest) -> bool {
+    if Path::new(&request.path).extension().is_some() {
+        return false;
+    }
+    let ranges = negotiate::parse_header(request.header("Accept").unwrap_or(""));
+    let explicit_html = ranges.iter().any(|range| range.value.eq_ignore_ascii_case("text/html"));
+    explicit_html && negotiate::media_quality(&ranges, "text/html") > 0.0
+}
+
+/// The index files to try for `dir`, in order: those named in its `.index`
+/// file, or `DEFAULT_INDEX` when it has none
+fn index_names(dir: &Path, context: &Context) -> Arc<Vec<String>> {
+    context
+        .directory_indexes
+        .get(&dir.join(DIRECTORY_INDEX_FILE), parse_index_file)
+        .unwrap_or_else(|| Arc::new(vec![DEFAULT_INDEX.to_string()]))
+}
+
+/// Parses an `.index` file: one file name per line, `#` starting a comment.
+/// Names must be files in the directory itself, so paths are skipped.
+fn parse_index_file(contents: &str, path: &Path) -> Vec<String> {
+    let mut names = Vec::new();
+    for line in contents.lines() {
+        let name = line.split('#').next().unwrap_or("").trim();
+        if name.is_empty() {
+            continue;
+        }
+        if name.contains('/') || name == ".." {
+            log_error!("Warning: skipping index name {} in {}, it must be a plain file name", name, path.display());
+            continue;
+        }
+        names.push(name.to_string());
+    }
+    names
+}
+
+/// Whether `request` gets the welcome page: it asks for `/`, which would
+/// otherwise be an error because there's no content at all yet
+fn is_welcome(request: &HttpRequest, context: &Context) -> bool {
+    let config = &context.config;
+    config.welcome && request.path == "/" && listing::roots_are_empty(&config.roots)
+}
+
+/// The built-in welcome page. It mustn't be cached, since it stops applying
+/// as soon as the first file is added.
+fn welcome_page(context: &Context) -> HttpResponse {
+    log_info!("Document root is empty, serving the welcome page");
+    let html = listing::render_welcome(&context.config.roots);
+    HttpResponse::new(StatusCode::OK)
+        .with_body(html.into_bytes(), "text/html; charset=utf-8")
+        .with_header("Cache-Control", "no-store")
+}
+
+/// Serves a directory's index file, or a generated listing when enabled
+fn serve_directory(
+    request: &HttpRequest,
+    url_path: &str,
+    dir: &Path,
+    context: &Context,
+) -> HttpResponse {
+    let config = &context.config;
+    if !url_path.ends_with('/') {
+        return redirect_to_slash(request, url_path);
+    }
+
+    // The index may come from a later root than the directory itself
+    for name in index_names(dir, context).iter() {
+        let index_path = format!("{}{}", url_path, name);
+        if let Ok(Some((_, metadata))) = resolve_in_roots(context, &index_path)
+            && metadata.is_file()
+        {
+            let index_request = HttpRequest { path: index_path, ..request.clone() };
+            return serve_file(&index_request, context);
+        }
+    }
+
+    // The directory exists but has nothing we're allowed to show
+    if !config.dir_listing && is_welcome(request, context) {
+        return welcome_page(context);
+    }
+    if !config.dir_listing {
+        return error_response(request, 403, "This directory has no index file and listing is disabled");
+    }
+
+    let entries = match listing::read_entries(dir) {
+        Ok(entries) => entries,
+        Err(e) => {
+            let error = ServerError::io(format!("listing {}", dir.display()), e);
+            return server_error(request, "Failed to read directory", &error);
+        }
+    };
+
+    // Listings get validators too, so an unchanged directory can return 304
+    let dir_modified = fs::metadata(dir).and_then(|metadata| metadata.modified()).ok();
+    let validators = listing::validators(dir_modified, &entries);
+    if validators::is_not_modified(request, &validators) {
+        return validators.apply(HttpResponse::new(304));
+    }
+
+    let html = listing::render(url_path, &entries);
+    validators
+        .apply(HttpResponse::new(200).with_body(html.into_bytes(), "text/html; charset=utf-8"))
+        .with_header("Accept-Ranges", "none")
+}
+
+/// Relative links in a directory only work if its URL ends with a slash,
+/// so a directory asked for without one is redirected to it
+fn redirect_to_slash(request: &HttpRequest, url_path: &str) -> HttpResponse {
+    let location = match &request.query {
+        Some(query) => format!("{}/?{}", url_path, query),
+        None => format!("{}/", url_path),
+    };
+    HttpResponse::text(301, "Moved Permanently").with_header("Location", &location)
+}
+
+/// Builds an error response whose body format follows the request's `Accept` header:
+/// JSON for API clients and plain text otherwise. Browsers may get a custom page
+/// instead, see `apply_error_page`.
+pub(crate) fn error_response(request: &HttpRequest, status: impl Into<StatusCode>, message: &str) -> HttpResponse {
+    let status = status.into();
+    let ranges = negotiate::parse_header(request.header("Accept").unwrap_or("*/*"));
+    let json_quality = negotiate::media_quality(&ranges, "application/json");
+    let html_quality = negotiate::media_quality(&ranges, "text/html");
+    let text_quality = negotiate::media_quality(&ranges, "text/plain");
+
+    let response = if json_quality > 0.0 && json_quality > text_quality && json_quality >= html_quality {
+        let body = format!(
+            "{{\"status\":{},\"error\":{},\"message\":{}}}",
+            status,
+            access_log::json_string(status.canonical_reason()),
+            access_log::json_string(Some(message))
+        );
+        HttpResponse::new(status).with_body(body.into_bytes(), "application/json")
+    } else {
+        HttpResponse::text(status, message)
+    };
+
+    response.with_header("Vary", "Accept")
+}
+
+/// Builds a 500 whose body carries only the generic `message`, logging the
+/// cause. The cause is kept on the response for `--verbose-errors`.
+pub(crate) fn server_error(request: &HttpRequest, message: &str, error: &ServerError) -> HttpResponse {
+    log_error!("{}: {}", message, error);
+    let mut response = error_response(request, StatusCode::INTERNAL_SERVER_ERROR, message);
+    response.error_detail = Some(format!("{} ({})", message, error));
+    response
+}
+
+/// Swaps a 5xx's generic body for one that includes the internal cause
+fn show_error_detail(request: &HttpRequest, mut response: HttpResponse) -> HttpResponse {
+    match response.error_detail.take() {
+        Some(detail) => error_response(request, response.status, &detail),
+        None => response,
+    }
+}
+
+/// Replaces a plain-text error body with the custom `<status>.html` page from the
+/// first document root that has one, when the client prefers HTML
+fn apply_error_page(request: &HttpRequest, response: HttpResponse, roots: &[PathBuf]) -> HttpResponse {
+    let plain_text = response.header("Content-Type").is_some_and(|t| t.starts_with("text/plain"));
+    let is_error = response.status.is_client_error() || response.status.is_server_error();
+    if !is_error || !plain_text {
+        return response;
+    }
+
+    let ranges = negotiate::parse_header(request.header("Accept").unwrap_or("*/*"));
+    let html_quality = negotiate::media_quality(&ranges, "text/html");
+    if html_quality <= 0.0 || html_quality < negotiate::media_quality(&ranges, "text/plain") {
+        return response;
+    }
+
+    let page_name = format!("{}.html", response.status);
+    let Some(page) = roots.iter().find_map(|root| fs::read(root.join(&page_name)).ok()) else {
+        return response;
+    };
+
+    let mut headers = response.headers;
+    headers.retain(|(name, _)| !name.eq_ignore_ascii_case("Content-Type"));
+    let mut page_response = HttpResponse { headers, body: Vec::new(), ..HttpResponse::new(response.status) }
+        .with_body(page, "text/html; charset=utf-8");
+    if page_response.header("Vary").is_none() {
+        page_response = page_response.with_header("Vary", "Accept");
+    }
+    page_response
+}
+
+/// Sends an HTTP response to the client, with the `Connection` header matching `disposition`.
+/// With `head_only` the headers are the same but the body is left out. Header
+/// names are spelled in the configured case, in the order the response lists
+/// them, followed by any configured `--header`s the response didn't set.
+/// Returns how much of the body got out, and whether all of it did.
+fn send_response<W: Write>(
+    stream: &mut W,
+    mut response: HttpResponse,
+    disposition: Disposition,
+    head_only: bool,
+    config: &Config,
+) -> Sent {
+    let header_case = config.header_case;
+    // Create the status line and standard headers
+    let mut head = format!(
+        "{} {} {}\r\n",
+        response.version.unwrap_or(HTTP_VERSION),
+        response.status,
+        response.status.canonical_reason().unwrap_or("Unknown")
+    );
+    let mut push_header = |name: &str, value: &str| {
+        head.push_str(&format!("{}: {}\r\n", header_case.apply(name), value));
+    };
+    push_header("Server", SERVER_NAME);
+    // Deterministic mode freezes the clock at the epoch, over any Date a
+    // proxied upstream sent too; otherwise an upstream's own Date stands
+    if config.deterministic {
+        push_header("Date", &http_date::format_http_date(SystemTime::UNIX_EPOCH));
+    } else if response.header("Date").is_none() {
+        push_header("Date", &http_date::format_http_date(SystemTime::now()));
+    }
+
+    // A 204 or 304 must not carry a body, so it doesn't get a Content-Length
+    // or chunked framing either. A chunked body's length isn't known up front.
+    let allows_body = response.status.allows_body();
+    if !allows_body && response.discard_body() {
+        log_error!("Warning: dropping the body of a {} response, which mustn't have one", response.status);
+    }
+    let chunked = response.is_chunked();
+    if chunked {
+        push_header("Transfer-Encoding", "chunked");
+        if !response.trailers.is_empty() {
+            let names: Vec<String> = response.trailers.iter().map(|(name, _)| header_case.apply(name)).collect();
+            push_header("Trailer", &names.join(", "));
+        }
+    } else if allows_body && !response.close_delimited {
+        push_header("Content-Length", &response.content_length().to_string());
+    }
+    for (name, value) in &response.headers {
+        if !(config.deterministic && name.eq_ignore_ascii_case("Date")) {
+            push_header(name, value);
+        }
+    }
+    // Configured headers fill in whatever the response didn't set itself
+    for (name, value) in &config.headers {
+        if response.header(name).is_none() {
+            push_header(name, value);
+        }
+    }
+    match disposition {
+        Disposition::KeepAlive { timeout, max } => {
+            push_header("Connection", "keep-alive");
+            // The countdown differs for every request on the connection
+            if config.deterministic {
+                push_header("Keep-Alive", &format!("timeout={}", timeout.as_secs()));
+            } else {
+                push_header("Keep-Alive", &format!("timeout={}, max={}", timeout.as_secs(), max));
+            }
+        }
+        Disposition::Close => push_header("Connection", "close"),
+    }
+    head.push_str("\r\n");
+
+    // Write the headers followed by the body to the stream.
+    // Only the body counts towards --rate-limit-bps.
+    let mut stream = CountingWriter { inner: stream, written: 0 };
+    let result = stream.write_all(head.as_bytes()).and_then(|_| {
+        if head_only {
+            return Ok(0);
+        }
+        match config.rate_limit_bps {
+            Some(bytes_per_sec) => {
+                write_body(&mut Throttle::new(&mut stream, bytes_per_sec), &mut response, header_case)
+            }
+            None => write_body(&mut stream, &mut response, header_case),
+        }
+    });
+    let e = match result {
+        Ok(bytes) => {
+            log_info!("Response sent successfully");
+            return Sent { body_bytes: bytes, complete: true };
+        },
+        Err(e) => e,
+    };
+    // A client cancelling a download is routine, not a server failure:
+    // stop writing and let the connection close
+    if is_disconnect(&e) && stream.written == 0 {
+        log_info!("Client disconnected before the response was sent: {}", e);
+    } else if is_disconnect(&e) {
+        log_info!("Client disconnected after {} bytes of the response, abandoning it: {}", stream.written, e);
+    } else if stream.written > 0 {
+        // Once the headers are out the status can't change any more, so the
+        // body is left cut short, without the chunk that would end it, and
+        // the connection closes so the client can tell
+        log_error!("Response failed after {} bytes, closing the connection: {}", stream.written, e);
+    } else {
+        log_error!("Failed to send response: {}", e);
+    }
+    // What got past the head is what the client received of the body
+    Sent { body_bytes: stream.written.saturating_sub(head.len()), complete: false }
+}
+
+/// How much of a response reached the client
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Sent {
+    /// Body bytes written, counting those of a response cut short
+    body_bytes: usize,
+    /// Whether the whole response was written
+    complete: bool,
+}
+
+/// Passes writes through, at most `MAX_WRITE` bytes at a time, counting the
+/// bytes that made it out
+struct CountingWriter<'a, W> {
+    inner: &'a mut W,
+    written: usize,
+}
+
+impl<W: Write> Write for CountingWriter<'_, W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let size = self.inner.write(&buf[..buf.len().min(MAX_WRITE)])?;
+        self.written += size;
+        Ok(size)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Writes `response`'s body as its headers announced it: streamed or
+/// trailered bodies in chunks unless the close ends them, anything else as is
+fn write_body<W: Write>(stream: &mut W, response: &mut HttpResponse, header_case: HeaderCase) -> io::Result<usize> {
+    let sized = response.content_length.filter(|_| !response.is_chunked());
+    match response.stream.take() {
+        Some(mut source) if response.close_delimited => write_until_end(stream, &mut source),
+        Some(mut source) if let Some(len) = sized => write_sized(stream, &mut source, len),
+        Some(mut source) => write_chunked(stream, &mut source, &response.trailers, header_case),
+        None if response.is_chunked() => {
+            write_chunked(stream, &mut response.body.as_slice(), &response.trailers, header_case)
+        }
+        None => stream.write_all(&response.body).map(|_| response.body.len()),
+    }
+}
+
+/// Copies `source` to the stream as it is, for a body whose end is the
+/// connection's. Returns the number of body bytes written.
+fn write_until_end<W: Write, R: Read + ?Sized>(stream: &mut W, source: &mut R) -> io::Result<usize> {
+    let mut chunk = vec![0; CHUNK_SIZE];
+    let mut total = 0;
+    loop {
+        let size = match source.read(&mut chunk) {
+            Ok(0) => return Ok(total),
+            Ok(size) => size,
+            Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+            Err(e) => return Err(io::Error::other(format!("reading the body: {}", e))),
+        };
+        stream.write_all(&chunk[..size])?;
+        // An event stream trickles; each piece should reach the client as it comes
+        stream.flush()?;
+        total += size;
+    }
+}
+
+/// Copies the `len` bytes of a stream whose headers announced its length.
+/// One that ends short fails, as the client would otherwise wait for the rest.
+fn write_sized<W: Write, R: Read + ?Sized>(stream: &mut W, source: &mut R, len: u64) -> io::Result<usize> {
+    let written = write_until_end(stream, &mut source.take(len))?;
+    if (written as u64) < len {
+        return Err(io::Error::other(format!("reading the body: it ended after {} of {} bytes", written, len)));
+    }
+    Ok(written)
+}
+
+/// Copies `source` to the stream with chunked transfer coding, ending with
+/// the trailer fields. Returns the number of body bytes written.
+fn write_chunked<W: Write, R: Read + ?Sized>(
+    stream: &mut W,
+    source: &mut R,
+    trailers: &[(String, String)],
+    header_case: HeaderCase,
+) -> io::Result<usize> {
+    let mut chunk = vec![0; CHUNK_SIZE];
+    let mut total = 0;
+    loop {
+        let size = match source.read(&mut chunk) {
+            Ok(0) => break,
+            Ok(size) => size,
+            Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+            // Kept apart from write errors, which may just mean the client left
+            Err(e) => return Err(io::Error::other(format!("reading the body: {}", e))),
+        };
+        stream.write_all(format!("{:x}\r\n", size).as_bytes())?;
+        stream.write_all(&chunk[..size])?;
+        stream.write_all(b"\r\n")?;
+        total += size;
+    }
+
+    let mut end = String::from("0\r\n");
+    for (name, value) in trailers {
+        end.push_str(&format!("{}: {}\r\n", header_case.apply(name), value));
+    }
+    end.push_str("\r\n");
+    stream.write_all(end.as_bytes())?;
+    Ok(total)
+}
+
+/// Returns true for IO errors that just mean the client went away
+fn is_disconnect(error: &io::Error) -> bool {
+    matches!(
+        error.kind(),
+        ErrorKind::ConnectionReset | ErrorKind::ConnectionAborted | ErrorKind::BrokenPipe
+    )
+}
+
+/// Determines the Content-Type header based on the file extension, falling back to the content
+fn get_content_type<'a>(path: &Path, content: &[u8], mime_types: &'a MimeTypes) -> &'a str {
+    // Prefer the extension when the file has one
+    if let Some(extension) = path.extension().and_then(|ext| ext.to_str()) {
+        return mime_types.lookup(extension).unwrap_or(mime::DEFAULT_MIME_TYPE);
+    }
+
+    // Extensionless files are recognised by their leading bytes
+    mime::sniff(content)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use super::*;
+    use crate::testing::{self, Response, TestServer, TempDir, read_response, read_to_close};
+
+    fn parse(head: &str) -> HttpRequest {
+        http::parse_request(head.as_bytes(), Default::default()).expect("test request should parse")
+    }
+
+    #[test]
+    fn connections_over_the_limit_get_503() {
+        let root = TempDir::new();
+        let server = TestServer::with_args(&[
+            "--root", root.path().to_str().unwrap(),
+            "--max-connections", "2",
+            "--retry-after", "7",
+        ]);
+        // Two connections that never send a request hold both slots
+        let held: Vec<TcpStream> = (0..2).map(|_| server.connect()).collect();
+        std::thread::sleep(Duration::from_millis(100));
+
+        let mut over = server.connect();
+        let response = Response::parse(&read_to_close(&mut over));
+        assert_eq!(response.status, 503);
+        assert_eq!(response.header("Retry-After"), Some("7"));
+        assert_eq!(response.header("Connection"), Some("close"));
+
+        // Closing one frees its slot for the next client
+        drop(held);
+        std::thread::sleep(Duration::from_millis(100));
+        assert_eq!(server.get("/missing").status, 404);
+    }
+
+    #[test]
+    fn rate_limited_clients_get_429_with_retry_after() {
+        let server = TestServer::with_args(&["--rate-limit", "1"]);
+        assert_eq!(server.get("/missing").status, 404);
+
+        let limited = server.get("/missing");
+        assert_eq!(limited.status, 429);
+        let seconds: u64 = limited.header("Retry-After").expect("429 needs Retry-After").parse().unwrap();
+        assert!(seconds >= 1);
+    }
+
+    #[test]
+    fn full_queue_under_reject_gets_503_with_retry_after() {
+        let server = TestServer::with_args(&["--workers", "1", "--overflow-policy", "reject", "--retry-after", "3"]);
+        // One connection keeps the worker busy, then enough more fill the queue behind it
+        let busy = server.connect();
+        thread::sleep(Duration::from_millis(100));
+        let queued: Vec<TcpStream> = (0..thread_pool::QUEUED_PER_WORKER).map(|_| server.connect()).collect();
+        thread::sleep(Duration::from_millis(100));
+
+        let mut rejected = server.connect();
+        let response = Response::parse(&read_to_close(&mut rejected));
+        assert_eq!(response.status, 503);
+        let seconds: u64 = response.header("Retry-After").expect("503 needs Retry-After").parse().unwrap();
+        assert_eq!(seconds, 3);
+        drop((busy, queued));
+    }
+
+    #[test]
+    fn disposition_follows_version_connection_header_and_limits() {
+        let config = testing::config(&["--keepalive-max", "3"]);
+        let keep = |head: &str, status: u16, served| {
+            Disposition::decide(Some(&parse(head)), StatusCode::from(status), true, served, &config)
+        };
+        let alive = |max| Disposition::KeepAlive { timeout: config.keepalive_timeout, max };
+
+        assert_eq!(keep("GET / HTTP/1.1\r\nHost: a\r\n\r\n", 200, 1), alive(2));
+        assert_eq!(keep("GET / HTTP/1.1\r\nHost: a\r\nConnection: close\r\n\r\n", 200, 1), Disposition::Close);
+        assert_eq!(keep("GET / HTTP/1.0\r\n\r\n", 200, 1), Disposition::Close);
+        assert_eq!(keep("GET / HTTP/1.0\r\nConnection: Keep-Alive\r\n\r\n", 200, 1), alive(2));
+        // Errors that leave the stream in doubt and a spent budget both close
+        assert_eq!(keep("GET / HTTP/1.1\r\nHost: a\r\n\r\n", 400, 1), Disposition::Close);
+        assert_eq!(keep("GET / HTTP/1.1\r\nHost: a\r\n\r\n", 200, 3), Disposition::Close);
+        assert_eq!(Disposition::decide(None, StatusCode::from(400), true, 0, &config), Disposition::Close);
+    }
+
+    #[test]
+    fn error_response_closes_a_keep_alive_connection() {
+        let root = TempDir::new();
+        root.write("x.txt", "A");
+        let server = TestServer::with_args(&["--root", root.path().to_str().unwrap()]);
+        let mut stream = server.connect();
+
+        stream.write_all(b"GET /x.txt HTTP/1.1\r\nHost: a\r\n\r\n").unwrap();
+        let first = read_response(&mut stream);
+        assert_eq!(first.status, 200);
+        assert_eq!(first.header("Connection"), Some("keep-alive"));
+
+        // A body length that can't be read leaves the stream in doubt
+        stream.write_all(b"GET /x.txt HTTP/1.1\r\nHost: a\r\nContent-Length: ten\r\n\r\n").unwrap();
+        let second = Response::parse(&read_to_close(&mut stream));
+        assert_eq!(second.status, 400);
+        assert_eq!(second.header("Connection"), Some("close"));
+        assert_eq!(second.header("Keep-Alive"), None);
+    }
+
+    #[test]
+    fn head_reports_the_length_of_a_large_file_without_reading_it() {
+        let root = TempDir::new();
+        // Sparse, so it costs nothing to create but would take a while to read
+        let size = 4u64 << 30;
+        fs::File::create(root.path().join("movie.mp4")).unwrap().set_len(size).unwrap();
+        let server = TestServer::with_args(&["--root", root.path().to_str().unwrap()]);
+
+        let started = Instant::now();
+        let response = server.request("HEAD", "/movie.mp4", &[]);
+        assert!(started.elapsed() < Duration::from_secs(2), "HEAD took {:?}", started.elapsed());
+        assert_eq!(response.status, 200);
+        assert_eq!(response.header("Content-Length"), Some(size.to_string().as_str()));
+        assert_eq!(response.header("Content-Type"), Some("video/mp4"));
+        assert!(response.body.is_empty());
+    }
+
+    #[test]
+    fn read_errors_map_to_their_own_status() {
+        let request = parse("GET /f HTTP/1.1\r\nHost: a\r\n\r\n");
+        let status = |kind| file_error_response(&request, Path::new("f"), io::Error::from(kind)).status.as_u16();
+        assert_eq!(status(ErrorKind::NotFound), 404);
+        assert_eq!(status(ErrorKind::PermissionDenied), 403);
+        assert_eq!(status(ErrorKind::InvalidData), 500);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn unreadable_file_is_403() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let root = TempDir::new();
+        let secret = root.write("secret.txt", "hidden");
+        fs::set_permissions(&secret, fs::Permissions::from_mode(0o000)).unwrap();
+        // Root reads anything, so there is no permission error to see
+        if fs::File::open(&secret).is_ok() {
+            return;
+        }
+        let server = TestServer::with_args(&["--root", root.path().to_str().unwrap()]);
+        assert_eq!(server.get("/secret.txt").status, 403);
+        assert_eq!(server.get("/absent.txt").status, 404);
+    }
+
+    /// Decodes a chunked body with the same reader that takes uploads,
+    /// returning the data and the trailer fields
+    fn decode_chunked(encoded: &[u8]) -> (Vec<u8>, Vec<(String, String)>) {
+        let request = parse("POST / HTTP/1.1\r\nHost: a\r\nTransfer-Encoding: chunked\r\n\r\n");
+        let mut stream = io::Cursor::new(Vec::new());
+        let mut buffer = encoded.to_vec();
+        let mut body = RequestBody::new(&request, &mut stream, &mut buffer, u64::MAX).unwrap();
+        let data = body.read_all().expect("the chunked body should decode");
+        (data, body.trailers().to_vec())
+    }
+
+    #[test]
+    fn chunked_writer_sends_trailers_after_the_last_chunk() {
+        let data = vec![b'z'; CHUNK_SIZE + 10];
+        let trailers = vec![("x-checksum".to_string(), "abc123".to_string())];
+        let mut encoded = Vec::new();
+        let written = write_chunked(&mut encoded, &mut data.as_slice(), &trailers, HeaderCase::Canonical).unwrap();
+        assert_eq!(written, data.len());
+        assert!(encoded.ends_with(b"0\r\nX-Checksum: abc123\r\n\r\n"));
+
+        let (decoded, received) = decode_chunked(&encoded);
+        assert_eq!(decoded, data);
+        assert_eq!(received, vec![("X-Checksum".to_string(), "abc123".to_string())]);
+    }
+
+    #[test]
+    fn trailers_are_announced_and_sent_over_http_1_1() {
+        let mut server = Server::bind(testing::config(&[])).unwrap();
+        server.context.router.route(Method::Get, "/signed", |_, _| {
+            HttpResponse::new(StatusCode::OK)
+                .with_stream(io::Cursor::new(b"payload".to_vec()), "text/plain")
+                .with_trailer("X-Checksum", "c0ffee")
+        });
+        let server = TestServer::serve(server);
+
+        let raw = server.exchange(b"GET /signed HTTP/1.1\r\nHost: a\r\nConnection: close\r\n\r\n");
+        let response = Response::parse(&raw);
+        assert_eq!(response.header("Transfer-Encoding"), Some("chunked"));
+        assert_eq!(response.header("Trailer"), Some("X-Checksum"));
+        let head_end = raw.windows(4).position(|window| window == b"\r\n\r\n").unwrap() + 4;
+        let (body, trailers) = decode_chunked(&raw[head_end..]);
+        assert_eq!(body, b"payload");
+        assert_eq!(trailers, vec![("X-Checksum".to_string(), "c0ffee".to_string())]);
+
+        // HTTP/1.0 can't carry them, so it gets the plain body alone, ended by the close
+        let old = server.send(b"GET /signed HTTP/1.0\r\nConnection: keep-alive\r\n\r\n");
+        assert_eq!(old.header("Trailer"), None);
+        assert_eq!(old.header("Transfer-Encoding"), None);
+        assert_eq!(old.header("Content-Length"), None);
+        assert_eq!(old.header("Connection"), Some("close"));
+        assert_eq!(old.body, b"payload");
+    }
+
+    #[test]
+    fn spa_fallback_serves_routes_but_not_missing_assets() {
+        let root = TempDir::new();
+        let index = root.write("index.html", "<div id=app></div>");
+        let server = TestServer::with_args(&[
+            "--root", root.path().to_str().unwrap(),
+            "--spa-fallback", index.to_str().unwrap(),
+        ]);
+        let html = [("Accept", "text/html,application/xhtml+xml,*/*;q=0.8")];
+
+        let route = server.request("GET", "/some/route", &html);
+        assert_eq!(route.status, 200);
+        assert_eq!(route.body, b"<div id=app></div>");
+        assert_eq!(server.request("GET", "/missing.js", &html).status, 404);
+        // Only clients asking for HTML get the app
+        assert_eq!(server.request("GET", "/some/route", &[("Accept", "application/json")]).status, 404);
+    }
+
+    #[test]
+    fn keep_alive_header_counts_down_the_remaining_requests() {
+        let root = TempDir::new();
+        root.write("x.txt", "A");
+        let root = root.path().to_str().unwrap();
+        let server = TestServer::with_args(&["--root", root, "--keepalive-max", "3", "--keepalive-timeout", "7"]);
+        let mut stream = server.connect();
+
+        let mut advertised = Vec::new();
+        for _ in 0..2 {
+            stream.write_all(b"GET /x.txt HTTP/1.1\r\nHost: a\r\n\r\n").unwrap();
+            advertised.push(read_response(&mut stream).header("Keep-Alive").map(str::to_string));
+        }
+        assert_eq!(advertised, [Some("timeout=7, max=2".to_string()), Some("timeout=7, max=1".to_string())]);
+
+        // The last request the budget allows closes instead
+        stream.write_all(b"GET /x.txt HTTP/1.1\r\nHost: a\r\n\r\n").unwrap();
+        let last = Response::parse(&read_to_close(&mut stream));
+        assert_eq!(last.header("Connection"), Some("close"));
+        assert_eq!(last.header("Keep-Alive"), None);
+    }
+
+    #[test]
+    fn configured_headers_go_on_every_response_a_handler_left_them_off() {
+        let root = TempDir::new();
+        root.write("index.html", "<p>home</p>");
+        let server = TestServer::with_args(&[
+            "--root", root.path().to_str().unwrap(),
+            "--header", "X-Content-Type-Options: nosniff",
+            "--header", "X-Frame-Options: DENY",
+            "--header", "Content-Type: application/octet-stream",
+        ]);
+
+        for (path, status) in [("/index.html", 200), ("/missing", 404)] {
+            let response = server.get(path);
+            assert_eq!(response.status, status);
+            assert_eq!(response.header("X-Content-Type-Options"), Some("nosniff"), "{}", path);
+            assert_eq!(response.header("X-Frame-Options"), Some("DENY"), "{}", path);
+            // The handler's own Content-Type wins, and isn't doubled
+            assert!(response.header("Content-Type").unwrap().starts_with("text/"), "{}", path);
+            assert_eq!(response.headers.iter().filter(|(name, _)| name == "Content-Type").count(), 1);
+        }
+    }
+
+    /// Takes `limit` bytes, then fails the way a socket does once the client hung up
+    struct HangUp {
+        received: Vec<u8>,
+        limit: usize,
+    }
+
+    impl Write for HangUp {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            let room = self.limit - self.received.len();
+            if room == 0 {
+                return Err(io::Error::from(ErrorKind::BrokenPipe));
+            }
+            let size = buf.len().min(room);
+            self.received.extend_from_slice(&buf[..size]);
+            Ok(size)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn aborted_download_reports_the_bytes_that_got_out() {
+        let config = testing::config(&[]);
+        let response = HttpResponse::new(200).with_body(vec![b'x'; 100_000], "text/plain");
+        let mut client = HangUp { received: Vec::new(), limit: 5000 };
+        let sent = send_response(&mut client, response, Disposition::Close, false, &config);
+
+        let head_len = client.received.windows(4).position(|window| window == b"\r\n\r\n").unwrap() + 4;
+        assert_eq!(sent, Sent { body_bytes: 5000 - head_len, complete: false });
+
+        let mut client = HangUp { received: Vec::new(), limit: usize::MAX };
+        let response = HttpResponse::new(200).with_body(vec![b'x'; 100], "text/plain");
+        let sent = send_response(&mut client, response, Disposition::Close, false, &config);
+        assert_eq!(sent, Sent { body_bytes: 100, complete: true });
+    }
+
+    #[test]
+    fn server_carries_on_after_a_client_hangs_up_mid_download() {
+        let root = TempDir::new();
+        root.write("big.bin", vec![0u8; 8 * 1024 * 1024]);
+        root.write("small.txt", "still here");
+        let server = TestServer::with_args(&["--root", root.path().to_str().unwrap()]);
+
+        let mut stream = server.connect();
+        stream.write_all(b"GET /big.bin HTTP/1.1\r\nHost: a\r\n\r\n").unwrap();
+        let mut start = [0; 64];
+        stream.read_exact(&mut start).unwrap();
+        drop(stream);
+
+        assert_eq!(server.get("/small.txt").body, b"still here");
+    }
+
+    /// The ETag and Vary of a GET of `path`, then of a revalidation with that ETag
+    fn validators_of(server: &TestServer, path: &str, accept: &[(&str, &str)]) -> [(u16, String, Option<String>); 2] {
+        let summary = |response: Response| {
+            let vary = response.header("Vary").map(str::to_string);
+            (response.status, response.header("ETag").unwrap().to_string(), vary)
+        };
+        let full = summary(server.request("GET", path, accept));
+        let headers: Vec<(&str, &str)> = accept.iter().copied().chain([("If-None-Match", full.1.as_str())]).collect();
+        let revalidated = summary(server.request("GET", path, &headers));
+        [full, revalidated]
+    }
+
+    #[test]
+    fn not_modified_carries_the_etag_and_vary_of_the_full_response() {
+        let root = TempDir::new();
+        root.write("app.js", "let x = 1;\n".repeat(500));
+        let server = TestServer::with_args(&["--root", root.path().to_str().unwrap()]);
+
+        let [full, revalidated] = validators_of(&server, "/app.js", &[("Accept-Encoding", "gzip")]);
+        assert!(full.1.starts_with("W/"), "{:?}", full);
+        assert_eq!(revalidated, (304, full.1, full.2));
+        let [full, revalidated] = validators_of(&server, "/app.js", &[]);
+        assert!(!full.1.starts_with("W/"), "{:?}", full);
+        assert_eq!(full.2.as_deref(), Some("Accept-Encoding"));
+        assert_eq!(revalidated, (304, full.1, full.2));
+    }
+
+    #[test]
+    fn precompressed_not_modified_matches_its_200() {
+        let root = TempDir::new();
+        root.write("app.js", "let x = 1;");
+        root.write("app.js.gz", compress::Encoding::Gzip.encode(b"let x = 1;", 6));
+        let server = TestServer::with_args(&["--root", root.path().to_str().unwrap(), "--precompressed"]);
+
+        let [full, revalidated] = validators_of(&server, "/app.js", &[("Accept-Encoding", "gzip")]);
+        assert_eq!(full.0, 200);
+        assert!(full.1.starts_with("W/"), "{:?}", full);
+        assert_eq!(full.2.as_deref(), Some("Accept-Encoding"));
+        assert_eq!(revalidated, (304, full.1, full.2));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn precompressed_siblings_get_the_symlink_checks() {
+        let dir = TempDir::new();
+        let outside = dir.write("secret.gz", compress::Encoding::Gzip.encode(b"secret", 6));
+        let root = dir.write("root/app.js", "let x = 1;").parent().unwrap().to_path_buf();
+        dir.write("root/inner.js", "let y = 2;");
+        let inner_gz = dir.write("root/inner.gz", compress::Encoding::Gzip.encode(b"let y = 2;", 6));
+        std::os::unix::fs::symlink(&outside, root.join("app.js.gz")).unwrap();
+        std::os::unix::fs::symlink(&inner_gz, root.join("inner.js.gz")).unwrap();
+        let gzip = [("Accept-Encoding", "gzip")];
+
+        let server = TestServer::with_args(&["--root", root.to_str().unwrap(), "--precompressed", "--no-compress"]);
+        let escaping = server.request("GET", "/app.js", &gzip);
+        assert_eq!(escaping.header("Content-Encoding"), None);
+        assert_eq!(escaping.body, b"let x = 1;");
+        let inside = server.request("GET", "/inner.js", &gzip);
+        assert_eq!(inside.header("Content-Encoding"), Some("gzip"));
+
+        let args = ["--root", root.to_str().unwrap(), "--precompressed", "--no-compress", "--no-follow-symlinks"];
+        let strict = TestServer::with_args(&args);
+        assert_eq!(strict.request("GET", "/inner.js", &gzip).header("Content-Encoding"), None);
+    }
+
+    #[test]
+    fn every_response_is_dated_with_the_current_time() {
+        let server = TestServer::with_args(&[]);
+        let before = SystemTime::now() - Duration::from_secs(1);
+        let response = server.get("/missing");
+        let date = http_date::parse_http_date(response.header("Date").expect("responses carry a Date")).unwrap();
+        assert!(date >= before && date <= SystemTime::now(), "{:?}", response.header("Date"));
+    }
+
+    #[test]
+    fn deterministic_responses_are_byte_identical_across_runs() {
+        let root = TempDir::new();
+        root.write("page.html", "<p>golden</p>".repeat(200));
+        let root = root.path().to_str().unwrap();
+        let keep_alive = "GET /page.html HTTP/1.1\r\nHost: test\r\nAccept-Encoding: gzip\r\n\r\n";
+        let requests = [
+            keep_alive.to_string(),
+            keep_alive.to_string(),
+            "GET /page.html HTTP/1.1\r\nHost: test\r\nRange: bytes=0-9\r\n\r\n".to_string(),
+            String::from_utf8(testing::build_request("GET", "/missing", &[], b"")).unwrap(),
+        ]
+        .concat();
+        let run = |args: &[&str]| TestServer::with_args(args).exchange(&requests);
+
+        let first = run(&["--root", root, "--deterministic"]);
+        thread::sleep(Duration::from_millis(1100));
+        let second = run(&["--root", root, "--deterministic"]);
+        assert!(first == second, "{}\n---\n{}", String::from_utf8_lossy(&first), String::from_utf8_lossy(&second));
+        let text = String::from_utf8_lossy(&first);
+        assert_eq!(text.matches("Date: Thu, 01 Jan 1970 00:00:00 GMT\r\n").count(), 4, "{}", text);
+        assert_eq!(text.matches("Content-Encoding: gzip").count(), 2, "{}", text);
+        assert!(text.contains("HTTP/1.1 206 ") && text.contains("HTTP/1.1 404 "), "{}", text);
+        assert!(!text.to_ascii_lowercase().contains("x-request-id"), "{}", text);
+
+        // Without the flag the same exchange differs from run to run
+        assert!(run(&["--root", root]) != run(&["--root", root]));
+    }
+
+    #[test]
+    fn no_content_and_not_modified_end_with_their_headers() {
+        let root = TempDir::new();
+        root.write("x.txt", "hello");
+        let mut server = Server::bind(testing::config(&["--root", root.path().to_str().unwrap()])).unwrap();
+        server.context.router.route(Method::Get, "/careless", |_, _| {
+            HttpResponse::text(StatusCode::NO_CONTENT, "stray").with_header("Content-Length", "5")
+        });
+        server.context.router.route(Method::Get, "/streamed", |_, _| {
+            HttpResponse::new(StatusCode::NO_CONTENT).with_stream(io::Cursor::new(b"stray".to_vec()), "text/plain")
+        });
+        server.context.router.route(Method::Post, "/empty", |_, _| HttpResponse::new(StatusCode::OK));
+        let server = TestServer::serve(server);
+        let etag = server.get("/x.txt").header("ETag").unwrap().to_string();
+
+        // On one connection, so a stray body byte would garble the next response
+        let mut stream = server.connect();
+        let requests = [
+            "GET /careless HTTP/1.1\r\nHost: a\r\n\r\n".to_string(),
+            "GET /streamed HTTP/1.1\r\nHost: a\r\n\r\n".to_string(),
+            format!("GET /x.txt HTTP/1.1\r\nHost: a\r\nIf-None-Match: {}\r\n\r\n", etag),
+            "POST /empty HTTP/1.1\r\nHost: a\r\nContent-Length: 0\r\nConnection: close\r\n\r\n".to_string(),
+        ];
+        stream.write_all(requests.concat().as_bytes()).unwrap();
+        let received = String::from_utf8(read_to_close(&mut stream)).unwrap();
+        let responses: Vec<&str> = received.split_inclusive("\r\n\r\n").collect();
+        assert_eq!(responses.len(), 4, "{}", received);
+        for (response, status) in responses[..3].iter().zip(["204", "204", "304"]) {
+            assert!(response.starts_with(&format!("HTTP/1.1 {} ", status)), "{}", response);
+            let lower = response.to_ascii_lowercase();
+            assert!(!lower.contains("content-length") && !lower.contains("transfer-encoding"), "{}", response);
+        }
+        assert!(responses[2].contains(&format!("ETag: {}\r\n", etag)), "{}", responses[2]);
+        // An empty 200 says so rather than leaving its length open
+        assert!(responses[3].starts_with("HTTP/1.1 200 OK\r\n"), "{}", responses[3]);
+        assert!(responses[3].contains("Content-Length: 0\r\n"), "{}", responses[3]);
+    }
+
+    #[test]
+    fn ranged_head_gets_the_partial_content_headers_without_a_body() {
+        let root = TempDir::new();
+        root.write("big.txt", "0123456789".repeat(100));
+        let server = TestServer::with_args(&["--root", root.path().to_str().unwrap()]);
+        let headers = [("Range", "bytes=10-19"), ("Accept-Encoding", "gzip")];
+
+        let get = server.request("GET", "/big.txt", &headers);
+        let head = server.request("HEAD", "/big.txt", &headers);
+        assert_eq!(head.status, 206);
+        assert_eq!(head.header("Content-Range"), Some("bytes 10-19/1000"));
+        assert_eq!(head.header("Content-Length"), Some("10"));
+        assert_eq!(head.header("Accept-Ranges"), Some("bytes"));
+        assert!(head.body.is_empty(), "{:?}", head.body);
+        let per_response = |response: &Response| {
+            let mut headers = response.headers.clone();
+            headers.retain(|(name, _)| name != "Date" && name != "X-Request-Id");
+            headers
+        };
+        assert_eq!((head.status, per_response(&head)), (get.status, per_response(&get)));
+
+        let unsatisfiable = server.request("HEAD", "/big.txt", &[("Range", "bytes=5000-")]);
+        assert_eq!(unsatisfiable.status, 416);
+        assert_eq!(unsatisfiable.header("Content-Range"), Some("bytes */1000"));
+        assert!(unsatisfiable.body.is_empty());
+    }
+
+    #[test]
+    fn immutable_patterns_match_by_path_or_extension() {
+        let config = testing::config(&["--immutable", "/assets/*, *.woff2"]);
+        for path in ["/assets/app.3f9a.js", "/assets/css/site.77c1.css", "/fonts/inter.WOFF2"] {
+            assert!(is_immutable(path, &config), "{}", path);
+        }
+        for path in ["/index.html", "/app.js", "/fonts/inter.woff", "/static/app.js"] {
+            assert!(!is_immutable(path, &config), "{}", path);
+        }
+        assert!(crate::Config::from_args(["--immutable", "assets"].map(String::from).into_iter()).is_err());
+    }
+
+    #[test]
+    fn matching_assets_are_immutable_and_html_is_not() {
+        let root = TempDir::new();
+        root.write("assets/app.3f9a.js", "console.log(1)");
+        root.write("index.html", "<p>home</p>");
+        let server = TestServer::with_args(&["--root", root.path().to_str().unwrap(), "--immutable", "*.js"]);
+
+        let asset = server.get("/assets/app.3f9a.js");
+        assert_eq!(asset.status, 200);
+        assert_eq!(asset.header("Cache-Control"), Some(IMMUTABLE_CACHE_CONTROL));
+        // A revalidation, should a cache try one anyway, keeps the directive
+        let etag = asset.header("ETag").unwrap();
+        let revalidated = server.request("GET", "/assets/app.3f9a.js", &[("If-None-Match", etag)]);
+        assert_eq!(revalidated.status, 304);
+        assert_eq!(revalidated.header("Cache-Control"), Some(IMMUTABLE_CACHE_CONTROL));
+
+        for (path, status) in [("/index.html", 200), ("/", 200), ("/assets/missing.js", 404)] {
+            let response = server.get(path);
+            assert_eq!(response.status, status, "{}", path);
+            assert_ne!(response.header("Cache-Control"), Some(IMMUTABLE_CACHE_CONTROL), "{}", path);
+        }
+    }
+
+    /// A body source that hands out `pieces` and then fails, like a file
+    /// whose disk goes away partway through
+    struct FailingAfter {
+        pieces: Vec<&'static [u8]>,
+    }
+
+    impl Read for FailingAfter {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            if self.pieces.is_empty() {
+                return Err(io::Error::other("the disk went away"));
+            }
+            let piece = self.pieces.remove(0);
+            buf[..piece.len()].copy_from_slice(piece);
+            Ok(piece.len())
+        }
+    }
+
+    #[test]
+    fn a_stream_failing_before_its_first_byte_is_a_500() {
+        let mut server = Server::bind(testing::config(&[])).unwrap();
+        server.context.router.route(Method::Get, "/broken", |_, _| {
+            HttpResponse::new(StatusCode::OK).with_stream(FailingAfter { pieces: Vec::new() }, "text/plain")
+        });
+        let server = TestServer::serve(server);
+        let response = server.get("/broken");
+        assert_eq!(response.status, 500);
+        assert_eq!(response.header("Transfer-Encoding"), None);
+    }
+
+    #[test]
+    fn a_stream_failing_midway_closes_the_connection_without_ending_the_body() {
+        let mut server = Server::bind(testing::config(&[])).unwrap();
+        server.context.router.route(Method::Get, "/flaky", |_, _| {
+            let pieces = vec![&b"first piece"[..], b"second piece"];
+            HttpResponse::new(StatusCode::OK).with_stream(FailingAfter { pieces }, "text/plain")
+        });
+        let server = TestServer::serve(server);
+
+        // A keep-alive connection with a second request queued behind the first
+        let mut stream = server.connect();
+        let request = "GET /flaky HTTP/1.1\r\nHost: a\r\n\r\n";
+        stream.write_all(request.repeat(2).as_bytes()).unwrap();
+        let received = read_to_close(&mut stream);
+        let text = String::from_utf8_lossy(&received);
+        assert!(text.starts_with("HTTP/1.1 200 OK\r\n"), "{}", text);
+        assert!(text.ends_with("\r\nb\r\nfirst piece\r\nc\r\nsecond piece\r\n"), "{}", text);
+        // Neither the terminating chunk nor an answer to the second request follows
+        assert!(!text.contains("\r\n0\r\n"), "{}", text);
+        assert_eq!(text.matches("HTTP/1.1 ").count(), 1, "{}", text);
+    }
+
+    #[test]
+    fn later_1_x_minors_get_what_http_1_1_gets() {
+        let root = TempDir::new();
+        root.write("x.txt", "A");
+        let root = root.path().to_str().unwrap();
+        let mut server = Server::bind(testing::config(&["--root", root, "--writable", root])).unwrap();
+        server.context.router.route(Method::Get, "/stream", |_, _| {
+            HttpResponse::new(StatusCode::OK).with_stream(io::Cursor::new(b"streamed".to_vec()), "text/plain")
+        });
+        let server = TestServer::serve(server);
+
+        // An HTTP/1.2 client keeps the connection by default and gets chunks;
+        // the HTTP/1.0 request after it gets its own version back and a close
+        let mut stream = server.connect();
+        stream.write_all(b"GET /stream HTTP/1.2\r\nHost: a\r\n\r\nGET /x.txt HTTP/1.0\r\n\r\n").unwrap();
+        let received = String::from_utf8(read_to_close(&mut stream)).unwrap();
+        let (first, second) = received.split_once("\r\n0\r\n\r\n").expect("the chunked body should end");
+        assert!(first.starts_with("HTTP/1.1 200 OK\r\n"), "{}", first);
+        assert!(first.contains("Transfer-Encoding: chunked\r\n") && first.contains("Connection: keep-alive\r\n"));
+        assert!(second.starts_with("HTTP/1.0 200 OK\r\n"), "{}", second);
+        assert!(second.contains("Connection: close\r\n") && second.ends_with("\r\n\r\nA"), "{}", second);
+
+        // and is told to go ahead with a body it's holding back
+        let mut stream = server.connect();
+        let head = "PUT /up.txt HTTP/1.2\r\nHost: a\r\nConnection: close\r\nExpect: 100-continue\r\n\
+                    Content-Length: 2\r\n\r\n";
+        stream.write_all(head.as_bytes()).unwrap();
+        let mut interim = [0; 25];
+        stream.read_exact(&mut interim).unwrap();
+        assert_eq!(&interim, b"HTTP/1.1 100 Continue\r\n\r\n");
+        stream.write_all(b"hi").unwrap();
+        assert_eq!(Response::parse(&read_to_close(&mut stream)).status, 201);
+    }
+
+    #[test]
+    fn redirect_hosts_lose_their_port_and_must_be_plain() {
+        assert_eq!(strip_port("example.com:8080"), "example.com");
+        assert_eq!(strip_port("[::1]:8080"), "[::1]");
+        assert_eq!(strip_port("[::1]"), "[::1]");
+        assert_eq!(strip_port("example.com"), "example.com");
+        for host in ["example.com", "10.0.0.1", "[::1]", "my_host-1.local"] {
+            assert!(is_valid_host(host), "{}", host);
+        }
+        for host in ["", "evil.com/x", "user@evil.com", "a b", "evil.com?x"] {
+            assert!(!is_valid_host(host), "{}", host);
+        }
+    }
+
+    #[test]
+    fn plaintext_requests_are_sent_to_https_with_path_and_query() {
+        let root = TempDir::new();
+        root.write("x.txt", "A");
+        let server = TestServer::with_args(&["--root", root.path().to_str().unwrap(), "--redirect-https"]);
+
+        let redirect = server.get("/a%20b/caf%C3%A9.txt?q=1&r=a%2Fb");
+        assert_eq!(redirect.status, 301);
+        assert_eq!(redirect.header("Location"), Some("https://test/a%20b/caf%C3%A9.txt?q=1&r=a%2Fb"));
+        let located = |host: &str, path: &str| {
+            let raw = format!("GET {} HTTP/1.1\r\nHost: {}\r\nConnection: close\r\n\r\n", path, host);
+            let response = server.send(raw);
+            (response.status, response.header("Location").map(str::to_string))
+        };
+        assert_eq!(located("example.com:8080", "/x.txt"), (301, Some("https://example.com/x.txt".to_string())));
+        assert_eq!(located("[::1]:8080", "/"), (301, Some("https://[::1]/".to_string())));
+        assert_eq!(located("evil.com/x", "/x.txt"), (400, None));
+        // A Host-less HTTP/1.0 request has no host to send it to, and isn't served either
+        assert_eq!(server.send(b"GET /x.txt HTTP/1.0\r\n\r\n").status, 400);
+        assert_ne!(server.request("OPTIONS", "*", &[]).status, 301);
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn thread_names_show_up_in_the_thread_listing() {
+        let server = TestServer::with_args(&["--thread-name", "x", "--workers", "2"]);
+        // Once a request is answered, the accept thread and the pool are running
+        assert_eq!(server.get("/missing").status, 404);
+        let names: Vec<String> = fs::read_dir("/proc/self/task")
+            .unwrap()
+            .filter_map(|task| fs::read_to_string(task.ok()?.path().join("comm")).ok())
+            .map(|name| name.trim_end().to_string())
+            .collect();
+        for expected in ["x-w0", "x-w1", "x-accept0"] {
+            assert!(names.iter().any(|name| name == expected), "no {} in {:?}", expected, names);
+        }
+        assert!(!names.iter().any(|name| name == "x-w2"), "{:?}", names);
+    }
+
+    #[test]
+    fn a_file_cut_short_mid_download_ends_the_connection() {
+        let root = TempDir::new();
+        let logs = TempDir::new();
+        let log_file = logs.path().join("error.log");
+        let len = 16 << 20;
+        let path = root.write("big.bin", vec![b'x'; len]);
+        let server = TestServer::with_args(&[
+            "--root", root.path().to_str().unwrap(),
+            "--log-file", log_file.to_str().unwrap(),
+        ]);
+
+        // Take the file's contents away once the headers have gone out
+        let mut stream = server.connect();
+        stream.write_all(b"GET /big.bin HTTP/1.1\r\nHost: a\r\nX-Request-Id: cut-short\r\n\r\n").unwrap();
+        let mut received = Vec::new();
+        let mut buffer = [0; 4096];
+        while !received.windows(4).any(|window| window == b"\r\n\r\n") {
+            let size = stream.read(&mut buffer).unwrap();
+            assert!(size > 0, "the connection closed before the headers");
+            received.extend_from_slice(&buffer[..size]);
+        }
+        fs::File::options().write(true).open(&path).unwrap().set_len(0).unwrap();
+        received.extend(read_to_close(&mut stream));
+
+        let response = Response::parse(&received);
+        assert_eq!(response.status, 200);
+        assert_eq!(response.header("Content-Length"), Some(len.to_string().as_str()));
+        assert!(response.body.len() < len, "{} bytes arrived", response.body.len());
+        assert!(response.body.iter().all(|&byte| byte == b'x'));
+        drop(server);
+
+        // Other tests may log to the same file meanwhile, so only this request's line counts
+        let log = fs::read_to_string(&log_file).unwrap();
+        assert!(log.lines().any(|line| line.starts_with("[cut-short] Response failed after ")), "{}", log);
+    }
+
+    #[test]
+    fn rate_limit_bps_paces_a_file_download() {
+        let root = TempDir::new();
+        let contents: Vec<u8> = (0..20 * 1024).map(|i| (i % 251) as u8).collect();
+        root.write("paced.bin", &contents);
+        let server = TestServer::with_args(&["--root", root.path().to_str().unwrap(), "--rate-limit-bps", "10K"]);
+
+        let started = Instant::now();
+        let response = server.get("/paced.bin");
+        let elapsed = started.elapsed();
+        assert_eq!((response.status, response.body), (200, contents));
+        // 20K at 10K a second; the throttle is behind schedule for no more than a slice
+        assert!(elapsed >= Duration::from_millis(1800), "took {:?}", elapsed);
+        assert!(elapsed < Duration::from_secs(5), "took {:?}", elapsed);
+
+        // A range is paced by its own length
+        let started = Instant::now();
+        let partial = server.request("GET", "/paced.bin", &[("Range", "bytes=0-5119")]);
+        assert_eq!((partial.status, partial.body.len()), (206, 5120));
+        assert!(started.elapsed() >= Duration::from_millis(400), "took {:?}", started.elapsed());
+        assert!(started.elapsed() < Duration::from_millis(1500), "took {:?}", started.elapsed());
+    }
+
+    #[test]
+    fn files_too_large_to_hold_are_neither_compressed_nor_transformed() {
+        let root = TempDir::new();
+        let len = MAX_BUFFERED_FILE + 1;
+        for name in ["big.txt", "big.html"] {
+            fs::File::create(root.path().join(name)).unwrap().set_len(len).unwrap();
+        }
+        let server = TestServer::with_args(&["--root", root.path().to_str().unwrap(), "--live-reload"]);
+
+        let text = server.request("HEAD", "/big.txt", &[("Accept-Encoding", "gzip")]);
+        assert_eq!(text.status, 200);
+        assert_eq!(text.header("Content-Encoding"), None);
+        assert_eq!(text.header("Content-Length"), Some(len.to_string().as_str()));
+        // Without the whole page in memory there's nothing to inject the script into
+        assert_eq!(server.get("/big.html").status, 500);
+    }
+}
@@ -0,0 +1,126 @@
+use std::collections::hash_map::DefaultHasher;
+use std::fs;
+use std::hash::{Hash, Hasher};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::http::percent_encode;
+use crate::validators::Validators;
+
+/// What we need to know about each directory entry to list it
+pub struct Entry {
+    pub name: String,
+    pub is_dir: bool,
+    pub size: u64,
+    pub modified: Option<SystemTime>,
+}
+
+/// Reads a directory's entries, sorted by name
+pub fn read_entries(dir: &Path) -> io::Result<Vec<Entry>> {
+    let mut entries = Vec::new();
+
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let metadata = match entry.metadata() {
+            Ok(metadata) => metadata,
+            // Entries can vanish between read_dir and metadata; just skip them
+            Err(_) => continue,
+        };
+        entries.push(Entry {
+            name: entry.file_name().to_string_lossy().into_owned(),
+            is_dir: metadata.is_dir(),
+            size: metadata.len(),
+            modified: metadata.modified().ok(),
+        });
+    }
+
+    entries.sort_by(|a, b| a.name.cmp(&b.name));
+    Ok(entries)
+}
+
+/// Computes validators for a generated listing.
+///
+/// The ETag is a hash over every entry's name, size and mtime, so it changes
+/// whenever an entry is added, removed or modified. Last-Modified is the
+/// newest of the directory's own mtime (bumped by adds/removes) and its entries'.
+pub fn validators(dir_modified: Option<SystemTime>, entries: &[Entry]) -> Validators {
+    let mut hasher = DefaultHasher::new();
+    let mut newest = dir_modified;
+
+    for entry in entries {
+        entry.name.hash(&mut hasher);
+        entry.is_dir.hash(&mut hasher);
+        entry.size.hash(&mut hasher);
+        entry
+            .modified
+            .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+            .hash(&mut hasher);
+
+        if entry.modified > newest {
+            newest = entry.modified;
+        }
+    }
+
+    Validators {
+        etag: format!("W/\"dir-{:016x}\"", hasher.finish()),
+        last_modified: newest,
+    }
+}
+
+/// Renders an HTML page listing the entries of the directory at `url_path`
+pub fn render(url_path: &str, entries: &[Entry]) -> String {
+    let title = html_escape(url_path);
+    let mut html = format!(
+        "<!DOCTYPE html>\n<html>\n<head><meta charset=\"utf-8\"><title>Index of {}</title></head>\n\
+         <body>\n<h1>Index of {}</h1>\n<ul>\n",
+        title, title
+    );
+
+    if url_path != "/" {
+        html.push_str("<li><a href=\"../\">../</a></li>\n");
+    }
+
+    for entry in entries {
+        let suffix = if entry.is_dir { "/" } else { "" };
+        html.push_str(&format!(
+            "<li><a href=\"{}{}\">{}{}</a>{}</li>\n",
+            percent_encode(&entry.name),
+            suffix,
+            html_escape(&entry.name),
+            suffix,
+            if entry.is_dir { String::new() } else { format!(" ({} bytes)", entry.size) }
+        ));
+    }
+
+    html.push_str("</ul>\n</body>\n</html>\n");
+    html
+}
+
+/// True when none of `roots` has anything in it (or exists at all), which is
+/// when `/` shows the welcome page
+pub fn roots_are_empty(roots: &[PathBuf]) -> bool {
+    roots.iter().all(|root| fs::read_dir(root).map_or(true, |mut entries| entries.next().is_none()))
+}
+
+/// The built-in page answering `/` on a fresh install with nothing to serve yet
+pub fn render_welcome(roots: &[PathBuf]) -> String {
+    let roots: Vec<String> =
+        roots.iter().map(|root| format!("<code>{}</code>", html_escape(&root.display().to_string()))).collect();
+    format!(
+        "<!DOCTYPE html>\n<html>\n<head><meta charset=\"utf-8\"><title>It works!</title></head>\n\
+         <body>\n<h1>It works &mdash; RawRustServer is running</h1>\n\
+         <p>This is the server's default page: the document root is empty.</p>\n\
+         <p>Add an <code>index.html</code> to {} to replace it, or start the server with \
+         <code>--no-welcome</code> to turn it off.</p>\n</body>\n</html>\n",
+        roots.join(" or ")
+    )
+}
+
+/// Escapes text for safe inclusion in HTML
+fn html_escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
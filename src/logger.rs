@@ -0,0 +1,184 @@
+use std::cell::RefCell;
+use std::fmt;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/// Logs an informational message (stdout, or the log file if configured)
+macro_rules! log_info {
+    ($($arg:tt)*) => {
+        $crate::logger::write_line($crate::logger::Level::Info, format_args!($($arg)*))
+    };
+}
+
+/// Logs an error message (stderr, or the log file if configured)
+macro_rules! log_error {
+    ($($arg:tt)*) => {
+        $crate::logger::write_line($crate::logger::Level::Error, format_args!($($arg)*))
+    };
+}
+
+/// Severity of a log line. Decides stdout vs stderr when logging to the
+/// terminal, and whether the line is written at all under `--log-level`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Level {
+    Info,
+    Error,
+}
+
+impl Level {
+    pub fn parse(value: &str) -> Option<Level> {
+        match value {
+            "info" => Some(Level::Info),
+            "error" => Some(Level::Error),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Level::Info => "info",
+            Level::Error => "error",
+        }
+    }
+}
+
+/// The log file currently being written, if `--log-file` was given
+struct LogFile {
+    path: PathBuf,
+    file: File,
+    size: u64,
+    max_size: Option<u64>,
+}
+
+static LOG_FILE: Mutex<Option<LogFile>> = Mutex::new(None);
+
+/// Set from the SIGHUP handler; the next write reopens the file
+static REOPEN_REQUESTED: AtomicBool = AtomicBool::new(false);
+
+/// Set by `--log-level error`: informational lines are dropped
+static ERRORS_ONLY: AtomicBool = AtomicBool::new(false);
+
+thread_local! {
+    /// ID of the request this thread is currently handling, if any
+    static REQUEST_ID: RefCell<Option<String>> = const { RefCell::new(None) };
+}
+
+/// While alive, prefixes this thread's log lines with a request ID
+pub struct RequestIdScope;
+
+impl RequestIdScope {
+    pub fn enter(id: Option<String>) -> Self {
+        REQUEST_ID.with(|current| *current.borrow_mut() = id);
+        RequestIdScope
+    }
+}
+
+impl Drop for RequestIdScope {
+    fn drop(&mut self) {
+        REQUEST_ID.with(|current| *current.borrow_mut() = None);
+    }
+}
+
+/// Sends all further log output to `path`, opened in append mode.
+/// With `max_size`, the file is rotated to `<path>.1` once it would grow past that many bytes.
+pub fn init_file(path: &Path, max_size: Option<u64>) -> io::Result<()> {
+    let file = open_append(path)?;
+    let size = file.metadata()?.len();
+
+    let mut guard = LOG_FILE.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+    *guard = Some(LogFile { path: path.to_path_buf(), file, size, max_size });
+    Ok(())
+}
+
+/// Sets the least severe level that still gets written. Access log lines
+/// aren't affected; `--no-access-log` controls those.
+pub fn set_level(level: Level) {
+    ERRORS_ONLY.store(level == Level::Error, Ordering::SeqCst);
+}
+
+/// Whether lines at `level` are currently written
+pub fn enabled(level: Level) -> bool {
+    level == Level::Error || !ERRORS_ONLY.load(Ordering::SeqCst)
+}
+
+/// Asks the logger to reopen its file before the next write.
+/// This only touches an atomic, so it is safe to call from a signal handler.
+pub fn request_reopen() {
+    REOPEN_REQUESTED.store(true, Ordering::SeqCst);
+}
+
+/// Writes one line to the configured destination, tagged with the
+/// current request ID when there is one
+pub fn write_line(level: Level, args: fmt::Arguments) {
+    if !enabled(level) {
+        return;
+    }
+    REQUEST_ID.with(|current| match current.borrow().as_deref() {
+        Some(id) => write_raw(level, format_args!("[{}] {}", id, args)),
+        None => write_raw(level, args),
+    });
+}
+
+/// Writes an access log line as-is; access lines carry the request ID in their own field
+pub fn write_access(line: &str) {
+    write_raw(Level::Info, format_args!("{}", line));
+}
+
+fn write_raw(level: Level, args: fmt::Arguments) {
+    let mut guard = LOG_FILE.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+
+    let log_file = match guard.as_mut() {
+        Some(log_file) => log_file,
+        None => {
+            match level {
+                Level::Info => println!("{}", args),
+                Level::Error => eprintln!("{}", args),
+            }
+            return;
+        }
+    };
+
+    let line = format!("{}\n", args);
+    if let Err(e) = log_file.write(line.as_bytes()) {
+        // Never lose a log line silently: fall back to stderr
+        eprintln!("Failed to write to log file {}: {}", log_file.path.display(), e);
+        eprint!("{}", line);
+    }
+}
+
+impl LogFile {
+    fn write(&mut self, line: &[u8]) -> io::Result<()> {
+        // External logrotate moved the file away and sent SIGHUP
+        if REOPEN_REQUESTED.swap(false, Ordering::SeqCst) {
+            self.reopen()?;
+        }
+
+        // Size-based rotation: keep exactly one previous file around
+        if let Some(max_size) = self.max_size
+            && self.size > 0
+            && self.size + line.len() as u64 > max_size
+        {
+            let mut rotated = self.path.clone().into_os_string();
+            rotated.push(".1");
+            fs::rename(&self.path, rotated)?;
+            self.reopen()?;
+        }
+
+        self.file.write_all(line)?;
+        self.size += line.len() as u64;
+        Ok(())
+    }
+
+    fn reopen(&mut self) -> io::Result<()> {
+        self.file = open_append(&self.path)?;
+        self.size = self.file.metadata()?.len();
+        Ok(())
+    }
+}
+
+fn open_append(path: &Path) -> io::Result<File> {
+    OpenOptions::new().create(true).append(true).open(path)
+}
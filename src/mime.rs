@@ -0,0 +1,261 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+
+/// Content type used when a file has an extension we don't recognise
+pub const DEFAULT_MIME_TYPE: &str = "application/octet-stream";
+
+/// Per-directory file overriding the content types of the files next to it
+pub const DIRECTORY_MIME_FILE: &str = ".mime";
+
+/// How many leading bytes `sniff` looks at
+pub const SNIFF_LEN: usize = 512;
+
+/// Leading bytes of formats recognised by `sniff`
+const MAGIC_NUMBERS: &[(&[u8], &str)] = &[
+    (b"\x89PNG\r\n\x1a\n", "image/png"),
+    (b"\xff\xd8\xff", "image/jpeg"),
+    (b"GIF87a", "image/gif"),
+    (b"GIF89a", "image/gif"),
+    (b"%PDF-", "application/pdf"),
+    (b"PK\x03\x04", "application/zip"),
+    (b"\x1f\x8b", "application/gzip"),
+    (b"\0asm", "application/wasm"),
+];
+
+/// Built-in extension to MIME type table.
+/// Extra entries can be supplied at startup with `--mime-types <file>`.
+const BUILTIN_MIME_TYPES: &[(&str, &str)] = &[
+    ("html", "text/html; charset=utf-8"),
+    ("htm", "text/html; charset=utf-8"),
+    ("css", "text/css; charset=utf-8"),
+    ("js", "text/javascript; charset=utf-8"),
+    ("mjs", "text/javascript; charset=utf-8"),
+    ("json", "application/json"),
+    ("map", "application/json"),
+    ("webmanifest", "application/manifest+json"),
+    ("txt", "text/plain; charset=utf-8"),
+    ("xml", "application/xml"),
+    ("csv", "text/csv; charset=utf-8"),
+    ("md", "text/markdown; charset=utf-8"),
+    ("svg", "image/svg+xml"),
+    ("png", "image/png"),
+    ("jpg", "image/jpeg"),
+    ("jpeg", "image/jpeg"),
+    ("gif", "image/gif"),
+    ("webp", "image/webp"),
+    ("avif", "image/avif"),
+    ("ico", "image/x-icon"),
+    ("bmp", "image/bmp"),
+    ("woff", "font/woff"),
+    ("woff2", "font/woff2"),
+    ("ttf", "font/ttf"),
+    ("otf", "font/otf"),
+    ("mp3", "audio/mpeg"),
+    ("wav", "audio/wav"),
+    ("mp4", "video/mp4"),
+    ("webm", "video/webm"),
+    ("ogg", "audio/ogg"),
+    ("opus", "audio/opus"),
+    // Browsers require this exact type for WebAssembly.instantiateStreaming
+    ("wasm", "application/wasm"),
+    ("pdf", "application/pdf"),
+    ("zip", "application/zip"),
+    ("gz", "application/gzip"),
+];
+
+/// Maps file extensions to MIME types
+pub struct MimeTypes {
+    types: HashMap<String, String>,
+    /// Match extensions regardless of case, so `PHOTO.JPG` is a JPEG. Off
+    /// with `--strict-extensions`, where only the exact extension counts.
+    fold_case: bool,
+}
+
+impl MimeTypes {
+    /// Creates a table containing only the built-in types
+    pub fn new(fold_case: bool) -> Self {
+        let types = BUILTIN_MIME_TYPES
+            .iter()
+            .map(|(ext, mime)| (ext.to_string(), mime.to_string()))
+            .collect();
+        MimeTypes { types, fold_case }
+    }
+
+    /// Looks up the MIME type for an extension (without the leading dot)
+    pub fn lookup(&self, extension: &str) -> Option<&str> {
+        let mime = match self.types.get(extension) {
+            None if self.fold_case => self.types.get(&extension.to_ascii_lowercase()),
+            mime => mime,
+        };
+        mime.map(|mime| mime.as_str())
+    }
+
+    /// Merges entries from an nginx-style `mime.types` file into the table.
+    ///
+    /// Each entry looks like `application/wasm wasm;` and may list several
+    /// extensions. The optional `types { ... }` wrapper and `#` comments are
+    /// accepted. Entries from the file replace built-in ones. Malformed lines
+    /// are skipped with a warning. Returns the number of extensions loaded.
+    pub fn load_file(&mut self, path: &Path) -> std::io::Result<usize> {
+        let contents = fs::read_to_string(path)?;
+        Ok(self.merge(&contents, path))
+    }
+
+    /// Parses a directory's `.mime` file, which uses the `mime.types` format.
+    /// The extension `*` matches every file in the directory.
+    pub fn parse_directory_file(contents: &str, path: &Path, fold_case: bool) -> MimeTypes {
+        let mut types = MimeTypes { types: HashMap::new(), fold_case };
+        types.merge(contents, path);
+        types
+    }
+
+    /// The type a directory's `.mime` table gives a file with `extension`:
+    /// an exact entry first, then the `*` entry
+    pub fn lookup_override(&self, extension: Option<&str>) -> Option<&str> {
+        extension.and_then(|ext| self.lookup(ext)).or_else(|| self.lookup("*"))
+    }
+
+    /// Adds the entries in `contents`, read from `path`, to the table
+    fn merge(&mut self, contents: &str, path: &Path) -> usize {
+        let mut loaded = 0;
+
+        for (index, raw_line) in contents.lines().enumerate() {
+            // Strip comments and surrounding whitespace
+            let line = raw_line.split('#').next().unwrap_or("").trim();
+            if line.is_empty() || line == "}" || line.replace(' ', "") == "types{" {
+                continue;
+            }
+
+            match parse_mime_line(line) {
+                Some((mime, extensions)) => {
+                    for ext in extensions {
+                        let ext = if self.fold_case { ext.to_ascii_lowercase() } else { ext.to_string() };
+                        self.types.insert(ext, mime.to_string());
+                        loaded += 1;
+                    }
+                }
+                None => {
+                    log_error!(
+                        "Warning: skipping malformed line {} in {}: {}",
+                        index + 1,
+                        path.display(),
+                        raw_line.trim()
+                    );
+                }
+            }
+        }
+
+        loaded
+    }
+}
+
+/// Guesses a MIME type from the first `SNIFF_LEN` bytes of a file, for files
+/// without an extension. Unrecognised binary data is `DEFAULT_MIME_TYPE`.
+pub fn sniff(content: &[u8]) -> &'static str {
+    let content = &content[..content.len().min(SNIFF_LEN)];
+    if let Some((_, mime)) = MAGIC_NUMBERS.iter().find(|(magic, _)| content.starts_with(magic)) {
+        return mime;
+    }
+
+    // Markup is recognised by its first tag, ignoring a UTF-8 BOM and leading whitespace
+    let text = content.strip_prefix(b"\xef\xbb\xbf").unwrap_or(content);
+    let start = text.iter().position(|byte| !byte.is_ascii_whitespace()).unwrap_or(text.len());
+    let text = &text[start..];
+    let starts_with_tag = |tag: &[u8]| text.len() >= tag.len() && text[..tag.len()].eq_ignore_ascii_case(tag);
+    if starts_with_tag(b"<!DOCTYPE html") || starts_with_tag(b"<html") {
+        return "text/html; charset=utf-8";
+    }
+    if starts_with_tag(b"<?xml") {
+        return "application/xml";
+    }
+
+    if looks_like_text(content) { "text/plain; charset=utf-8" } else { DEFAULT_MIME_TYPE }
+}
+
+/// True for UTF-8 without control characters other than whitespace. A
+/// character cut off at the end of the sniffed prefix doesn't count against it.
+fn looks_like_text(content: &[u8]) -> bool {
+    let valid = match std::str::from_utf8(content) {
+        Ok(text) => text,
+        Err(e) if e.error_len().is_none() => std::str::from_utf8(&content[..e.valid_up_to()]).unwrap_or(""),
+        Err(_) => return false,
+    };
+    valid.chars().all(|c| !c.is_control() || matches!(c, '\t' | '\n' | '\r' | '\x0c'))
+}
+
+/// Parses a single `type ext1 ext2;` entry
+fn parse_mime_line(line: &str) -> Option<(&str, Vec<&str>)> {
+    let body = line.strip_suffix(';')?;
+    let mut tokens = body.split_whitespace();
+
+    let mime = tokens.next()?;
+    if !mime.contains('/') || mime.starts_with('/') || mime.ends_with('/') {
+        return None;
+    }
+
+    let extensions: Vec<&str> = tokens.map(|ext| ext.trim_start_matches('.')).collect();
+    if extensions.is_empty() || extensions.iter().any(|ext| ext.is_empty()) {
+        return None;
+    }
+
+    Some((mime, extensions))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{TempDir, TestServer};
+
+    #[test]
+    fn modern_web_types_are_built_in() {
+        let types = MimeTypes::new(true);
+        assert_eq!(types.lookup("wasm"), Some("application/wasm"));
+        assert_eq!(types.lookup("webmanifest"), Some("application/manifest+json"));
+        assert_eq!(types.lookup("mjs"), Some("text/javascript; charset=utf-8"));
+        assert_eq!(types.lookup("avif"), Some("image/avif"));
+        assert_eq!(types.lookup("webp"), Some("image/webp"));
+    }
+
+    #[test]
+    fn wasm_is_served_as_application_wasm() {
+        let root = TempDir::new();
+        root.write("app.wasm", b"\0asm\x01\0\0\0");
+        let server = TestServer::with_args(&["--root", root.path().to_str().unwrap()]);
+
+        let response = server.get("/app.wasm");
+        assert_eq!(response.status, 200);
+        assert_eq!(response.header("Content-Type"), Some("application/wasm"));
+        assert_eq!(response.body, b"\0asm\x01\0\0\0");
+    }
+
+    #[test]
+    fn extension_case_is_folded_unless_strict() {
+        let folding = MimeTypes::new(true);
+        assert_eq!(folding.lookup("JPG"), Some("image/jpeg"));
+        assert_eq!(folding.lookup("CsS"), folding.lookup("css"));
+        let strict = MimeTypes::new(false);
+        assert_eq!(strict.lookup("jpg"), Some("image/jpeg"));
+        assert_eq!(strict.lookup("JPG"), None);
+
+        // A mapping for the uppercase form itself still counts in strict mode
+        let table = |fold_case| MimeTypes::parse_directory_file("image/x-shout JPG;", Path::new(".mime"), fold_case);
+        assert_eq!(table(false).lookup("JPG"), Some("image/x-shout"));
+        assert_eq!(table(false).lookup("jpg"), None);
+        assert_eq!(table(true).lookup("jpg"), Some("image/x-shout"));
+    }
+
+    #[test]
+    fn uppercase_jpg_is_a_jpeg_unless_extensions_are_strict() {
+        let root = TempDir::new();
+        root.write("PHOTO.JPG", b"\xff\xd8\xff\xe0");
+        let root = root.path().to_str().unwrap();
+
+        let folding = TestServer::with_args(&["--root", root]);
+        assert_eq!(folding.get("/PHOTO.JPG").header("Content-Type"), Some("image/jpeg"));
+        let strict = TestServer::with_args(&["--root", root, "--strict-extensions"]);
+        let response = strict.get("/PHOTO.JPG");
+        assert_eq!(response.status, 200);
+        assert_eq!(response.header("Content-Type"), Some(DEFAULT_MIME_TYPE));
+    }
+}
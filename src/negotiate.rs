@@ -0,0 +1,54 @@
+/// One entry of an `Accept`-style header, e.g. `text/html;q=0.9`
+pub struct MediaRange<'a> {
+    pub value: &'a str,
+    pub quality: f32,
+}
+
+/// Splits an `Accept`-style header into its entries and their q-values.
+/// Entries with a missing or unparseable `q` parameter default to 1.0.
+pub fn parse_header(header: &str) -> Vec<MediaRange<'_>> {
+    header
+        .split(',')
+        .filter_map(|item| {
+            let mut params = item.split(';');
+            let value = params.next()?.trim();
+            if value.is_empty() {
+                return None;
+            }
+
+            let quality = params
+                .filter_map(|param| param.trim().strip_prefix("q="))
+                .filter_map(|q| q.trim().parse::<f32>().ok())
+                .next()
+                .unwrap_or(1.0)
+                .clamp(0.0, 1.0);
+
+            Some(MediaRange { value, quality })
+        })
+        .collect()
+}
+
+/// Returns how acceptable `mime` (e.g. `application/json`) is according to
+/// the parsed `Accept` ranges, using the most specific matching range.
+pub fn media_quality(ranges: &[MediaRange], mime: &str) -> f32 {
+    let (kind, _) = mime.split_once('/').unwrap_or((mime, ""));
+    let mut best: Option<(u8, f32)> = None;
+
+    for range in ranges {
+        let specificity = if range.value.eq_ignore_ascii_case(mime) {
+            2
+        } else if range.value.strip_suffix("/*").is_some_and(|t| t.eq_ignore_ascii_case(kind)) {
+            1
+        } else if range.value == "*/*" {
+            0
+        } else {
+            continue;
+        };
+
+        if best.is_none_or(|(s, _)| specificity > s) {
+            best = Some((specificity, range.quality));
+        }
+    }
+
+    best.map(|(_, quality)| quality).unwrap_or(0.0)
+}
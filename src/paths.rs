@@ -0,0 +1,182 @@
+use std::borrow::Cow;
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+
+/// A document root, with its real location worked out once up front rather
+/// than on every request
+pub struct Root {
+    /// The root as configured
+    path: PathBuf,
+    /// `path` with every symlink resolved, or `None` if it didn't exist yet
+    canonical: Option<PathBuf>,
+}
+
+impl Root {
+    pub fn new(path: &Path) -> Root {
+        Root { path: path.to_path_buf(), canonical: fs::canonicalize(path).ok() }
+    }
+
+    /// The directory request paths are joined onto: the real location when
+    /// known, so finding the file doesn't resolve the root's symlinks again
+    pub fn base(&self) -> &Path {
+        self.canonical.as_deref().unwrap_or(&self.path)
+    }
+
+    /// The real location, resolved now if the root was missing at startup
+    fn canonical(&self) -> Option<Cow<'_, Path>> {
+        match &self.canonical {
+            Some(canonical) => Some(Cow::Borrowed(canonical)),
+            None => fs::canonicalize(&self.path).ok().map(Cow::Owned),
+        }
+    }
+}
+
+/// Joins a decoded URL path onto `root`, refusing anything that could escape it.
+///
+/// Returns `None` if the path contains `..` segments, backslashes, NUL bytes
+/// or anything else that isn't a plain file or directory name.
+pub fn safe_join(root: &Path, url_path: &str) -> Option<PathBuf> {
+    if url_path.contains('\\') || url_path.contains('\0') {
+        return None;
+    }
+
+    let mut joined = root.to_path_buf();
+    for segment in url_path.split('/') {
+        match segment {
+            "" | "." => continue,
+            ".." => return None,
+            _ => {}
+        }
+
+        // Reject anything the platform would treat as more than a plain name
+        // (e.g. a drive prefix like `C:` on Windows)
+        let mut components = Path::new(segment).components();
+        match (components.next(), components.next()) {
+            (Some(Component::Normal(_)), None) => joined.push(segment),
+            _ => return None,
+        }
+    }
+
+    Some(joined)
+}
+
+/// Checks that `path`, found under `root.base()` by `safe_join`, really is inside it.
+///
+/// A symlink can lead anywhere, so when `follow_symlinks` is set the real
+/// location of `path` has to lie within the real location of `root`. Without
+/// it, no component below `root` may be a symlink at all. Either way only
+/// `path` is resolved; the root's real location was worked out at startup.
+pub fn is_contained(root: &Root, path: &Path, follow_symlinks: bool) -> bool {
+    if follow_symlinks {
+        return match (root.canonical(), fs::canonicalize(path)) {
+            (Some(root), Ok(path)) => path.starts_with(root),
+            _ => false,
+        };
+    }
+
+    let Ok(relative) = path.strip_prefix(root.base()) else {
+        return false;
+    };
+    let mut current = root.base().to_path_buf();
+    relative.components().all(|component| {
+        current.push(component);
+        fs::symlink_metadata(&current).is_ok_and(|metadata| !metadata.file_type().is_symlink())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{TempDir, TestServer};
+
+    #[test]
+    fn safe_join_refuses_anything_but_plain_names() {
+        let root = Path::new("/srv/www");
+        assert_eq!(safe_join(root, "/a/./b//c.txt"), Some(PathBuf::from("/srv/www/a/b/c.txt")));
+        for path in ["/../etc/passwd", "/a/../../b", "/a\\b", "/a\0b"] {
+            assert_eq!(safe_join(root, path), None, "{:?}", path);
+        }
+    }
+
+    /// A root holding `real.txt` and two symlinks, one to `real.txt` and
+    /// one to a file outside the root
+    #[cfg(unix)]
+    fn root_with_symlinks() -> (TempDir, PathBuf) {
+        let dir = TempDir::new();
+        dir.write("outside.txt", "outside");
+        let root = dir.write("root/real.txt", "inside").parent().unwrap().to_path_buf();
+        std::os::unix::fs::symlink(root.join("real.txt"), root.join("inside-link.txt")).unwrap();
+        std::os::unix::fs::symlink(dir.path().join("outside.txt"), root.join("outside-link.txt")).unwrap();
+        (dir, root)
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn followed_symlinks_must_stay_inside_the_root() {
+        let (_dir, root) = root_with_symlinks();
+        let resolved = Root::new(&root);
+        assert!(is_contained(&resolved, &root.join("inside-link.txt"), true));
+        assert!(!is_contained(&resolved, &root.join("outside-link.txt"), true));
+
+        let server = TestServer::with_args(&["--root", root.to_str().unwrap()]);
+        let inside = server.get("/inside-link.txt");
+        assert_eq!((inside.status, inside.body), (200, b"inside".to_vec()));
+        assert_eq!(server.get("/outside-link.txt").status, 403);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn no_follow_symlinks_refuses_every_link() {
+        let (_dir, root) = root_with_symlinks();
+        assert!(!is_contained(&Root::new(&root), &root.join("inside-link.txt"), false));
+        assert!(is_contained(&Root::new(&root), &root.join("real.txt"), false));
+
+        let server = TestServer::with_args(&["--root", root.to_str().unwrap(), "--no-follow-symlinks"]);
+        assert_eq!(server.get("/real.txt").status, 200);
+        assert_eq!(server.get("/inside-link.txt").status, 403);
+        assert_eq!(server.get("/outside-link.txt").status, 403);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn a_symlinked_root_is_resolved_once_at_startup() {
+        let dir = TempDir::new();
+        dir.write("first/page.txt", "first");
+        dir.write("second/page.txt", "second");
+        let link = dir.path().join("site");
+        std::os::unix::fs::symlink(dir.path().join("first"), &link).unwrap();
+
+        let root = Root::new(&link);
+        assert_eq!(root.base(), fs::canonicalize(dir.path().join("first")).unwrap());
+        let link_arg = link.to_str().unwrap();
+        let servers = [
+            TestServer::with_args(&["--root", link_arg]),
+            TestServer::with_args(&["--root", link_arg, "--no-follow-symlinks"]),
+        ];
+        for server in &servers {
+            assert_eq!(server.get("/page.txt").body, b"first");
+        }
+
+        // Repointing the link behind the servers' backs goes unnoticed,
+        // since requests are joined onto the location resolved at startup
+        fs::remove_file(&link).unwrap();
+        std::os::unix::fs::symlink(dir.path().join("second"), &link).unwrap();
+        for server in &servers {
+            let page = server.get("/page.txt");
+            assert_eq!((page.status, page.body), (200, b"first".to_vec()));
+        }
+        assert_eq!(Root::new(&link).base(), fs::canonicalize(dir.path().join("second")).unwrap());
+    }
+
+    #[test]
+    fn a_root_missing_at_startup_is_served_once_it_exists() {
+        let dir = TempDir::new();
+        let root = dir.path().join("later");
+        let server = TestServer::with_args(&["--root", root.to_str().unwrap()]);
+        assert_eq!(server.get("/page.txt").status, 404);
+
+        dir.write("later/page.txt", "arrived");
+        let page = server.get("/page.txt");
+        assert_eq!((page.status, page.body), (200, b"arrived".to_vec()));
+    }
+}
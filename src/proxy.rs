@@ -0,0 +1,339 @@
+use std::io::{self, ErrorKind, Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::time::{Duration, Instant};
+
+use crate::error_response;
+use crate::http::{self, HttpRequest, HttpResponse, Method, StatusCode};
+
+/// Request headers that only describe our connection to the client, or are
+/// rewritten for the upstream one, and so aren't forwarded
+const HOP_BY_HOP: &[&str] = &[
+    "connection",
+    "keep-alive",
+    "proxy-connection",
+    "proxy-authorization",
+    "te",
+    "trailer",
+    "transfer-encoding",
+    "upgrade",
+    "content-length",
+    "expect",
+];
+
+/// Largest upstream response head we're willing to buffer
+const MAX_UPSTREAM_HEAD: usize = 64 * 1024;
+
+/// A server that `--proxy` forwards a path prefix to, and how patiently
+pub struct Upstream {
+    /// `host:port` to connect to
+    pub address: String,
+    pub connect_timeout: Duration,
+    /// Longest wait for any single read from (or write to) the upstream
+    pub read_timeout: Duration,
+    /// Longest the whole exchange may take once connected, however steadily
+    /// the upstream trickles its answer
+    pub timeout: Duration,
+    /// Largest answer, head and body, buffered before giving up
+    pub max_response: usize,
+    /// Further connection attempts after a failed one, for idempotent methods
+    pub retries: u32,
+}
+
+/// Why forwarding failed, which decides between 502 and 504
+enum Failure {
+    /// The upstream couldn't be reached or sent something unusable
+    BadGateway(String),
+    /// The upstream didn't answer in time
+    Timeout(String),
+}
+
+/// Forwards `request` and its `body` to `upstream` and relays the answer.
+/// An upstream that can't be reached gets a 502, after any retries the
+/// method allows, and one that runs out of time gets a 504.
+pub fn forward(request: &HttpRequest, body: &[u8], upstream: &Upstream) -> HttpResponse {
+    match exchange(request, body, upstream) {
+        Ok(response) => response,
+        Err(Failure::BadGateway(reason)) => {
+            log_error!("Proxying {} to {} failed: {}", request.path, upstream.address, reason);
+            error_response(request, StatusCode::BAD_GATEWAY, "The upstream server could not be reached")
+        }
+        Err(Failure::Timeout(reason)) => {
+            log_error!("Proxying {} to {} timed out: {}", request.path, upstream.address, reason);
+            error_response(request, StatusCode::GATEWAY_TIMEOUT, "The upstream server did not answer in time")
+        }
+    }
+}
+
+fn exchange(request: &HttpRequest, body: &[u8], upstream: &Upstream) -> Result<HttpResponse, Failure> {
+    let mut stream = connect(request, upstream)?;
+    let deadline = Instant::now() + upstream.timeout;
+    // One read or write that stalls for longer than this fails with WouldBlock or TimedOut
+    let timeouts = stream
+        .set_read_timeout(Some(upstream.read_timeout))
+        .and_then(|_| stream.set_write_timeout(Some(upstream.read_timeout)));
+    if let Err(e) = timeouts {
+        return Err(Failure::BadGateway(e.to_string()));
+    }
+
+    let sent = stream.write_all(&upstream_head(request, body.len())).and_then(|_| stream.write_all(body));
+    sent.map_err(|e| failure("sending the request", &e))?;
+
+    let received = read_response(&mut stream, upstream, deadline)?;
+    parse_response(request, &received).ok_or_else(|| Failure::BadGateway("malformed response".to_string()))
+}
+
+/// Reads the upstream's answer, which ends where it closes the connection.
+/// Each read waits no longer than the read timeout or what's left before
+/// `deadline`, and an answer past `max_response` bytes is abandoned.
+fn read_response(stream: &mut TcpStream, upstream: &Upstream, deadline: Instant) -> Result<Vec<u8>, Failure> {
+    let mut received = Vec::new();
+    let mut chunk = vec![0; 16 * 1024];
+    loop {
+        let left = deadline.saturating_duration_since(Instant::now());
+        if left.is_zero() {
+            return Err(Failure::Timeout(format!("no complete response within {:?}", upstream.timeout)));
+        }
+        let timeout = stream.set_read_timeout(Some(left.min(upstream.read_timeout)));
+        timeout.map_err(|e| Failure::BadGateway(e.to_string()))?;
+        let size = match stream.read(&mut chunk) {
+            Ok(0) => return Ok(received),
+            Ok(size) => size,
+            Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+            Err(e) => return Err(failure("reading the response", &e)),
+        };
+        if received.len() + size > upstream.max_response {
+            return Err(Failure::BadGateway(format!("response over {} bytes", upstream.max_response)));
+        }
+        received.extend_from_slice(&chunk[..size]);
+    }
+}
+
+/// Connects to the upstream, trying again after a failure as many times as
+/// `retries` allows. A request whose method isn't idempotent gets a single
+/// attempt, since a retry could repeat its effect.
+fn connect(request: &HttpRequest, upstream: &Upstream) -> Result<TcpStream, Failure> {
+    let attempts = if is_idempotent(&request.method) { upstream.retries + 1 } else { 1 };
+    let mut last_error = None;
+    for attempt in 1..=attempts {
+        match connect_once(&upstream.address, upstream.connect_timeout) {
+            Ok(stream) => return Ok(stream),
+            Err(e) => {
+                log_info!("Connecting to {} failed (attempt {} of {}): {}", upstream.address, attempt, attempts, e);
+                last_error = Some(e);
+            }
+        }
+    }
+    let error = last_error.unwrap_or_else(|| io::Error::from(ErrorKind::NotConnected));
+    Err(failure("connecting", &error))
+}
+
+/// Tries each address `address` resolves to in turn
+pub fn connect_once(address: &str, timeout: Duration) -> io::Result<TcpStream> {
+    let mut last_error = io::Error::new(ErrorKind::NotFound, "the address resolved to nothing");
+    for candidate in address.to_socket_addrs()? {
+        match TcpStream::connect_timeout(&candidate, timeout) {
+            Ok(stream) => return Ok(stream),
+            Err(e) => last_error = e,
+        }
+    }
+    Err(last_error)
+}
+
+fn failure(action: &str, error: &io::Error) -> Failure {
+    let reason = format!("{}: {}", action, error);
+    match error.kind() {
+        ErrorKind::TimedOut | ErrorKind::WouldBlock => Failure::Timeout(reason),
+        _ => Failure::BadGateway(reason),
+    }
+}
+
+/// Methods that can be sent twice with the same effect as once
+fn is_idempotent(method: &Method) -> bool {
+    match method {
+        Method::Get | Method::Head | Method::Put | Method::Delete | Method::Options => true,
+        Method::Post | Method::Patch => false,
+        Method::Other(token) => token == "TRACE",
+    }
+}
+
+/// The request line and headers sent upstream: the client's, minus the
+/// hop-by-hop ones, with the body's length and `Connection: close`
+fn upstream_head(request: &HttpRequest, body_len: usize) -> Vec<u8> {
+    let path: Vec<String> = request.path.split('/').map(http::percent_encode).collect();
+    let mut head = format!("{} {}", request.method, path.join("/"));
+    if let Some(query) = &request.query {
+        head.push_str(&format!("?{}", query));
+    }
+    head.push_str(" HTTP/1.1\r\n");
+
+    // Headers named in Connection are hop-by-hop too
+    let listed: Vec<String> = request
+        .header("Connection")
+        .unwrap_or_default()
+        .split(',')
+        .map(|name| name.trim().to_ascii_lowercase())
+        .collect();
+    for (name, value) in &request.headers {
+        if !HOP_BY_HOP.contains(&name.as_str()) && !listed.contains(name) {
+            head.push_str(&format!("{}: {}\r\n", name, value));
+        }
+    }
+    if body_len > 0 || matches!(request.method, Method::Post | Method::Put | Method::Patch) {
+        head.push_str(&format!("content-length: {}\r\n", body_len));
+    }
+    head.push_str("connection: close\r\n\r\n");
+    head.into_bytes()
+}
+
+/// Turns the upstream's whole answer into a response for the client.
+/// Framing headers are dropped, since the body is sent on with ours, and so
+/// is `Server`, which we send ourselves.
+fn parse_response(request: &HttpRequest, received: &[u8]) -> Option<HttpResponse> {
+    let head_end = http::find_head_end(received).filter(|&end| end <= MAX_UPSTREAM_HEAD)?;
+    let head = std::str::from_utf8(&received[..head_end]).ok()?;
+    let mut lines = head.lines();
+    let status_line = lines.next()?;
+    let mut parts = status_line.splitn(3, ' ');
+    if !parts.next()?.starts_with("HTTP/1.") {
+        return None;
+    }
+    let status: u16 = parts.next()?.parse().ok().filter(|status| (200..600).contains(status))?;
+
+    let mut response = HttpResponse::new(status);
+    let (mut chunked, mut length) = (false, None);
+    for line in lines.filter(|line| !line.is_empty()) {
+        let (name, value) = line.split_once(':')?;
+        let (name, value) = (name.trim(), value.trim());
+        if name.eq_ignore_ascii_case("Transfer-Encoding") {
+            chunked = value.eq_ignore_ascii_case("chunked");
+        } else if name.eq_ignore_ascii_case("Content-Length") {
+            length = Some(value.parse::<usize>().ok()?);
+        } else if !HOP_BY_HOP.contains(&name.to_ascii_lowercase().as_str()) && !name.eq_ignore_ascii_case("Server") {
+            response = response.with_header(name, value);
+        }
+    }
+
+    let rest = &received[head_end..];
+    if request.method == Method::Head || !response.status.allows_body() {
+        // A HEAD still reports the length the GET would have
+        response.content_length = length.map(|length| length as u64);
+        return Some(response);
+    }
+    response.body = if chunked {
+        dechunk(rest)?
+    } else {
+        match length {
+            Some(length) => rest.get(..length)?.to_vec(),
+            None => rest.to_vec(),
+        }
+    };
+    Some(response)
+}
+
+/// Decodes a chunked body, dropping any trailers
+fn dechunk(mut data: &[u8]) -> Option<Vec<u8>> {
+    let mut body = Vec::new();
+    loop {
+        let line_end = data.windows(2).position(|window| window == b"\r\n")?;
+        let size_line = std::str::from_utf8(&data[..line_end]).ok()?;
+        let size = usize::from_str_radix(size_line.split(';').next()?.trim(), 16).ok()?;
+        data = &data[line_end + 2..];
+        if size == 0 {
+            return Some(body);
+        }
+        body.extend_from_slice(data.get(..size)?);
+        data = data.get(size + 2..)?;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::testing::{TestServer, read_to_close};
+    use std::io::{Read, Write};
+    use std::net::{TcpListener, TcpStream};
+    use std::thread::{self, JoinHandle};
+    use std::time::{Duration, Instant};
+
+    /// An upstream that reads one request's head and hands the connection to `answer`
+    fn upstream(answer: impl FnOnce(TcpStream) + Send + 'static) -> (String, JoinHandle<()>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        let handle = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut head = Vec::new();
+            let mut byte = [0; 1];
+            while !head.ends_with(b"\r\n\r\n") && stream.read(&mut byte).unwrap_or(0) == 1 {
+                head.push(byte[0]);
+            }
+            answer(stream);
+        });
+        (address, handle)
+    }
+
+    fn proxying(address: &str, args: &[&str]) -> TestServer {
+        let rule = format!("/api={}", address);
+        TestServer::with_args(&[&["--proxy", &rule], args].concat())
+    }
+
+    #[test]
+    fn answers_are_relayed() {
+        let (address, handle) = upstream(|mut stream| {
+            stream.write_all(b"HTTP/1.1 201 Created\r\nContent-Length: 2\r\nX-From: upstream\r\n\r\nok").unwrap();
+        });
+        let server = proxying(&address, &[]);
+        let response = server.get("/api/items?page=2");
+        assert_eq!(response.status, 201);
+        assert_eq!(response.header("X-From"), Some("upstream"));
+        assert_eq!(response.body, b"ok");
+        handle.join().unwrap();
+    }
+
+    #[test]
+    fn an_unreachable_upstream_is_a_bad_gateway() {
+        // Bound and dropped again, so nothing listens there
+        let address = TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().to_string();
+        let server = proxying(&address, &["--proxy-retries", "2"]);
+        assert_eq!(server.get("/api/items").status, 502);
+    }
+
+    #[test]
+    fn a_silent_upstream_times_out() {
+        let (address, handle) = upstream(|mut stream| {
+            // Says nothing until the proxy gives up and hangs up
+            let _ = read_to_close(&mut stream);
+        });
+        let server = proxying(&address, &["--proxy-read-timeout", "1"]);
+        assert_eq!(server.get("/api/items").status, 504);
+        drop(server);
+        handle.join().unwrap();
+    }
+
+    #[test]
+    fn a_trickling_upstream_runs_into_the_overall_deadline() {
+        let (address, handle) = upstream(|mut stream| {
+            // Never quiet for long enough to trip the read timeout, but never done either
+            let _ = stream.write_all(b"HTTP/1.1 200 OK\r\n");
+            while stream.write_all(b"X-Drip: .\r\n").is_ok() {
+                thread::sleep(Duration::from_millis(200));
+            }
+        });
+        let server = proxying(&address, &["--proxy-read-timeout", "5", "--proxy-timeout", "2"]);
+        let started = Instant::now();
+        assert_eq!(server.get("/api/items").status, 504);
+        assert!(started.elapsed() < Duration::from_secs(5), "took {:?}", started.elapsed());
+        drop(server);
+        handle.join().unwrap();
+    }
+
+    #[test]
+    fn an_oversized_answer_is_abandoned() {
+        let (address, handle) = upstream(|mut stream| {
+            let _ = stream.write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 4096\r\n\r\n");
+            let _ = stream.write_all(&[b'x'; 4096]);
+        });
+        let server = proxying(&address, &["--proxy-max-response", "1K"]);
+        assert_eq!(server.get("/api/items").status, 502);
+        drop(server);
+        handle.join().unwrap();
+    }
+}
@@ -0,0 +1,91 @@
+use crate::http::HttpRequest;
+use crate::http_date::parse_http_date;
+use crate::validators::Validators;
+
+/// What a request's `Range` header asks for, given the resource length
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RangeRequest {
+    /// No usable range: send the whole resource with 200
+    Full,
+    /// Send bytes `start..=end` with 206
+    Partial { start: u64, end: u64 },
+    /// The range starts past the end of the resource: 416
+    Unsatisfiable,
+}
+
+/// Interprets the `Range` and `If-Range` headers for a resource of `len` bytes.
+///
+/// Only a single byte range is supported. Multiple ranges, other units and
+/// malformed headers are ignored, which the spec allows, so the client gets
+/// the full resource.
+pub fn evaluate(request: &HttpRequest, validators: &Validators, len: u64) -> RangeRequest {
+    let Some(header) = request.header("Range") else {
+        return RangeRequest::Full;
+    };
+    // A stale If-Range means the client's partial copy is useless: send everything
+    if let Some(if_range) = request.header("If-Range")
+        && !if_range_matches(if_range, validators)
+    {
+        return RangeRequest::Full;
+    }
+    parse(header, len)
+}
+
+fn parse(header: &str, len: u64) -> RangeRequest {
+    let Some(spec) = header.trim().strip_prefix("bytes=") else {
+        return RangeRequest::Full;
+    };
+    let Some((first, last)) = spec.split_once('-') else {
+        return RangeRequest::Full;
+    };
+    if spec.contains(',') {
+        return RangeRequest::Full;
+    }
+    let (first, last) = (first.trim(), last.trim());
+
+    // `bytes=-N` asks for the last N bytes
+    if first.is_empty() {
+        return match last.parse::<u64>() {
+            Ok(0) => RangeRequest::Unsatisfiable,
+            Ok(_) if len == 0 => RangeRequest::Unsatisfiable,
+            Ok(suffix) => RangeRequest::Partial { start: len.saturating_sub(suffix), end: len - 1 },
+            Err(_) => RangeRequest::Full,
+        };
+    }
+
+    let Ok(start) = first.parse::<u64>() else {
+        return RangeRequest::Full;
+    };
+    let end = if last.is_empty() {
+        u64::MAX
+    } else {
+        match last.parse::<u64>() {
+            Ok(end) if end >= start => end,
+            _ => return RangeRequest::Full,
+        }
+    };
+
+    if start >= len {
+        return RangeRequest::Unsatisfiable;
+    }
+    RangeRequest::Partial { start, end: end.min(len - 1) }
+}
+
+/// `If-Range` holds either an entity tag, which must match strongly, or a
+/// date, which must equal the resource's Last-Modified exactly
+fn if_range_matches(if_range: &str, validators: &Validators) -> bool {
+    let if_range = if_range.trim();
+    if if_range.starts_with('"') || if_range.starts_with("W/") {
+        return !if_range.starts_with("W/") && if_range == validators.etag;
+    }
+
+    match (parse_http_date(if_range), validators.last_modified) {
+        (Some(date), Some(modified)) => {
+            let seconds = |time: std::time::SystemTime| {
+                time.duration_since(std::time::UNIX_EPOCH).map(|age| age.as_secs()).unwrap_or(0)
+            };
+            seconds(date) == seconds(modified)
+        }
+        _ => false,
+    }
+}
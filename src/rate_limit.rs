@@ -0,0 +1,73 @@
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Buckets kept before those that have refilled completely are dropped
+const MAX_TRACKED_CLIENTS: usize = 10_000;
+
+/// One client's token bucket
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+/// Per-client-IP token buckets: each client may make `rate` requests per
+/// second on average, with bursts of up to `rate` requests
+pub struct RateLimiter {
+    rate: f64,
+    buckets: Mutex<HashMap<IpAddr, Bucket>>,
+}
+
+impl RateLimiter {
+    pub fn new(rate: u32) -> Self {
+        RateLimiter { rate: f64::from(rate), buckets: Mutex::new(HashMap::new()) }
+    }
+
+    /// Takes a token for `client`. When its bucket is empty, returns how long
+    /// until the next token arrives.
+    pub fn check(&self, client: IpAddr) -> Result<(), Duration> {
+        let now = Instant::now();
+        let mut buckets = self.buckets.lock().unwrap();
+        if buckets.len() >= MAX_TRACKED_CLIENTS {
+            // A full bucket is the same as no bucket, so it can go
+            buckets.retain(|_, bucket| self.refilled(bucket, now) < self.rate);
+        }
+
+        let bucket = buckets.entry(client).or_insert(Bucket { tokens: self.rate, updated: now });
+        bucket.tokens = self.refilled(bucket, now);
+        bucket.updated = now;
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            Ok(())
+        } else {
+            Err(Duration::from_secs_f64((1.0 - bucket.tokens) / self.rate))
+        }
+    }
+
+    /// The bucket's tokens at `now`, capped at the burst size
+    fn refilled(&self, bucket: &Bucket, now: Instant) -> f64 {
+        let elapsed = now.duration_since(bucket.updated).as_secs_f64();
+        (bucket.tokens + elapsed * self.rate).min(self.rate)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn empty_bucket_reports_the_refill_time() {
+        let limiter = RateLimiter::new(4);
+        let client: IpAddr = "192.0.2.1".parse().unwrap();
+        for _ in 0..4 {
+            assert!(limiter.check(client).is_ok());
+        }
+        let wait = limiter.check(client).unwrap_err();
+        // One token takes a quarter of a second at 4 per second
+        assert!(wait > Duration::ZERO && wait <= Duration::from_millis(250), "waited {:?}", wait);
+
+        // Other clients have buckets of their own
+        assert!(limiter.check("192.0.2.2".parse().unwrap()).is_ok());
+    }
+}
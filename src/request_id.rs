@@ -0,0 +1,40 @@
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Longest incoming `X-Request-Id` we're willing to reuse
+const MAX_INCOMING_ID_LEN: usize = 128;
+
+static COUNTER: AtomicU64 = AtomicU64::new(0);
+
+/// Generates a random 32-character hex request ID.
+///
+/// `RandomState` is seeded from the OS, so hashing a counter and the current
+/// time with it gives IDs that are unique and unpredictable enough for
+/// correlating log lines, without needing a `rand` dependency.
+pub fn generate() -> String {
+    let count = COUNTER.fetch_add(1, Ordering::Relaxed);
+    let nanos = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_nanos()).unwrap_or(0);
+
+    let state = RandomState::new();
+    let mut halves = [0u64; 2];
+    for (i, half) in halves.iter_mut().enumerate() {
+        let mut hasher = state.build_hasher();
+        hasher.write_u64(count);
+        hasher.write_u128(nanos);
+        hasher.write_usize(i);
+        *half = hasher.finish();
+    }
+
+    format!("{:016x}{:016x}", halves[0], halves[1])
+}
+
+/// Returns the incoming ID if it's safe to echo back and log
+pub fn sanitize_incoming(value: &str) -> Option<&str> {
+    let value = value.trim();
+    let valid = !value.is_empty()
+        && value.len() <= MAX_INCOMING_ID_LEN
+        && value.bytes().all(|b| b.is_ascii_alphanumeric() || b"-_.:".contains(&b));
+    valid.then_some(value)
+}
@@ -0,0 +1,98 @@
+use std::path::Path;
+
+/// A rule that maps a request path onto a different file path before the
+/// filesystem lookup. Rules see the percent-decoded path.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RewriteRule {
+    /// Replace a leading `from` with `to`, e.g. `/docs/` to `/manual/v2/`
+    Prefix { from: String, to: String },
+    /// Append a suffix to paths without an extension, e.g. `.html` so
+    /// `/about` serves `about.html`. Only applies if the result exists.
+    Suffix(String),
+}
+
+impl RewriteRule {
+    /// Parses a `--rewrite` value: `<from>=<to>` for a prefix rule
+    pub fn parse_prefix(value: &str) -> Option<RewriteRule> {
+        let (from, to) = value.split_once('=')?;
+        if !from.starts_with('/') || !to.starts_with('/') {
+            return None;
+        }
+        Some(RewriteRule::Prefix { from: from.to_string(), to: to.to_string() })
+    }
+
+    /// Parses a `--rewrite-suffix` value such as `.html`
+    pub fn parse_suffix(value: &str) -> Option<RewriteRule> {
+        if value.is_empty() || value.contains('/') {
+            return None;
+        }
+        Some(RewriteRule::Suffix(value.to_string()))
+    }
+
+    /// The rewritten path, or `None` if the rule doesn't apply. `exists`
+    /// reports whether a candidate path would find a file.
+    fn apply(&self, path: &str, exists: &dyn Fn(&str) -> bool) -> Option<String> {
+        match self {
+            RewriteRule::Prefix { from, to } => path.strip_prefix(from.as_str()).map(|rest| format!("{}{}", to, rest)),
+            RewriteRule::Suffix(suffix) => {
+                // Directories keep their trailing-slash redirect and index handling
+                if path.ends_with('/') || Path::new(path).extension().is_some() {
+                    return None;
+                }
+                let candidate = format!("{}{}", path, suffix);
+                exists(&candidate).then_some(candidate)
+            }
+        }
+    }
+}
+
+/// Runs `path` through every rule in order, each seeing the previous result
+pub fn rewrite(rules: &[RewriteRule], path: &str, exists: &dyn Fn(&str) -> bool) -> String {
+    rules.iter().fold(path.to_string(), |path, rule| rule.apply(&path, exists).unwrap_or(path))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{TempDir, TestServer};
+
+    #[test]
+    fn rules_apply_in_order_and_only_where_they_fit() {
+        let rules = [
+            RewriteRule::parse_prefix("/docs/=/manual/").unwrap(),
+            RewriteRule::parse_suffix(".html").unwrap(),
+        ];
+        let exists = |path: &str| path == "/manual/intro.html";
+        assert_eq!(rewrite(&rules, "/docs/intro", &exists), "/manual/intro.html");
+        assert_eq!(rewrite(&rules, "/docs/missing", &exists), "/manual/missing");
+        assert_eq!(rewrite(&rules, "/docs/style.css", &exists), "/manual/style.css");
+        assert_eq!(rewrite(&rules, "/docs/", &exists), "/manual/");
+        assert_eq!(RewriteRule::parse_prefix("docs=/manual"), None);
+        assert_eq!(RewriteRule::parse_suffix("a/b"), None);
+    }
+
+    #[test]
+    fn suffix_rule_serves_about_html_for_about() {
+        let root = TempDir::new();
+        root.write("about.html", "<h1>About</h1>");
+        let server = TestServer::with_args(&["--root", root.path().to_str().unwrap(), "--rewrite-suffix", ".html"]);
+
+        let response = server.get("/about");
+        assert_eq!(response.status, 200);
+        assert_eq!(response.body, b"<h1>About</h1>");
+        assert_eq!(server.get("/contact").status, 404);
+    }
+
+    #[test]
+    fn rewritten_paths_still_get_the_traversal_check() {
+        let parent = TempDir::new();
+        parent.write("secret.txt", "secret");
+        let root = parent.write("site/index.html", "home");
+        let root = root.parent().unwrap().to_str().unwrap();
+        let server = TestServer::with_args(&["--root", root, "--rewrite", "/files/=/../"]);
+
+        let response = server.get("/files/secret.txt");
+        assert_ne!(response.status, 200);
+        assert!(!response.body.windows(6).any(|window| window == b"secret"));
+    }
+}
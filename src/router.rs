@@ -0,0 +1,158 @@
+use crate::body::RequestBody;
+use crate::cors::CorsRule;
+use crate::http::{HttpRequest, HttpResponse, Method};
+
+/// A handler that receives the parsed request and its whole body
+pub type BufferedHandler = Box<dyn Fn(&HttpRequest, &[u8]) -> HttpResponse + Send + Sync>;
+
+/// A handler that reads the body itself as it arrives, for uploads too large
+/// to hold in memory
+pub type StreamingHandler = Box<dyn Fn(&HttpRequest, &mut RequestBody) -> HttpResponse + Send + Sync>;
+
+/// A request handler
+pub enum Handler {
+    Buffered(BufferedHandler),
+    Streaming(StreamingHandler),
+}
+
+/// A single registered route
+struct Route {
+    method: Method,
+    pattern: String,
+    handler: Handler,
+}
+
+impl Route {
+    fn matches(&self, path: &str) -> bool {
+        pattern_matches(&self.pattern, path)
+    }
+}
+
+/// Patterns are either an exact path (`/api/items`) or a prefix
+/// ending in `/*` (`/api/*` matches `/api` and everything below it)
+pub fn pattern_matches(pattern: &str, path: &str) -> bool {
+    match pattern.strip_suffix("/*") {
+        Some(prefix) => {
+            path == prefix
+                || path.strip_prefix(prefix).is_some_and(|rest| rest.starts_with('/'))
+        }
+        None => pattern == path,
+    }
+}
+
+/// Maps (method, path) pairs to handlers. Requests that match no route
+/// fall through to static file serving.
+#[derive(Default)]
+pub struct Router {
+    routes: Vec<Route>,
+    /// CORS rules by path pattern, whatever the method
+    cors: Vec<(String, CorsRule)>,
+}
+
+impl Router {
+    pub fn new() -> Self {
+        Router::default()
+    }
+
+    /// Registers a handler for `method` requests whose path matches `pattern`.
+    /// Earlier registrations win when several routes match.
+    pub fn route<F>(&mut self, method: Method, pattern: &str, handler: F) -> &mut Self
+    where
+        F: Fn(&HttpRequest, &[u8]) -> HttpResponse + Send + Sync + 'static,
+    {
+        self.add(method, pattern, Handler::Buffered(Box::new(handler)))
+    }
+
+    /// Like `route`, but the handler streams the request body instead of
+    /// getting it buffered
+    pub fn route_streaming<F>(&mut self, method: Method, pattern: &str, handler: F) -> &mut Self
+    where
+        F: Fn(&HttpRequest, &mut RequestBody) -> HttpResponse + Send + Sync + 'static,
+    {
+        self.add(method, pattern, Handler::Streaming(Box::new(handler)))
+    }
+
+    /// Applies `rule` to cross-origin requests whose path matches `pattern`,
+    /// preflights included. Earlier registrations win when several match.
+    pub fn cors(&mut self, pattern: &str, rule: CorsRule) -> &mut Self {
+        self.cors.push((pattern.to_string(), rule));
+        self
+    }
+
+    /// Finds the CORS rule registered for `path`, if any
+    pub fn find_cors(&self, path: &str) -> Option<&CorsRule> {
+        self.cors.iter().find(|(pattern, _)| pattern_matches(pattern, path)).map(|(_, rule)| rule)
+    }
+
+    fn add(&mut self, method: Method, pattern: &str, handler: Handler) -> &mut Self {
+        self.routes.push(Route { method, pattern: pattern.to_string(), handler });
+        self
+    }
+
+    /// Finds the handler for a request, if any route matches
+    pub fn find(&self, method: &Method, path: &str) -> Option<&Handler> {
+        self.routes
+            .iter()
+            .find(|route| route.method == *method && route.matches(path))
+            .map(|route| &route.handler)
+    }
+
+    /// Every registered route as (method, pattern, handler), in the order
+    /// they are tried
+    pub fn routes(&self) -> impl Iterator<Item = (&Method, &str, &Handler)> {
+        self.routes.iter().map(|route| (&route.method, route.pattern.as_str(), &route.handler))
+    }
+
+    /// Lists the methods that have a route for `path`, for the `Allow` header
+    pub fn allowed_methods(&self, path: &str) -> Vec<&Method> {
+        distinct_methods(self.routes.iter().filter(|route| route.matches(path)))
+    }
+
+    /// Lists every method that has a route for any path
+    pub fn all_methods(&self) -> Vec<&Method> {
+        distinct_methods(self.routes.iter())
+    }
+}
+
+fn distinct_methods<'a>(routes: impl Iterator<Item = &'a Route>) -> Vec<&'a Method> {
+    let mut methods: Vec<&Method> = Vec::new();
+    for route in routes {
+        if !methods.contains(&&route.method) {
+            methods.push(&route.method);
+        }
+    }
+    methods
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Server;
+    use crate::http::StatusCode;
+    use crate::testing::{self, TestServer, build_request};
+
+    #[test]
+    fn patch_routes_receive_the_body() {
+        let mut server = Server::bind(testing::config(&[])).unwrap();
+        server.context.router.route(Method::Patch, "/items/*", |request, body| {
+            let echoed = format!("{} {}: {}", request.method, request.path, String::from_utf8_lossy(body));
+            HttpResponse::text(StatusCode::OK, &echoed)
+        });
+        let server = TestServer::serve(server);
+
+        let response = server.send(build_request("PATCH", "/items/7", &[], b"{\"done\":true}"));
+        assert_eq!(response.status, 200);
+        assert_eq!(response.body, b"PATCH /items/7: {\"done\":true}");
+    }
+
+    #[test]
+    fn routes_match_by_method_and_pattern() {
+        let mut router = Router::new();
+        router.route(Method::Patch, "/api/*", |_, _| HttpResponse::new(StatusCode::NO_CONTENT));
+        assert!(router.find(&Method::Patch, "/api").is_some());
+        assert!(router.find(&Method::Patch, "/api/items").is_some());
+        assert!(router.find(&Method::Patch, "/apis").is_none());
+        assert!(router.find(&Method::Post, "/api/items").is_none());
+        assert_eq!(router.allowed_methods("/api/items"), vec![&Method::Patch]);
+    }
+}
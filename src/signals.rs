@@ -0,0 +1,85 @@
+//! Minimal POSIX signal handling without pulling in the `libc` crate.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/// Set by SIGHUP until the server gets around to reloading its configuration
+static RELOAD_REQUESTED: AtomicBool = AtomicBool::new(false);
+
+#[cfg(unix)]
+mod unix {
+    use std::os::raw::c_int;
+
+    pub(super) const SIGHUP: c_int = 1;
+
+    unsafe extern "C" {
+        fn signal(signum: c_int, handler: extern "C" fn(c_int)) -> usize;
+    }
+
+    extern "C" fn on_sighup(_signum: c_int) {
+        // Only async-signal-safe work here: flip a flag and return
+        crate::logger::request_reopen();
+        super::RELOAD_REQUESTED.store(true, super::Ordering::SeqCst);
+    }
+
+    pub fn install() {
+        // SAFETY: the handler only stores to an atomic, which is async-signal-safe
+        unsafe {
+            signal(SIGHUP, on_sighup);
+        }
+    }
+}
+
+/// Installs the SIGHUP handler that makes the logger reopen its file and
+/// asks for the configuration to be reloaded. Does nothing on platforms
+/// without POSIX signals.
+pub fn install_handlers() {
+    #[cfg(unix)]
+    unix::install();
+}
+
+/// Whether SIGHUP arrived since the last call
+pub fn take_reload_request() -> bool {
+    RELOAD_REQUESTED.swap(false, Ordering::SeqCst)
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use std::io::Write;
+    use std::os::raw::c_int;
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    use super::unix;
+    use crate::testing::{TempDir, TestServer, read_response};
+
+    unsafe extern "C" {
+        fn raise(signum: c_int) -> c_int;
+    }
+
+    #[test]
+    fn sighup_reloads_the_config_file() {
+        let dir = TempDir::new();
+        let old_root = dir.write("old/page.txt", "old").parent().unwrap().to_path_buf();
+        let new_root = dir.write("new/page.txt", "new").parent().unwrap().to_path_buf();
+        let config_file = dir.write("server.conf", format!("root {}\n", old_root.display()));
+        let server = TestServer::with_args(&["--config", config_file.to_str().unwrap()]);
+        assert_eq!(server.get("/page.txt").body, b"old");
+
+        // A keep-alive connection opened before the reload outlives it
+        let mut stream = server.connect();
+        stream.write_all(b"GET /page.txt HTTP/1.1\r\nHost: a\r\n\r\n").unwrap();
+        assert_eq!(read_response(&mut stream).body, b"old");
+
+        dir.write("server.conf", format!("root {}\n", new_root.display()));
+        // SAFETY: the server installed its SIGHUP handler, which only sets flags
+        assert_eq!(unsafe { raise(unix::SIGHUP) }, 0);
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while server.get("/page.txt").body != b"new" {
+            assert!(Instant::now() < deadline, "the new root never took effect");
+            thread::sleep(Duration::from_millis(20));
+        }
+
+        stream.write_all(b"GET /page.txt HTTP/1.1\r\nHost: a\r\n\r\n").unwrap();
+        assert_eq!(read_response(&mut stream).status, 200);
+    }
+}
@@ -0,0 +1,97 @@
+//! Socket buffer sizes, set through `setsockopt` without pulling in the `libc` crate.
+
+use std::io;
+use std::net::TcpStream;
+
+/// Which of a socket's kernel buffers to size
+#[derive(Debug, Clone, Copy)]
+pub enum Buffer {
+    Send,
+    Receive,
+}
+
+impl Buffer {
+    /// The socket option behind the buffer, as it's known in logs
+    pub fn option_name(self) -> &'static str {
+        match self {
+            Buffer::Send => "SO_SNDBUF",
+            Buffer::Receive => "SO_RCVBUF",
+        }
+    }
+}
+
+#[cfg(any(target_os = "linux", target_os = "android"))]
+mod consts {
+    use std::os::raw::c_int;
+
+    pub const SOL_SOCKET: c_int = 1;
+    pub const SO_SNDBUF: c_int = 7;
+    pub const SO_RCVBUF: c_int = 8;
+}
+
+#[cfg(all(unix, not(any(target_os = "linux", target_os = "android"))))]
+mod consts {
+    use std::os::raw::c_int;
+
+    // The values macOS and the BSDs share
+    pub const SOL_SOCKET: c_int = 0xffff;
+    pub const SO_SNDBUF: c_int = 0x1001;
+    pub const SO_RCVBUF: c_int = 0x1002;
+}
+
+#[cfg(unix)]
+mod unix {
+    use std::io;
+    use std::net::TcpStream;
+    use std::os::fd::AsRawFd;
+    use std::os::raw::{c_int, c_void};
+
+    use super::Buffer;
+    use super::consts::{SO_RCVBUF, SO_SNDBUF, SOL_SOCKET};
+
+    unsafe extern "C" {
+        fn setsockopt(socket: c_int, level: c_int, name: c_int, value: *const c_void, len: u32) -> c_int;
+        fn getsockopt(socket: c_int, level: c_int, name: c_int, value: *mut c_void, len: *mut u32) -> c_int;
+    }
+
+    fn option(buffer: Buffer) -> c_int {
+        match buffer {
+            Buffer::Send => SO_SNDBUF,
+            Buffer::Receive => SO_RCVBUF,
+        }
+    }
+
+    pub fn set(stream: &TcpStream, buffer: Buffer, size: usize) -> io::Result<usize> {
+        let fd = stream.as_raw_fd();
+        let requested = c_int::try_from(size).unwrap_or(c_int::MAX);
+        let len = size_of::<c_int>() as u32;
+        // SAFETY: the descriptor is open for as long as `stream` is borrowed, and
+        // the value pointer and length describe a live c_int
+        let result = unsafe { setsockopt(fd, SOL_SOCKET, option(buffer), (&raw const requested).cast(), len) };
+        if result != 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        let mut effective: c_int = 0;
+        let mut len = len;
+        // SAFETY: as above, with `len` telling the kernel how much it may write
+        let result = unsafe { getsockopt(fd, SOL_SOCKET, option(buffer), (&raw mut effective).cast(), &mut len) };
+        if result != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(usize::try_from(effective).unwrap_or(0))
+    }
+}
+
+/// Asks the kernel to give `stream` a `buffer` of `size` bytes and returns the
+/// size it settled on, which may differ: Linux doubles the request to leave
+/// room for bookkeeping and clamps it to `net.core.wmem_max` or `rmem_max`.
+pub fn set_buffer_size(stream: &TcpStream, buffer: Buffer, size: usize) -> io::Result<usize> {
+    #[cfg(unix)]
+    return unix::set(stream, buffer, size);
+    #[cfg(not(unix))]
+    {
+        let _ = (stream, buffer, size);
+        Err(io::Error::new(io::ErrorKind::Unsupported, "socket buffer sizes can't be set on this platform"))
+    }
+}
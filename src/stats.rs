@@ -0,0 +1,141 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Instant;
+
+use crate::http::StatusCode;
+use crate::thread_pool;
+
+/// Lowest and highest status codes counted individually
+const FIRST_STATUS: u16 = 100;
+const LAST_STATUS: u16 = 599;
+
+/// Server-wide counters reported by the status endpoint. Everything is a
+/// plain atomic, so recording a response never takes a lock.
+pub struct Stats {
+    started: Instant,
+    requests: AtomicU64,
+    bytes_sent: AtomicU64,
+    /// One counter per status code from 100 to 599
+    by_status: Vec<AtomicU64>,
+    /// Requests answered by each pool worker, indexed like the workers. Only
+    /// worker `i` writes counter `i`, so a skewed queue shows up as uneven counts.
+    by_worker: Vec<AtomicU64>,
+}
+
+impl Stats {
+    /// Counters for a server running `workers` worker threads
+    pub fn new(workers: usize) -> Self {
+        Stats {
+            started: Instant::now(),
+            requests: AtomicU64::new(0),
+            bytes_sent: AtomicU64::new(0),
+            by_status: (FIRST_STATUS..=LAST_STATUS).map(|_| AtomicU64::new(0)).collect(),
+            by_worker: (0..workers).map(|_| AtomicU64::new(0)).collect(),
+        }
+    }
+
+    /// Counts one response. Codes outside 100-599 only count towards the total.
+    pub fn record(&self, status: StatusCode, bytes: usize) {
+        self.requests.fetch_add(1, Ordering::Relaxed);
+        self.bytes_sent.fetch_add(bytes as u64, Ordering::Relaxed);
+        if let Some(counter) = status.as_u16().checked_sub(FIRST_STATUS).and_then(|i| self.by_status.get(i as usize)) {
+            counter.fetch_add(1, Ordering::Relaxed);
+        }
+        if let Some(counter) = thread_pool::current_worker().and_then(|id| self.by_worker.get(id)) {
+            counter.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /// Serializes the counters as a JSON object. Status codes that never
+    /// occurred are left out.
+    pub fn to_json(&self, active_connections: usize) -> String {
+        let mut by_class = [0u64; 5];
+        let mut by_status = Vec::new();
+        for (code, counter) in (FIRST_STATUS..).zip(&self.by_status) {
+            let count = counter.load(Ordering::Relaxed);
+            if count > 0 {
+                by_class[usize::from(code / 100 - 1)] += count;
+                by_status.push(format!("\"{}\":{}", code, count));
+            }
+        }
+        let by_class: Vec<String> =
+            by_class.iter().enumerate().map(|(i, count)| format!("\"{}xx\":{}", i + 1, count)).collect();
+        let by_worker: Vec<String> =
+            self.by_worker.iter().map(|counter| counter.load(Ordering::Relaxed).to_string()).collect();
+
+        format!(
+            "{{\"uptime_secs\":{},\"requests\":{},\"bytes_sent\":{},\"active_connections\":{},\
+             \"responses_by_class\":{{{}}},\"responses_by_status\":{{{}}},\"requests_by_worker\":[{}]}}",
+            self.started.elapsed().as_secs(),
+            self.requests.load(Ordering::Relaxed),
+            self.bytes_sent.load(Ordering::Relaxed),
+            active_connections,
+            by_class.join(","),
+            by_status.join(","),
+            by_worker.join(",")
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::testing::{TempDir, TestServer};
+    use crate::thread_pool::{OverflowPolicy, ThreadPool};
+
+    /// The numbers in the `requests_by_worker` array of a status document
+    fn worker_counts(json: &str) -> Vec<u64> {
+        let start = json.find("\"requests_by_worker\":[").expect("the status has worker counts") + 22;
+        let list = &json[start..start + json[start..].find(']').unwrap()];
+        list.split(',').filter(|count| !count.is_empty()).map(|count| count.parse().unwrap()).collect()
+    }
+
+    fn total(json: &str) -> u64 {
+        let start = json.find("\"requests\":").unwrap() + 11;
+        json[start..].split(|c: char| !c.is_ascii_digit()).next().unwrap().parse().unwrap()
+    }
+
+    #[test]
+    fn each_worker_counts_what_it_recorded() {
+        let stats = Arc::new(Stats::new(3));
+        {
+            let pool = ThreadPool::new(3, OverflowPolicy::Block, "test-stats");
+            for i in 0..30u16 {
+                let stats = Arc::clone(&stats);
+                pool.execute(i, move |i| stats.record(StatusCode::from(200 + i % 2), 10)).unwrap();
+            }
+            // Dropping the pool waits for every job
+        }
+        let json = stats.to_json(0);
+        let counts = worker_counts(&json);
+        assert_eq!(counts.len(), 3);
+        assert_eq!(counts.iter().sum::<u64>(), 30, "{}", json);
+        assert_eq!(total(&json), 30);
+        assert!(json.contains("\"200\":15,\"201\":15"), "{}", json);
+    }
+
+    #[test]
+    fn worker_counts_add_up_to_the_requests_served() {
+        let root = TempDir::new();
+        root.write("x.txt", "A");
+        let server = TestServer::with_args(&["--root", root.path().to_str().unwrap(), "--workers", "3"]);
+        for i in 0..20 {
+            let path = if i % 4 == 0 { "/missing" } else { "/x.txt" };
+            server.get(path);
+        }
+        // A keep-alive connection's requests all go to the worker holding it.
+        // The last one closes it, which happens only once all five are counted.
+        let mut stream = server.connect();
+        let requests = "GET /x.txt HTTP/1.1\r\nHost: a\r\n\r\n".repeat(4) + "GET /x.txt HTTP/1.0\r\n\r\n";
+        std::io::Write::write_all(&mut stream, requests.as_bytes()).unwrap();
+        let received = crate::testing::read_to_close(&mut stream);
+        assert_eq!(String::from_utf8_lossy(&received).matches(" 200 OK\r\n").count(), 5);
+
+        let json = String::from_utf8(server.get("/__status").body).unwrap();
+        let counts = worker_counts(&json);
+        assert_eq!(counts.len(), 3, "{}", json);
+        assert_eq!(counts.iter().sum::<u64>(), total(&json), "{}", json);
+        assert_eq!(total(&json), 25, "{}", json);
+    }
+}
@@ -0,0 +1,221 @@
+//! Helpers shared by the unit tests: scratch directories, a server running
+//! on an ephemeral port, and a client that speaks raw HTTP to it.
+
+use std::fs;
+use std::io::{Read, Write};
+use std::net::{SocketAddr, TcpStream};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use crate::{Config, Server};
+
+/// How long a test client waits for the server before giving up
+const CLIENT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// A directory under the system temp dir, removed again when dropped
+pub struct TempDir {
+    path: PathBuf,
+}
+
+impl TempDir {
+    pub fn new() -> TempDir {
+        static NEXT: AtomicUsize = AtomicUsize::new(0);
+        let name = format!("rrhs-test-{}-{}", std::process::id(), NEXT.fetch_add(1, Ordering::SeqCst));
+        let path = std::env::temp_dir().join(name);
+        fs::create_dir_all(&path).expect("failed to create a temp dir");
+        TempDir { path }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Writes `contents` to `relative`, creating its parent directories
+    pub fn write(&self, relative: &str, contents: impl AsRef<[u8]>) -> PathBuf {
+        let path = self.path.join(relative);
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent).expect("failed to create a parent dir");
+        }
+        fs::write(&path, contents).expect("failed to write a test file");
+        path
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.path);
+    }
+}
+
+/// Parses `args` as command-line options, listening on an ephemeral port and
+/// only logging errors unless they say otherwise
+pub fn config(args: &[&str]) -> Config {
+    let defaults = ["--bind", "127.0.0.1:0", "--log-level", "error", "--no-access-log"];
+    let args = defaults.iter().chain(args).map(|arg| arg.to_string());
+    Config::from_args(args).expect("test options should parse")
+}
+
+/// A server serving on its own thread until dropped
+pub struct TestServer {
+    addr: SocketAddr,
+    shutdown: Arc<AtomicBool>,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl TestServer {
+    pub fn start(config: Config) -> TestServer {
+        TestServer::serve(Server::bind(config).expect("test server should bind"))
+    }
+
+    /// Runs an already bound server, e.g. one whose router a test extended
+    pub fn serve(server: Server) -> TestServer {
+        let addr = server.local_addr().expect("test server has an address");
+        let shutdown = Arc::new(AtomicBool::new(false));
+        let flag = Arc::clone(&shutdown);
+        let handle = thread::spawn(move || {
+            let _ = server.run_until(&flag);
+        });
+        TestServer { addr, shutdown, handle: Some(handle) }
+    }
+
+    /// Starts a server with `args` on top of the test defaults
+    pub fn with_args(args: &[&str]) -> TestServer {
+        TestServer::start(config(args))
+    }
+
+    /// Opens a connection with the client timeout set
+    pub fn connect(&self) -> TcpStream {
+        let stream = TcpStream::connect(self.addr).expect("test server should accept");
+        stream.set_read_timeout(Some(CLIENT_TIMEOUT)).unwrap();
+        stream
+    }
+
+    /// Sends `raw` on a new connection and reads until the server closes it
+    pub fn exchange(&self, raw: impl AsRef<[u8]>) -> Vec<u8> {
+        let mut stream = self.connect();
+        stream.write_all(raw.as_ref()).unwrap();
+        read_to_close(&mut stream)
+    }
+
+    /// Sends `raw`, which should ask for the connection to close, and parses the answer
+    pub fn send(&self, raw: impl AsRef<[u8]>) -> Response {
+        Response::parse(&self.exchange(raw))
+    }
+
+    /// An HTTP/1.1 request for `path` with `headers`, closing the connection afterwards
+    pub fn request(&self, method: &str, path: &str, headers: &[(&str, &str)]) -> Response {
+        self.send(build_request(method, path, headers, b""))
+    }
+
+    pub fn get(&self, path: &str) -> Response {
+        self.request("GET", path, &[])
+    }
+}
+
+impl Drop for TestServer {
+    fn drop(&mut self) {
+        self.shutdown.store(true, Ordering::SeqCst);
+        if let Some(handle) = self.handle.take() {
+            let _ = handle.join();
+        }
+    }
+}
+
+/// An HTTP/1.1 request with a Host and `Connection: close`, plus `headers` and `body`
+pub fn build_request(method: &str, path: &str, headers: &[(&str, &str)], body: &[u8]) -> Vec<u8> {
+    let mut raw = format!("{} {} HTTP/1.1\r\nHost: test\r\nConnection: close\r\n", method, path);
+    for (name, value) in headers {
+        raw.push_str(&format!("{}: {}\r\n", name, value));
+    }
+    if !body.is_empty() {
+        raw.push_str(&format!("Content-Length: {}\r\n", body.len()));
+    }
+    raw.push_str("\r\n");
+    let mut raw = raw.into_bytes();
+    raw.extend_from_slice(body);
+    raw
+}
+
+/// Reads until EOF, keeping what arrived if the connection is reset
+pub fn read_to_close(stream: &mut TcpStream) -> Vec<u8> {
+    let mut received = Vec::new();
+    let mut chunk = [0; 8192];
+    loop {
+        match stream.read(&mut chunk) {
+            Ok(0) | Err(_) => return received,
+            Ok(size) => received.extend_from_slice(&chunk[..size]),
+        }
+    }
+}
+
+/// Reads one response off a connection that stays open, using its
+/// `Content-Length` to find the end
+pub fn read_response(stream: &mut TcpStream) -> Response {
+    let mut received = Vec::new();
+    let mut byte = [0; 1];
+    while !received.ends_with(b"\r\n\r\n") {
+        match stream.read(&mut byte) {
+            Ok(1) => received.push(byte[0]),
+            _ => break,
+        }
+    }
+    let head = Response::parse(&received);
+    let len = head.header("Content-Length").and_then(|len| len.parse().ok()).unwrap_or(0);
+    let mut body = vec![0; len];
+    stream.read_exact(&mut body).expect("the body should arrive in full");
+    received.extend_from_slice(&body);
+    Response::parse(&received)
+}
+
+/// A response as it came off the wire
+#[derive(Debug)]
+pub struct Response {
+    pub status: u16,
+    /// Header names as sent, in the order sent
+    pub headers: Vec<(String, String)>,
+    /// The body with any chunked encoding removed
+    pub body: Vec<u8>,
+}
+
+impl Response {
+    pub fn parse(raw: &[u8]) -> Response {
+        let head_end = raw.windows(4).position(|window| window == b"\r\n\r\n").map_or(raw.len(), |end| end + 4);
+        let head = String::from_utf8_lossy(&raw[..head_end]);
+        let mut lines = head.split("\r\n");
+        let status_line = lines.next().unwrap_or_default();
+        let status = status_line.split(' ').nth(1).and_then(|code| code.parse().ok()).unwrap_or(0);
+        let headers: Vec<(String, String)> = lines
+            .filter_map(|line| line.split_once(':'))
+            .map(|(name, value)| (name.to_string(), value.trim().to_string()))
+            .collect();
+        let mut response = Response { status, headers, body: Vec::new() };
+        let body = &raw[head_end..];
+        let chunked = response.header("Transfer-Encoding") == Some("chunked");
+        response.body = if chunked { dechunk(body) } else { body.to_vec() };
+        response
+    }
+
+    /// The first header called `name`, in any case
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers.iter().find(|(key, _)| key.eq_ignore_ascii_case(name)).map(|(_, value)| value.as_str())
+    }
+}
+
+/// Joins the chunks of a chunked body, ignoring extensions and trailers
+fn dechunk(mut body: &[u8]) -> Vec<u8> {
+    let mut joined = Vec::new();
+    while let Some(line_end) = body.windows(2).position(|window| window == b"\r\n") {
+        let size_line = String::from_utf8_lossy(&body[..line_end]);
+        let size = usize::from_str_radix(size_line.split(';').next().unwrap_or_default().trim(), 16).unwrap_or(0);
+        if size == 0 {
+            break;
+        }
+        let start = line_end + 2;
+        joined.extend_from_slice(&body[start..(start + size).min(body.len())]);
+        body = &body[(start + size + 2).min(body.len())..];
+    }
+    joined
+}
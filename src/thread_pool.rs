@@ -0,0 +1,214 @@
+use std::cell::Cell;
+use std::collections::VecDeque;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread::{self, JoinHandle};
+
+/// A unit of work handed to the pool
+type Job = Box<dyn FnOnce() + Send + 'static>;
+
+/// Jobs allowed to wait in the queue per worker thread
+pub(crate) const QUEUED_PER_WORKER: usize = 4;
+/// How far past its normal size `OverflowPolicy::Grow` lets the queue grow
+const GROWTH_FACTOR: usize = 4;
+
+thread_local! {
+    /// Index of the pool worker running on this thread, if it is one
+    static WORKER_ID: Cell<Option<usize>> = const { Cell::new(None) };
+}
+
+/// The index of the worker running the current job, from 0 up to the pool
+/// size. `None` outside the pool, e.g. on the accept thread.
+pub fn current_worker() -> Option<usize> {
+    WORKER_ID.get()
+}
+
+/// What `execute` does when every worker is busy and the queue is full
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OverflowPolicy {
+    /// Wait for a free slot, which stalls the accept thread
+    Block,
+    /// Hand the job back so the caller can turn the client away
+    Reject,
+    /// Keep queueing up to `GROWTH_FACTOR` times the normal size, then block
+    Grow,
+}
+
+impl OverflowPolicy {
+    pub fn parse(value: &str) -> Option<OverflowPolicy> {
+        match value {
+            "block" => Some(OverflowPolicy::Block),
+            "reject" => Some(OverflowPolicy::Reject),
+            "grow" => Some(OverflowPolicy::Grow),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            OverflowPolicy::Block => "block",
+            OverflowPolicy::Reject => "reject",
+            OverflowPolicy::Grow => "grow",
+        }
+    }
+}
+
+struct Queue {
+    jobs: VecDeque<Job>,
+    /// Set when the pool is dropped; workers exit once the queue is empty
+    closed: bool,
+}
+
+/// State shared between the pool and its workers
+struct Shared {
+    queue: Mutex<Queue>,
+    /// Signalled when a job is queued or the pool closes
+    job_ready: Condvar,
+    /// Signalled when a worker takes a job, freeing a slot
+    slot_free: Condvar,
+}
+
+/// A fixed-size pool of worker threads pulling jobs from a bounded queue
+pub struct ThreadPool {
+    workers: Vec<Worker>,
+    shared: Arc<Shared>,
+    capacity: usize,
+    policy: OverflowPolicy,
+}
+
+impl ThreadPool {
+    /// Creates a pool with `size` worker threads, named `<name>-w0`, `<name>-w1`
+    /// and so on. `size` must be at least 1.
+    pub fn new(size: usize, policy: OverflowPolicy, name: &str) -> ThreadPool {
+        assert!(size > 0, "thread pool needs at least one worker");
+
+        let shared = Arc::new(Shared {
+            queue: Mutex::new(Queue { jobs: VecDeque::new(), closed: false }),
+            job_ready: Condvar::new(),
+            slot_free: Condvar::new(),
+        });
+
+        let workers = (0..size).map(|id| Worker::new(id, format!("{}-w{}", name, id), Arc::clone(&shared))).collect();
+
+        ThreadPool { workers, shared, capacity: size * QUEUED_PER_WORKER, policy }
+    }
+
+    /// Queues `job(input)` to run on the next free worker. When the queue
+    /// is full the overflow policy decides; under `Reject` the input is handed
+    /// back untouched so the caller can still use it, e.g. to answer the client.
+    pub fn execute<T, F>(&self, input: T, job: F) -> Result<(), T>
+    where
+        T: Send + 'static,
+        F: FnOnce(T) + Send + 'static,
+    {
+        let limit = match self.policy {
+            OverflowPolicy::Block | OverflowPolicy::Reject => self.capacity,
+            OverflowPolicy::Grow => self.capacity * GROWTH_FACTOR,
+        };
+
+        let mut queue = self.shared.queue.lock().unwrap();
+        while queue.jobs.len() >= limit {
+            if self.policy == OverflowPolicy::Reject {
+                return Err(input);
+            }
+            queue = self.shared.slot_free.wait(queue).unwrap();
+        }
+        if queue.jobs.len() == self.capacity {
+            log_info!("Work queue full at {} jobs, growing it", self.capacity);
+        }
+        queue.jobs.push_back(Box::new(move || job(input)));
+        self.shared.job_ready.notify_one();
+        Ok(())
+    }
+}
+
+impl Drop for ThreadPool {
+    /// Closes the queue and waits for running jobs to finish
+    fn drop(&mut self) {
+        self.shared.queue.lock().unwrap().closed = true;
+        self.shared.job_ready.notify_all();
+
+        for worker in &mut self.workers {
+            if let Some(handle) = worker.handle.take() {
+                let _ = handle.join();
+            }
+        }
+    }
+}
+
+/// A single worker thread
+struct Worker {
+    handle: Option<JoinHandle<()>>,
+}
+
+impl Worker {
+    fn new(id: usize, name: String, shared: Arc<Shared>) -> Worker {
+        let spawned = thread::Builder::new().name(name).spawn(move || {
+            WORKER_ID.set(Some(id));
+            loop {
+                // Hold the lock only long enough to take one job off the queue
+                let job = {
+                    let mut queue = shared.queue.lock().unwrap();
+                    while queue.jobs.is_empty() && !queue.closed {
+                        queue = shared.job_ready.wait(queue).unwrap();
+                    }
+                    queue.jobs.pop_front()
+                };
+
+                match job {
+                    Some(job) => {
+                        shared.slot_free.notify_one();
+                        // A job that panics must not take its worker down with it,
+                        // or the pool would shrink for good with every panic
+                        if panic::catch_unwind(AssertUnwindSafe(job)).is_err() {
+                            log_error!("Worker {} recovered from a panicking job", id);
+                        }
+                    }
+                    // Closed with nothing left to do: the pool is shutting down
+                    None => break,
+                }
+            }
+            log_info!("Worker {} shutting down", id);
+        });
+
+        Worker { handle: Some(spawned.expect("failed to spawn a worker thread")) }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::mpsc;
+    use std::time::Duration;
+
+    #[test]
+    fn worker_survives_a_panicking_job() {
+        let pool = ThreadPool::new(1, OverflowPolicy::Block, "test-panic");
+        pool.execute((), |()| panic!("job failed on purpose")).unwrap();
+
+        // The single worker must still be there to run the next job
+        let (sender, receiver) = mpsc::channel();
+        pool.execute(sender, |sender| sender.send(thread::current().name().map(str::to_string)).unwrap()).unwrap();
+        let name = receiver.recv_timeout(Duration::from_secs(5)).expect("the worker should still run jobs");
+        assert_eq!(name.as_deref(), Some("test-panic-w0"));
+    }
+
+    #[test]
+    fn reject_hands_the_input_back_when_full() {
+        let pool = ThreadPool::new(1, OverflowPolicy::Reject, "test-reject");
+        let (release, blocked) = mpsc::channel::<()>();
+        let (started, running) = mpsc::channel();
+        pool.execute((started, blocked), |(started, blocked)| {
+            started.send(()).unwrap();
+            let _ = blocked.recv();
+        })
+        .unwrap();
+        // Once the worker is busy with it, only the queue is left
+        running.recv_timeout(Duration::from_secs(5)).unwrap();
+        for i in 0..QUEUED_PER_WORKER {
+            pool.execute(i, |_| {}).unwrap();
+        }
+        assert_eq!(pool.execute(99, |_| {}), Err(99));
+        drop(release);
+    }
+}
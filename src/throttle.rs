@@ -0,0 +1,43 @@
+use std::io::{self, Write};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Slices written per second of budget. Smaller slices keep the pace smooth
+/// instead of sending a burst and then going quiet.
+const SLICES_PER_SECOND: u64 = 10;
+
+/// A writer that paces everything written through it to at most
+/// `bytes_per_sec`, sleeping whenever it gets ahead of schedule. The pace is
+/// measured from when the throttle was created, so a slow start doesn't earn
+/// a burst later on.
+pub struct Throttle<'a, W: ?Sized> {
+    inner: &'a mut W,
+    bytes_per_sec: u64,
+    started: Instant,
+    written: u64,
+}
+
+impl<'a, W: Write + ?Sized> Throttle<'a, W> {
+    pub fn new(inner: &'a mut W, bytes_per_sec: u64) -> Self {
+        Throttle { inner, bytes_per_sec: bytes_per_sec.max(1), started: Instant::now(), written: 0 }
+    }
+}
+
+impl<W: Write + ?Sized> Write for Throttle<'_, W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let slice = (self.bytes_per_sec / SLICES_PER_SECOND).max(1) as usize;
+        let size = self.inner.write(&buf[..buf.len().min(slice)])?;
+        self.written += size as u64;
+
+        // Wait until the bytes sent so far are within budget
+        let due = Duration::from_secs_f64(self.written as f64 / self.bytes_per_sec as f64);
+        if let Some(ahead) = due.checked_sub(self.started.elapsed()) {
+            thread::sleep(ahead);
+        }
+        Ok(size)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
@@ -0,0 +1,192 @@
+use std::io::{self, ErrorKind};
+use std::net::{Shutdown, SocketAddr, TcpStream};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::connection::Connection;
+use crate::{LiveContext, proxy};
+
+/// Bytes needed to tell the two apart: a TLS record header starts with its
+/// type and a two-byte version, and every HTTP method is at least three letters
+const PEEK_LEN: usize = 3;
+
+/// How often to look again while the first bytes trickle in
+const PEEK_INTERVAL: Duration = Duration::from_millis(10);
+
+/// What the first bytes on a `--tls-bind` port say the client speaks
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Protocol {
+    /// A TLS handshake record
+    Tls,
+    /// A plaintext request line, from an `http://` URL with the https port in it
+    Http,
+    /// Neither, or nothing arrived in time
+    Unknown,
+}
+
+/// Classifies the first bytes of a connection. A TLS handshake record is
+/// `0x16` followed by the SSL 3 or a TLS version (`0x03` then `0x00` to
+/// `0x04`); a request line starts with a method, which is uppercase letters,
+/// so the two can't be confused.
+pub fn classify(bytes: &[u8]) -> Protocol {
+    match bytes {
+        [0x16, 0x03, 0x00..=0x04, ..] => Protocol::Tls,
+        [first, second, third, ..] if [first, second, third].iter().all(|byte| byte.is_ascii_uppercase()) => {
+            Protocol::Http
+        }
+        _ => Protocol::Unknown,
+    }
+}
+
+/// Serves one connection accepted on a `--tls-bind` port: a TLS handshake
+/// is passed on to `--tls-backend` untouched, and plaintext HTTP gets the
+/// usual request handling with every answer a redirect to https.
+pub fn serve(stream: TcpStream, peer: Option<SocketAddr>, live: &LiveContext) {
+    let context = live.load();
+    let config = &context.config;
+    match peek(&stream, config.request_timeout) {
+        Ok(Protocol::Tls) => {
+            let Some(backend) = &config.tls_backend else {
+                return;
+            };
+            match proxy::connect_once(backend, config.proxy_connect_timeout) {
+                Ok(upstream) => {
+                    let (sent, received) = splice(&stream, &upstream, config.keepalive_timeout);
+                    log_info!("TLS connection passed to {} closed ({} bytes up, {} down)", backend, sent, received);
+                }
+                Err(e) => log_error!("Couldn't pass a TLS connection on to {}: {}", backend, e),
+            }
+        }
+        Ok(Protocol::Http) => {
+            log_info!("Plaintext HTTP on a TLS port, redirecting to https");
+            Connection::new(stream, peer, live).redirecting_to_https().serve();
+        }
+        Ok(Protocol::Unknown) => log_info!("Connection on a TLS port sent neither TLS nor HTTP, closing"),
+        Err(e) => log_info!("Couldn't read the first bytes on a TLS port: {}", e),
+    }
+}
+
+/// Looks at the first bytes without consuming them, waiting up to `timeout`
+/// for `PEEK_LEN` of them to arrive
+fn peek(stream: &TcpStream, timeout: Duration) -> io::Result<Protocol> {
+    let deadline = Instant::now() + timeout;
+    let mut first = [0; PEEK_LEN];
+    loop {
+        match stream.peek(&mut first) {
+            // Nothing at all means the client closed the connection
+            Ok(size) if size == PEEK_LEN || size == 0 || Instant::now() >= deadline => {
+                return Ok(classify(&first[..size]));
+            }
+            // Peeking again returns at once with the same bytes, so wait for more between looks
+            Ok(_) => thread::sleep(PEEK_INTERVAL),
+            Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+            Err(e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => return Ok(Protocol::Unknown),
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+/// Copies bytes both ways between `client` and `backend` until either side
+/// closes or stays quiet for `idle`. Returns the bytes sent each way.
+fn splice(client: &TcpStream, backend: &TcpStream, idle: Duration) -> (u64, u64) {
+    for stream in [client, backend] {
+        let _ = stream.set_read_timeout(Some(idle));
+        let _ = stream.set_write_timeout(Some(idle));
+    }
+    // Once one direction ends the other can't carry on, so both sockets are shut
+    let copy = |mut from: &TcpStream, mut to: &TcpStream| {
+        let copied = io::copy(&mut from, &mut to).unwrap_or(0);
+        let _ = client.shutdown(Shutdown::Both);
+        let _ = backend.shutdown(Shutdown::Both);
+        copied
+    };
+    thread::scope(|scope| {
+        let upstream = scope.spawn(|| copy(client, backend));
+        let downstream = copy(backend, client);
+        (upstream.join().unwrap_or(0), downstream)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Server;
+    use crate::testing::{self, Response, TempDir, TestServer, read_to_close};
+    use std::io::{Read, Write};
+    use std::net::TcpListener;
+
+    #[test]
+    fn first_bytes_tell_tls_from_plaintext_http() {
+        assert_eq!(classify(b"GET /login HTTP/1.1\r\nHost: example.com\r\n\r\n"), Protocol::Http);
+        assert_eq!(classify(b"OPTIONS * HTTP/1.1\r\n"), Protocol::Http);
+        assert_eq!(classify(b"PUT"), Protocol::Http);
+        // A TLS 1.2 ClientHello record header, and the SSL 3 and TLS 1.0 record versions
+        assert_eq!(classify(&[0x16, 0x03, 0x01, 0x02, 0x00, 0x01]), Protocol::Tls);
+        assert_eq!(classify(&[0x16, 0x03, 0x00]), Protocol::Tls);
+        assert_eq!(classify(&[0x16, 0x03, 0x03]), Protocol::Tls);
+        for other in [&b""[..], b"GE", b"get / HTTP/1.1", b"\r\nGET", &[0x16, 0x03, 0x05], &[0x17, 0x03, 0x03]] {
+            assert_eq!(classify(other), Protocol::Unknown, "{:?}", other);
+        }
+    }
+
+    #[test]
+    fn plaintext_is_redirected_and_tls_is_passed_to_the_backend() {
+        // A stand-in TLS terminator that answers whatever it gets with its reverse
+        let backend = TcpListener::bind("127.0.0.1:0").unwrap();
+        let backend_addr = backend.local_addr().unwrap().to_string();
+        let terminator = std::thread::spawn(move || {
+            let (mut stream, _) = backend.accept().unwrap();
+            let mut hello = [0; 6];
+            stream.read_exact(&mut hello).unwrap();
+            hello.reverse();
+            stream.write_all(&hello).unwrap();
+        });
+
+        let root = TempDir::new();
+        root.write("login.html", "plain");
+        let config = testing::config(&[
+            "--root",
+            root.path().to_str().unwrap(),
+            "--tls-bind",
+            "127.0.0.1:0",
+            "--tls-backend",
+            &backend_addr,
+        ]);
+        let server = Server::bind(config).unwrap();
+        let addrs = server.local_addrs().unwrap();
+        let (plain, tls) = (addrs[0], addrs[1]);
+        let server = TestServer::serve(server);
+        let connect = |addr| {
+            let stream = TcpStream::connect(addr).unwrap();
+            stream.set_read_timeout(Some(Duration::from_secs(10))).unwrap();
+            stream
+        };
+
+        let mut misdial = connect(tls);
+        misdial.write_all(&testing::build_request("GET", "/login.html?next=%2Fhome", &[], b"")).unwrap();
+        let redirect = Response::parse(&read_to_close(&mut misdial));
+        assert_eq!(redirect.status, 301);
+        assert_eq!(redirect.header("Location"), Some("https://test/login.html?next=%2Fhome"));
+
+        let mut handshake = connect(tls);
+        handshake.write_all(&[0x16, 0x03, 0x01, 0x00, 0x05, 0x01]).unwrap();
+        assert_eq!(read_to_close(&mut handshake), [0x01, 0x05, 0x00, 0x01, 0x03, 0x16]);
+        terminator.join().unwrap();
+
+        // The plaintext port itself still serves
+        let mut direct = connect(plain);
+        direct.write_all(&testing::build_request("GET", "/login.html", &[], b"")).unwrap();
+        assert_eq!(Response::parse(&read_to_close(&mut direct)).body, b"plain");
+        // and hangs up on a handshake, having no TLS to offer
+        let mut wrong_port = connect(plain);
+        wrong_port.write_all(&[0x16, 0x03, 0x01, 0x00, 0x05, 0x01]).unwrap();
+        assert!(read_to_close(&mut wrong_port).is_empty());
+        drop(server);
+    }
+
+    #[test]
+    fn tls_bind_needs_a_backend() {
+        let args = ["--tls-bind", "127.0.0.1:0"].map(String::from);
+        assert!(crate::Config::from_args(args.into_iter()).is_err());
+    }
+}
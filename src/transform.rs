@@ -0,0 +1,173 @@
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::path::Path;
+use std::sync::Arc;
+
+/// Extensions the built-in HTML transforms apply to
+const HTML_EXTENSIONS: &[&str] = &["html", "htm"];
+
+/// Script injected by `--live-reload`. It polls the page's own ETag and
+/// reloads once the file behind it changes.
+const LIVE_RELOAD_SNIPPET: &str = "<script>
+(function () {
+  var tag = null;
+  setInterval(function () {
+    fetch(location.href, { method: \"HEAD\", cache: \"no-store\" }).then(function (response) {
+      var current = response.headers.get(\"ETag\");
+      if (tag !== null && current !== tag) location.reload();
+      tag = current;
+    });
+  }, 1000);
+})();
+</script>
+";
+
+type TransformFn = dyn Fn(&[u8]) -> Vec<u8> + Send + Sync;
+
+/// A rewrite of served file contents, applied after the file is read and
+/// before the response is built, to files with one of its extensions.
+///
+/// The name ends up in the ETag of every transformed file, so a transform
+/// whose output changes should change its name too, or clients will keep
+/// their copies of the old output.
+#[derive(Clone)]
+pub struct Transform {
+    name: String,
+    /// Lowercase extensions without the dot
+    extensions: Vec<String>,
+    apply: Arc<TransformFn>,
+}
+
+impl Transform {
+    /// A transform running `apply` on files with one of `extensions`, which
+    /// are matched without the dot and ignoring case
+    pub fn new(
+        name: &str,
+        extensions: &[&str],
+        apply: impl Fn(&[u8]) -> Vec<u8> + Send + Sync + 'static,
+    ) -> Transform {
+        Transform {
+            name: name.to_string(),
+            extensions: extensions.iter().map(|ext| ext.trim_start_matches('.').to_ascii_lowercase()).collect(),
+            apply: Arc::new(apply),
+        }
+    }
+
+    /// Inserts `snippet` into HTML files just before the last `</body>`, or
+    /// at the end of pages that have none
+    pub fn inject_before_body(name: &str, snippet: &str) -> Transform {
+        let snippet = snippet.as_bytes().to_vec();
+        Transform::new(name, HTML_EXTENSIONS, move |contents| {
+            let at = rfind_ignore_case(contents, b"</body>").unwrap_or(contents.len());
+            let mut output = Vec::with_capacity(contents.len() + snippet.len());
+            output.extend_from_slice(&contents[..at]);
+            output.extend_from_slice(&snippet);
+            output.extend_from_slice(&contents[at..]);
+            output
+        })
+    }
+
+    /// The script `--live-reload` injects into HTML pages
+    pub fn live_reload() -> Transform {
+        Transform::inject_before_body("live-reload", LIVE_RELOAD_SNIPPET)
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn applies_to(&self, path: &Path) -> bool {
+        path.extension()
+            .and_then(|ext| ext.to_str())
+            .is_some_and(|ext| self.extensions.iter().any(|wanted| wanted.eq_ignore_ascii_case(ext)))
+    }
+}
+
+/// The transforms in `transforms` that apply to `path`, in order
+pub fn for_path<'a>(transforms: &'a [Transform], path: &Path) -> Vec<&'a Transform> {
+    transforms.iter().filter(|transform| transform.applies_to(path)).collect()
+}
+
+/// Runs each of `applied` over `contents` in turn
+pub fn apply_all(applied: &[&Transform], contents: &[u8]) -> Vec<u8> {
+    applied.iter().fold(contents.to_vec(), |contents, transform| (transform.apply)(&contents))
+}
+
+/// A token for the ETag of a file served through `applied`, so that the
+/// transformed body never shares a tag with the file on disk and turning a
+/// transform on or off invalidates what clients have cached
+pub fn etag_token(applied: &[&Transform]) -> String {
+    let mut hasher = DefaultHasher::new();
+    for transform in applied {
+        transform.name.hash(&mut hasher);
+    }
+    format!("t{:x}", hasher.finish())
+}
+
+fn rfind_ignore_case(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack.windows(needle.len()).rposition(|window| window.eq_ignore_ascii_case(needle))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{self, TempDir, TestServer};
+
+    #[test]
+    fn snippets_go_before_the_last_closing_body_tag() {
+        let inject = Transform::inject_before_body("test", "<s/>");
+        let applied = [&inject];
+        assert_eq!(apply_all(&applied, b"<body>a</BODY>"), b"<body>a<s/></BODY>");
+        assert_eq!(apply_all(&applied, b"<p></body></body>"), b"<p></body><s/></body>");
+        assert_eq!(apply_all(&applied, b"<p>no body"), b"<p>no body<s/>");
+
+        let transforms = [inject.clone(), Transform::new("upper", &[".CSS"], |css| css.to_ascii_uppercase())];
+        assert_eq!(for_path(&transforms, Path::new("a/index.HTML")).len(), 1);
+        assert_eq!(for_path(&transforms, Path::new("style.css"))[0].name(), "upper");
+        assert!(for_path(&transforms, Path::new("README")).is_empty());
+        assert_ne!(etag_token(&[&transforms[0]]), etag_token(&[&transforms[1]]));
+    }
+
+    #[test]
+    fn served_html_gets_the_live_reload_script() {
+        let root = TempDir::new();
+        let page = "<html><body><p>hello</p></body></html>";
+        root.write("index.html", page);
+        root.write("style.css", "p {}");
+        let root = root.path().to_str().unwrap();
+        let plain = TestServer::with_args(&["--root", root, "--no-compress"]).get("/index.html");
+
+        let server = TestServer::with_args(&["--root", root, "--no-compress", "--live-reload"]);
+        let response = server.get("/index.html");
+        let body = String::from_utf8(response.body.clone()).unwrap();
+        assert!(body.contains(LIVE_RELOAD_SNIPPET), "{}", body);
+        assert!(body.ends_with(&format!("<p>hello</p>{}</body></html>", LIVE_RELOAD_SNIPPET)), "{}", body);
+        assert_eq!(response.header("Content-Length"), Some(body.len().to_string().as_str()));
+        assert!(response.header("ETag").is_some());
+        assert_ne!(response.header("ETag"), plain.header("ETag"));
+
+        // HEAD and ranges see the same transformed bytes; other types are untouched
+        let head = server.request("HEAD", "/index.html", &[]);
+        assert_eq!(head.header("Content-Length"), response.header("Content-Length"));
+        assert_eq!(head.header("ETag"), response.header("ETag"));
+        let tail = server.request("GET", "/index.html", &[("Range", "bytes=-14")]);
+        assert_eq!(tail.body, b"</body></html>");
+        assert_eq!(server.get("/style.css").body, b"p {}");
+        let etag = response.header("ETag").unwrap();
+        assert_eq!(server.request("GET", "/index.html", &[("If-None-Match", etag)]).status, 304);
+    }
+
+    #[test]
+    fn library_transforms_run_on_matching_files() {
+        let root = TempDir::new();
+        root.write("style.css", "p {\r\n}\r\n");
+        let mut config = testing::config(&["--root", root.path().to_str().unwrap(), "--no-compress"]);
+        config.transforms.push(Transform::new("strip-cr", &["css"], |css| {
+            css.iter().copied().filter(|&byte| byte != b'\r').collect()
+        }));
+        let server = TestServer::start(config);
+        let response = server.get("/style.css");
+        assert_eq!(response.body, b"p {\n}\n");
+        assert_eq!(response.header("Content-Length"), Some("6"));
+    }
+}
@@ -0,0 +1,110 @@
+use std::fs::Metadata;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::http::{HttpRequest, HttpResponse};
+use crate::http_date::{format_http_date, parse_http_date};
+
+/// Cache validators (ETag and Last-Modified) for a response
+pub struct Validators {
+    pub etag: String,
+    pub last_modified: Option<SystemTime>,
+}
+
+impl Validators {
+    /// Builds validators for a regular file from its size and modification time
+    pub fn for_file(metadata: &Metadata) -> Self {
+        Validators::for_entry(metadata.len(), metadata.modified().ok())
+    }
+
+    /// Validators for a file known only by its size and modification time,
+    /// e.g. one in an archive
+    pub fn for_entry(len: u64, modified: Option<SystemTime>) -> Self {
+        let nanos = modified
+            .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+            .map(|age| age.as_nanos())
+            .unwrap_or(0);
+
+        Validators {
+            etag: format!("\"{:x}-{:x}\"", len, nanos),
+            last_modified: modified,
+        }
+    }
+
+    /// Appends `token` to the entity tag, for a body derived from the file
+    /// rather than its bytes as stored
+    pub fn with_etag_token(mut self, token: &str) -> Self {
+        if let Some(quoted) = self.etag.strip_suffix('"') {
+            self.etag = format!("{}-{}\"", quoted, token);
+        }
+        self
+    }
+
+    /// Adds the `ETag` and `Last-Modified` headers to a response
+    pub fn apply(&self, response: HttpResponse) -> HttpResponse {
+        let response = response.with_header("ETag", &self.etag);
+        match self.last_modified {
+            Some(time) => response.with_header("Last-Modified", &format_http_date(time)),
+            None => response,
+        }
+    }
+}
+
+/// Returns true if the request's conditional headers say the client's
+/// cached copy is still current, meaning a 304 should be sent instead.
+///
+/// `If-None-Match` takes precedence; `If-Modified-Since` is only consulted
+/// when the client did not send an entity tag.
+pub fn is_not_modified(request: &HttpRequest, validators: &Validators) -> bool {
+    if let Some(if_none_match) = request.header("If-None-Match") {
+        return if_none_match.split(',').map(str::trim).any(|tag| {
+            tag == "*" || weak_eq(tag, &validators.etag)
+        });
+    }
+
+    if let (Some(since), Some(modified)) = (request.header("If-Modified-Since"), validators.last_modified)
+        && let Some(since) = parse_http_date(since)
+    {
+        // HTTP dates only have second precision, so compare whole seconds
+        return whole_seconds(modified) <= whole_seconds(since);
+    }
+
+    false
+}
+
+/// Returns true if the request's `If-Match` or `If-Unmodified-Since` header
+/// says the client's idea of the resource is out of date, meaning a write must
+/// be refused with 412. `current` is `None` when the resource doesn't exist.
+///
+/// `If-Match` takes precedence and compares entity tags strongly, so a write
+/// only goes ahead against the exact representation the client last saw.
+pub fn precondition_failed(request: &HttpRequest, current: Option<&Validators>) -> bool {
+    if let Some(if_match) = request.header("If-Match") {
+        let Some(current) = current else {
+            return true;
+        };
+        return !if_match.split(',').map(str::trim).any(|tag| tag == "*" || strong_eq(tag, &current.etag));
+    }
+
+    if let (Some(since), Some(modified)) =
+        (request.header("If-Unmodified-Since"), current.and_then(|current| current.last_modified))
+        && let Some(since) = parse_http_date(since)
+    {
+        return whole_seconds(modified) > whole_seconds(since);
+    }
+
+    false
+}
+
+/// Strong entity-tag comparison: weak tags never match
+fn strong_eq(a: &str, b: &str) -> bool {
+    !a.starts_with("W/") && !b.starts_with("W/") && a == b
+}
+
+/// Weak entity-tag comparison: `W/"x"` and `"x"` are considered equal
+fn weak_eq(a: &str, b: &str) -> bool {
+    a.trim_start_matches("W/") == b.trim_start_matches("W/")
+}
+
+fn whole_seconds(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH).map(|age| age.as_secs()).unwrap_or(0)
+}
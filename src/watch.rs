@@ -0,0 +1,254 @@
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
+use std::time::{Duration, SystemTime};
+
+/// Path of the event stream `--watch` pushes reloads to
+pub const EVENTS_PATH: &str = "/__livereload";
+
+/// Script `--watch` injects into HTML pages: it subscribes to the event
+/// stream and reloads the page on every event
+pub const CLIENT_SNIPPET: &str = "<script>
+new EventSource(\"/__livereload\").onmessage = function () { location.reload(); };
+</script>
+";
+
+/// How often the document roots are scanned for changes
+const POLL_INTERVAL: Duration = Duration::from_millis(250);
+/// How long an event stream may sit silent before it sends a comment, which
+/// is how a closed tab is noticed and its worker freed
+const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(15);
+
+/// Deepest directory below a root that's scanned, so a runaway tree can't
+/// stall the watcher
+const MAX_SCAN_DEPTH: usize = 32;
+
+/// Every file below the roots, with the size and modification time it had
+type Snapshot = HashMap<PathBuf, (u64, Option<SystemTime>)>;
+
+struct State {
+    /// Bumped once per burst of changes
+    generation: u64,
+    /// Set once the watcher stops, which ends every event stream
+    closed: bool,
+}
+
+/// Watches the document roots by polling them, and wakes every subscribed
+/// event stream when something below them changes.
+///
+/// Changes are debounced: an editor saving several files, or writing one in
+/// steps, produces a single reload once a whole poll interval passes with
+/// nothing new.
+pub struct Watcher {
+    state: Mutex<State>,
+    changed: Condvar,
+}
+
+impl Watcher {
+    pub fn new() -> Self {
+        Watcher { state: Mutex::new(State { generation: 0, closed: false }), changed: Condvar::new() }
+    }
+
+    /// Scans `roots()` until `stopped` is set, then closes every event stream.
+    /// The roots are asked for on every scan, so a reload that changes them
+    /// is picked up.
+    pub fn run(&self, roots: impl Fn() -> Vec<PathBuf>, stopped: &AtomicBool) {
+        let mut snapshot = scan(&roots());
+        let mut pending = false;
+        while !stopped.load(Ordering::SeqCst) {
+            thread::sleep(POLL_INTERVAL);
+            let current = scan(&roots());
+            if current != snapshot {
+                snapshot = current;
+                pending = true;
+            } else if pending {
+                pending = false;
+                log_info!("Files changed, telling pages to reload");
+                self.state.lock().unwrap().generation += 1;
+                self.changed.notify_all();
+            }
+        }
+        self.state.lock().unwrap().closed = true;
+        self.changed.notify_all();
+    }
+
+    /// A `text/event-stream` body that sends an event after every change
+    pub fn subscribe(self: &Arc<Self>) -> EventStream {
+        let seen = self.state.lock().unwrap().generation;
+        // Reconnect quickly after a restart, so a page doesn't miss the next change
+        EventStream { watcher: Arc::clone(self), seen, pending: b"retry: 1000\n\n".to_vec(), offset: 0 }
+    }
+}
+
+/// One subscriber's event stream. Reads block until there is an event or a
+/// heartbeat to send, and reach the end once the watcher stops.
+pub struct EventStream {
+    watcher: Arc<Watcher>,
+    seen: u64,
+    /// Bytes of the current message not yet read, from `offset` on
+    pending: Vec<u8>,
+    offset: usize,
+}
+
+impl Read for EventStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.offset == self.pending.len() {
+            let state = self.watcher.state.lock().unwrap();
+            let (state, _) = self
+                .watcher
+                .changed
+                .wait_timeout_while(state, HEARTBEAT_INTERVAL, |state| {
+                    state.generation == self.seen && !state.closed
+                })
+                .unwrap();
+            if state.closed {
+                return Ok(0);
+            }
+            self.pending = if state.generation != self.seen {
+                self.seen = state.generation;
+                b"data: reload\n\n".to_vec()
+            } else {
+                b": heartbeat\n\n".to_vec()
+            };
+            self.offset = 0;
+        }
+        let size = buf.len().min(self.pending.len() - self.offset);
+        buf[..size].copy_from_slice(&self.pending[self.offset..self.offset + size]);
+        self.offset += size;
+        Ok(size)
+    }
+}
+
+/// Records every file below `roots`. Unreadable entries are left out, so
+/// they only count as a change when they come or go.
+fn scan(roots: &[PathBuf]) -> Snapshot {
+    let mut snapshot = HashMap::new();
+    let mut visited = HashSet::new();
+    for root in roots {
+        scan_dir(root, 0, &mut visited, &mut snapshot);
+    }
+    snapshot
+}
+
+/// Records the files below `dir`, `depth` levels below a root. Symlinks to
+/// directories are followed, but each directory is scanned once however
+/// many links lead to it, so a link back up the tree can't loop forever;
+/// `visited` holds the canonical paths of those scanned so far.
+fn scan_dir(dir: &Path, depth: usize, visited: &mut HashSet<PathBuf>, snapshot: &mut Snapshot) {
+    if depth > MAX_SCAN_DEPTH {
+        return;
+    }
+    let Ok(canonical) = fs::canonicalize(dir) else {
+        return;
+    };
+    if !visited.insert(canonical) {
+        return;
+    }
+    let Ok(entries) = fs::read_dir(dir) else {
+        return;
+    };
+    for entry in entries.flatten() {
+        let path = entry.path();
+        // Only a link needs resolving; the entry's own type is known without another stat
+        let metadata = match entry.file_type() {
+            Ok(file_type) if file_type.is_symlink() => fs::metadata(&path),
+            Ok(_) => entry.metadata(),
+            Err(e) => Err(e),
+        };
+        match metadata {
+            Ok(metadata) if metadata.is_dir() => scan_dir(&path, depth + 1, visited, snapshot),
+            Ok(metadata) => {
+                snapshot.insert(path, (metadata.len(), metadata.modified().ok()));
+            }
+            Err(_) => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{TempDir, TestServer};
+    use std::io::Write;
+
+    /// Reads from `stream` until what arrived contains `wanted`
+    fn read_until(stream: &mut impl Read, wanted: &str) -> String {
+        let mut received = Vec::new();
+        let mut chunk = [0; 1024];
+        while !String::from_utf8_lossy(&received).contains(wanted) {
+            let size = stream.read(&mut chunk).expect("the stream should keep going");
+            assert!(size > 0, "the stream ended early: {}", String::from_utf8_lossy(&received));
+            received.extend_from_slice(&chunk[..size]);
+        }
+        String::from_utf8(received).unwrap()
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn symlink_loops_and_deep_trees_are_scanned_once() {
+        let root = TempDir::new();
+        root.write("a/page.html", "a");
+        std::os::unix::fs::symlink(root.path(), root.path().join("a/up")).unwrap();
+        std::os::unix::fs::symlink(root.path().join("a/page.html"), root.path().join("linked.html")).unwrap();
+        let deep: String = (0..MAX_SCAN_DEPTH + 5).map(|level| format!("d{}/", level)).collect();
+        root.write(&format!("{}buried.txt", deep), "b");
+        root.write("d0/shallow.txt", "s");
+
+        let snapshot = scan(&[root.path().to_path_buf()]);
+        let mut names: Vec<_> =
+            snapshot.keys().map(|path| path.file_name().unwrap().to_str().unwrap().to_string()).collect();
+        names.sort();
+        assert_eq!(names, ["linked.html", "page.html", "shallow.txt"]);
+    }
+
+    #[test]
+    fn a_burst_of_changes_is_one_reload_event() {
+        let root = TempDir::new();
+        root.write("index.html", "1");
+        let watcher = Arc::new(Watcher::new());
+        let stopped = AtomicBool::new(false);
+        let roots = vec![root.path().to_path_buf()];
+        thread::scope(|scope| {
+            scope.spawn(|| watcher.run(|| roots.clone(), &stopped));
+            let mut events = watcher.subscribe();
+            assert_eq!(read_until(&mut events, "\n\n"), "retry: 1000\n\n");
+            thread::sleep(POLL_INTERVAL * 2);
+            root.write("index.html", "22");
+            root.write("style.css", "p {}");
+            assert_eq!(read_until(&mut events, "\n\n"), "data: reload\n\n");
+            assert_eq!(watcher.state.lock().unwrap().generation, 1);
+            stopped.store(true, Ordering::SeqCst);
+            // Stopping ends the stream
+            let mut rest = Vec::new();
+            events.read_to_end(&mut rest).unwrap();
+            assert!(rest.is_empty());
+        });
+    }
+
+    #[test]
+    fn http_1_0_subscribers_get_the_stream_unbuffered() {
+        let root = TempDir::new();
+        root.write("index.html", "<body></body>");
+        let server = TestServer::with_args(&["--root", root.path().to_str().unwrap(), "--watch"]);
+        assert!(String::from_utf8(server.get("/index.html").body).unwrap().contains(CLIENT_SNIPPET));
+
+        for version in ["HTTP/1.0", "HTTP/1.1"] {
+            let mut stream = server.connect();
+            write!(stream, "GET {} {}\r\nHost: a\r\n\r\n", EVENTS_PATH, version).unwrap();
+            let head = read_until(&mut stream, "retry: 1000");
+            assert!(head.starts_with(&format!("{} 200 OK\r\n", version)), "{}", head);
+            assert!(head.contains("Content-Type: text/event-stream"), "{}", head);
+            assert!(!head.contains("Content-Length"), "{}", head);
+            if version == "HTTP/1.0" {
+                assert!(head.contains("Connection: close") && !head.contains("chunked"), "{}", head);
+            }
+            thread::sleep(POLL_INTERVAL * 2);
+            root.write(&format!("{}.txt", version), "changed");
+            read_until(&mut stream, "data: reload");
+        }
+    }
+}
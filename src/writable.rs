@@ -0,0 +1,257 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, ErrorKind};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use crate::body::RequestBody;
+use crate::http::{HttpRequest, HttpResponse, StatusCode};
+use crate::validators::{self, Validators};
+use crate::error::ServerError;
+use crate::{error_response, paths, server_error};
+
+/// Handles `PUT /path`: streams the request body into that file under `root`.
+/// Returns 201 when the file is new and 204 when an existing file was replaced,
+/// or 412 when `If-Match`/`If-Unmodified-Since` no longer match the current file.
+pub fn handle_put(request: &HttpRequest, body: &mut RequestBody, root: &Path) -> HttpResponse {
+    let Some(file_path) = paths::safe_join(root, &request.path) else {
+        return error_response(request, StatusCode::FORBIDDEN, "Access to the requested path is forbidden");
+    };
+    if request.path.ends_with('/') {
+        return error_response(request, StatusCode::CONFLICT, "Cannot PUT to a directory path");
+    }
+
+    let current = match fs::metadata(&file_path) {
+        Ok(metadata) if metadata.is_dir() => {
+            return error_response(request, StatusCode::CONFLICT, "A directory exists at this path");
+        }
+        Ok(metadata) => Some(Validators::for_file(&metadata)),
+        Err(_) => None,
+    };
+    if validators::precondition_failed(request, current.as_ref()) {
+        return precondition_failed(request);
+    }
+
+    // Like WebDAV, we don't create missing parent collections implicitly
+    let parent_exists = file_path.parent().is_some_and(Path::is_dir);
+    if !parent_exists {
+        return error_response(request, StatusCode::CONFLICT, "The parent directory does not exist");
+    }
+
+    // Write to a temporary file first so readers never see a half-written file
+    let result = create_temp(&file_path).and_then(|(temp_path, mut file)| {
+        let result = io::copy(body, &mut file).and_then(|written| fs::rename(&temp_path, &file_path).map(|_| written));
+        if result.is_err() {
+            let _ = fs::remove_file(&temp_path);
+        }
+        result
+    });
+
+    match result {
+        Ok(written) => {
+            log_info!("Stored {} bytes at {}", written, file_path.display());
+            for (name, value) in body.trailers() {
+                log_info!("Upload trailer {}: {}", name, value);
+            }
+            let response = if current.is_some() {
+                HttpResponse::new(StatusCode::NO_CONTENT)
+            } else {
+                HttpResponse::text(StatusCode::CREATED, "Created").with_header("Location", &request.path)
+            };
+            // The new ETag lets the client make its next write conditional on this one
+            match fs::metadata(&file_path) {
+                Ok(metadata) => Validators::for_file(&metadata).apply(response),
+                Err(_) => response,
+            }
+        }
+        Err(e) => {
+            // The client's fault, not the disk's
+            if let Some(error) = body.failure() {
+                return error_response(request, error.status(), error.message());
+            }
+            let error = ServerError::io(format!("writing {}", file_path.display()), e);
+            server_error(request, "Failed to store the file", &error)
+        }
+    }
+}
+
+/// Creates the temporary file an upload to `file_path` is written to. Each
+/// upload gets its own, so concurrent PUTs to one path can't truncate each
+/// other's data; the last rename to finish wins with a complete file.
+fn create_temp(file_path: &Path) -> io::Result<(PathBuf, File)> {
+    static NEXT_UPLOAD: AtomicU64 = AtomicU64::new(0);
+    // Names already taken were left behind by a crashed process with our pid
+    const ATTEMPTS: usize = 16;
+    let mut last_error = None;
+    for _ in 0..ATTEMPTS {
+        let mut temp_name = file_path.as_os_str().to_owned();
+        temp_name.push(format!(".upload-{}-{}", std::process::id(), NEXT_UPLOAD.fetch_add(1, Ordering::Relaxed)));
+        let temp_path = PathBuf::from(temp_name);
+        match OpenOptions::new().write(true).create_new(true).open(&temp_path) {
+            Ok(file) => return Ok((temp_path, file)),
+            Err(e) if e.kind() == ErrorKind::AlreadyExists => last_error = Some(e),
+            Err(e) => return Err(e),
+        }
+    }
+    Err(last_error.unwrap_or_else(|| io::Error::other("no free temporary file name")))
+}
+
+/// Handles `DELETE /path`: removes that file from `root`
+pub fn handle_delete(request: &HttpRequest, root: &Path) -> HttpResponse {
+    let Some(file_path) = paths::safe_join(root, &request.path) else {
+        return error_response(request, StatusCode::FORBIDDEN, "Access to the requested path is forbidden");
+    };
+
+    let current = match fs::metadata(&file_path) {
+        Ok(metadata) if metadata.is_dir() => {
+            return error_response(request, StatusCode::CONFLICT, "Refusing to delete a directory");
+        }
+        Ok(metadata) => Some(Validators::for_file(&metadata)),
+        Err(_) => None,
+    };
+    if validators::precondition_failed(request, current.as_ref()) {
+        return precondition_failed(request);
+    }
+
+    match fs::remove_file(&file_path) {
+        Ok(()) => {
+            log_info!("Deleted {}", file_path.display());
+            HttpResponse::new(StatusCode::NO_CONTENT)
+        }
+        Err(e) if e.kind() == ErrorKind::NotFound => {
+            error_response(request, StatusCode::NOT_FOUND, "The requested file was not found")
+        }
+        Err(e) => {
+            let error = ServerError::io(format!("deleting {}", file_path.display()), e);
+            server_error(request, "Failed to delete the file", &error)
+        }
+    }
+}
+
+fn precondition_failed(request: &HttpRequest) -> HttpResponse {
+    error_response(request, StatusCode::PRECONDITION_FAILED, "The file has changed since it was last fetched")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+    use std::sync::Barrier;
+    use crate::testing::{TempDir, TestServer, build_request, read_to_close};
+
+    fn writable_server(root: &TempDir, extra: &[&str]) -> TestServer {
+        let mut args = vec!["--root", root.path().to_str().unwrap(), "--writable", root.path().to_str().unwrap()];
+        args.extend_from_slice(extra);
+        TestServer::with_args(&args)
+    }
+
+    #[test]
+    fn put_creates_then_replaces_and_delete_removes() {
+        let root = TempDir::new();
+        let server = writable_server(&root, &[]);
+
+        let created = server.send(build_request("PUT", "/note.txt", &[], b"first"));
+        assert_eq!(created.status, 201);
+        assert_eq!(created.header("Location"), Some("/note.txt"));
+        let replaced = server.send(build_request("PUT", "/note.txt", &[], b"second"));
+        assert_eq!(replaced.status, 204);
+        assert_eq!(fs::read(root.path().join("note.txt")).unwrap(), b"second");
+
+        assert_eq!(server.request("DELETE", "/note.txt", &[]).status, 204);
+        assert!(!root.path().join("note.txt").exists());
+        assert_eq!(server.request("DELETE", "/note.txt", &[]).status, 404);
+    }
+
+    #[test]
+    fn stale_validators_make_writes_fail_with_412() {
+        let root = TempDir::new();
+        let server = writable_server(&root, &[]);
+        let created = server.send(build_request("PUT", "/doc.txt", &[], b"v1"));
+        let etag = created.header("ETag").expect("writes return the new ETag").to_string();
+
+        // Another writer replaces the file, so the first client's ETag goes stale
+        let updated = server.send(build_request("PUT", "/doc.txt", &[("If-Match", &etag)], b"version 2"));
+        assert_eq!(updated.status, 204);
+        let stale = server.send(build_request("PUT", "/doc.txt", &[("If-Match", &etag)], b"v3"));
+        assert_eq!(stale.status, 412);
+        assert_eq!(server.request("DELETE", "/doc.txt", &[("If-Match", &etag)]).status, 412);
+        assert_eq!(fs::read(root.path().join("doc.txt")).unwrap(), b"version 2");
+
+        let past = "Sat, 01 Jan 2000 00:00:00 GMT";
+        let unmodified = server.send(build_request("PUT", "/doc.txt", &[("If-Unmodified-Since", past)], b"v4"));
+        assert_eq!(unmodified.status, 412);
+        // If-Match: * only requires that the file exists
+        assert_eq!(server.send(build_request("PUT", "/new.txt", &[("If-Match", "*")], b"x")).status, 412);
+        assert_eq!(server.request("DELETE", "/doc.txt", &[("If-Match", "*")]).status, 204);
+    }
+
+    #[test]
+    fn put_over_the_body_limit_is_refused() {
+        let root = TempDir::new();
+        let server = writable_server(&root, &["--max-body", "4"]);
+
+        let response = server.send(build_request("PUT", "/big.txt", &[], b"too large"));
+        assert_eq!(response.status, 413);
+        assert!(!root.path().join("big.txt").exists());
+    }
+
+    #[test]
+    fn chunked_upload_with_a_trailer_is_stored() {
+        let root = TempDir::new();
+        let server = writable_server(&root, &[]);
+        let chunked = [("Transfer-Encoding", "chunked")];
+
+        let mut upload = build_request("PUT", "/sum.txt", &chunked, b"");
+        upload.extend_from_slice(b"5\r\nhello\r\n0\r\nX-Checksum: 5d41402a\r\n\r\n");
+        assert_eq!(server.send(upload).status, 201);
+        assert_eq!(fs::read(root.path().join("sum.txt")).unwrap(), b"hello");
+
+        // A trailer that would change how the body reads is refused, and nothing is stored
+        let mut smuggling = build_request("PUT", "/bad.txt", &chunked, b"");
+        smuggling.extend_from_slice(b"5\r\nhello\r\n0\r\nContent-Length: 3\r\n\r\n");
+        assert_eq!(server.send(smuggling).status, 400);
+        assert!(!root.path().join("bad.txt").exists());
+    }
+
+    #[test]
+    fn temp_files_are_unique_per_upload() {
+        let dir = TempDir::new();
+        let target = dir.path().join("file.txt");
+        let (first, _file) = create_temp(&target).unwrap();
+        let (second, _file) = create_temp(&target).unwrap();
+        assert_ne!(first, second);
+        assert!(first.exists() && second.exists());
+    }
+
+    #[test]
+    fn concurrent_puts_never_mix_their_bodies() {
+        let root = TempDir::new();
+        let server = writable_server(&root, &["--workers", "8"]);
+        let bodies: Vec<Vec<u8>> = (b'a'..=b'h').map(|byte| vec![byte; 256 * 1024]).collect();
+
+        // Every upload is half sent before any finishes, so they all overlap
+        let halfway = Barrier::new(bodies.len());
+        std::thread::scope(|scope| {
+            for body in &bodies {
+                let (server, halfway) = (&server, &halfway);
+                scope.spawn(move || {
+                    let request = build_request("PUT", "/shared.bin", &[], body);
+                    let (first, rest) = request.split_at(request.len() / 2);
+                    let mut stream = server.connect();
+                    stream.write_all(first).unwrap();
+                    halfway.wait();
+                    stream.write_all(rest).unwrap();
+                    read_to_close(&mut stream)
+                });
+            }
+        });
+
+        let stored = fs::read(root.path().join("shared.bin")).unwrap();
+        assert!(bodies.contains(&stored), "the stored file must be one upload in full");
+        let leftovers: Vec<_> = fs::read_dir(root.path())
+            .unwrap()
+            .filter_map(Result::ok)
+            .filter(|entry| entry.file_name().to_string_lossy().contains(".upload-"))
+            .collect();
+        assert!(leftovers.is_empty(), "temporary files left behind: {:?}", leftovers);
+    }
+}